@@ -2,10 +2,12 @@ extern crate elf as elflib;
 extern crate riscv_sandbox;
 
 use riscv_sandbox::elf;
+use riscv_sandbox::htif::Htif;
 use riscv_sandbox::machine::{IntegerMachine, MultiCoreIMachine, rv32pthread::Machine};
 use riscv_sandbox::memory::Memory;
 use std::collections::{HashMap, BTreeMap};
 use std::env;
+use std::process;
 use std::sync::{Arc, Mutex};
 
 fn main() {
@@ -39,6 +41,13 @@ fn main() {
     println!("setting pc to 0x{:x}", pc as usize);
     machine.set_pc(pc);
     machine.set_i_register(1, 0);
+    if let Some(htif) = Htif::from_elf(&file) {
+        memory.allocate_at(htif.tohost(), 8);
+        if let Some(fromhost) = htif.fromhost() {
+            memory.allocate_at(fromhost, 8);
+        }
+        machine.enable_htif(htif);
+    }
     let mut i = 0;
 
     let mem = Arc::new(Mutex::new(memory));
@@ -54,5 +63,9 @@ fn main() {
         }
     }
 
-    println!("[SIM] program ended in {} cycles with value {}", i, machine.get_i_register(10))
+    println!("[SIM] program ended in {} cycles with value {}", i, machine.get_i_register(10));
+    if let Some(code) = machine.exit_code() {
+        println!("[SIM] program exited with code {}", code);
+        process::exit(code as i32);
+    }
 }
@@ -0,0 +1,28 @@
+extern crate riscv_sandbox;
+
+use riscv_sandbox::conformance::{self, Report, Target};
+use std::env;
+use std::process;
+
+// riscv_tests DIR [MAX_CYCLES [MACHINE...]]
+//
+// Runs the riscv-tests and riscv-arch-test ELFs of DIR on the machines
+// (rv32imc, rv32pthread, simtx; all of them by default), and exits with 1
+// if any test fails.
+fn main() {
+    let mut args = env::args(); args.next();
+
+    let dir = args.next().expect("You need to give a directory of tests");
+    let limit = args.next().map_or(1_000_000, |l| l.parse().expect("MAX_CYCLES must be a number"));
+    let mut targets : Vec<Target> = args.map(|t| t.parse().unwrap_or_else(|e| panic!("{}", e))).collect();
+    if targets.is_empty() {
+        targets = Target::all().to_vec();
+    }
+
+    let tests = conformance::discover(&dir).expect("Cannot read the directory of tests");
+    let report = Report::run(&tests, &targets, limit);
+    print!("{}", report);
+    if report.failures().next().is_some() {
+        process::exit(1)
+    }
+}
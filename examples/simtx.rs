@@ -1,44 +1,106 @@
 extern crate elf as elflib;
 extern crate riscv_sandbox;
-#[macro_use]
 extern crate clap;
 
-use clap::Values;
+use clap::{Arg, ArgAction, ArgMatches, Command, value_parser};
 
+use riscv_sandbox::cache::{CachedMemory, CacheConfig, HierarchyConfig};
+use riscv_sandbox::cfg::Cfg;
 use riscv_sandbox::elf;
-use riscv_sandbox::machine::{MultiCoreIMachine, simtx::Machine as SIMTX, simtx::scheduler::{TimeShareScheduler, LexicoScheduler, LoopAwareScheduler}};
+use riscv_sandbox::htif::Htif;
+use riscv_sandbox::machine::{MultiCoreIMachine, simtx::Machine as SIMTX, simtx::scheduler::{SimtxScheduler, SchedulerRegistry, SchedulerVisitor}};
+use riscv_sandbox::machine::simtx::files;
+use riscv_sandbox::machine::simtx::timing::TimingConfig;
+use riscv_sandbox::machine::simtx::ipdom::ReconvergencePoints;
+use riscv_sandbox::machine::simtx::predictor::PredictorConfig;
+use riscv_sandbox::machine::simtx::formation::FormationConfig;
 use riscv_sandbox::memory::Memory;
+use riscv_sandbox::symbolizer::Symbolizer;
+use riscv_sandbox::trace::{Tracer, TraceFilter, TraceFormat};
+use riscv_sandbox::types::BitSet;
 
 use std::collections::{HashMap, BTreeMap};
 use std::env;
+use std::fs;
+use std::marker::PhantomData;
+use std::process;
 use std::sync::{Arc, Mutex};
-use std::ops::DerefMut;
 
-fn is_usize(s:String) -> Result<(), String> {
-    s.parse::<usize>().map_err(|e| String::from("Must be a number")).map(|_| ())
+// parses a memory range given as beg-end, in hexadecimal
+fn parse_range(range:&str) -> (usize, usize) {
+    let mut iter = range.splitn(2, '-');
+    let mut bound = || iter.next().and_then(|v| usize::from_str_radix(v, 16).ok())
+        .expect("[ERR] Memory ranges are given as beg-end, in hexadecimal");
+    let beg = bound();
+    (beg, bound())
 }
 
-fn main() {
+type SimMemory = Arc<Mutex<CachedMemory<BTreeMap<usize, [u8;4096]>>>>;
+
+// Everything the simulation needs but the scheduler, chosen by name
+struct Simulation<'a, BV> {
+    conf:&'a ArgMatches,
+    file:&'a elflib::File,
+    memory:SimMemory,
+    calls:HashMap<i32, String>,
+    pc:i32,
+    stackend:usize,
+    stackstart:usize,
+    stacksize:usize,
+    mask:PhantomData<BV>,
+}
 
-    let conf = clap_app!(myapp =>
-        (version: "1.0")
-        (author: "Arthur Blanleuil")
-        (about: "A SIMTX emulator written in Rust")
-        (@arg TPW: +required +takes_value {is_usize} "Sets the number of threads per warps")
-        (@arg NBW: +required +takes_value {is_usize} "Sets the number of warps")
-        (@arg monitored: -m --monitor [pc]... "Provide a list of pc to parse")
-        (@arg command: * ... "The command to run")
-    ).get_matches();
+impl<'a, BV:BitSet> SchedulerVisitor for Simulation<'a, BV> {
+    type Output = ();
+    fn visit<S:SimtxScheduler>(self) {
+        simulate::<S, BV>(self)
+    }
+}
 
-    let tpw : usize = conf.value_of("TPW").unwrap().parse()
-        .expect("TPW must be a number");
-    let nb_warps : usize = conf.value_of("NBW").unwrap().parse()
-        .expect("NBW must be a number");
+fn main() {
 
-    let monitored_pc = conf.values_of("monitored")
-        .or(Some(Values::default())).unwrap();
+    let conf = Command::new("myapp")
+        .version("1.0")
+        .author("Arthur Blanleuil")
+        .about("A SIMTX emulator written in Rust")
+        .arg(Arg::new("TPW").required(true).value_parser(value_parser!(usize)).help("Sets the number of threads per warps"))
+        .arg(Arg::new("NBW").required(true).value_parser(value_parser!(usize)).help("Sets the number of warps"))
+        .arg(Arg::new("monitored").short('m').long("monitor").value_name("pc").num_args(1..).help("Provide a list of pc to parse"))
+        .arg(Arg::new("sandbox").long("sandbox").value_name("dir").help("Directory the program can open files in (defaults to the current one)"))
+        .arg(Arg::new("memcheck").long("memcheck").action(ArgAction::SetTrue).help("Checks the memory accesses of the program"))
+        .arg(Arg::new("timing").long("timing").action(ArgAction::SetTrue).help("Counts cycles with the timing model"))
+        .arg(Arg::new("latency").long("latency").value_name("name_cycles").num_args(1..).help("Sets a latency of the timing model (e.g. mul=4)"))
+        .arg(Arg::new("coalescing").long("coalescing").value_name("line_size").num_args(0..=1).help("Analyses the memory accesses of the warps (64 bytes lines by default)"))
+        .arg(Arg::new("ipdom").long("ipdom").action(ArgAction::SetTrue).help("Reconverges at immediate post-dominators with a stack instead of using the scheduler"))
+        .arg(Arg::new("dot").long("dot").help("Writes the control-flow graph of the program to a DOT file"))
+        .arg(Arg::new("scheduler").long("scheduler").help("Sets the scheduler of the warps (lexico by default)"))
+        .arg(Arg::new("sched_param").long("sched-param").value_name("name_value").num_args(1..).help("Sets a parameter of the scheduler (e.g. rr_rounds=128)"))
+        .arg(Arg::new("predictor").long("predictor").help("Predicts the divergence of the branches (kind[:entries[:folded|direct]], kind being counter, history, pattern or perceptron)"))
+        .arg(Arg::new("formation").long("formation").help("Issues the paths of different warps at the same PC together (dwf[:banks] or tbc:warps[:banks])"))
+        .arg(Arg::new("stats_json").long("stats-json").help("Writes the SIMT statistics to a JSON file"))
+        .arg(Arg::new("stats_csv").long("stats-csv").help("Writes the SIMT statistics to a CSV file"))
+        .arg(Arg::new("stats_period").long("stats-period").value_parser(value_parser!(u64)).help("Samples the paths of the warps every so many cycles (1024 by default)"))
+        .arg(Arg::new("trace").long("trace").help("Writes the instructions committed by the threads to a file"))
+        .arg(Arg::new("trace_format").long("trace-format").help("Format of the trace: spike (as --log-commits, by default) or binary"))
+        .arg(Arg::new("trace_pcs").long("trace-pcs").help("Only traces the instructions in a PC range (e.g. 10074-10100)"))
+        .arg(Arg::new("trace_warp").long("trace-warp").value_parser(value_parser!(usize)).help("Only traces the instructions of a warp"))
+        .arg(Arg::new("trace_skip").long("trace-skip").value_parser(value_parser!(u64)).help("Skips the first traced instructions"))
+        .arg(Arg::new("trace_count").long("trace-count").value_parser(value_parser!(u64)).help("Stops tracing after so many instructions"))
+        .arg(Arg::new("seed").long("seed").value_parser(value_parser!(u64)).help("Seeds the random choices of the scheduler (0 by default)"))
+        .arg(Arg::new("memdump").short('d').long("dump").value_name("memory_range").num_args(1..).help("Provide a list of memory range to dump (e.g. 0-3ff)"))
+        .arg(Arg::new("map").long("map").value_name("memory_range").num_args(1..).help("Provide a list of memory range to allocate for the program (e.g. 10010000-10020000)"))
+        .arg(Arg::new("stacksize").short('s').long("stack").value_name("size").help("Sets the stack size of each thread"))
+        .arg(Arg::new("all_threads").long("all-threads").action(ArgAction::SetTrue).help("Starts every thread at main, each on its own stack"))
+        .arg(Arg::new("l1i").long("l1i").help("Simulates a L1 instruction cache (size:ways:line_size[:lru|plru|random[:wb|wt]])"))
+        .arg(Arg::new("l1d").long("l1d").help("Simulates a L1 data cache"))
+        .arg(Arg::new("l2").long("l2").help("Simulates a L2 cache"))
+        .arg(Arg::new("command").required(true).num_args(1..).help("The command to run"))
+        .get_matches();
 
-    let mut args = conf.values_of("command").unwrap();
+    let tpw = *conf.get_one::<usize>("TPW").unwrap();
+    let nb_warps = *conf.get_one::<usize>("NBW").unwrap();
+
+    let mut args = conf.get_many::<String>("command").unwrap();
 
     // get exec path and parse executable file
     let exec_path = args.next().unwrap();
@@ -52,44 +114,154 @@ fn main() {
 
     let nb_th = tpw * nb_warps;
 
-    // create some memory buffer to load instructions and rodata
-    let memory = Arc::new(Mutex::new(BTreeMap::new()));
-    let stacksize = 0x00200000;//0x800
+    let cache = |level:&str| conf.get_one::<String>(level).map(String::as_str)
+        .map(|c| c.parse::<CacheConfig>().expect("[ERR] Bad cache configuration"));
+    let caches = HierarchyConfig { l1i:cache("l1i"), l1d:cache("l1d"), l2:cache("l2") };
+
+    // create some memory buffer to load instructions and rodata, loading
+    // goes around the caches
+    let memory = Arc::new(Mutex::new(CachedMemory::new(BTreeMap::new(), caches)));
+    let stacksize = conf.get_one::<String>("stacksize").map(String::as_str)
+        .map_or(0x00200000, |v| v.parse().expect("[ERR] The stack size must be a number"));
     let stackstart;// = 0x0ff00000;
     let stackend;// = stackstart - stacksize;
     {
         let mut memory = memory.lock().unwrap();
-        let (pbeg, pend) = elf::load_program(&file, memory.deref_mut()).unwrap();
+        let memory = memory.inner_mut();
+        let (pbeg, pend) = elf::load_program(&file, memory).unwrap();
 
         stackend = pend;
         stackstart = stackend + nb_th * stacksize;
         println!("[SIM] Program sits on memory range {:x}-{:x} ({} bytes).", pbeg, pend, pend - pbeg);
         println!("[SIM] Stack bottom sits at {:x}", stackstart);
 
-        memory.allocate_at(stackstart - stacksize, stacksize);
-
-        if let Some(addr) = elf::get_symbol_address(&file, "stderr") {
-            println!("[SIM] Found stderr at {:x}, writing 2 at this address", addr);
-            memory.set_32(addr as usize, 2);
+        if conf.get_flag("all_threads") {
+            memory.allocate_at(stackend, nb_th * stacksize);
+        } else {
+            memory.allocate_at(stackstart - stacksize, stacksize);
         }
-        if let Some(addr) = elf::get_symbol_address(&file, "stdout") {
-            println!("[SIM] Found stdout at {:x}, writing 2 at this address", addr);
-            memory.set_32(addr as usize, 0);
+        for range in conf.get_many::<String>("map").unwrap_or_default() {
+            let (beg, end) = parse_range(range);
+            memory.allocate_at(beg, end - beg);
+        }
+
+        for (name, fd) in &[("stdin", files::STDIN), ("stdout", files::STDOUT), ("stderr", files::STDERR)] {
+            if let Some(addr) = elf::get_symbol_address(&file, name) {
+                println!("[SIM] Found {} at {:x}, writing {} at this address", name, addr, files::stream(*fd));
+                memory.set_32(addr as usize, files::stream(*fd));
+            }
         }
     }
 
+    // the execution masks must hold a bit per thread of a warp
+    let result = match tpw {
+        0..=32 => with_scheduler::<u32>(&conf, &file, memory, calls, pc, stackend, stackstart, stacksize),
+        33..=64 => with_scheduler::<u64>(&conf, &file, memory, calls, pc, stackend, stackstart, stacksize),
+        _ => with_scheduler::<u128>(&conf, &file, memory, calls, pc, stackend, stackstart, stacksize),
+    };
+    if let Err(e) = result {
+        println!("[ERR] {}", e);
+        process::exit(1);
+    }
+}
+
+fn with_scheduler<'a, BV:BitSet>(conf:&'a ArgMatches, file:&'a elflib::File, memory:SimMemory
+                                 , calls:HashMap<i32, String>, pc:i32
+                                 , stackend:usize, stackstart:usize, stacksize:usize) -> Result<(), String> {
+    let name = conf.get_one::<String>("scheduler").map(String::as_str).unwrap_or("lexico");
+    let sim : Simulation<BV> = Simulation { conf, file, memory, calls, pc, stackend, stackstart, stacksize, mask:PhantomData };
+    SchedulerRegistry::with_builtins().visit(name, sim)
+}
+
+fn simulate<S:SimtxScheduler, BV:BitSet>(sim:Simulation<BV>) {
+    let Simulation { conf, file, memory, calls, pc, stackend, stackstart, stacksize, .. } = sim;
+    let tpw = *conf.get_one::<usize>("TPW").unwrap();
+    let nb_warps = *conf.get_one::<usize>("NBW").unwrap();
+    let monitored_pc = conf.get_many::<String>("monitored").unwrap_or_default();
+    let mut args = conf.get_many::<String>("command").unwrap();
+    let exec_path = args.next().unwrap();
+
     // create the machine and set it up
-    #[cfg(all(not(loopaware), not(timeshare)))]
-    let mut machine : SIMTX<LexicoScheduler> = SIMTX::new(tpw, nb_warps, calls);
-    #[cfg(timeshare)]
-    let mut machine : SIMTX<TimeShareScheduler> = SIMTX::new(tpw, nb_warps, calls);
-    #[cfg(loopaware)]
-    let mut machine : SIMTX<LoopAwareScheduler> = SIMTX::new(tpw, nb_warps, calls);
+    let mut machine : SIMTX<S, BV> = SIMTX::new(tpw, nb_warps, calls);
+    if let Some(&seed) = conf.get_one::<u64>("seed") {
+        machine.seed(seed);
+    }
+    for param in conf.get_many::<String>("sched_param").unwrap_or_default() {
+        let mut kv = param.splitn(2, '=');
+        let name = kv.next().unwrap();
+        let value = kv.next().expect("[ERR] Scheduler parameters are given as name=value");
+        machine.set_scheduler_param(name, value).expect("[ERR] Bad scheduler parameter");
+    }
+    if let Some(predictor) = conf.get_one::<String>("predictor").map(String::as_str) {
+        let config = predictor.parse::<PredictorConfig>().expect("[ERR] Bad divergence predictor");
+        machine.set_divergence_predictor(config.build());
+    }
+    machine.set_symbolizer(Symbolizer::from_elf(file));
+    if let Some(htif) = Htif::from_elf(file) {
+        println!("[SIM] Found tohost at {:x}, the program exits through it", htif.tohost());
+        machine.enable_htif(htif);
+    }
+    machine.place_stack(stackend, stacksize);
+    if conf.get_flag("memcheck") {
+        machine.enable_memcheck();
+    }
+    if conf.get_flag("timing") {
+        let mut config = TimingConfig::default();
+        for latency in conf.get_many::<String>("latency").unwrap_or_default() {
+            let mut kv = latency.splitn(2, '=');
+            let name = kv.next().unwrap();
+            let cycles = kv.next().and_then(|v| v.parse().ok())
+                .expect("[ERR] Latencies are given as name=cycles");
+            config.set(name, cycles).expect("[ERR] Bad latency");
+        }
+        machine.enable_timing(config);
+    }
+    let cfg = Cfg::from_elf(file).expect("[ERR] This ELF file has no .text section");
+    machine.load_program(&cfg);
+    if let Some(path) = conf.get_one::<String>("dot").map(String::as_str) {
+        fs::write(path, cfg.to_dot()).expect("[ERR] Cannot write the DOT file");
+    }
+    if conf.get_flag("ipdom") {
+        machine.enable_ipdom(ReconvergencePoints::from_cfg(&cfg));
+    }
+    if let Some(formation) = conf.get_one::<String>("formation").map(String::as_str) {
+        let config = formation.parse::<FormationConfig>().expect("[ERR] Bad warp formation");
+        machine.enable_warp_formation(config);
+    }
+    if conf.contains_id("coalescing") {
+        let line_size = conf.get_one::<String>("coalescing").map(String::as_str)
+            .map_or(64, |v| v.parse().expect("[ERR] The line size must be a number"));
+        machine.enable_coalescing(line_size);
+    }
+    if let Some(dir) = conf.get_one::<String>("sandbox").map(String::as_str) {
+        machine.set_sandbox(dir);
+    }
+    if let Some(&period) = conf.get_one::<u64>("stats_period") {
+        machine.set_stats_period(period);
+    }
+    if let Some(path) = conf.get_one::<String>("trace").map(String::as_str) {
+        let format = conf.get_one::<String>("trace_format").map(String::as_str)
+            .map_or(TraceFormat::Spike, |f| f.parse().expect("[ERR] Bad trace format"));
+        let filter = TraceFilter {
+            pcs: conf.get_one::<String>("trace_pcs").map(String::as_str).map(|range| {
+                let (beg, end) = parse_range(range);
+                (beg as u32, end as u32)
+            }),
+            warp: conf.get_one::<usize>("trace_warp").copied(),
+            skip: conf.get_one::<u64>("trace_skip").map_or(0, |&n| n),
+            count: conf.get_one::<u64>("trace_count").copied(),
+        };
+        machine.enable_trace(Tracer::to_file(format, filter, path).expect("[ERR] Cannot create the trace file"));
+    }
+    for name in machine.unresolved_host_calls() {
+        println!("[SIM] Warning: no host call handler for imported function {}", name);
+    }
     println!("[SIM] Setting pc to 0x{:x}", pc as usize);
 
     {
         // Compute argc and argv
         let mut memory = memory.lock().unwrap();
+        let memory = memory.inner_mut();
         let mut argc = 1;
         let mut argv = vec![exec_path.clone()];
         while let Some(s) = args.next() {
@@ -121,17 +293,21 @@ fn main() {
             i += 4;
         }
 
-        // setup core[0] registers
-        let c = machine.pop_first_idle();
-        machine.set_pc_of(c, pc);
-        machine.set_i_register_of(c, 1, 0);
-        machine.set_i_register_of(c, 2, stackstart as i32);
-        machine.set_i_register_of(c, 3, 0x1206c + 1940);
-        machine.set_i_register_of(c, 10, argc);
-        machine.set_i_register_of(c, 11, 0x14);
+        // setup core[0] registers, or the ones of every core, each with
+        // its own stack
+        let nb_th = if conf.get_flag("all_threads") { tpw * nb_warps } else { 1 };
+        for _ in 0..nb_th {
+            let c = machine.pop_first_idle();
+            machine.set_pc_of(c, pc);
+            machine.set_i_register_of(c, 1, 0);
+            machine.set_i_register_of(c, 2, (stackstart - c * stacksize) as i32);
+            machine.set_i_register_of(c, 3, 0x1206c + 1940);
+            machine.set_i_register_of(c, 10, argc);
+            machine.set_i_register_of(c, 11, 0x14);
+        }
     }
 
-    let mut i = 0;
+    let mut i : u64 = 0;
 
     // execute the program until its end
     loop {
@@ -143,11 +319,45 @@ fn main() {
         }
     }
 
+    if let Some(error) = machine.error() {
+        println!("[SIM] Error: {}", error);
+    }
+    machine.finish_trace().expect("[ERR] Cannot write the trace");
+
     for pc in monitored_pc {
-        machine.print_branch_hist(i32::from_str_radix(pc.into(), 16).unwrap());
+        machine.print_branch_hist(i32::from_str_radix(pc, 16).unwrap());
     }
 
     machine.print_relevant_pcs();
     machine.print_branch_stats();
-    println!("[SIM] program ended in {} cycles with value {}", i, machine.get_i_register_of(0, 10));
+    machine.print_prediction_report();
+    machine.print_heap_stats();
+    if conf.get_flag("memcheck") {
+        machine.print_memcheck_report();
+    }
+    machine.print_timing_report();
+    machine.print_coalescing_report();
+    machine.print_formation_report();
+    machine.print_simt_stats();
+    let stats = machine.simt_stats();
+    if let Some(path) = conf.get_one::<String>("stats_json").map(String::as_str) {
+        fs::write(path, stats.to_json()).expect("[ERR] Cannot write the JSON statistics");
+    }
+    if let Some(path) = conf.get_one::<String>("stats_csv").map(String::as_str) {
+        fs::write(path, stats.to_csv()).expect("[ERR] Cannot write the CSV statistics");
+    }
+    memory.lock().unwrap().print_report();
+    for range in conf.get_many::<String>("memdump").unwrap_or_default() {
+        let (beg, end) = parse_range(range);
+        let mut memory = memory.lock().unwrap();
+        for i in (beg..end).step_by(4) {
+            println!("{:08x}: 0x{:08x}", i, memory.inner_mut().get_32(i));
+        }
+    }
+    let cycles = machine.timing().map_or(i, |t| t.cycle());
+    println!("[SIM] program ended in {} cycles with value {}", cycles, machine.get_i_register_of(0, 10));
+    if let Some(code) = machine.exit_code() {
+        println!("[SIM] program exited with code {}", code);
+        process::exit(code as i32);
+    }
 }
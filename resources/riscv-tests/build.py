@@ -0,0 +1,84 @@
+#!/usr/bin/env python3
+"""Builds the tests of this directory:
+
+- the `rv32ui-p-*`, `rv32um-p-*`, `rv32ua-p-*` and `rv32uc-p-*` tests of
+  isa/, linked as by env/p/link.ld into isa/, named as riscv-tests names
+  them.
+- the self-tests of src/, with code at 0x80000000, `tohost` at 0x80001000,
+  `fromhost` at 0x80001040, and the signature region from 0x80001100
+  (`begin_signature`) for SIGNATURE_WORDS words (`end_signature`). They
+  must not need relocations (use `li` with absolute addresses).
+
+Needs the C preprocessor and llvm-mc, but no RISC-V toolchain: see
+rvlink.py.
+
+    ./build.py
+"""
+
+import os
+
+import rvlink
+
+TEXT = 0x80000000
+DATA = 0x80001000
+TOHOST = DATA
+FROMHOST = DATA + 0x40
+SIGNATURE = DATA + 0x100
+SIGNATURE_WORDS = 16
+DATA_SIZE = 0x200
+
+# env/p/link.ld
+P_BASE = 0x80000000
+P_SECTIONS = [(".text.init", 1), (".tohost", 0x1000), (".text", 0x1000), (".data", 0x1000), (".bss", 1)]
+
+# the extensions each suite is assembled with, as riscv-tests builds rv32uc
+# for rv32gc and the others for rv32g
+SUITES = {"rv32ui": "+m,-c,-relax", "rv32um": "+m,-c,-relax",
+          "rv32ua": "+m,+a,-c,-relax", "rv32uc": "+m,+a,+c,-relax"}
+
+HERE = os.path.dirname(os.path.abspath(__file__))
+
+
+def selftest(source):
+    obj = rvlink.assemble(source)
+    text = next(s for s in rvlink.read_object(obj)[0] if s.name == ".text")
+    text.addr = TEXT
+    data = rvlink.Section(".data", rvlink.SHT_PROGBITS, rvlink.SHF_ALLOC | rvlink.SHF_WRITE, bytes(DATA_SIZE), 64)
+    data.addr = DATA
+    symbols = [rvlink.Symbol(name, value - section.addr, section, rvlink.STB_GLOBAL, 0)
+               for name, value, section in [("_start", TEXT, text), ("tohost", TOHOST, data),
+                                            ("fromhost", FROMHOST, data),
+                                            ("begin_signature", SIGNATURE, data),
+                                            ("end_signature", SIGNATURE + 4 * SIGNATURE_WORDS, data)]]
+    return rvlink.write_elf([text, data], symbols, TEXT)
+
+
+def isa_test(suite, source):
+    isa = os.path.join(HERE, "isa")
+    includes = [os.path.join(HERE, "env", "p"), os.path.join(isa, "macros", "scalar")]
+    obj = rvlink.assemble(os.path.join(isa, suite, source), includes, mattr=SUITES[suite])
+    return rvlink.link(obj, P_BASE, P_SECTIONS)
+
+
+def main():
+    for suite in sorted(SUITES):
+        for source in sorted(os.listdir(os.path.join(HERE, "isa", suite))):
+            if not source.endswith(".S"):
+                continue
+            name = "{}-p-{}".format(suite, source[:-2])
+            with open(os.path.join(HERE, "isa", name), "wb") as f:
+                f.write(isa_test(suite, source))
+            print(name)
+
+    src = os.path.join(HERE, "src")
+    for source in sorted(os.listdir(src)):
+        if not source.endswith(".S"):
+            continue
+        name = source[:-2] + ".elf"
+        with open(os.path.join(HERE, name), "wb") as f:
+            f.write(selftest(os.path.join(src, source)))
+        print(name)
+
+
+if __name__ == "__main__":
+    main()
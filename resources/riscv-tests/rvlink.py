@@ -0,0 +1,239 @@
+"""Assembles and links RV32 test programs without a RISC-V toolchain: the
+sources go through the host C preprocessor and llvm-mc, and the objects are
+linked here, as by a linker script placing the sections one after the other.
+
+Only the relocations the tests need are handled: the absolute and
+PC-relative ones of `la`, `lui`, `auipc`, the branches, `jal` and `call`.
+The objects are assembled without relaxation, so that the code keeps the
+size it was given by the assembler.
+"""
+
+import os
+import struct
+import subprocess
+import tempfile
+
+SHT_PROGBITS, SHT_SYMTAB, SHT_STRTAB, SHT_RELA, SHT_NOBITS = 1, 2, 3, 4, 8
+SHF_WRITE, SHF_ALLOC, SHF_EXECINSTR = 1, 2, 4
+SHN_UNDEF, SHN_ABS = 0, 0xfff1
+STB_LOCAL, STB_GLOBAL, STB_WEAK = 0, 1, 2
+STT_SECTION = 3
+
+R_RISCV_32 = 1
+R_RISCV_BRANCH = 16
+R_RISCV_JAL = 17
+R_RISCV_CALL = 18
+R_RISCV_CALL_PLT = 19
+R_RISCV_PCREL_HI20 = 23
+R_RISCV_PCREL_LO12_I = 24
+R_RISCV_PCREL_LO12_S = 25
+R_RISCV_HI20 = 26
+R_RISCV_LO12_I = 27
+R_RISCV_LO12_S = 28
+R_RISCV_RELAX = 51
+
+MASK = 0xffffffff
+
+
+def assemble(source, includes=(), defines=None, mattr="+m,-c,-relax"):
+    """Preprocesses and assembles `source`, giving the bytes of the object."""
+    defines = dict(defines or {}, __riscv_xlen=32)
+    cpp = ["cpp", "-x", "assembler-with-cpp", "-P"]
+    cpp += ["-I" + i for i in includes]
+    cpp += ["-D{}={}".format(k, v) for k, v in sorted(defines.items())]
+    with tempfile.TemporaryDirectory() as tmp:
+        pre = os.path.join(tmp, "test.s")
+        obj = os.path.join(tmp, "test.o")
+        subprocess.check_call(cpp + [source, "-o", pre])
+        subprocess.check_call(["llvm-mc", "-triple=riscv32", "-mattr=" + mattr,
+                               "-filetype=obj", pre, "-o", obj])
+        with open(obj, "rb") as f:
+            return f.read()
+
+
+class Section:
+    def __init__(self, name, kind, flags, data, align, size=None):
+        self.name = name
+        self.kind = kind
+        self.flags = flags
+        self.data = bytearray(data)
+        self.align = max(align, 1)
+        self.size = len(data) if size is None else size
+        self.addr = 0
+        self.relocations = []
+
+
+class Symbol:
+    def __init__(self, name, value, section, bind, kind):
+        self.name = name
+        self.value = value
+        self.section = section
+        self.bind = bind
+        self.kind = kind
+
+
+def read_object(obj):
+    """The allocated sections of an ELF32 relocatable object, by name, and
+    its symbols."""
+    shoff, = struct.unpack_from("<I", obj, 0x20)
+    shentsize, shnum, shstrndx = struct.unpack_from("<HHH", obj, 0x2e)
+    headers = [struct.unpack_from("<IIIIIIIIII", obj, shoff + i * shentsize) for i in range(shnum)]
+
+    def string(table, offset):
+        start = headers[table][4] + offset
+        return obj[start:obj.index(b"\0", start)].decode()
+
+    sections = {}
+    for i, (name, kind, flags, _, offset, size, _, _, align, _) in enumerate(headers):
+        if flags & SHF_ALLOC:
+            data = obj[offset:offset + size] if kind != SHT_NOBITS else b""
+            sections[i] = Section(string(shstrndx, name), kind, flags, data, align, size)
+
+    symbols = []
+    for (_, kind, _, _, offset, size, link, _, _, entsize) in headers:
+        if kind != SHT_SYMTAB:
+            continue
+        for j in range(size // entsize):
+            name, value, _, info, _, shndx = struct.unpack_from("<IIIBBH", obj, offset + j * entsize)
+            symbols.append(Symbol(string(link, name), value, sections.get(shndx, shndx),
+                                  info >> 4, info & 0xf))
+
+    for (_, kind, _, _, offset, size, _, info, _, entsize) in headers:
+        if kind == SHT_RELA and info in sections:
+            for j in range(size // entsize):
+                where, rinfo, addend = struct.unpack_from("<IIi", obj, offset + j * entsize)
+                sections[info].relocations.append((where, rinfo & 0xff, symbols[rinfo >> 8], addend))
+    return list(sections.values()), symbols
+
+
+def layout(sections, base, order):
+    """Places the sections named in `order`, a list of (name, alignment),
+    from `base`: each one is aligned on the given alignment and on its own."""
+    addr = base
+    placed = []
+    for name, align in order:
+        addr = (addr + align - 1) & ~(align - 1)
+        for section in sections:
+            if section.name == name:
+                addr = (addr + section.align - 1) & ~(section.align - 1)
+                section.addr = addr
+                addr += section.size
+                placed.append(section)
+    return placed
+
+
+def address(symbol):
+    if isinstance(symbol.section, Section):
+        return symbol.section.addr + symbol.value
+    if symbol.section == SHN_ABS:
+        return symbol.value
+    if symbol.bind == STB_WEAK:
+        return 0
+    raise ValueError("undefined symbol " + symbol.name)
+
+
+def relocate(sections):
+    for section in sections:
+        # the value of the `auipc` of each PC-relative pair, by address
+        hi = {}
+        for where, kind, symbol, addend in section.relocations:
+            if kind in (R_RISCV_PCREL_HI20, R_RISCV_CALL, R_RISCV_CALL_PLT):
+                hi[section.addr + where] = (address(symbol) + addend - section.addr - where) & MASK
+
+        for where, kind, symbol, addend in section.relocations:
+            pc = section.addr + where
+            value = (address(symbol) + addend) & MASK
+            insn, = struct.unpack_from("<I", section.data, where)
+            if kind == R_RISCV_32:
+                insn = value
+            elif kind in (R_RISCV_HI20, R_RISCV_PCREL_HI20, R_RISCV_CALL, R_RISCV_CALL_PLT):
+                if kind != R_RISCV_HI20:
+                    value = hi[pc]
+                insn = (insn & 0xfff) | (((value + 0x800) >> 12) << 12 & MASK)
+                if kind in (R_RISCV_CALL, R_RISCV_CALL_PLT):
+                    jalr, = struct.unpack_from("<I", section.data, where + 4)
+                    struct.pack_into("<I", section.data, where + 4, (jalr & 0xfffff) | ((value & 0xfff) << 20))
+            elif kind in (R_RISCV_LO12_I, R_RISCV_PCREL_LO12_I):
+                if kind == R_RISCV_PCREL_LO12_I:
+                    value = hi[value]
+                insn = (insn & 0xfffff) | ((value & 0xfff) << 20)
+            elif kind in (R_RISCV_LO12_S, R_RISCV_PCREL_LO12_S):
+                if kind == R_RISCV_PCREL_LO12_S:
+                    value = hi[value]
+                insn = (insn & 0x1fff07f) | ((value & 0xfe0) << 20) | ((value & 0x1f) << 7)
+            elif kind == R_RISCV_BRANCH:
+                off = (value - pc) & MASK
+                insn = (insn & 0x1fff07f) | ((off >> 12 & 1) << 31) | ((off >> 5 & 0x3f) << 25) \
+                    | ((off >> 1 & 0xf) << 8) | ((off >> 11 & 1) << 7)
+            elif kind == R_RISCV_JAL:
+                off = (value - pc) & MASK
+                insn = (insn & 0xfff) | ((off >> 20 & 1) << 31) | ((off >> 1 & 0x3ff) << 21) \
+                    | ((off >> 11 & 1) << 20) | ((off >> 12 & 0xff) << 12)
+            elif kind != R_RISCV_RELAX:
+                raise ValueError("relocation {} not handled".format(kind))
+            struct.pack_into("<I", section.data, where, insn & MASK)
+
+
+def write_elf(sections, symbols, entry):
+    """An ELF32 executable of the placed `sections`, a segment each, with the
+    named `symbols`."""
+    names = [s.name for s in sections] + [".symtab", ".strtab", ".shstrtab"]
+    shstrtab = b"\0" + b"".join(n.encode() + b"\0" for n in names)
+
+    kept = [s for s in symbols if s.name and not s.name.startswith(".L") and s.kind != STT_SECTION
+            and isinstance(s.section, Section) and s.section in sections]
+    kept.sort(key=lambda s: s.bind != STB_LOCAL)
+    strtab = b"\0"
+    symtab = bytes(16)
+    for s in kept:
+        symtab += struct.pack("<IIIBBH", len(strtab), address(s), 0, s.bind << 4 | s.kind, 0,
+                              sections.index(s.section) + 1)
+        strtab += s.name.encode() + b"\0"
+    first_global = 1 + sum(1 for s in kept if s.bind == STB_LOCAL)
+
+    ehsize, phsize, shsize = 52, 32, 40
+    offset = ehsize + len(sections) * phsize
+    offsets = []
+    for s in sections:
+        offset = (offset + 3) & ~3
+        offsets.append(offset)
+        offset += len(s.data)
+    symtab_off = (offset + 3) & ~3
+    strtab_off = symtab_off + len(symtab)
+    shstrtab_off = strtab_off + len(strtab)
+    sh_off = (shstrtab_off + len(shstrtab) + 3) & ~3
+
+    out = b"\x7fELF" + bytes([1, 1, 1, 0]) + bytes(8)
+    # ET_EXEC, EM_RISCV
+    out += struct.pack("<HHIIIIIHHHHHH", 2, 0xf3, 1, entry, ehsize, sh_off, 0,
+                       ehsize, phsize, len(sections), shsize, len(names) + 1, len(names))
+    for s, off in zip(sections, offsets):
+        # PT_LOAD, R = 4, W = 2, X = 1
+        flags = 4 | (2 if s.flags & SHF_WRITE else 0) | (1 if s.flags & SHF_EXECINSTR else 0)
+        out += struct.pack("<IIIIIIII", 1, off, s.addr, s.addr, len(s.data), s.size, flags, s.align)
+    for s, off in zip(sections, offsets):
+        out += bytes(off - len(out)) + bytes(s.data)
+    out += bytes(symtab_off - len(out)) + symtab + strtab + shstrtab
+    out += bytes(sh_off - len(out))
+
+    def name(n):
+        return shstrtab.index(n.encode() + b"\0")
+    out += bytes(shsize)
+    for s, off in zip(sections, offsets):
+        out += struct.pack("<IIIIIIIIII", name(s.name), s.kind, s.flags, s.addr, off, s.size, 0, 0, s.align, 0)
+    # SYMTAB linked to .strtab
+    out += struct.pack("<IIIIIIIIII", name(".symtab"), SHT_SYMTAB, 0, 0, symtab_off, len(symtab),
+                       len(sections) + 2, first_global, 4, 16)
+    out += struct.pack("<IIIIIIIIII", name(".strtab"), SHT_STRTAB, 0, 0, strtab_off, len(strtab), 0, 0, 1, 0)
+    out += struct.pack("<IIIIIIIIII", name(".shstrtab"), SHT_STRTAB, 0, 0, shstrtab_off, len(shstrtab), 0, 0, 1, 0)
+    return out
+
+
+def link(obj, base, order, entry="_start"):
+    """Links the object `obj` as the linker script of `order`, see `layout`,
+    and gives the bytes of the executable."""
+    sections, symbols = read_object(obj)
+    placed = layout([s for s in sections if s.size > 0], base, order)
+    relocate(placed)
+    start = next(s for s in symbols if s.name == entry)
+    return write_elf(placed, symbols, address(start))
@@ -0,0 +1,282 @@
+//! Private write-back data caches, one per core, kept coherent by a snooping
+//! MESI or MOESI protocol on a shared bus.
+//!
+//! Besides the traffic of the protocol, every invalidation is checked for
+//! false sharing: the invalidated core had not touched any of the bytes the
+//! writer is writing, so the line only moved because both sit in it.
+
+use super::{Cache, CacheConfig, CacheStats, WritePolicy};
+use memory::Memory;
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Protocol {
+    Mesi,
+    /// MESI with an Owned state: a modified line read by another core is
+    /// shared dirty instead of written back.
+    Moesi,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineState {
+    Modified,
+    Owned,
+    Exclusive,
+    Shared,
+    Invalid,
+}
+
+impl LineState {
+    fn dirty(self) -> bool {
+        self == LineState::Modified || self == LineState::Owned
+    }
+}
+
+/// The bus traffic of the protocol, for a line or for all of them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CoherenceStats {
+    /// Read misses, and write misses (read for ownership).
+    pub bus_reads:u64,
+    pub bus_read_exclusives:u64,
+    /// Writes to a shared line, which only invalidate the other copies.
+    pub upgrades:u64,
+    /// Copies invalidated by the writes of another core.
+    pub invalidations:u64,
+    /// Invalidations where the cores never touched the same bytes.
+    pub false_sharing:u64,
+    /// Misses served by the dirty copy of another cache.
+    pub interventions:u64,
+    /// Dirty lines written back to memory, when evicted or shared.
+    pub writebacks:u64,
+}
+
+impl CoherenceStats {
+    fn add(&mut self, other:&CoherenceStats) {
+        self.bus_reads += other.bus_reads;
+        self.bus_read_exclusives += other.bus_read_exclusives;
+        self.upgrades += other.upgrades;
+        self.invalidations += other.invalidations;
+        self.false_sharing += other.false_sharing;
+        self.interventions += other.interventions;
+        self.writebacks += other.writebacks;
+    }
+}
+
+impl fmt::Display for CoherenceStats {
+    fn fmt(&self, f:&mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} bus reads, {} read-exclusives, {} upgrades, {} invalidations ({} false sharing), {} interventions, {} writebacks"
+               , self.bus_reads, self.bus_read_exclusives, self.upgrades, self.invalidations
+               , self.false_sharing, self.interventions, self.writebacks)
+    }
+}
+
+// The state of a line in each cache, and the bytes each core touched since
+// it got its copy.
+#[derive(Clone, Debug)]
+struct LineInfo {
+    states:Vec<LineState>,
+    touched:Vec<u128>,
+}
+
+/// The private data caches of `cores` cores.
+#[derive(Clone, Debug)]
+pub struct Coherence {
+    protocol:Protocol,
+    line_size:usize,
+    caches:Vec<Cache>,
+    lines:HashMap<usize, LineInfo>,
+    per_line:BTreeMap<usize, CoherenceStats>,
+}
+
+impl Coherence {
+    /// Gives each core a cache of `config`, always write-back. Panics if the
+    /// configuration is not valid, or if the lines are longer than 128 bytes.
+    pub fn new(cores:usize, config:CacheConfig, protocol:Protocol) -> Coherence {
+        if config.line_size > 128 {
+            panic!("invalid cache configuration: lines of {} bytes are longer than 128 bytes", config.line_size)
+        }
+        let config = CacheConfig { write_policy:WritePolicy::WriteBack, ..config };
+        Coherence {
+            protocol,
+            line_size:config.line_size,
+            caches:(0..cores).map(|_| Cache::new(config)).collect(),
+            lines:HashMap::new(),
+            per_line:BTreeMap::new(),
+        }
+    }
+
+    pub fn protocol(&self) -> Protocol {
+        self.protocol
+    }
+
+    pub fn cores(&self) -> usize {
+        self.caches.len()
+    }
+
+    /// The state of the line holding `addr` in the cache of `core`.
+    pub fn state(&self, core:usize, addr:usize) -> LineState {
+        let line = addr / self.line_size * self.line_size;
+        self.lines.get(&line).map_or(LineState::Invalid, |l| l.states[core])
+    }
+
+    /// Hits and misses of the cache of `core`.
+    pub fn cache_stats(&self, core:usize) -> &CacheStats {
+        self.caches[core].stats()
+    }
+
+    /// The traffic of every line which has been cached, by address.
+    pub fn per_line(&self) -> &BTreeMap<usize, CoherenceStats> {
+        &self.per_line
+    }
+
+    pub fn total(&self) -> CoherenceStats {
+        self.per_line.values().fold(CoherenceStats::default(), |mut t, s| { t.add(s); t })
+    }
+
+    /// The memory as seen by `core`, through its cache.
+    pub fn view<'a>(&'a mut self, core:usize, mem:&'a mut dyn Memory) -> CoherentMemory<'a> {
+        CoherentMemory { mem, coherence:RefCell::new(self), core }
+    }
+
+    /// Reads or writes `size` bytes at `addr` from `core`.
+    pub fn access(&mut self, core:usize, addr:usize, size:usize, write:bool) {
+        let first = addr / self.line_size;
+        let last = (addr + size.max(1) - 1) / self.line_size;
+        for line in first..=last {
+            let start = (line * self.line_size).max(addr);
+            let end = ((line + 1) * self.line_size).min(addr + size.max(1));
+            let bytes = (start..end).fold(0u128, |m, b| m | 1 << (b % self.line_size));
+            self.access_line(core, line * self.line_size, bytes, write);
+        }
+    }
+
+    fn access_line(&mut self, core:usize, line:usize, bytes:u128, write:bool) {
+        let cores = self.caches.len();
+        let state = self.state(core, line);
+
+        let access = self.caches[core].access(line, write);
+        if let Some(victim) = access.evicted {
+            let info = self.lines.get_mut(&victim).unwrap();
+            if info.states[core].dirty() {
+                self.per_line.entry(victim).or_default().writebacks += 1;
+            }
+            info.states[core] = LineState::Invalid;
+            info.touched[core] = 0;
+        }
+
+        let info = self.lines.entry(line).or_insert_with(|| LineInfo {
+            states:vec![LineState::Invalid; cores],
+            touched:vec![0; cores],
+        });
+        let stats = self.per_line.entry(line).or_default();
+
+        let new_state = match (state, write) {
+            (LineState::Invalid, false) => {
+                stats.bus_reads += 1;
+                let mut shared = false;
+                for other in (0..cores).filter(|c| *c != core) {
+                    match info.states[other] {
+                        LineState::Invalid => continue,
+                        LineState::Modified => {
+                            stats.interventions += 1;
+                            if self.protocol == Protocol::Moesi {
+                                info.states[other] = LineState::Owned;
+                            } else {
+                                stats.writebacks += 1;
+                                self.caches[other].clean(line);
+                                info.states[other] = LineState::Shared;
+                            }
+                        },
+                        LineState::Owned => stats.interventions += 1,
+                        LineState::Exclusive => info.states[other] = LineState::Shared,
+                        LineState::Shared => {},
+                    }
+                    shared = true;
+                }
+                if shared { LineState::Shared } else { LineState::Exclusive }
+            },
+            (LineState::Invalid, true) | (LineState::Shared, true) | (LineState::Owned, true) => {
+                if state == LineState::Invalid {
+                    stats.bus_read_exclusives += 1;
+                } else {
+                    stats.upgrades += 1;
+                }
+                for other in (0..cores).filter(|c| *c != core) {
+                    let other_state = info.states[other];
+                    if other_state == LineState::Invalid { continue }
+                    if other_state.dirty() && state == LineState::Invalid {
+                        stats.interventions += 1;
+                    }
+                    stats.invalidations += 1;
+                    if info.touched[other] & bytes == 0 {
+                        stats.false_sharing += 1;
+                    }
+                    self.caches[other].invalidate(line);
+                    info.states[other] = LineState::Invalid;
+                    info.touched[other] = 0;
+                }
+                LineState::Modified
+            },
+            (LineState::Exclusive, true) => LineState::Modified,
+            (state, _) => state,
+        };
+        info.states[core] = new_state;
+        info.touched[core] |= bytes;
+    }
+}
+
+/// A memory whose loads and stores go through the cache of a core.
+/// Instruction fetches bypass it.
+pub struct CoherentMemory<'a> {
+    mem:&'a mut dyn Memory,
+    coherence:RefCell<&'a mut Coherence>,
+    core:usize,
+}
+
+impl<'a> CoherentMemory<'a> {
+    fn access(&self, addr:usize, size:usize, write:bool) {
+        self.coherence.borrow_mut().access(self.core, addr, size, write)
+    }
+}
+
+impl<'a> Memory for CoherentMemory<'a> {
+    fn get_8(&self, addr:usize) -> u8 {
+        self.access(addr, 1, false);
+        self.mem.get_8(addr)
+    }
+
+    fn get_16(&self, addr:usize) -> u16 {
+        self.access(addr, 2, false);
+        self.mem.get_16(addr)
+    }
+
+    fn get_32(&self, addr:usize) -> u32 {
+        self.access(addr, 4, false);
+        self.mem.get_32(addr)
+    }
+
+    fn set_8(&mut self, addr:usize, value:u8) {
+        self.access(addr, 1, true);
+        self.mem.set_8(addr, value)
+    }
+
+    fn set_16(&mut self, addr:usize, value:u16) {
+        self.access(addr, 2, true);
+        self.mem.set_16(addr, value)
+    }
+
+    fn set_32(&mut self, addr:usize, value:u32) {
+        self.access(addr, 4, true);
+        self.mem.set_32(addr, value)
+    }
+
+    fn fetch_16(&self, addr:usize) -> u16 { self.mem.fetch_16(addr) }
+    fn fetch_32(&self, addr:usize) -> u32 { self.mem.fetch_32(addr) }
+
+    fn allocate_at(&mut self, start:usize, size:usize) -> bool {
+        self.mem.allocate_at(start, size)
+    }
+}
@@ -0,0 +1,524 @@
+//! Set-associative caches, and a `Memory` wrapper putting a hierarchy of them
+//! (split L1 instruction and data caches, and an L2) in front of any memory.
+//!
+//! The caches only keep the tags of the lines: the data always comes from
+//! the wrapped memory, so a cached memory behaves exactly like the memory it
+//! wraps, and only counts hits, misses and evictions.
+
+/// Private caches kept coherent by a snooping protocol.
+pub mod coherence;
+
+use memory::Memory;
+use types::Rng;
+
+use std::cell::RefCell;
+use std::fmt;
+use std::str::FromStr;
+
+/// Which line of a set is evicted on a miss.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Replacement {
+    /// Least recently used.
+    Lru,
+    /// Tree pseudo-LRU, the associativity must be a power of two.
+    Plru,
+    /// Pseudo-random, from a fixed seed so that runs are reproducible.
+    Random,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WritePolicy {
+    /// Writes stay in the cache (allocating the line on a miss) until the
+    /// dirty line is evicted.
+    WriteBack,
+    /// Writes go to the next level at once, and do not allocate lines.
+    WriteThrough,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CacheConfig {
+    /// Capacity and line size in bytes.
+    pub size:usize,
+    pub associativity:usize,
+    pub line_size:usize,
+    pub replacement:Replacement,
+    pub write_policy:WritePolicy,
+}
+
+impl CacheConfig {
+    /// A LRU write-back cache.
+    pub fn new(size:usize, associativity:usize, line_size:usize) -> CacheConfig {
+        CacheConfig {
+            size, associativity, line_size,
+            replacement:Replacement::Lru,
+            write_policy:WritePolicy::WriteBack,
+        }
+    }
+
+    pub fn sets(&self) -> usize {
+        self.size / (self.associativity * self.line_size)
+    }
+
+    /// Checks that the geometry describes a cache.
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.line_size.is_power_of_two() {
+            return Err(format!("line size {} is not a power of two", self.line_size))
+        }
+        if self.associativity == 0 || self.associativity > 64 {
+            return Err(format!("associativity {} is not between 1 and 64", self.associativity))
+        }
+        if self.size == 0 || self.size % (self.associativity * self.line_size) != 0 {
+            return Err(format!("size {} is not a multiple of {} ways of {} bytes"
+                               , self.size, self.associativity, self.line_size))
+        }
+        if self.replacement == Replacement::Plru && !self.associativity.is_power_of_two() {
+            return Err(format!("pseudo-LRU needs a power of two associativity, not {}"
+                               , self.associativity))
+        }
+        Ok(())
+    }
+}
+
+/// Parses `size:associativity:line_size[:replacement[:write_policy]]`, such
+/// as `32K:4:64` or `256K:8:64:plru:wt`. The size may end with `K` or `M`,
+/// the replacement is `lru`, `plru` or `random` and the write policy `wb` or
+/// `wt`.
+impl FromStr for CacheConfig {
+    type Err = String;
+
+    fn from_str(s:&str) -> Result<CacheConfig, String> {
+        fn number(s:&str) -> Result<usize, String> {
+            let (digits, unit) = match s.chars().last() {
+                Some('k') | Some('K') => (&s[..s.len()-1], 1024),
+                Some('m') | Some('M') => (&s[..s.len()-1], 1024 * 1024),
+                _ => (s, 1),
+            };
+            digits.parse::<usize>().map(|n| n * unit)
+                .map_err(|_| format!("`{}` is not a number of bytes", s))
+        }
+
+        let fields : Vec<&str> = s.split(':').collect();
+        if fields.len() < 3 || fields.len() > 5 {
+            return Err(format!("`{}` is not size:associativity:line_size[:replacement[:write_policy]]", s))
+        }
+        let mut config = CacheConfig::new(number(fields[0])?, number(fields[1])?, number(fields[2])?);
+        if let Some(replacement) = fields.get(3) {
+            config.replacement = match *replacement {
+                "lru" => Replacement::Lru,
+                "plru" => Replacement::Plru,
+                "random" => Replacement::Random,
+                r => return Err(format!("unknown replacement policy `{}`", r)),
+            };
+        }
+        if let Some(policy) = fields.get(4) {
+            config.write_policy = match *policy {
+                "wb" => WritePolicy::WriteBack,
+                "wt" => WritePolicy::WriteThrough,
+                p => return Err(format!("unknown write policy `{}`", p)),
+            };
+        }
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+impl fmt::Display for CacheConfig {
+    fn fmt(&self, f:&mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} bytes, {}-way, {} byte lines, {:?}, {:?}"
+               , self.size, self.associativity, self.line_size
+               , self.replacement, self.write_policy)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Line accesses, an access crossing a line counting twice.
+    pub reads:u64,
+    pub writes:u64,
+    pub hits:u64,
+    pub misses:u64,
+    /// Valid lines replaced, and the dirty ones among them.
+    pub evictions:u64,
+    pub writebacks:u64,
+}
+
+impl CacheStats {
+    pub fn accesses(&self) -> u64 {
+        self.reads + self.writes
+    }
+
+    pub fn hit_rate(&self) -> f64 {
+        if self.accesses() == 0 { 0. } else { self.hits as f64 / self.accesses() as f64 }
+    }
+}
+
+impl fmt::Display for CacheStats {
+    fn fmt(&self, f:&mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} accesses ({} reads, {} writes), {} hits, {} misses ({:.1}% hits), {} evictions, {} writebacks"
+               , self.accesses(), self.reads, self.writes, self.hits, self.misses
+               , self.hit_rate() * 100., self.evictions, self.writebacks)
+    }
+}
+
+/// What an access did to a cache, and what the next level has to do.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CacheAccess {
+    pub hit:bool,
+    /// The line was brought in, and has to be read from the next level.
+    pub fill:bool,
+    /// Address of the line evicted to make room for this one.
+    pub evicted:Option<usize>,
+    /// Address of the dirty line evicted, to write to the next level.
+    pub writeback:Option<usize>,
+    /// The write has to be done in the next level too.
+    pub write_through:bool,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Line {
+    valid:bool,
+    dirty:bool,
+    tag:usize,
+    last_use:u64,
+}
+
+/// A single set-associative cache.
+#[derive(Clone, Debug)]
+pub struct Cache {
+    config:CacheConfig,
+    sets:usize,
+    lines:Vec<Line>,
+    // pseudo-LRU tree of each set: bit `n` set when the victim is right of node `n`
+    plru:Vec<u64>,
+    clock:u64,
+    rng:Rng,
+    stats:CacheStats,
+}
+
+impl Cache {
+    /// Panics if the configuration is not valid, see `CacheConfig::validate`.
+    pub fn new(config:CacheConfig) -> Cache {
+        if let Err(e) = config.validate() {
+            panic!("invalid cache configuration: {}", e)
+        }
+        let sets = config.sets();
+        Cache {
+            config, sets,
+            lines:vec![Line::default(); sets * config.associativity],
+            plru:vec![0; sets],
+            clock:0,
+            rng:Rng::new(0),
+            stats:CacheStats::default(),
+        }
+    }
+
+    pub fn config(&self) -> &CacheConfig {
+        &self.config
+    }
+
+    pub fn stats(&self) -> &CacheStats {
+        &self.stats
+    }
+
+    /// Whether the line holding `addr` is in the cache.
+    pub fn contains(&self, addr:usize) -> bool {
+        let (set, tag) = self.locate(addr);
+        self.find(set, tag).is_some()
+    }
+
+    /// Reads or writes the line holding `addr`.
+    pub fn access(&mut self, addr:usize, write:bool) -> CacheAccess {
+        self.clock += 1;
+        if write { self.stats.writes += 1 } else { self.stats.reads += 1 }
+        let write_back = self.config.write_policy == WritePolicy::WriteBack;
+        let mut access = CacheAccess {
+            hit:false, fill:false, evicted:None, writeback:None,
+            write_through:write && !write_back,
+        };
+
+        let (set, tag) = self.locate(addr);
+        if let Some(way) = self.find(set, tag) {
+            self.stats.hits += 1;
+            access.hit = true;
+            self.touch(set, way, write && write_back);
+            return access
+        }
+
+        self.stats.misses += 1;
+        if write && !write_back {
+            return access
+        }
+        let way = self.victim(set);
+        let line = self.lines[set * self.config.associativity + way];
+        if line.valid {
+            self.stats.evictions += 1;
+            access.evicted = Some((line.tag * self.sets + set) * self.config.line_size);
+            if line.dirty {
+                self.stats.writebacks += 1;
+                access.writeback = Some((line.tag * self.sets + set) * self.config.line_size);
+            }
+        }
+        self.lines[set * self.config.associativity + way] = Line {
+            valid:true, dirty:false, tag, last_use:0,
+        };
+        access.fill = true;
+        self.touch(set, way, write);
+        access
+    }
+
+    /// Invalidates the line holding `addr`, returning whether it was dirty.
+    pub fn invalidate(&mut self, addr:usize) -> bool {
+        let (set, tag) = self.locate(addr);
+        match self.find(set, tag) {
+            Some(way) => {
+                let line = &mut self.lines[set * self.config.associativity + way];
+                line.valid = false;
+                line.dirty
+            },
+            None => false,
+        }
+    }
+
+    /// Marks the line holding `addr` as written back, returning whether it
+    /// was dirty.
+    pub fn clean(&mut self, addr:usize) -> bool {
+        let (set, tag) = self.locate(addr);
+        match self.find(set, tag) {
+            Some(way) => {
+                let line = &mut self.lines[set * self.config.associativity + way];
+                let dirty = line.dirty;
+                line.dirty = false;
+                dirty
+            },
+            None => false,
+        }
+    }
+
+    /// Invalidates every line, returning the addresses of the dirty ones.
+    pub fn flush(&mut self) -> Vec<usize> {
+        let mut dirty = Vec::new();
+        for (i, line) in self.lines.iter_mut().enumerate() {
+            if line.valid && line.dirty {
+                let set = i / self.config.associativity;
+                dirty.push((line.tag * self.sets + set) * self.config.line_size);
+            }
+            *line = Line::default();
+        }
+        dirty
+    }
+
+    fn locate(&self, addr:usize) -> (usize, usize) {
+        let line = addr / self.config.line_size;
+        (line % self.sets, line / self.sets)
+    }
+
+    fn find(&self, set:usize, tag:usize) -> Option<usize> {
+        let ways = self.config.associativity;
+        self.lines[set * ways..(set + 1) * ways].iter()
+            .position(|l| l.valid && l.tag == tag)
+    }
+
+    fn touch(&mut self, set:usize, way:usize, dirty:bool) {
+        let ways = self.config.associativity;
+        let line = &mut self.lines[set * ways + way];
+        line.last_use = self.clock;
+        line.dirty |= dirty;
+
+        // point every node on the path away from `way`
+        let levels = ways.trailing_zeros();
+        let mut node = 1;
+        for level in (0..levels).rev() {
+            let right = (way >> level) & 1;
+            if right == 1 {
+                self.plru[set] &= !(1 << node);
+            } else {
+                self.plru[set] |= 1 << node;
+            }
+            node = node * 2 + right;
+        }
+    }
+
+    fn victim(&mut self, set:usize) -> usize {
+        let ways = self.config.associativity;
+        let lines = &self.lines[set * ways..(set + 1) * ways];
+        if let Some(way) = lines.iter().position(|l| !l.valid) {
+            return way
+        }
+        match self.config.replacement {
+            Replacement::Lru => {
+                (0..ways).min_by_key(|w| lines[*w].last_use).unwrap()
+            },
+            Replacement::Plru => {
+                let mut node = 1;
+                let mut way = 0;
+                for _ in 0..ways.trailing_zeros() {
+                    let right = ((self.plru[set] >> node) & 1) as usize;
+                    way = way * 2 + right;
+                    node = node * 2 + right;
+                }
+                way
+            },
+            Replacement::Random => (self.rng.next_u64() % ways as u64) as usize,
+        }
+    }
+}
+
+/// The caches of a `CachedMemory`, each level being optional. Without a
+/// L1I cache, instructions are fetched through the L1D cache.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HierarchyConfig {
+    pub l1i:Option<CacheConfig>,
+    pub l1d:Option<CacheConfig>,
+    pub l2:Option<CacheConfig>,
+}
+
+#[derive(Clone, Debug)]
+struct Hierarchy {
+    l1i:Option<Cache>,
+    l1d:Option<Cache>,
+    l2:Option<Cache>,
+}
+
+impl Hierarchy {
+    fn access(&mut self, addr:usize, size:usize, write:bool, fetch:bool) {
+        let l1 = match (fetch, self.l1i.as_mut()) {
+            (true, Some(l1i)) => Some(l1i),
+            _ => self.l1d.as_mut(),
+        };
+        let line_size = l1.as_ref().map(|c| c.config().line_size)
+            .or(self.l2.as_ref().map(|c| c.config().line_size))
+            .unwrap_or(1);
+        let first = addr / line_size;
+        let last = (addr + size.max(1) - 1) / line_size;
+
+        match l1 {
+            Some(l1) => for line in first..=last {
+                let access = l1.access(line * line_size, write);
+                if let Some(l2) = self.l2.as_mut() {
+                    if let Some(victim) = access.writeback {
+                        l2.access(victim, true);
+                    }
+                    if access.fill {
+                        l2.access(line * line_size, false);
+                    }
+                    if access.write_through {
+                        l2.access(line * line_size, true);
+                    }
+                }
+            },
+            None => if let Some(l2) = self.l2.as_mut() {
+                for line in first..=last {
+                    l2.access(line * line_size, write);
+                }
+            },
+        }
+    }
+}
+
+/// Wraps `memory` with a cache hierarchy, shared by every core of the machine
+/// using it. Instruction fetches (see `Memory::fetch_32`) go through the L1I
+/// cache, loads and stores through the L1D cache, and both miss to the L2.
+pub struct CachedMemory<M:Memory> {
+    memory:M,
+    caches:RefCell<Hierarchy>,
+}
+
+impl<M:Memory> CachedMemory<M> {
+    /// Panics if a cache configuration is not valid.
+    pub fn new(memory:M, config:HierarchyConfig) -> CachedMemory<M> {
+        CachedMemory {
+            memory,
+            caches:RefCell::new(Hierarchy {
+                l1i:config.l1i.map(Cache::new),
+                l1d:config.l1d.map(Cache::new),
+                l2:config.l2.map(Cache::new),
+            }),
+        }
+    }
+
+    pub fn inner(&self) -> &M {
+        &self.memory
+    }
+
+    pub fn inner_mut(&mut self) -> &mut M {
+        &mut self.memory
+    }
+
+    pub fn into_inner(self) -> M {
+        self.memory
+    }
+
+    /// The statistics of each level, `None` for the absent ones.
+    pub fn l1i_stats(&self) -> Option<CacheStats> {
+        self.caches.borrow().l1i.as_ref().map(|c| *c.stats())
+    }
+
+    pub fn l1d_stats(&self) -> Option<CacheStats> {
+        self.caches.borrow().l1d.as_ref().map(|c| *c.stats())
+    }
+
+    pub fn l2_stats(&self) -> Option<CacheStats> {
+        self.caches.borrow().l2.as_ref().map(|c| *c.stats())
+    }
+
+    pub fn print_report(&self) {
+        let caches = self.caches.borrow();
+        if caches.l1i.is_none() && caches.l1d.is_none() && caches.l2.is_none() { return }
+        println!("=== Caches ===");
+        for (name, cache) in &[("L1I", &caches.l1i), ("L1D", &caches.l1d), ("L2", &caches.l2)] {
+            if let Some(cache) = cache {
+                println!("{} ({}): {}", name, cache.config(), cache.stats());
+            }
+        }
+    }
+
+    fn access(&self, addr:usize, size:usize, write:bool, fetch:bool) {
+        self.caches.borrow_mut().access(addr, size, write, fetch)
+    }
+}
+
+impl<M:Memory> Memory for CachedMemory<M> {
+    fn get_8(&self, addr:usize) -> u8 {
+        self.access(addr, 1, false, false);
+        self.memory.get_8(addr)
+    }
+
+    fn get_16(&self, addr:usize) -> u16 {
+        self.access(addr, 2, false, false);
+        self.memory.get_16(addr)
+    }
+
+    fn get_32(&self, addr:usize) -> u32 {
+        self.access(addr, 4, false, false);
+        self.memory.get_32(addr)
+    }
+
+    fn set_8(&mut self, addr:usize, value:u8) {
+        self.access(addr, 1, true, false);
+        self.memory.set_8(addr, value)
+    }
+
+    fn set_16(&mut self, addr:usize, value:u16) {
+        self.access(addr, 2, true, false);
+        self.memory.set_16(addr, value)
+    }
+
+    fn set_32(&mut self, addr:usize, value:u32) {
+        self.access(addr, 4, true, false);
+        self.memory.set_32(addr, value)
+    }
+
+    fn fetch_16(&self, addr:usize) -> u16 {
+        self.access(addr, 2, false, true);
+        self.memory.fetch_16(addr)
+    }
+
+    fn fetch_32(&self, addr:usize) -> u32 {
+        self.access(addr, 4, false, true);
+        self.memory.fetch_32(addr)
+    }
+
+    fn allocate_at(&mut self, start:usize, size:usize) -> bool {
+        self.memory.allocate_at(start, size)
+    }
+}
@@ -0,0 +1,515 @@
+//! Static control-flow graph of a program, recovered by decoding its code.
+//!
+//! The graph is intraprocedural: a call ends its basic block and falls
+//! through to the instruction after it, and returns (or any other indirect
+//! jump) leave the graph through a virtual exit node. The blocks are grouped
+//! in functions, which start at the function symbols of the ELF file and at
+//! the targets of direct calls.
+//!
+//! Besides post-dominators, which give the reconvergence points of branches,
+//! the graph has the dominators of each function and its natural loops, and
+//! can be written in the DOT format of Graphviz.
+
+use isa::{Instruction, OpCode};
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+/// How a basic block ends.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Terminator {
+    /// Runs into the next block, which starts at a jump target.
+    FallThrough,
+    /// A conditional branch, to its target or to the next block.
+    Branch,
+    /// A direct jump (`jal zero`).
+    Jump,
+    /// A direct or indirect call, which comes back to the next block.
+    Call,
+    /// An indirect jump, such as a return, which leaves the graph.
+    Indirect,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BasicBlock {
+    /// Address of the first instruction, and right after the last one.
+    pub start:usize,
+    pub end:usize,
+    /// Address of the last instruction.
+    pub last:usize,
+    pub terminator:Terminator,
+    /// Start addresses of the blocks control can go to, and come from.
+    pub successors:Vec<usize>,
+    pub predecessors:Vec<usize>,
+    /// Target of the direct call ending the block.
+    pub callee:Option<usize>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Function {
+    /// Name of the symbol, for the functions found in the ELF file.
+    pub name:Option<String>,
+    pub entry:usize,
+    /// Start addresses of the blocks reachable from the entry, by address.
+    pub blocks:Vec<usize>,
+}
+
+/// A natural loop: the blocks which can reach a back edge without going
+/// through its header, the header dominating them all. Back edges to the
+/// same header make a single loop.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Loop {
+    pub header:usize,
+    /// Blocks with a back edge to the header.
+    pub latches:Vec<usize>,
+    /// Start addresses of the blocks of the loop, by address.
+    pub blocks:Vec<usize>,
+    /// The lowest address of the loop, and right after the highest one. Code
+    /// which is not part of the loop may sit in between.
+    pub start:usize,
+    pub end:usize,
+    /// Index in `Cfg::loops()` of the innermost loop around this one.
+    pub parent:Option<usize>,
+    /// 1 for the outermost loops.
+    pub depth:usize,
+}
+
+impl Loop {
+    /// Whether the block starting at `block` is part of the loop.
+    pub fn contains(&self, block:usize) -> bool {
+        self.blocks.binary_search(&block).is_ok()
+    }
+}
+
+/// The basic blocks of a piece of code, grouped in functions, with their
+/// dominators, post-dominators and loops.
+#[derive(Clone, Debug)]
+pub struct Cfg {
+    blocks:Vec<BasicBlock>,
+    // block start -> index in `blocks`
+    index:BTreeMap<usize, usize>,
+    functions:Vec<Function>,
+    // block -> index in `functions`
+    function_of:Vec<usize>,
+    idoms:Vec<Option<usize>>,
+    ipdoms:Vec<Option<usize>>,
+    loops:Vec<Loop>,
+}
+
+impl Cfg {
+    /// Decodes `code`, loaded at address `base`, RVC instructions included.
+    pub fn build(code:&[u8], base:usize) -> Cfg {
+        Cfg::build_with_symbols(code, base, &BTreeMap::new())
+    }
+
+    /// Builds the graph of the `.text` section of `file`, whose function
+    /// symbols name the functions.
+    pub fn from_elf(file:&elflib::File) -> Option<Cfg> {
+        let text = file.get_section(".text")?;
+        let mut symbols = BTreeMap::new();
+        if let Some(symtab) = file.get_section(".symtab") {
+            if let Ok(syms) = file.get_symbols(symtab) {
+                for sym in syms {
+                    if sym.symtype == elflib::types::STT_FUNC && !sym.name.is_empty() {
+                        symbols.insert(sym.value as usize, sym.name);
+                    }
+                }
+            }
+        }
+        Some(Cfg::build_with_symbols(&text.data, text.shdr.addr as usize, &symbols))
+    }
+
+    // `symbols`: entry -> name of the known functions
+    fn build_with_symbols(code:&[u8], base:usize, symbols:&BTreeMap<usize, String>) -> Cfg {
+        let mut instructions : Vec<(usize, usize, Instruction)> = Vec::new();
+        let mut offset = 0;
+        while offset + 2 <= code.len() {
+            let low = code[offset] as u32 | (code[offset + 1] as u32) << 8;
+            let (size, i) = if Instruction(low).is_compressed() {
+                (2, Instruction(low).uncompressed())
+            } else if offset + 4 <= code.len() {
+                let high = code[offset + 2] as u32 | (code[offset + 3] as u32) << 8;
+                (4, Instruction(low | high << 16))
+            } else {
+                break
+            };
+            instructions.push((base + offset, size, i));
+            offset += size;
+        }
+        let end = base + offset;
+        let in_code = |addr:usize| addr >= base && addr < end;
+        let target = |pc:usize, i:&Instruction| (pc as i32).wrapping_add(i.jump_offset()) as usize;
+
+        // leaders: the first instruction, functions, jump targets and what
+        // follows jumps
+        let mut leaders = vec![base];
+        leaders.extend(symbols.keys());
+        for (pc, size, i) in &instructions {
+            if !i.is_jump() { continue }
+            if i.get_opcode_enum() != OpCode::JALR {
+                leaders.push(target(*pc, i));
+            }
+            leaders.push(pc + size);
+        }
+        leaders.retain(|l| in_code(*l));
+        leaders.sort();
+        leaders.dedup();
+
+        let mut blocks = Vec::new();
+        let mut index = BTreeMap::new();
+        let mut current = 0;
+        for (n, (pc, size, i)) in instructions.iter().enumerate() {
+            let next = pc + size;
+            let block_ends = n + 1 == instructions.len() || leaders.binary_search(&next).is_ok();
+            if !block_ends { continue }
+
+            let (terminator, mut successors) = match i.get_opcode_enum() {
+                OpCode::BRANCH => (Terminator::Branch, vec![target(*pc, i), next]),
+                OpCode::JAL if i.get_rd() == 0 => (Terminator::Jump, vec![target(*pc, i)]),
+                OpCode::JAL | OpCode::JALR if i.get_rd() != 0 => (Terminator::Call, vec![next]),
+                OpCode::JALR => (Terminator::Indirect, vec![]),
+                _ => (Terminator::FallThrough, vec![next]),
+            };
+            successors.retain(|s| in_code(*s));
+            successors.dedup();
+            let callee = Some(target(*pc, i))
+                .filter(|t| i.get_opcode_enum() == OpCode::JAL && i.get_rd() != 0 && in_code(*t));
+
+            index.insert(instructions[current].0, blocks.len());
+            blocks.push(BasicBlock {
+                start:instructions[current].0, end:next, last:*pc,
+                terminator, successors, predecessors:Vec::new(), callee,
+            });
+            current = n + 1;
+        }
+
+        for b in 0..blocks.len() {
+            for s in blocks[b].successors.clone() {
+                let start = blocks[b].start;
+                blocks[index[&s]].predecessors.push(start);
+            }
+        }
+
+        let mut cfg = Cfg {
+            blocks, index,
+            functions:Vec::new(), function_of:Vec::new(),
+            idoms:Vec::new(), ipdoms:Vec::new(), loops:Vec::new(),
+        };
+        cfg.find_functions(base, symbols);
+        cfg.idoms = cfg.compute_dominators();
+        cfg.ipdoms = cfg.compute_post_dominators();
+        cfg.loops = cfg.find_loops();
+        cfg
+    }
+
+    /// The blocks, by address.
+    pub fn blocks(&self) -> &[BasicBlock] {
+        &self.blocks
+    }
+
+    /// The functions, by entry. Code reached by no known entry makes
+    /// functions without a name.
+    pub fn functions(&self) -> &[Function] {
+        &self.functions
+    }
+
+    /// The function holding the instruction at `pc`.
+    pub fn function_of(&self, pc:usize) -> Option<&Function> {
+        let block = self.block_of(pc)?;
+        Some(&self.functions[self.function_of[self.index[&block.start]]])
+    }
+
+    /// The natural loops, by header. Loops which are not natural (entered
+    /// elsewhere than at their header) are not found.
+    pub fn loops(&self) -> &[Loop] {
+        &self.loops
+    }
+
+    /// The innermost loop around the instruction at `pc`.
+    pub fn loop_of(&self, pc:usize) -> Option<&Loop> {
+        let block = self.block_of(pc)?.start;
+        self.loops.iter()
+            .filter(|l| l.contains(block))
+            .max_by_key(|l| l.depth)
+    }
+
+    /// Start address of the block which immediately dominates the block
+    /// holding `pc`: the last block every path from the entry of the function
+    /// goes through. `None` for the entry.
+    pub fn immediate_dominator(&self, pc:usize) -> Option<usize> {
+        let block = self.block_of(pc)?;
+        self.idoms[self.index[&block.start]].map(|b| self.blocks[b].start)
+    }
+
+    /// Whether the instruction at `a` runs before the one at `b` on every path
+    /// from the entry of the function to `b`.
+    pub fn dominates(&self, a:usize, b:usize) -> bool {
+        self.is_ancestor(&self.idoms, a, b)
+    }
+
+    /// Whether the instruction at `a` runs after the one at `b` on every path
+    /// from `b` which leaves the function.
+    pub fn post_dominates(&self, a:usize, b:usize) -> bool {
+        self.is_ancestor(&self.ipdoms, a, b)
+    }
+
+    /// The block holding the instruction at `pc`.
+    pub fn block_of(&self, pc:usize) -> Option<&BasicBlock> {
+        let (_, b) = self.index.range(..=pc).next_back()?;
+        let block = &self.blocks[*b];
+        if pc < block.end { Some(block) } else { None }
+    }
+
+    /// Start address of the block which immediately post-dominates the block
+    /// holding `pc`: the first block every path from it goes through. `None`
+    /// when the paths only meet at the exit of the function.
+    pub fn immediate_post_dominator(&self, pc:usize) -> Option<usize> {
+        let block = self.block_of(pc)?;
+        self.ipdoms[self.index[&block.start]].map(|b| self.blocks[b].start)
+    }
+
+    /// Writes the graph in the DOT format, a cluster per function. Loop
+    /// headers have a double border, back edges are dashed and calls dotted.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph cfg {\n    node [shape=box, fontname=monospace];\n");
+        for (n, function) in self.functions.iter().enumerate() {
+            let name = function.name.clone().unwrap_or_else(|| format!("0x{:x}", function.entry));
+            writeln!(dot, "    subgraph cluster_{} {{\n        label=\"{}\";", n, name).unwrap();
+            for start in &function.blocks {
+                let block = &self.blocks[self.index[start]];
+                let header = if self.loops.iter().any(|l| l.header == *start) { ", peripheries=2" } else { "" };
+                writeln!(dot, "        b{:x} [label=\"0x{:x} - 0x{:x}\"{}];", start, block.start, block.last, header).unwrap();
+            }
+            dot.push_str("    }\n");
+        }
+        for block in &self.blocks {
+            for (n, s) in block.successors.iter().enumerate() {
+                let mut attributes = Vec::new();
+                if block.terminator == Terminator::Branch && block.successors.len() == 2 {
+                    attributes.push(if n == 0 { "label=T" } else { "label=F" });
+                }
+                if self.dominates(*s, block.start) {
+                    attributes.push("style=dashed");
+                }
+                writeln!(dot, "    b{:x} -> b{:x} [{}];", block.start, s, attributes.join(", ")).unwrap();
+            }
+            if let Some(callee) = block.callee {
+                writeln!(dot, "    b{:x} -> b{:x} [style=dotted];", block.start, callee).unwrap();
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    // Whether the block of `a` is on the way from the block of `b` to the root
+    // of `tree`, given as the parent of every block.
+    fn is_ancestor(&self, tree:&[Option<usize>], a:usize, b:usize) -> bool {
+        let (a, b) = match (self.block_of(a), self.block_of(b)) {
+            (Some(a), Some(b)) => (self.index[&a.start], self.index[&b.start]),
+            _ => return false,
+        };
+        let mut node = Some(b);
+        while let Some(n) = node {
+            if n == a { return true }
+            node = tree[n];
+        }
+        false
+    }
+
+    // Gives every block to a function: the blocks reachable from a symbol or
+    // a call target without going through another entry, then the blocks
+    // reachable from the first block left, and so on.
+    fn find_functions(&mut self, base:usize, symbols:&BTreeMap<usize, String>) {
+        let mut entries : BTreeSet<usize> = symbols.keys().cloned()
+            .filter(|e| self.index.contains_key(e))
+            .collect();
+        entries.extend(self.blocks.iter().filter_map(|b| b.callee));
+        if !self.blocks.is_empty() {
+            entries.insert(base);
+        }
+
+        let mut owner : Vec<Option<usize>> = vec![None; self.blocks.len()];
+        let mut pending : Vec<usize> = entries.iter().map(|e| self.index[e]).collect();
+        pending.reverse();
+        let mut next_free = 0;
+        loop {
+            let entry = match pending.pop() {
+                Some(entry) => entry,
+                None => {
+                    while next_free < self.blocks.len() && owner[next_free].is_some() {
+                        next_free += 1;
+                    }
+                    if next_free == self.blocks.len() { break }
+                    next_free
+                },
+            };
+            if owner[entry].is_some() { continue }
+
+            let function = self.functions.len();
+            let mut blocks = Vec::new();
+            let mut stack = vec![entry];
+            owner[entry] = Some(function);
+            while let Some(b) = stack.pop() {
+                blocks.push(self.blocks[b].start);
+                for s in &self.blocks[b].successors {
+                    let s = self.index[s];
+                    if owner[s].is_none() && !entries.contains(&self.blocks[s].start) {
+                        owner[s] = Some(function);
+                        stack.push(s);
+                    }
+                }
+            }
+            blocks.sort();
+            let start = self.blocks[entry].start;
+            self.functions.push(Function { name:symbols.get(&start).cloned(), entry:start, blocks });
+        }
+
+        // by entry, renumbering the blocks
+        let mut order : Vec<usize> = (0..self.functions.len()).collect();
+        order.sort_by_key(|f| self.functions[*f].entry);
+        let mut renumber = vec![0; order.len()];
+        for (new, old) in order.iter().enumerate() {
+            renumber[*old] = new;
+        }
+        self.function_of = owner.into_iter().map(|f| renumber[f.unwrap()]).collect();
+        self.functions.sort_by_key(|f| f.entry);
+    }
+
+    // Dominators of each function, from a virtual root going to every entry.
+    // Edges between functions (tail calls) are left out.
+    fn compute_dominators(&self) -> Vec<Option<usize>> {
+        let root = self.blocks.len();
+        let mut successors : Vec<Vec<usize>> = self.blocks.iter().enumerate()
+            .map(|(b, block)| block.successors.iter()
+                 .map(|s| self.index[s])
+                 .filter(|s| self.function_of[*s] == self.function_of[b])
+                 .collect())
+            .collect();
+        successors.push(self.functions.iter().map(|f| self.index[&f.entry]).collect());
+
+        let mut idoms = dominators(root, &successors);
+        idoms.pop();
+        idoms.into_iter().map(|d| d.filter(|d| *d != root)).collect()
+    }
+
+    fn find_loops(&self) -> Vec<Loop> {
+        // header -> latches
+        let mut back_edges : BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+        for block in &self.blocks {
+            for s in &block.successors {
+                if self.dominates(*s, block.start) {
+                    back_edges.entry(*s).or_default().push(block.start);
+                }
+            }
+        }
+
+        let mut loops : Vec<Loop> = back_edges.into_iter().map(|(header, latches)| {
+            let mut body = BTreeSet::new();
+            body.insert(header);
+            let mut stack = latches.clone();
+            while let Some(b) = stack.pop() {
+                if body.insert(b) {
+                    let block = &self.blocks[self.index[&b]];
+                    stack.extend(block.predecessors.iter().filter(|p| self.dominates(header, **p)));
+                }
+            }
+            let blocks : Vec<usize> = body.into_iter().collect();
+            let end = blocks.iter().map(|b| self.blocks[self.index[b]].end).max().unwrap();
+            Loop { header, latches, start:blocks[0], end, blocks, parent:None, depth:1 }
+        }).collect();
+
+        // the smallest other loop holding the header encloses the loop
+        for l in 0..loops.len() {
+            loops[l].parent = (0..loops.len())
+                .filter(|o| *o != l && loops[*o].contains(loops[l].header))
+                .min_by_key(|o| loops[*o].blocks.len());
+        }
+        for l in 0..loops.len() {
+            let mut parent = loops[l].parent;
+            while let Some(p) = parent {
+                loops[l].depth += 1;
+                parent = loops[p].parent;
+            }
+        }
+        loops
+    }
+
+    // Dominators of the reversed graph, rooted at a virtual exit which every
+    // block leaving the graph goes to.
+    fn compute_post_dominators(&self) -> Vec<Option<usize>> {
+        let exit = self.blocks.len();
+        let mut reversed : Vec<Vec<usize>> = self.blocks.iter()
+            .map(|b| b.predecessors.iter().map(|p| self.index[p]).collect())
+            .collect();
+        reversed.push(self.blocks.iter().enumerate()
+            .filter(|(_, b)| b.successors.is_empty())
+            .map(|(i, _)| i)
+            .collect());
+
+        let mut ipdoms = dominators(exit, &reversed);
+        ipdoms.pop();
+        ipdoms.into_iter().map(|d| d.filter(|d| *d != exit)).collect()
+    }
+}
+
+// The immediate dominator of every node reachable from `root`, following
+// `successors` (Cooper, Harvey and Kennedy's iterative algorithm).
+fn dominators(root:usize, successors:&[Vec<usize>]) -> Vec<Option<usize>> {
+    let n = successors.len();
+
+    // postorder, without recursion
+    let mut order = Vec::with_capacity(n);
+    let mut seen = vec![false; n];
+    let mut stack = vec![(root, 0)];
+    seen[root] = true;
+    while let Some((node, child)) = stack.pop() {
+        if let Some(&next) = successors[node].get(child) {
+            stack.push((node, child + 1));
+            if !seen[next] {
+                seen[next] = true;
+                stack.push((next, 0));
+            }
+        } else {
+            order.push(node);
+        }
+    }
+    let mut number = vec![0; n];
+    for (i, node) in order.iter().enumerate() {
+        number[*node] = i;
+    }
+    let mut predecessors = vec![Vec::new(); n];
+    for node in order.iter() {
+        for s in &successors[*node] {
+            predecessors[*s].push(*node);
+        }
+    }
+
+    let mut idom : Vec<Option<usize>> = vec![None; n];
+    idom[root] = Some(root);
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for node in order.iter().rev().filter(|n| **n != root) {
+            let mut new_idom = None;
+            for p in predecessors[*node].iter().filter(|p| idom[**p].is_some()) {
+                new_idom = Some(match new_idom {
+                    None => *p,
+                    Some(other) => {
+                        let (mut a, mut b) = (*p, other);
+                        while a != b {
+                            while number[a] < number[b] { a = idom[a].unwrap() }
+                            while number[b] < number[a] { b = idom[b].unwrap() }
+                        }
+                        a
+                    },
+                });
+            }
+            if new_idom != idom[*node] {
+                idom[*node] = new_idom;
+                changed = true;
+            }
+        }
+    }
+    idom[root] = None;
+    idom
+}
@@ -0,0 +1,402 @@
+//! Runs test ELFs in the formats of riscv-tests (`rv32ui-p-*`, `rv32um-p-*`,
+//! ...) and of riscv-arch-test on the machines of this crate. Those of
+//! `resources/riscv-tests` follow the layout of riscv-tests, but are
+//! recreations built without a RISC-V toolchain (see its `build.py`), not
+//! the upstream binaries.
+//!
+//! A test stops when it exits through its `tohost` symbol, the machines
+//! running the host side of it (`htif`). For riscv-tests, exit code 0 means
+//! it passed and `n` that its test `n` failed. The
+//! riscv-arch-test ones come with a `.reference_output`, one 32-bit word per
+//! line in hexadecimal, which must match the memory between their
+//! `begin_signature` and `end_signature` symbols once stopped.
+//!
+//! Each test runs from its entry point on a fresh machine: `rv32imc`, the
+//! first core of `rv32pthread`, or SIMT-X with a single warp of a single
+//! thread. A machine which panics fails the test without stopping the run.
+
+use elf;
+use htif::Htif;
+use machine::{IntegerMachine, MultiCoreIMachine};
+use machine::rv32imc::Machine as RV32I;
+use machine::rv32pthread::Machine as Pthread;
+use machine::simtx::{Machine as SIMTX, scheduler::LexicoScheduler};
+use memory::Memory;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+/// The machines the tests run on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Target {
+    Rv32imc,
+    Rv32pthread,
+    Simtx,
+}
+
+impl Target {
+    pub fn all() -> [Target; 3] {
+        [Target::Rv32imc, Target::Rv32pthread, Target::Simtx]
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f:&mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            Target::Rv32imc => "rv32imc",
+            Target::Rv32pthread => "rv32pthread core",
+            Target::Simtx => "SIMT-X lane",
+        })
+    }
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(s:&str) -> Result<Target, String> {
+        match s {
+            "rv32imc" => Ok(Target::Rv32imc),
+            "rv32pthread" => Ok(Target::Rv32pthread),
+            "simtx" => Ok(Target::Simtx),
+            _ => Err(format!("unknown machine '{}' (rv32imc, rv32pthread or simtx)", s)),
+        }
+    }
+}
+
+/// A test ELF, and the signature it must leave for riscv-arch-test.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Test {
+    pub name:String,
+    pub path:PathBuf,
+    pub reference:Option<PathBuf>,
+}
+
+impl Test {
+    /// The test at `path`, its reference being `NAME.reference_output` next
+    /// to it or in a `references` directory next to it, if any.
+    pub fn new<P:AsRef<Path>>(path:P) -> Test {
+        let path = path.as_ref().to_path_buf();
+        let name = path.file_stem().map_or(String::new(), |s| s.to_string_lossy().into_owned());
+        let file = format!("{}.reference_output", name);
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        let reference = [dir.join(&file), dir.join("references").join(&file)].iter()
+            .find(|p| p.is_file())
+            .cloned();
+        Test { name, path, reference }
+    }
+}
+
+// `rv32ui-p-add`, `rv32uc-p-rvc`...: the physical memory tests of the
+// extensions we may run
+fn is_riscv_test(name:&str) -> bool {
+    ["rv32ui-p-", "rv32um-p-", "rv32ua-p-", "rv32uf-p-", "rv32uc-p-"].iter()
+        .any(|prefix| name.starts_with(prefix))
+        && !name.contains('.')
+}
+
+/// The tests of `dir`, by name: the riscv-tests ones, and the `.elf` files.
+pub fn discover<P:AsRef<Path>>(dir:P) -> io::Result<Vec<Test>> {
+    let mut tests = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let name = path.file_name().map_or(String::new(), |s| s.to_string_lossy().into_owned());
+        if path.is_file() && (is_riscv_test(&name) || name.ends_with(".elf")) {
+            tests.push(Test::new(path));
+        }
+    }
+    tests.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(tests)
+}
+
+/// How a test ended.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Pass,
+    /// `tohost` got `(n << 1) | 1`.
+    Fail(u32),
+    /// The first word of the signature which differs from the reference.
+    Signature { index:usize, expected:u32, got:u32 },
+    /// `tohost` was not written within the limit.
+    Timeout,
+    /// The machine panicked, or finished without writing `tohost`.
+    Crash(String),
+    /// The test could not be loaded.
+    Error(String),
+}
+
+impl Verdict {
+    pub fn passed(&self) -> bool {
+        *self == Verdict::Pass
+    }
+
+    /// A few characters, for tables.
+    pub fn short(&self) -> String {
+        match self {
+            Verdict::Pass => String::from("PASS"),
+            Verdict::Fail(n) => format!("FAIL #{}", n),
+            Verdict::Signature { index, .. } => format!("SIG @{}", index),
+            Verdict::Timeout => String::from("TIMEOUT"),
+            Verdict::Crash(_) => String::from("CRASH"),
+            Verdict::Error(_) => String::from("ERROR"),
+        }
+    }
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f:&mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Verdict::Pass => write!(f, "passed"),
+            Verdict::Fail(n) => write!(f, "test {} failed", n),
+            Verdict::Signature { index, expected, got } =>
+                write!(f, "signature word {}: expected 0x{:08x}, got 0x{:08x}", index, expected, got),
+            Verdict::Timeout => write!(f, "tohost not written in time"),
+            Verdict::Crash(why) => write!(f, "crashed: {}", why),
+            Verdict::Error(why) => write!(f, "not run: {}", why),
+        }
+    }
+}
+
+/// Compares a signature to the text of its reference.
+pub fn check_signature(signature:&[u32], reference:&str) -> Verdict {
+    let mut expected = Vec::new();
+    for line in reference.lines().map(|l| l.trim()).filter(|l| !l.is_empty()) {
+        match u32::from_str_radix(line, 16) {
+            Ok(word) => expected.push(word),
+            Err(_) => return Verdict::Error(format!("bad reference line '{}'", line)),
+        }
+    }
+    if expected.len() != signature.len() {
+        return Verdict::Error(format!("signature of {} words, reference of {}", signature.len(), expected.len()))
+    }
+    match expected.iter().zip(signature).position(|(e, g)| e != g) {
+        Some(index) => Verdict::Signature { index, expected:expected[index], got:signature[index] },
+        None => Verdict::Pass,
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TestResult {
+    pub test:String,
+    pub target:Target,
+    pub verdict:Verdict,
+    /// Cycles or steps run.
+    pub steps:u64,
+}
+
+enum Stop {
+    Halted(u32),
+    Finished,
+    Limit,
+}
+
+// Calls `step` until it gives an exit code, the machine finishes, or
+// `limit` steps
+fn run_until<F:FnMut() -> (bool, Option<u32>)>(mut step:F, limit:u64) -> (Stop, u64) {
+    for steps in 1..=limit {
+        match step() {
+            (_, Some(code)) => return (Stop::Halted(code), steps),
+            (true, None) => return (Stop::Finished, steps),
+            _ => { },
+        }
+    }
+    (Stop::Limit, limit)
+}
+
+fn panic_message(payload:Box<dyn std::any::Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(s) => *s,
+        Err(payload) => payload.downcast_ref::<&str>().map_or(String::from("panic"), |s| s.to_string()),
+    }
+}
+
+/// Runs `test` on `target` for at most `limit` cycles (or steps for
+/// SIMT-X).
+pub fn run(test:&Test, target:Target, limit:u64) -> TestResult {
+    let (verdict, steps) = match load(test) {
+        Ok(loaded) => execute(loaded, target, limit),
+        Err(why) => (Verdict::Error(why), 0),
+    };
+    TestResult { test:test.name.clone(), target, verdict, steps }
+}
+
+// The machines sign-extend their `i32` addresses: they are truncated back to
+// the 32 bits of the ELF ones
+struct Physical(BTreeMap<usize, [u8; 4096]>);
+
+impl Memory for Physical {
+    fn get_8(&self, addr:usize) -> u8 {
+        self.0.get_8(addr as u32 as usize)
+    }
+
+    fn set_8(&mut self, addr:usize, value:u8) {
+        self.0.set_8(addr as u32 as usize, value)
+    }
+
+    fn allocate_at(&mut self, start:usize, size:usize) -> bool {
+        self.0.allocate_at(start as u32 as usize, size)
+    }
+}
+
+struct Loaded {
+    memory:Physical,
+    entry:i32,
+    tohost:usize,
+    fromhost:Option<usize>,
+    // the signature region, and its reference
+    signature:Option<(usize, usize, String)>,
+}
+
+fn load(test:&Test) -> Result<Loaded, String> {
+    let file = elflib::File::open_path(&test.path).map_err(|e| format!("{:?}", e))?;
+    let tohost = elf::get_symbol_address(&file, "tohost").ok_or("no tohost symbol")? as u32 as usize;
+    let fromhost = elf::get_symbol_address(&file, "fromhost").map(|a| a as u32 as usize);
+    let signature = match &test.reference {
+        Some(path) => {
+            let reference = fs::read_to_string(path).map_err(|e| e.to_string())?;
+            let symbol = |name| elf::get_symbol_address(&file, name)
+                .map(|a| a as u32 as usize)
+                .ok_or(format!("no {} symbol", name));
+            Some((symbol("begin_signature")?, symbol("end_signature")?, reference))
+        },
+        None => None,
+    };
+
+    let mut memory = Physical(BTreeMap::new());
+    elf::load_program(&file, &mut memory).ok_or("cannot load the program")?;
+    memory.allocate_at(tohost, 8);
+    if let Some(fromhost) = fromhost {
+        memory.allocate_at(fromhost, 8);
+    }
+    Ok(Loaded { memory, entry:file.ehdr.entry as i32, tohost, fromhost, signature })
+}
+
+fn execute(loaded:Loaded, target:Target, limit:u64) -> (Verdict, u64) {
+    let Loaded { memory, entry, tohost, fromhost, signature } = loaded;
+    let memory = Arc::new(Mutex::new(memory));
+    // the tests get no console input
+    let htif = || {
+        let mut htif = Htif::new(tohost, fromhost);
+        htif.set_console(Box::new(io::empty()), Box::new(io::stdout()));
+        htif
+    };
+
+    let run = panic::catch_unwind(AssertUnwindSafe(|| match target {
+        Target::Rv32imc => {
+            let mut machine = RV32I::new();
+            machine.set_pc(entry);
+            machine.enable_htif(htif());
+            let mut mem = memory.lock().unwrap();
+            run_until(|| {
+                machine.cycle(&mut *mem);
+                (machine.finished(), machine.exit_code())
+            }, limit)
+        },
+        Target::Rv32pthread => {
+            let mut machine = Pthread::new(HashMap::new());
+            machine.set_pc_of(0, entry);
+            machine.enable_htif(htif());
+            let mut mem = memory.lock().unwrap();
+            run_until(|| {
+                machine.cycle(&mut *mem);
+                (MultiCoreIMachine::finished(&machine), machine.exit_code())
+            }, limit)
+        },
+        Target::Simtx => {
+            let mut machine : SIMTX<LexicoScheduler> = SIMTX::new(1, 1, HashMap::new());
+            machine.set_pc_of(0, entry);
+            machine.enable_htif(htif());
+            let mem : Arc<Mutex<dyn Memory + Send>> = memory.clone();
+            run_until(|| {
+                machine.step(mem.clone());
+                (machine.finished(), machine.exit_code())
+            }, limit)
+        },
+    }));
+
+    let (stop, steps) = match run {
+        Ok(stopped) => stopped,
+        Err(payload) => return (Verdict::Crash(panic_message(payload)), 0),
+    };
+    let verdict = match (stop, signature) {
+        (Stop::Halted(_), Some((begin, end, reference))) => {
+            let mem = memory.lock().unwrap_or_else(|e| e.into_inner());
+            let words : Vec<u32> = (begin..end).step_by(4).map(|addr| mem.get_32(addr)).collect();
+            check_signature(&words, &reference)
+        },
+        (Stop::Halted(0), None) => Verdict::Pass,
+        (Stop::Halted(code), None) => Verdict::Fail(code),
+        (Stop::Finished, _) => Verdict::Crash(String::from("finished without exiting through tohost")),
+        (Stop::Limit, _) => Verdict::Timeout,
+    };
+    (verdict, steps)
+}
+
+/// The results of tests on machines.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Report {
+    pub results:Vec<TestResult>,
+}
+
+impl Report {
+    /// Runs each test on each target.
+    pub fn run(tests:&[Test], targets:&[Target], limit:u64) -> Report {
+        let results = tests.iter()
+            .flat_map(|test| targets.iter().map(move |&target| run(test, target, limit)))
+            .collect();
+        Report { results }
+    }
+
+    pub fn get(&self, test:&str, target:Target) -> Option<&TestResult> {
+        self.results.iter().find(|r| r.test == test && r.target == target)
+    }
+
+    /// The tests passed by `target`, and those run.
+    pub fn passed(&self, target:Target) -> (usize, usize) {
+        let results = self.results.iter().filter(|r| r.target == target);
+        (results.clone().filter(|r| r.verdict.passed()).count(), results.count())
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item=&TestResult> {
+        self.results.iter().filter(|r| !r.verdict.passed())
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f:&mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut tests : Vec<&str> = Vec::new();
+        let mut targets : Vec<Target> = Vec::new();
+        for result in &self.results {
+            if !tests.contains(&result.test.as_str()) { tests.push(&result.test) }
+            if !targets.contains(&result.target) { targets.push(result.target) }
+        }
+        let width = tests.iter().map(|t| t.len()).max().unwrap_or(0).max(4);
+
+        let mut header = format!("{:<width$}", "test", width=width);
+        for target in &targets {
+            header += &format!("  {:<16}", target);
+        }
+        writeln!(f, "{}", header.trim_end())?;
+        for test in &tests {
+            let mut line = format!("{:<width$}", test, width=width);
+            for &target in &targets {
+                let cell = self.get(test, target).map_or(String::from("-"), |r| r.verdict.short());
+                line += &format!("  {:<16}", cell);
+            }
+            writeln!(f, "{}", line.trim_end())?;
+        }
+
+        for &target in &targets {
+            let (passed, run) = self.passed(target);
+            writeln!(f, "{}: {}/{} passed", target, passed, run)?;
+        }
+        for result in self.failures() {
+            writeln!(f, "{} on {}: {}", result.test, result.target, result.verdict)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,299 @@
+//! Lockstep co-simulation: a machine and the reference simulator of
+//! `machine::iss` run the same program, each on its own copy of the memory.
+//! After every instruction retired by the machine, the reference runs one
+//! too, and the PCs, the instructions, all the registers and the bytes
+//! stored by either of them are compared. The first difference stops the
+//! run and is reported with the state of both.
+//!
+//! The machines are seen as a single hart through `Dut`: `rv32imc::Machine`
+//! with `Rv32iDut`, the first core of `rv32pthread::Machine` with
+//! `PthreadCore`, and a thread of SIMT-X, alone in its warp, with
+//! `SimtxLane`. A program ends when the reference returns to address 0, as
+//! the machines of this crate do.
+
+use machine::IntegerMachine;
+use machine::MultiCoreIMachine;
+use machine::iss::{self, Trap};
+use machine::rv32imc;
+use machine::rv32pthread;
+use machine::simtx::{Machine as SIMTX, scheduler::SimtxScheduler};
+use memory::Memory;
+use isa::Instruction;
+use trace::{Tracer, TraceRecord, TraceFilter};
+use types::BitSet;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// A machine run in lockstep with the reference, seen as a single hart.
+pub trait Dut {
+    fn name(&self) -> String;
+
+    /// Runs the machine until it retires an instruction, and gives what the
+    /// instruction did, or `None` if the machine finished or stopped making
+    /// progress.
+    fn retire(&mut self) -> Option<TraceRecord>;
+
+    fn register(&self, reg:usize) -> u32;
+
+    fn memory(&self, addr:usize) -> u8;
+}
+
+// Cycles without retiring anything after which a machine is stuck
+const PATIENCE : usize = 64;
+
+/// `rv32imc::Machine`, retiring its instructions when they leave the `mem`
+/// step.
+pub struct Rv32iDut<'a> {
+    machine:&'a mut rv32imc::Machine,
+    memory:&'a mut dyn Memory,
+}
+
+impl<'a> Rv32iDut<'a> {
+    /// Traces `machine`, replacing the tracer it may have.
+    pub fn new(machine:&'a mut rv32imc::Machine, memory:&'a mut dyn Memory) -> Rv32iDut<'a> {
+        machine.enable_trace(Tracer::collecting(TraceFilter::default()));
+        Rv32iDut { machine, memory }
+    }
+}
+
+impl<'a> Dut for Rv32iDut<'a> {
+    fn name(&self) -> String {
+        String::from("rv32imc")
+    }
+
+    fn retire(&mut self) -> Option<TraceRecord> {
+        for _ in 0..PATIENCE {
+            self.machine.cycle(self.memory);
+            if let Some(record) = self.machine.tracer_mut().unwrap().take_records().pop() {
+                return Some(record)
+            }
+        }
+        None
+    }
+
+    fn register(&self, reg:usize) -> u32 {
+        self.machine.get_i_register(reg) as u32
+    }
+
+    fn memory(&self, addr:usize) -> u8 {
+        self.memory.get_8(addr)
+    }
+}
+
+/// The first core of `rv32pthread::Machine`, for programs which do not
+/// create threads.
+pub struct PthreadCore<'a> {
+    machine:&'a mut rv32pthread::Machine,
+    memory:&'a mut dyn Memory,
+}
+
+impl<'a> PthreadCore<'a> {
+    /// Traces `machine`, replacing the tracer it may have.
+    pub fn new(machine:&'a mut rv32pthread::Machine, memory:&'a mut dyn Memory) -> PthreadCore<'a> {
+        machine.enable_trace(Tracer::collecting(TraceFilter::default()));
+        PthreadCore { machine, memory }
+    }
+}
+
+impl<'a> Dut for PthreadCore<'a> {
+    fn name(&self) -> String {
+        String::from("rv32pthread core 0")
+    }
+
+    fn retire(&mut self) -> Option<TraceRecord> {
+        for _ in 0..PATIENCE {
+            self.machine.cycle(self.memory);
+            let records = self.machine.tracer_mut().unwrap().take_records();
+            if let Some(record) = records.into_iter().find(|r| r.hart == 0) {
+                return Some(record)
+            }
+        }
+        None
+    }
+
+    fn register(&self, reg:usize) -> u32 {
+        self.machine.get_i_register_of(0, reg) as u32
+    }
+
+    fn memory(&self, addr:usize) -> u8 {
+        self.memory.get_8(addr)
+    }
+}
+
+/// Thread `thread` of a SIMT-X machine, which must be the only one running
+/// in its warp.
+pub struct SimtxLane<'a, S:SimtxScheduler, BV:BitSet> {
+    machine:&'a mut SIMTX<S, BV>,
+    memory:Arc<Mutex<dyn Memory + Send>>,
+    thread:usize,
+}
+
+impl<'a, S:SimtxScheduler, BV:BitSet> SimtxLane<'a, S, BV> {
+    /// Traces `machine`, replacing the tracer it may have.
+    pub fn new(machine:&'a mut SIMTX<S, BV>, memory:Arc<Mutex<dyn Memory + Send>>, thread:usize) -> SimtxLane<'a, S, BV> {
+        machine.enable_trace(Tracer::collecting(TraceFilter::default()));
+        SimtxLane { machine, memory, thread }
+    }
+}
+
+impl<'a, S:SimtxScheduler, BV:BitSet> Dut for SimtxLane<'a, S, BV> {
+    fn name(&self) -> String {
+        format!("SIMT-X thread {}", self.thread)
+    }
+
+    fn retire(&mut self) -> Option<TraceRecord> {
+        for _ in 0..PATIENCE {
+            if self.machine.finished() {
+                return None
+            }
+            self.machine.step(self.memory.clone());
+            let records = self.machine.tracer_mut().unwrap().take_records();
+            if let Some(record) = records.into_iter().find(|r| r.hart == self.thread) {
+                return Some(record)
+            }
+        }
+        None
+    }
+
+    fn register(&self, reg:usize) -> u32 {
+        self.machine.get_i_register_of(self.thread, reg) as u32
+    }
+
+    fn memory(&self, addr:usize) -> u8 {
+        self.memory.lock().unwrap().get_8(addr)
+    }
+}
+
+/// A difference between the machine and the reference.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mismatch {
+    Pc { expected:u32, got:u32 },
+    Instruction { expected:u32, got:u32 },
+    Register { reg:usize, expected:u32, got:u32 },
+    Memory { addr:u32, expected:u8, got:u8 },
+    /// The reference could not run the instruction.
+    Trap(Trap),
+    /// The machine retired nothing more while the reference went on.
+    Stopped,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f:&mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mismatch::Pc { expected, got } =>
+                write!(f, "pc: expected 0x{:08x}, got 0x{:08x}", expected, got),
+            Mismatch::Instruction { expected, got } =>
+                write!(f, "instruction: expected 0x{:08x}, got 0x{:08x}", expected, got),
+            Mismatch::Register { reg, expected, got } =>
+                write!(f, "x{}: expected 0x{:08x}, got 0x{:08x}", reg, expected, got),
+            Mismatch::Memory { addr, expected, got } =>
+                write!(f, "byte at 0x{:08x}: expected 0x{:02x}, got 0x{:02x}", addr, expected, got),
+            Mismatch::Trap(trap) => write!(f, "the reference stopped: {}", trap),
+            Mismatch::Stopped => write!(f, "the machine stopped retiring instructions"),
+        }
+    }
+}
+
+/// Where the machine stopped agreeing with the reference.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Divergence {
+    pub dut:String,
+    /// Instructions retired by both before.
+    pub retired:u64,
+    pub mismatches:Vec<Mismatch>,
+    /// What the reference and the machine did.
+    pub expected:Option<TraceRecord>,
+    pub got:Option<TraceRecord>,
+    /// The registers of the reference and of the machine, once done.
+    pub expected_registers:[u32; 32],
+    pub got_registers:[u32; 32],
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f:&mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "=== {} DIVERGES FROM THE REFERENCE AFTER {} INSTRUCTIONS ===", self.dut, self.retired)?;
+        if let Some(record) = self.expected.as_ref().or(self.got.as_ref()) {
+            let i = Instruction(record.inst);
+            let i = if i.is_compressed() { i.uncompressed() } else { i };
+            writeln!(f, "at 0x{:08x}: {}", record.pc, i)?;
+        }
+        let line = |r:&Option<TraceRecord>| r.as_ref().map_or(String::from("-"), |r| r.to_string());
+        writeln!(f, "reference: {}", line(&self.expected))?;
+        writeln!(f, "machine:   {}", line(&self.got))?;
+        for mismatch in &self.mismatches {
+            writeln!(f, "{}", mismatch)?;
+        }
+        writeln!(f, "registers (reference, machine):")?;
+        for row in 0..8 {
+            let cells : Vec<String> = (0..4).map(|col| {
+                let reg = row * 4 + col;
+                let (e, g) = (self.expected_registers[reg], self.got_registers[reg]);
+                format!("{}x{:<2} 0x{:08x} 0x{:08x}", if e == g { ' ' } else { '*' }, reg, e, g)
+            }).collect();
+            writeln!(f, "{}", cells.join("  "))?;
+        }
+        Ok(())
+    }
+}
+
+// The bytes stored by `record`
+fn stored(record:&Option<TraceRecord>) -> Vec<u32> {
+    record.iter()
+        .flat_map(|r| r.accesses.iter())
+        .filter(|a| a.store.is_some())
+        .flat_map(|a| (0..a.size as u32).map(move |byte| a.addr.wrapping_add(byte)))
+        .collect()
+}
+
+/// Runs `dut` and `reference` (with its own copy of the memory, `memory`)
+/// until the reference returns to address 0 or has run `limit`
+/// instructions, and gives the instructions run, or the first divergence.
+pub fn lockstep<D:Dut>(dut:&mut D, reference:&mut iss::Machine, memory:&mut dyn Memory, limit:u64) -> Result<u64, Box<Divergence>> {
+    while reference.retired() < limit {
+        let retired = reference.retired();
+        let got = dut.retire();
+        let (expected, mut mismatches) = match (reference.step(memory), &got) {
+            (Err(trap), _) => (None, vec![Mismatch::Trap(trap)]),
+            (Ok(expected), None) => (Some(expected), vec![Mismatch::Stopped]),
+            (Ok(expected), Some(got)) => {
+                let mut mismatches = Vec::new();
+                if expected.pc != got.pc {
+                    mismatches.push(Mismatch::Pc { expected:expected.pc, got:got.pc });
+                }
+                if expected.inst != got.inst {
+                    mismatches.push(Mismatch::Instruction { expected:expected.inst, got:got.inst });
+                }
+                (Some(expected), mismatches)
+            },
+        };
+
+        let expected_registers = { let mut r = [0; 32]; for (i, v) in r.iter_mut().enumerate() { *v = reference.get_register(i) } r };
+        let got_registers = { let mut r = [0; 32]; for (i, v) in r.iter_mut().enumerate() { *v = dut.register(i) } r };
+        if mismatches.is_empty() {
+            for reg in 0..32 {
+                if expected_registers[reg] != got_registers[reg] {
+                    mismatches.push(Mismatch::Register { reg, expected:expected_registers[reg], got:got_registers[reg] });
+                }
+            }
+            let mut addresses = stored(&expected);
+            addresses.extend(stored(&got));
+            addresses.sort();
+            addresses.dedup();
+            for addr in addresses {
+                let (e, g) = (memory.get_8(addr as usize), dut.memory(addr as usize));
+                if e != g {
+                    mismatches.push(Mismatch::Memory { addr, expected:e, got:g });
+                }
+            }
+        }
+
+        if !mismatches.is_empty() {
+            return Err(Box::new(Divergence { dut:dut.name(), retired, mismatches, expected, got
+                                           , expected_registers, got_registers }))
+        }
+        if reference.pc() == 0 {
+            break
+        }
+    }
+    Ok(reference.retired())
+}
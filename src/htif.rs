@@ -0,0 +1,199 @@
+//! The host-target interface (HTIF) of Spike, through which the programs
+//! built for it (riscv-tests, riscv-arch-test, `pk` binaries) exit and do
+//! their I/O: they write a 64-bit command to their `tohost` symbol, and the
+//! host answers in `fromhost`.
+//!
+//! A command is `device << 56 | command << 48 | payload`:
+//!
+//! - device 0, an odd payload: exit with the code `payload >> 1`.
+//! - device 0, an even payload: the address of 8 64-bit words, a syscall
+//!   number and its arguments, as sent by `pk`. The result replaces the
+//!   number, and `fromhost` gets 1.
+//! - device 1, command 1: writes the byte of the payload to the console.
+//! - device 1, command 0: reads a byte of the console, given in `fromhost`
+//!   as `0x100 | byte` so that a NUL byte differs from no answer, as Spike.
+//!
+//! The host clears `tohost` once it took a command. RV32 programs write the
+//! low half of the command first, then the high one: a command is only taken
+//! once the program stored to the high word of `tohost`, as told by the
+//! machine through `on_store`.
+
+use elf;
+use memory::Memory;
+use std::io::{self, Read, Write};
+
+const SYS_CLOSE : u64 = 57;
+const SYS_READ : u64 = 63;
+const SYS_WRITE : u64 = 64;
+const SYS_EXIT : u64 = 93;
+const SYS_EXIT_GROUP : u64 = 94;
+
+// the most bytes copied at once between the console and the guest memory
+const CHUNK : usize = 64 * 1024;
+
+const EBADF : i64 = 9;
+const ENOSYS : i64 = 38;
+
+/// The host side of `tohost` and `fromhost`, told of the stores of a machine
+/// and polled after each of its steps.
+pub struct Htif {
+    tohost:usize,
+    fromhost:Option<usize>,
+    input:Box<dyn Read + Send>,
+    output:Box<dyn Write + Send>,
+    // whether the high word of tohost was stored to since the last poll
+    sent:bool,
+    exit:Option<u32>,
+}
+
+// The guest addresses are sign-extended, as the machines do
+fn address(value:u64) -> usize {
+    value as u32 as i32 as usize
+}
+
+fn get_64(mem:&dyn Memory, addr:usize) -> u64 {
+    mem.get_32(addr) as u64 | (mem.get_32(addr + 4) as u64) << 32
+}
+
+fn set_64(mem:&mut dyn Memory, addr:usize, value:u64) {
+    mem.set_32(addr, value as u32);
+    mem.set_32(addr + 4, (value >> 32) as u32);
+}
+
+impl Htif {
+    /// Watches `tohost`, answering in `fromhost` if there is one, with the
+    /// standard input and output as console.
+    pub fn new(tohost:usize, fromhost:Option<usize>) -> Htif {
+        Htif {
+            tohost,
+            fromhost,
+            input:Box::new(io::stdin()),
+            output:Box::new(io::stdout()),
+            sent:false,
+            exit:None,
+        }
+    }
+
+    /// Watches the `tohost` and `fromhost` symbols of `file`, or gives `None`
+    /// if it has no `tohost`.
+    pub fn from_elf(file:&elflib::File) -> Option<Htif> {
+        let tohost = elf::get_symbol_address(file, "tohost")?;
+        let fromhost = elf::get_symbol_address(file, "fromhost");
+        Some(Htif::new(tohost as usize, fromhost.map(|a| a as usize)))
+    }
+
+    /// Reads the console from `input` and writes it to `output`.
+    pub fn set_console(&mut self, input:Box<dyn Read + Send>, output:Box<dyn Write + Send>) {
+        self.input = input;
+        self.output = output;
+    }
+
+    pub fn tohost(&self) -> usize {
+        self.tohost
+    }
+
+    pub fn fromhost(&self) -> Option<usize> {
+        self.fromhost
+    }
+
+    /// The code the program exited with, once it did.
+    pub fn exit_code(&self) -> Option<u32> {
+        self.exit
+    }
+
+    /// The program stored `size` bytes at `addr`: a store to the high word
+    /// of `tohost` sends the command written there.
+    pub fn on_store(&mut self, addr:usize, size:usize) {
+        // the machines may sign-extend the addresses or not
+        let (addr, high) = (addr as u32 as u64, self.tohost.wrapping_add(4) as u32 as u64);
+        if addr < high + 4 && high < addr + size as u64 {
+            self.sent = true;
+        }
+    }
+
+    /// Takes the command sent to `tohost`, if any, and gives the exit code
+    /// once the program exited.
+    pub fn poll(&mut self, mem:&mut dyn Memory) -> Option<u32> {
+        if self.exit.is_some() || !self.sent {
+            return self.exit
+        }
+        self.sent = false;
+        let command = get_64(mem, self.tohost);
+        if command == 0 {
+            return None
+        }
+        set_64(mem, self.tohost, 0);
+
+        let (device, cmd, payload) = (command >> 56, (command >> 48) & 0xff, command & 0xffff_ffff_ffff);
+        match (device, cmd) {
+            (0, _) if payload & 1 == 1 => self.exit = Some((payload >> 1) as u32),
+            (0, _) => {
+                let args = address(payload);
+                let ret = self.syscall(mem, args);
+                set_64(mem, args, ret as u64);
+                self.respond(mem, device, cmd, 1);
+            },
+            (1, 1) => {
+                let _ = self.output.write_all(&[payload as u8]).and_then(|_| self.output.flush());
+            },
+            (1, 0) => {
+                let mut byte = [0];
+                // nothing is answered at the end of the input
+                if let Ok(1) = self.input.read(&mut byte) {
+                    self.respond(mem, device, cmd, 0x100 | byte[0] as u64);
+                }
+            },
+            _ => { },
+        }
+        self.exit
+    }
+
+    fn respond(&mut self, mem:&mut dyn Memory, device:u64, cmd:u64, payload:u64) {
+        if let Some(fromhost) = self.fromhost {
+            set_64(mem, fromhost, device << 56 | cmd << 48 | payload);
+        }
+    }
+
+    // Runs the syscall of the 8 words at `args`, and gives its result
+    fn syscall(&mut self, mem:&mut dyn Memory, args:usize) -> i64 {
+        let arg = |i:usize| get_64(mem, args + 8 * i);
+        let (number, a0, a1, a2) = (arg(0), arg(1), arg(2), arg(3));
+        match number {
+            SYS_WRITE if a0 == 1 || a0 == 2 => {
+                // by chunks, the guest giving any size, an error past the
+                // first one ending the write short
+                let (buf, size) = (address(a1), a2 as usize);
+                let mut done = 0;
+                while done < size {
+                    let bytes : Vec<u8> = (done..size.min(done + CHUNK)).map(|i| mem.get_8(buf + i)).collect();
+                    match self.output.write_all(&bytes).and_then(|_| self.output.flush()) {
+                        Ok(()) => done += bytes.len(),
+                        Err(_) if done == 0 => return -EBADF,
+                        Err(_) => break,
+                    }
+                }
+                done as i64
+            },
+            SYS_READ if a0 == 0 => {
+                // a short read rather than a buffer as large as the guest asks
+                let mut bytes = vec![0; (a2 as usize).min(CHUNK)];
+                match self.input.read(&mut bytes) {
+                    Ok(n) => {
+                        for (i, &byte) in bytes[..n].iter().enumerate() {
+                            mem.set_8(address(a1) + i, byte);
+                        }
+                        n as i64
+                    },
+                    Err(_) => -EBADF,
+                }
+            },
+            SYS_WRITE | SYS_READ => -EBADF,
+            SYS_EXIT | SYS_EXIT_GROUP => {
+                self.exit = Some(a0 as u32);
+                0
+            },
+            SYS_CLOSE => 0,
+            _ => -ENOSYS,
+        }
+    }
+}
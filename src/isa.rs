@@ -528,7 +528,7 @@ impl Instruction {
                         let r = (self.get_c_nzr() & 0b111) + 8;
                         let nzimm = self.get_c_nzimm_0_5();
                         let rs2 = self.get_cl_rd() + 8;
-                        let alucode = (nzimm >> 2) & 0b11;
+                        let alucode = (nzimm >> 3) & 0b11;
                         match code {
                             0b00 => { Instruction::srli(r, r, nzimm) },
                             0b01 => { Instruction::srai(r, r, nzimm) },
@@ -1076,249 +1076,254 @@ impl CsrId {
     pub fn level(&self) -> u8 {
         (((*self as u16) >> 8) & 0b11) as u8
     }
+
+    /// The CSR of number `value`, `None` for the ones not listed here.
+    pub fn from_number(value:u16) -> Option<CsrId> {
+        match value {
+            0x000 => Some(CsrId::USTATUS),
+            0x004 => Some(CsrId::UIE),
+            0x005 => Some(CsrId::UTVEC),
+            0x040 => Some(CsrId::USCRATCH),
+            0x041 => Some(CsrId::UEPC),
+            0x042 => Some(CsrId::UCAUSE),
+            0x043 => Some(CsrId::UTVAL),
+            0x044 => Some(CsrId::UIP),
+            0x001 => Some(CsrId::FFLAGS),
+            0x002 => Some(CsrId::FRM),
+            0x003 => Some(CsrId::FCSR),
+            0xC00 => Some(CsrId::CYCLE),
+            0xC01 => Some(CsrId::TIME),
+            0xC02 => Some(CsrId::INSTRET),
+            0xC03 => Some(CsrId::HPMCOUNTER03),
+            0xC04 => Some(CsrId::HPMCOUNTER04),
+            0xC05 => Some(CsrId::HPMCOUNTER05),
+            0xC06 => Some(CsrId::HPMCOUNTER06),
+            0xC07 => Some(CsrId::HPMCOUNTER07),
+            0xC08 => Some(CsrId::HPMCOUNTER08),
+            0xC09 => Some(CsrId::HPMCOUNTER09),
+            0xC0A => Some(CsrId::HPMCOUNTER10),
+            0xC0B => Some(CsrId::HPMCOUNTER11),
+            0xC0C => Some(CsrId::HPMCOUNTER12),
+            0xC0D => Some(CsrId::HPMCOUNTER13),
+            0xC0E => Some(CsrId::HPMCOUNTER14),
+            0xC0F => Some(CsrId::HPMCOUNTER15),
+            0xC10 => Some(CsrId::HPMCOUNTER16),
+            0xC11 => Some(CsrId::HPMCOUNTER17),
+            0xC12 => Some(CsrId::HPMCOUNTER18),
+            0xC13 => Some(CsrId::HPMCOUNTER19),
+            0xC14 => Some(CsrId::HPMCOUNTER20),
+            0xC15 => Some(CsrId::HPMCOUNTER21),
+            0xC16 => Some(CsrId::HPMCOUNTER22),
+            0xC17 => Some(CsrId::HPMCOUNTER23),
+            0xC18 => Some(CsrId::HPMCOUNTER24),
+            0xC19 => Some(CsrId::HPMCOUNTER25),
+            0xC1A => Some(CsrId::HPMCOUNTER26),
+            0xC1B => Some(CsrId::HPMCOUNTER27),
+            0xC1C => Some(CsrId::HPMCOUNTER28),
+            0xC1D => Some(CsrId::HPMCOUNTER29),
+            0xC1E => Some(CsrId::HPMCOUNTER30),
+            0xC1F => Some(CsrId::HPMCOUNTER31),
+            0xC80 => Some(CsrId::CYCLEH),
+            0xC81 => Some(CsrId::TIMEH),
+            0xC82 => Some(CsrId::INSTRETH),
+            0xC83 => Some(CsrId::HPMCOUNTER03H),
+            0xC84 => Some(CsrId::HPMCOUNTER04H),
+            0xC85 => Some(CsrId::HPMCOUNTER05H),
+            0xC86 => Some(CsrId::HPMCOUNTER06H),
+            0xC87 => Some(CsrId::HPMCOUNTER07H),
+            0xC88 => Some(CsrId::HPMCOUNTER08H),
+            0xC89 => Some(CsrId::HPMCOUNTER09H),
+            0xC8A => Some(CsrId::HPMCOUNTER10H),
+            0xC8B => Some(CsrId::HPMCOUNTER11H),
+            0xC8C => Some(CsrId::HPMCOUNTER12H),
+            0xC8D => Some(CsrId::HPMCOUNTER13H),
+            0xC8E => Some(CsrId::HPMCOUNTER14H),
+            0xC8F => Some(CsrId::HPMCOUNTER15H),
+            0xC90 => Some(CsrId::HPMCOUNTER16H),
+            0xC91 => Some(CsrId::HPMCOUNTER17H),
+            0xC92 => Some(CsrId::HPMCOUNTER18H),
+            0xC93 => Some(CsrId::HPMCOUNTER19H),
+            0xC94 => Some(CsrId::HPMCOUNTER20H),
+            0xC95 => Some(CsrId::HPMCOUNTER21H),
+            0xC96 => Some(CsrId::HPMCOUNTER22H),
+            0xC97 => Some(CsrId::HPMCOUNTER23H),
+            0xC98 => Some(CsrId::HPMCOUNTER24H),
+            0xC99 => Some(CsrId::HPMCOUNTER25H),
+            0xC9A => Some(CsrId::HPMCOUNTER26H),
+            0xC9B => Some(CsrId::HPMCOUNTER27H),
+            0xC9C => Some(CsrId::HPMCOUNTER28H),
+            0xC9D => Some(CsrId::HPMCOUNTER29H),
+            0xC9E => Some(CsrId::HPMCOUNTER30H),
+            0xC9F => Some(CsrId::HPMCOUNTER31H),
+            0x100 => Some(CsrId::SSTATUS),
+            0x102 => Some(CsrId::SEDELEG),
+            0x103 => Some(CsrId::SIDELEG),
+            0x104 => Some(CsrId::SIE),
+            0x105 => Some(CsrId::STVEC),
+            0x106 => Some(CsrId::SCOUNTEREN),
+            0x140 => Some(CsrId::SSCRATCH),
+            0x141 => Some(CsrId::SEPC),
+            0x142 => Some(CsrId::SCAUSE),
+            0x143 => Some(CsrId::STVAL),
+            0x144 => Some(CsrId::SIP),
+            0x180 => Some(CsrId::SATP),
+            0xA00 => Some(CsrId::HSTATUS),
+            0xA02 => Some(CsrId::HEDELEG),
+            0xA03 => Some(CsrId::HIDELEG),
+            0xA80 => Some(CsrId::HGATP),
+            0x200 => Some(CsrId::BSSTATUS),
+            0x204 => Some(CsrId::BSIE),
+            0x205 => Some(CsrId::BSTVEC),
+            0x240 => Some(CsrId::BSSCRATCH),
+            0x241 => Some(CsrId::BSEPC),
+            0x242 => Some(CsrId::BSCAUSE),
+            0x243 => Some(CsrId::BSTVAL),
+            0x244 => Some(CsrId::BSIP),
+            0x280 => Some(CsrId::BSATP),
+            0xF11 => Some(CsrId::MVENDORID),
+            0xF12 => Some(CsrId::MARCHID),
+            0xF13 => Some(CsrId::MIMPID),
+            0xF14 => Some(CsrId::MHARTID),
+            0x300 => Some(CsrId::MSTATUS),
+            0x301 => Some(CsrId::MISA),
+            0x302 => Some(CsrId::MEDELEG),
+            0x303 => Some(CsrId::MIDELEG),
+            0x304 => Some(CsrId::MIE),
+            0x305 => Some(CsrId::MTVEC),
+            0x306 => Some(CsrId::MCOUNTEREN),
+            0x340 => Some(CsrId::MSCRATCH),
+            0x341 => Some(CsrId::MEPC),
+            0x342 => Some(CsrId::MCAUSE),
+            0x343 => Some(CsrId::MTVAL),
+            0x344 => Some(CsrId::MIP),
+            0x3A0 => Some(CsrId::PMPCFG0),
+            0x3A1 => Some(CsrId::PMPCFG1),
+            0x3A2 => Some(CsrId::PMPCFG2),
+            0x3A3 => Some(CsrId::PMPCFG3),
+            0x3B0 => Some(CsrId::PMPADDR00),
+            0x3B1 => Some(CsrId::PMPADDR01),
+            0x3B2 => Some(CsrId::PMPADDR02),
+            0x3B3 => Some(CsrId::PMPADDR03),
+            0x3B4 => Some(CsrId::PMPADDR04),
+            0x3B5 => Some(CsrId::PMPADDR05),
+            0x3B6 => Some(CsrId::PMPADDR06),
+            0x3B7 => Some(CsrId::PMPADDR07),
+            0x3B8 => Some(CsrId::PMPADDR08),
+            0x3B9 => Some(CsrId::PMPADDR09),
+            0x3BA => Some(CsrId::PMPADDR10),
+            0x3BB => Some(CsrId::PMPADDR11),
+            0x3BC => Some(CsrId::PMPADDR12),
+            0x3BD => Some(CsrId::PMPADDR13),
+            0x3BE => Some(CsrId::PMPADDR14),
+            0x3BF => Some(CsrId::PMPADDR15),
+            0xB00 => Some(CsrId::MCYCLE),
+            0xB02 => Some(CsrId::MINSTRET),
+            0xB03 => Some(CsrId::MHMPCOUNTER03),
+            0xB04 => Some(CsrId::MHMPCOUNTER04),
+            0xB05 => Some(CsrId::MHMPCOUNTER05),
+            0xB06 => Some(CsrId::MHMPCOUNTER06),
+            0xB07 => Some(CsrId::MHMPCOUNTER07),
+            0xB08 => Some(CsrId::MHMPCOUNTER08),
+            0xB09 => Some(CsrId::MHMPCOUNTER09),
+            0xB0A => Some(CsrId::MHMPCOUNTER10),
+            0xB0B => Some(CsrId::MHMPCOUNTER11),
+            0xB0C => Some(CsrId::MHMPCOUNTER12),
+            0xB0D => Some(CsrId::MHMPCOUNTER13),
+            0xB0E => Some(CsrId::MHMPCOUNTER14),
+            0xB0F => Some(CsrId::MHMPCOUNTER15),
+            0xB10 => Some(CsrId::MHMPCOUNTER16),
+            0xB11 => Some(CsrId::MHMPCOUNTER17),
+            0xB12 => Some(CsrId::MHMPCOUNTER18),
+            0xB13 => Some(CsrId::MHMPCOUNTER19),
+            0xB14 => Some(CsrId::MHMPCOUNTER20),
+            0xB15 => Some(CsrId::MHMPCOUNTER21),
+            0xB16 => Some(CsrId::MHMPCOUNTER22),
+            0xB17 => Some(CsrId::MHMPCOUNTER23),
+            0xB18 => Some(CsrId::MHMPCOUNTER24),
+            0xB19 => Some(CsrId::MHMPCOUNTER25),
+            0xB1A => Some(CsrId::MHMPCOUNTER26),
+            0xB1B => Some(CsrId::MHMPCOUNTER27),
+            0xB1C => Some(CsrId::MHMPCOUNTER28),
+            0xB1D => Some(CsrId::MHMPCOUNTER29),
+            0xB1E => Some(CsrId::MHMPCOUNTER30),
+            0xB1F => Some(CsrId::MHMPCOUNTER31),
+            0xB80 => Some(CsrId::MCYCLEH),
+            0xB82 => Some(CsrId::MINSTRETH),
+            0xB83 => Some(CsrId::MHMPCOUNTER03H),
+            0xB84 => Some(CsrId::MHMPCOUNTER04H),
+            0xB85 => Some(CsrId::MHMPCOUNTER05H),
+            0xB86 => Some(CsrId::MHMPCOUNTER06H),
+            0xB87 => Some(CsrId::MHMPCOUNTER07H),
+            0xB88 => Some(CsrId::MHMPCOUNTER08H),
+            0xB89 => Some(CsrId::MHMPCOUNTER09H),
+            0xB8A => Some(CsrId::MHMPCOUNTER10H),
+            0xB8B => Some(CsrId::MHMPCOUNTER11H),
+            0xB8C => Some(CsrId::MHMPCOUNTER12H),
+            0xB8D => Some(CsrId::MHMPCOUNTER13H),
+            0xB8E => Some(CsrId::MHMPCOUNTER14H),
+            0xB8F => Some(CsrId::MHMPCOUNTER15H),
+            0xB90 => Some(CsrId::MHMPCOUNTER16H),
+            0xB91 => Some(CsrId::MHMPCOUNTER17H),
+            0xB92 => Some(CsrId::MHMPCOUNTER18H),
+            0xB93 => Some(CsrId::MHMPCOUNTER19H),
+            0xB94 => Some(CsrId::MHMPCOUNTER20H),
+            0xB95 => Some(CsrId::MHMPCOUNTER21H),
+            0xB96 => Some(CsrId::MHMPCOUNTER22H),
+            0xB97 => Some(CsrId::MHMPCOUNTER23H),
+            0xB98 => Some(CsrId::MHMPCOUNTER24H),
+            0xB99 => Some(CsrId::MHMPCOUNTER25H),
+            0xB9A => Some(CsrId::MHMPCOUNTER26H),
+            0xB9B => Some(CsrId::MHMPCOUNTER27H),
+            0xB9C => Some(CsrId::MHMPCOUNTER28H),
+            0xB9D => Some(CsrId::MHMPCOUNTER29H),
+            0xB9E => Some(CsrId::MHMPCOUNTER30H),
+            0xB9F => Some(CsrId::MHMPCOUNTER31H),
+            0x320 => Some(CsrId::MCOUNTINHIBIT),
+            0x323 => Some(CsrId::MHPEVENT03),
+            0x324 => Some(CsrId::MHPEVENT04),
+            0x325 => Some(CsrId::MHPEVENT05),
+            0x326 => Some(CsrId::MHPEVENT06),
+            0x327 => Some(CsrId::MHPEVENT07),
+            0x328 => Some(CsrId::MHPEVENT08),
+            0x329 => Some(CsrId::MHPEVENT09),
+            0x32a => Some(CsrId::MHPEVENT10),
+            0x32b => Some(CsrId::MHPEVENT11),
+            0x32c => Some(CsrId::MHPEVENT12),
+            0x32d => Some(CsrId::MHPEVENT13),
+            0x32e => Some(CsrId::MHPEVENT14),
+            0x32f => Some(CsrId::MHPEVENT15),
+            0x330 => Some(CsrId::MHPEVENT16),
+            0x331 => Some(CsrId::MHPEVENT17),
+            0x332 => Some(CsrId::MHPEVENT18),
+            0x333 => Some(CsrId::MHPEVENT19),
+            0x334 => Some(CsrId::MHPEVENT20),
+            0x335 => Some(CsrId::MHPEVENT21),
+            0x336 => Some(CsrId::MHPEVENT22),
+            0x337 => Some(CsrId::MHPEVENT23),
+            0x338 => Some(CsrId::MHPEVENT24),
+            0x339 => Some(CsrId::MHPEVENT25),
+            0x33a => Some(CsrId::MHPEVENT26),
+            0x33b => Some(CsrId::MHPEVENT27),
+            0x33c => Some(CsrId::MHPEVENT28),
+            0x33d => Some(CsrId::MHPEVENT29),
+            0x33e => Some(CsrId::MHPEVENT30),
+            0x33f => Some(CsrId::MHPEVENT31),
+            0x7A0 => Some(CsrId::TSELECT),
+            0x7A1 => Some(CsrId::TDATA1),
+            0x7A2 => Some(CsrId::TDATA2),
+            0x7A3 => Some(CsrId::TDATA3),
+            0x7B0 => Some(CsrId::DCSR),
+            0x7B1 => Some(CsrId::DPC),
+            0x7B2 => Some(CsrId::DSCRATCH0),
+            0x7B3 => Some(CsrId::DSCRATCH1),
+            _ => None,
+        }
+    }
 }
 
 impl From<u16> for CsrId {
     fn from(value:u16) -> CsrId {
-        match value {
-            0x000 => CsrId::USTATUS ,
-            0x004 => CsrId::UIE ,
-            0x005 => CsrId::UTVEC ,
-            0x040 => CsrId::USCRATCH ,
-            0x041 => CsrId::UEPC ,
-            0x042 => CsrId::UCAUSE ,
-            0x043 => CsrId::UTVAL ,
-            0x044 => CsrId::UIP ,
-            0x001 => CsrId::FFLAGS ,
-            0x002 => CsrId::FRM ,
-            0x003 => CsrId::FCSR ,
-            0xC00 => CsrId::CYCLE ,
-            0xC01 => CsrId::TIME ,
-            0xC02 => CsrId::INSTRET ,
-            0xC03 => CsrId::HPMCOUNTER03 ,
-            0xC04 => CsrId::HPMCOUNTER04 ,
-            0xC05 => CsrId::HPMCOUNTER05 ,
-            0xC06 => CsrId::HPMCOUNTER06 ,
-            0xC07 => CsrId::HPMCOUNTER07 ,
-            0xC08 => CsrId::HPMCOUNTER08 ,
-            0xC09 => CsrId::HPMCOUNTER09 ,
-            0xC0A => CsrId::HPMCOUNTER10 ,
-            0xC0B => CsrId::HPMCOUNTER11 ,
-            0xC0C => CsrId::HPMCOUNTER12 ,
-            0xC0D => CsrId::HPMCOUNTER13 ,
-            0xC0E => CsrId::HPMCOUNTER14 ,
-            0xC0F => CsrId::HPMCOUNTER15 ,
-            0xC10 => CsrId::HPMCOUNTER16 ,
-            0xC11 => CsrId::HPMCOUNTER17 ,
-            0xC12 => CsrId::HPMCOUNTER18 ,
-            0xC13 => CsrId::HPMCOUNTER19 ,
-            0xC14 => CsrId::HPMCOUNTER20 ,
-            0xC15 => CsrId::HPMCOUNTER21 ,
-            0xC16 => CsrId::HPMCOUNTER22 ,
-            0xC17 => CsrId::HPMCOUNTER23 ,
-            0xC18 => CsrId::HPMCOUNTER24 ,
-            0xC19 => CsrId::HPMCOUNTER25 ,
-            0xC1A => CsrId::HPMCOUNTER26 ,
-            0xC1B => CsrId::HPMCOUNTER27 ,
-            0xC1C => CsrId::HPMCOUNTER28 ,
-            0xC1D => CsrId::HPMCOUNTER29 ,
-            0xC1E => CsrId::HPMCOUNTER30 ,
-            0xC1F => CsrId::HPMCOUNTER31 ,
-            0xC80 => CsrId::CYCLEH ,
-            0xC81 => CsrId::TIMEH ,
-            0xC82 => CsrId::INSTRETH ,
-            0xC83 => CsrId::HPMCOUNTER03H ,
-            0xC84 => CsrId::HPMCOUNTER04H ,
-            0xC85 => CsrId::HPMCOUNTER05H ,
-            0xC86 => CsrId::HPMCOUNTER06H ,
-            0xC87 => CsrId::HPMCOUNTER07H ,
-            0xC88 => CsrId::HPMCOUNTER08H ,
-            0xC89 => CsrId::HPMCOUNTER09H ,
-            0xC8A => CsrId::HPMCOUNTER10H ,
-            0xC8B => CsrId::HPMCOUNTER11H ,
-            0xC8C => CsrId::HPMCOUNTER12H ,
-            0xC8D => CsrId::HPMCOUNTER13H ,
-            0xC8E => CsrId::HPMCOUNTER14H ,
-            0xC8F => CsrId::HPMCOUNTER15H ,
-            0xC90 => CsrId::HPMCOUNTER16H ,
-            0xC91 => CsrId::HPMCOUNTER17H ,
-            0xC92 => CsrId::HPMCOUNTER18H ,
-            0xC93 => CsrId::HPMCOUNTER19H ,
-            0xC94 => CsrId::HPMCOUNTER20H ,
-            0xC95 => CsrId::HPMCOUNTER21H ,
-            0xC96 => CsrId::HPMCOUNTER22H ,
-            0xC97 => CsrId::HPMCOUNTER23H ,
-            0xC98 => CsrId::HPMCOUNTER24H ,
-            0xC99 => CsrId::HPMCOUNTER25H ,
-            0xC9A => CsrId::HPMCOUNTER26H ,
-            0xC9B => CsrId::HPMCOUNTER27H ,
-            0xC9C => CsrId::HPMCOUNTER28H ,
-            0xC9D => CsrId::HPMCOUNTER29H ,
-            0xC9E => CsrId::HPMCOUNTER30H ,
-            0xC9F => CsrId::HPMCOUNTER31H ,
-            0x100 => CsrId::SSTATUS ,
-            0x102 => CsrId::SEDELEG ,
-            0x103 => CsrId::SIDELEG ,
-            0x104 => CsrId::SIE ,
-            0x105 => CsrId::STVEC ,
-            0x106 => CsrId::SCOUNTEREN ,
-            0x140 => CsrId::SSCRATCH ,
-            0x141 => CsrId::SEPC ,
-            0x142 => CsrId::SCAUSE ,
-            0x143 => CsrId::STVAL ,
-            0x144 => CsrId::SIP ,
-            0x180 => CsrId::SATP ,
-            0xA00 => CsrId::HSTATUS ,
-            0xA02 => CsrId::HEDELEG ,
-            0xA03 => CsrId::HIDELEG ,
-            0xA80 => CsrId::HGATP ,
-            0x200 => CsrId::BSSTATUS ,
-            0x204 => CsrId::BSIE ,
-            0x205 => CsrId::BSTVEC ,
-            0x240 => CsrId::BSSCRATCH ,
-            0x241 => CsrId::BSEPC ,
-            0x242 => CsrId::BSCAUSE ,
-            0x243 => CsrId::BSTVAL ,
-            0x244 => CsrId::BSIP ,
-            0x280 => CsrId::BSATP ,
-            0xF11 => CsrId::MVENDORID ,
-            0xF12 => CsrId::MARCHID ,
-            0xF13 => CsrId::MIMPID ,
-            0xF14 => CsrId::MHARTID ,
-            0x300 => CsrId::MSTATUS ,
-            0x301 => CsrId::MISA ,
-            0x302 => CsrId::MEDELEG ,
-            0x303 => CsrId::MIDELEG ,
-            0x304 => CsrId::MIE ,
-            0x305 => CsrId::MTVEC ,
-            0x306 => CsrId::MCOUNTEREN ,
-            0x340 => CsrId::MSCRATCH ,
-            0x341 => CsrId::MEPC ,
-            0x342 => CsrId::MCAUSE ,
-            0x343 => CsrId::MTVAL ,
-            0x344 => CsrId::MIP ,
-            0x3A0 => CsrId::PMPCFG0 ,
-            0x3A1 => CsrId::PMPCFG1 ,
-            0x3A2 => CsrId::PMPCFG2 ,
-            0x3A3 => CsrId::PMPCFG3 ,
-            0x3B0 => CsrId::PMPADDR00 ,
-            0x3B1 => CsrId::PMPADDR01 ,
-            0x3B2 => CsrId::PMPADDR02 ,
-            0x3B3 => CsrId::PMPADDR03 ,
-            0x3B4 => CsrId::PMPADDR04 ,
-            0x3B5 => CsrId::PMPADDR05 ,
-            0x3B6 => CsrId::PMPADDR06 ,
-            0x3B7 => CsrId::PMPADDR07 ,
-            0x3B8 => CsrId::PMPADDR08 ,
-            0x3B9 => CsrId::PMPADDR09 ,
-            0x3BA => CsrId::PMPADDR10 ,
-            0x3BB => CsrId::PMPADDR11 ,
-            0x3BC => CsrId::PMPADDR12 ,
-            0x3BD => CsrId::PMPADDR13 ,
-            0x3BE => CsrId::PMPADDR14 ,
-            0x3BF => CsrId::PMPADDR15 ,
-            0xB00 => CsrId::MCYCLE ,
-            0xB02 => CsrId::MINSTRET ,
-            0xB03 => CsrId::MHMPCOUNTER03 ,
-            0xB04 => CsrId::MHMPCOUNTER04 ,
-            0xB05 => CsrId::MHMPCOUNTER05 ,
-            0xB06 => CsrId::MHMPCOUNTER06 ,
-            0xB07 => CsrId::MHMPCOUNTER07 ,
-            0xB08 => CsrId::MHMPCOUNTER08 ,
-            0xB09 => CsrId::MHMPCOUNTER09 ,
-            0xB0A => CsrId::MHMPCOUNTER10 ,
-            0xB0B => CsrId::MHMPCOUNTER11 ,
-            0xB0C => CsrId::MHMPCOUNTER12 ,
-            0xB0D => CsrId::MHMPCOUNTER13 ,
-            0xB0E => CsrId::MHMPCOUNTER14 ,
-            0xB0F => CsrId::MHMPCOUNTER15 ,
-            0xB10 => CsrId::MHMPCOUNTER16 ,
-            0xB11 => CsrId::MHMPCOUNTER17 ,
-            0xB12 => CsrId::MHMPCOUNTER18 ,
-            0xB13 => CsrId::MHMPCOUNTER19 ,
-            0xB14 => CsrId::MHMPCOUNTER20 ,
-            0xB15 => CsrId::MHMPCOUNTER21 ,
-            0xB16 => CsrId::MHMPCOUNTER22 ,
-            0xB17 => CsrId::MHMPCOUNTER23 ,
-            0xB18 => CsrId::MHMPCOUNTER24 ,
-            0xB19 => CsrId::MHMPCOUNTER25 ,
-            0xB1A => CsrId::MHMPCOUNTER26 ,
-            0xB1B => CsrId::MHMPCOUNTER27 ,
-            0xB1C => CsrId::MHMPCOUNTER28 ,
-            0xB1D => CsrId::MHMPCOUNTER29 ,
-            0xB1E => CsrId::MHMPCOUNTER30 ,
-            0xB1F => CsrId::MHMPCOUNTER31 ,
-            0xB80 => CsrId::MCYCLEH ,
-            0xB82 => CsrId::MINSTRETH ,
-            0xB83 => CsrId::MHMPCOUNTER03H ,
-            0xB84 => CsrId::MHMPCOUNTER04H ,
-            0xB85 => CsrId::MHMPCOUNTER05H ,
-            0xB86 => CsrId::MHMPCOUNTER06H ,
-            0xB87 => CsrId::MHMPCOUNTER07H ,
-            0xB88 => CsrId::MHMPCOUNTER08H ,
-            0xB89 => CsrId::MHMPCOUNTER09H ,
-            0xB8A => CsrId::MHMPCOUNTER10H ,
-            0xB8B => CsrId::MHMPCOUNTER11H ,
-            0xB8C => CsrId::MHMPCOUNTER12H ,
-            0xB8D => CsrId::MHMPCOUNTER13H ,
-            0xB8E => CsrId::MHMPCOUNTER14H ,
-            0xB8F => CsrId::MHMPCOUNTER15H ,
-            0xB90 => CsrId::MHMPCOUNTER16H ,
-            0xB91 => CsrId::MHMPCOUNTER17H ,
-            0xB92 => CsrId::MHMPCOUNTER18H ,
-            0xB93 => CsrId::MHMPCOUNTER19H ,
-            0xB94 => CsrId::MHMPCOUNTER20H ,
-            0xB95 => CsrId::MHMPCOUNTER21H ,
-            0xB96 => CsrId::MHMPCOUNTER22H ,
-            0xB97 => CsrId::MHMPCOUNTER23H ,
-            0xB98 => CsrId::MHMPCOUNTER24H ,
-            0xB99 => CsrId::MHMPCOUNTER25H ,
-            0xB9A => CsrId::MHMPCOUNTER26H ,
-            0xB9B => CsrId::MHMPCOUNTER27H ,
-            0xB9C => CsrId::MHMPCOUNTER28H ,
-            0xB9D => CsrId::MHMPCOUNTER29H ,
-            0xB9E => CsrId::MHMPCOUNTER30H ,
-            0xB9F => CsrId::MHMPCOUNTER31H ,
-            0x320 => CsrId::MCOUNTINHIBIT ,
-            0x323 => CsrId::MHPEVENT03 ,
-            0x324 => CsrId::MHPEVENT04 ,
-            0x325 => CsrId::MHPEVENT05 ,
-            0x326 => CsrId::MHPEVENT06 ,
-            0x327 => CsrId::MHPEVENT07 ,
-            0x328 => CsrId::MHPEVENT08 ,
-            0x329 => CsrId::MHPEVENT09 ,
-            0x32a => CsrId::MHPEVENT10 ,
-            0x32b => CsrId::MHPEVENT11 ,
-            0x32c => CsrId::MHPEVENT12 ,
-            0x32d => CsrId::MHPEVENT13 ,
-            0x32e => CsrId::MHPEVENT14 ,
-            0x32f => CsrId::MHPEVENT15 ,
-            0x330 => CsrId::MHPEVENT16 ,
-            0x331 => CsrId::MHPEVENT17 ,
-            0x332 => CsrId::MHPEVENT18 ,
-            0x333 => CsrId::MHPEVENT19 ,
-            0x334 => CsrId::MHPEVENT20 ,
-            0x335 => CsrId::MHPEVENT21 ,
-            0x336 => CsrId::MHPEVENT22 ,
-            0x337 => CsrId::MHPEVENT23 ,
-            0x338 => CsrId::MHPEVENT24 ,
-            0x339 => CsrId::MHPEVENT25 ,
-            0x33a => CsrId::MHPEVENT26 ,
-            0x33b => CsrId::MHPEVENT27 ,
-            0x33c => CsrId::MHPEVENT28 ,
-            0x33d => CsrId::MHPEVENT29 ,
-            0x33e => CsrId::MHPEVENT30 ,
-            0x33f => CsrId::MHPEVENT31 ,
-            0x7A0 => CsrId::TSELECT ,
-            0x7A1 => CsrId::TDATA1 ,
-            0x7A2 => CsrId::TDATA2 ,
-            0x7A3 => CsrId::TDATA3 ,
-            0x7B0 => CsrId::DCSR ,
-            0x7B1 => CsrId::DPC ,
-            0x7B2 => CsrId::DSCRATCH0 ,
-            0x7B3 => CsrId::DSCRATCH1 ,
-            _ => panic!("Bad CsrId value"),
-        }
+        CsrId::from_number(value).expect("Bad CsrId value")
     }
 }
 
@@ -1484,7 +1489,7 @@ impl CsrField {
         match self {
             CsrField::TSR => T::slice_mask(23, 22),
             CsrField::TW => T::slice_mask(22, 21),
-            CsrField::TVM => T::slice_mask(20, 21),
+            CsrField::TVM => T::slice_mask(21, 20),
             CsrField::MPRV => T::slice_mask(18, 17),
             CsrField::MPP => T::slice_mask(13, 11),
             CsrField::MPIE => T::slice_mask(8, 7),
@@ -1496,7 +1501,7 @@ impl CsrField {
             CsrField::UEIE | CsrField::UEIP => T::slice_mask(9, 8),
             CsrField::SEIE | CsrField::SEIP => T::slice_mask(10, 9),
             CsrField::MTVecMODE | CsrField::STVecMODE => T::slice_mask(2, 0),
-            CsrField::MTVecBASE | CsrField::STVecBASE => T::slice_mask(31, 2),
+            CsrField::MTVecBASE | CsrField::STVecBASE => T::slice_mask(xlen, 2),
             CsrField::MXR => T::slice_mask(20, 19),
             CsrField::SUM  => T::slice_mask(19, 18),
             CsrField::SPP  => T::slice_mask(9, 8),
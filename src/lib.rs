@@ -19,9 +19,36 @@ pub mod machine;
 /// Memory interface abstraction used to implement any memory interface you want.
 pub mod memory;
 
+/// Cache hierarchy simulation, as a wrapper around any memory.
+pub mod cache;
+
 /// Types used for flexibility in simulator's traits datatypes.
 pub mod types;
 
 /// Helper functions for elf file reading.
 pub mod elf;
 
+
+/// Address to `function+offset` and `file:line` translation, built from the
+/// ELF symbols and DWARF line information.
+pub mod symbolizer;
+
+/// Control-flow graph of the code of a program: basic blocks and
+/// post-dominators.
+pub mod cfg;
+
+/// Per-instruction execution traces, in the format of Spike's
+/// `--log-commits` or a compact binary one.
+pub mod trace;
+
+/// The `tohost`/`fromhost` interface of Spike, through which test programs
+/// exit and use the console.
+pub mod htif;
+
+/// Lockstep co-simulation of the machines against the reference simulator of
+/// `machine::iss`, reporting the first divergence.
+pub mod cosim;
+
+/// Runs the riscv-tests and riscv-arch-test ELFs on the machines, telling
+/// which pass.
+pub mod conformance;
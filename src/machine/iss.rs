@@ -0,0 +1,259 @@
+//! A functional RV32IM instruction set simulator, written to be obviously
+//! correct rather than fast or complete: an instruction per `step`, decoded
+//! from its bits without the helpers of `isa`, memory read and written a
+//! byte at a time in little-endian order. It is the reference the other
+//! machines are checked against (see `cosim`).
+//!
+//! There are no CSRs, no privilege levels and no compressed instructions:
+//! anything else than RV32IM stops the simulator with a `Trap`.
+
+use memory::Memory;
+use trace::{TraceRecord, RegWrite, MemAccess};
+use std::fmt;
+
+/// Why the simulator could not run an instruction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Trap {
+    /// Not an RV32IM instruction (compressed ones included).
+    IllegalInstruction { pc:u32, inst:u32 },
+    /// A jump or branch to a PC which is not 4-byte aligned.
+    MisalignedFetch { pc:u32, target:u32 },
+    Ecall { pc:u32 },
+    Ebreak { pc:u32 },
+}
+
+impl fmt::Display for Trap {
+    fn fmt(&self, f:&mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Trap::IllegalInstruction { pc, inst } =>
+                write!(f, "illegal instruction 0x{:08x} at 0x{:08x}", inst, pc),
+            Trap::MisalignedFetch { pc, target } =>
+                write!(f, "jump to misaligned 0x{:08x} at 0x{:08x}", target, pc),
+            Trap::Ecall { pc } => write!(f, "ecall at 0x{:08x}", pc),
+            Trap::Ebreak { pc } => write!(f, "ebreak at 0x{:08x}", pc),
+        }
+    }
+}
+
+// The fields of an instruction
+fn rd(inst:u32) -> usize { ((inst >> 7) & 0x1f) as usize }
+fn rs1(inst:u32) -> usize { ((inst >> 15) & 0x1f) as usize }
+fn rs2(inst:u32) -> usize { ((inst >> 20) & 0x1f) as usize }
+fn funct3(inst:u32) -> u32 { (inst >> 12) & 0x7 }
+fn funct7(inst:u32) -> u32 { inst >> 25 }
+
+// The immediates, sign-extended
+fn imm_i(inst:u32) -> u32 { ((inst as i32) >> 20) as u32 }
+fn imm_s(inst:u32) -> u32 { (((inst as i32) >> 25) << 5) as u32 | ((inst >> 7) & 0x1f) }
+fn imm_u(inst:u32) -> u32 { inst & 0xfffff000 }
+
+fn imm_b(inst:u32) -> u32 {
+    (((inst as i32) >> 31) << 12) as u32
+        | ((inst >> 7) & 0x1) << 11
+        | ((inst >> 25) & 0x3f) << 5
+        | ((inst >> 8) & 0xf) << 1
+}
+
+fn imm_j(inst:u32) -> u32 {
+    (((inst as i32) >> 31) << 20) as u32
+        | ((inst >> 12) & 0xff) << 12
+        | ((inst >> 20) & 0x1) << 11
+        | ((inst >> 21) & 0x3ff) << 1
+}
+
+/// The state of the simulated hart.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Machine {
+    registers:[u32; 32],
+    pc:u32,
+    retired:u64,
+}
+
+impl Machine {
+    /// A hart about to run the instruction at `pc`, with all its registers
+    /// cleared.
+    pub fn new(pc:u32) -> Machine {
+        Machine { registers:[0; 32], pc, retired:0 }
+    }
+
+    pub fn pc(&self) -> u32 {
+        self.pc
+    }
+
+    pub fn set_pc(&mut self, pc:u32) {
+        self.pc = pc
+    }
+
+    pub fn get_register(&self, reg:usize) -> u32 {
+        self.registers[reg]
+    }
+
+    /// Writes to `x0` are ignored.
+    pub fn set_register(&mut self, reg:usize, value:u32) {
+        if reg != 0 {
+            self.registers[reg] = value
+        }
+    }
+
+    /// The instructions run so far.
+    pub fn retired(&self) -> u64 {
+        self.retired
+    }
+
+    fn load(mem:&dyn Memory, addr:u32, size:u32) -> u32 {
+        (0..size).fold(0, |value, byte| value | (mem.get_8(addr.wrapping_add(byte) as usize) as u32) << (8 * byte))
+    }
+
+    fn store(mem:&mut dyn Memory, addr:u32, size:u32, value:u32) {
+        for byte in 0..size {
+            mem.set_8(addr.wrapping_add(byte) as usize, (value >> (8 * byte)) as u8);
+        }
+    }
+
+    /// Runs the instruction at the PC, and tells what it did: the registers
+    /// written (but `x0`) and the memory accessed. Nothing changes when it
+    /// traps.
+    pub fn step(&mut self, mem:&mut dyn Memory) -> Result<TraceRecord, Trap> {
+        let pc = self.pc;
+        let inst = Self::load(mem, pc, 2);
+        let inst = if inst & 0b11 == 0b11 { Self::load(mem, pc, 4) } else { inst };
+        let illegal = Trap::IllegalInstruction { pc, inst };
+        let mut record = TraceRecord::new(0, pc as i32, inst);
+        if inst & 0b11 != 0b11 {
+            return Err(illegal)
+        }
+
+        let x1 = self.registers[rs1(inst)];
+        let x2 = self.registers[rs2(inst)];
+        let mut next = pc.wrapping_add(4);
+        let mut result = None;
+
+        match inst & 0x7f {
+            0b0110111 => result = Some(imm_u(inst)), // LUI
+            0b0010111 => result = Some(pc.wrapping_add(imm_u(inst))), // AUIPC
+            0b1101111 => { // JAL
+                result = Some(next);
+                next = pc.wrapping_add(imm_j(inst));
+            },
+            0b1100111 if funct3(inst) == 0 => { // JALR
+                result = Some(next);
+                next = x1.wrapping_add(imm_i(inst)) & !1;
+            },
+            0b1100011 => { // BRANCH
+                let taken = match funct3(inst) {
+                    0b000 => x1 == x2,
+                    0b001 => x1 != x2,
+                    0b100 => (x1 as i32) < (x2 as i32),
+                    0b101 => (x1 as i32) >= (x2 as i32),
+                    0b110 => x1 < x2,
+                    0b111 => x1 >= x2,
+                    _ => return Err(illegal),
+                };
+                if taken {
+                    next = pc.wrapping_add(imm_b(inst));
+                }
+            },
+            0b0000011 => { // LOAD
+                let addr = x1.wrapping_add(imm_i(inst));
+                let (size, signed) = match funct3(inst) {
+                    0b000 => (1, true),
+                    0b001 => (2, true),
+                    0b010 => (4, false),
+                    0b100 => (1, false),
+                    0b101 => (2, false),
+                    _ => return Err(illegal),
+                };
+                let value = Self::load(mem, addr, size);
+                let shift = 32 - 8 * size;
+                result = Some(if signed { (((value << shift) as i32) >> shift) as u32 } else { value });
+                record.accesses.push(MemAccess::load(addr as usize, size as usize));
+            },
+            0b0100011 => { // STORE
+                let addr = x1.wrapping_add(imm_s(inst));
+                let size = match funct3(inst) {
+                    0b000 => 1,
+                    0b001 => 2,
+                    0b010 => 4,
+                    _ => return Err(illegal),
+                };
+                let value = if size == 4 { x2 } else { x2 & ((1 << (8 * size)) - 1) };
+                Self::store(mem, addr, size, value);
+                record.accesses.push(MemAccess::store(addr as usize, size as usize, value as u64));
+            },
+            0b0010011 => { // OP-IMM
+                let imm = imm_i(inst);
+                let shamt = imm & 0x1f;
+                result = Some(match (funct3(inst), funct7(inst)) {
+                    (0b000, _) => x1.wrapping_add(imm),
+                    (0b010, _) => ((x1 as i32) < (imm as i32)) as u32,
+                    (0b011, _) => (x1 < imm) as u32,
+                    (0b100, _) => x1 ^ imm,
+                    (0b110, _) => x1 | imm,
+                    (0b111, _) => x1 & imm,
+                    (0b001, 0b0000000) => x1 << shamt,
+                    (0b101, 0b0000000) => x1 >> shamt,
+                    (0b101, 0b0100000) => ((x1 as i32) >> shamt) as u32,
+                    _ => return Err(illegal),
+                });
+            },
+            0b0110011 => { // OP
+                let shamt = x2 & 0x1f;
+                let (s1, s2) = (x1 as i32 as i64, x2 as i32 as i64);
+                let (u1, u2) = (x1 as u64, x2 as u64);
+                result = Some(match (funct7(inst), funct3(inst)) {
+                    (0b0000000, 0b000) => x1.wrapping_add(x2),
+                    (0b0100000, 0b000) => x1.wrapping_sub(x2),
+                    (0b0000000, 0b001) => x1 << shamt,
+                    (0b0000000, 0b010) => ((x1 as i32) < (x2 as i32)) as u32,
+                    (0b0000000, 0b011) => (x1 < x2) as u32,
+                    (0b0000000, 0b100) => x1 ^ x2,
+                    (0b0000000, 0b101) => x1 >> shamt,
+                    (0b0100000, 0b101) => ((x1 as i32) >> shamt) as u32,
+                    (0b0000000, 0b110) => x1 | x2,
+                    (0b0000000, 0b111) => x1 & x2,
+                    (0b0000001, 0b000) => x1.wrapping_mul(x2), // MUL
+                    (0b0000001, 0b001) => ((s1 * s2) >> 32) as u32, // MULH
+                    (0b0000001, 0b010) => ((s1 * u2 as i64) >> 32) as u32, // MULHSU
+                    (0b0000001, 0b011) => ((u1 * u2) >> 32) as u32, // MULHU
+                    (0b0000001, 0b100) => match x2 { // DIV
+                        0 => !0,
+                        _ => (x1 as i32).wrapping_div(x2 as i32) as u32,
+                    },
+                    (0b0000001, 0b101) => match x2 { // DIVU
+                        0 => !0,
+                        _ => x1 / x2,
+                    },
+                    (0b0000001, 0b110) => match x2 { // REM
+                        0 => x1,
+                        _ => (x1 as i32).wrapping_rem(x2 as i32) as u32,
+                    },
+                    (0b0000001, 0b111) => match x2 { // REMU
+                        0 => x1,
+                        _ => x1 % x2,
+                    },
+                    _ => return Err(illegal),
+                });
+            },
+            0b0001111 => { }, // FENCE
+            0b1110011 => return Err(match inst { // SYSTEM
+                0x00000073 => Trap::Ecall { pc },
+                0x00100073 => Trap::Ebreak { pc },
+                _ => illegal,
+            }),
+            _ => return Err(illegal),
+        }
+
+        if next & 0b11 != 0 {
+            return Err(Trap::MisalignedFetch { pc, target:next })
+        }
+        if let Some(value) = result {
+            if rd(inst) != 0 {
+                self.registers[rd(inst)] = value;
+                record.writes.push(RegWrite::x(rd(inst), value as i32));
+            }
+        }
+        self.pc = next;
+        self.retired += 1;
+        Ok(record)
+    }
+}
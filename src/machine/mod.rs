@@ -8,6 +8,10 @@ pub mod rv32pthread;
 /// An implementation of the SIMT-X machine (SIMT on CPU)
 pub mod simtx;
 
+/// A functional RV32IM simulator, simple enough to be the reference the
+/// other machines are checked against
+pub mod iss;
+
 use memory::Memory;
 use types::MachineInteger;
 use isa::{CsrId, CsrField};
@@ -297,10 +301,17 @@ pub trait IntegerMachine {
             },
             CsrId::MTVEC => {
                 self.set_csr_field(CsrField::MTVecMODE, value.bit_slice(2, 0));
-                self.set_csr_field(CsrField::MTVecBASE, value.bit_slice(31, 2));
+                self.set_csr_field(CsrField::MTVecBASE, value.bit_slice(xlen, 2));
                 Some(())
             },
+            CsrId::MSCRATCH => { self.set_csr_field(CsrField::MSCRATCH, value); Some(()) },
             CsrId::MEPC => { self.set_csr_field(CsrField::MEPC, value); Some(()) },
+            CsrId::MCAUSE => {
+                self.set_csr_field(CsrField::MCauseCode, value.bit_slice(xlen-1, 0));
+                self.set_csr_field(CsrField::MCauseInterrupt, value.bit_slice(xlen, xlen-1));
+                Some(())
+            },
+            CsrId::MTVAL => { self.set_csr_field(CsrField::MTVAL, value); Some(()) },
             CsrId::SSTATUS => {
                 self.set_csr_field(CsrField::MXR, value.bit_slice(20, 19));
                 self.set_csr_field(CsrField::SUM, value.bit_slice(19, 18));
@@ -311,9 +322,10 @@ pub trait IntegerMachine {
             },
             CsrId::STVEC => {
                 self.set_csr_field(CsrField::STVecMODE, value.bit_slice(2, 0));
-                self.set_csr_field(CsrField::STVecBASE, value.bit_slice(31, 2));
+                self.set_csr_field(CsrField::STVecBASE, value.bit_slice(xlen, 2));
                 Some(())
             },
+            CsrId::SSCRATCH => { self.set_csr_field(CsrField::SSCRATCH, value); Some(()) },
             CsrId::SEPC => { self.set_csr_field(CsrField::SEPC, value); Some(()) },
             CsrId::SCAUSE => {
                 self.set_csr_field(CsrField::SCauseCode, value.bit_slice(xlen-1, 0));
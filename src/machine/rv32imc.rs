@@ -1,6 +1,9 @@
 use machine::IntegerMachine;
 use isa::{Instruction, OpCode, CsrId, CsrField};
 use memory::Memory;
+use trace::{Tracer, TraceRecord, RegWrite, MemAccess};
+use htif::Htif;
+use std::io;
 
 /// Represent the data which we need to send to the `write back` step
 #[derive(Debug)]
@@ -29,6 +32,17 @@ impl From<u8> for WordSize {
     }
 }
 
+impl WordSize {
+    pub fn bytes(&self) -> usize {
+        match self {
+            WordSize::B => 1,
+            WordSize::H => 2,
+            WordSize::W => 4,
+            WordSize::D => 8,
+        }
+    }
+}
+
 pub enum MemAction {
     Load,
     Store,
@@ -38,6 +52,8 @@ pub enum MemAction {
 /// It also contains information to forward to the next step (`write back`)
 pub struct MemData {
     pub pc: i32,
+    /// as fetched, `None` for the bubbles of the pipeline
+    pub instruction: Option<Instruction>,
 
     /// data forwarding from ex stage
     pub wb_perform: bool,
@@ -50,17 +66,32 @@ pub struct MemData {
     pub perform: Option<MemAction>,
     pub addr: usize,
     pub size: WordSize,
+    /// loads of bytes and halves sign-extend them (`lb`, `lh`)
+    pub signed: bool,
+}
+
+impl MemData {
+    /// The address and size of the store to perform, if any.
+    pub fn stored(&self) -> Option<(usize, usize)> {
+        match self.perform {
+            Some(MemAction::Store) => Some((self.addr, self.size.bytes())),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Copy, Clone)]
 pub struct PipelineState {
     pub pc: i32,
     pub instruction: Instruction,
+    /// `false` for a bubble
+    pub valid: bool,
 }
 
 impl PipelineState {
+    /// A bubble
     pub fn empty() -> PipelineState {
-        PipelineState { pc: 0, instruction: Instruction::nop() }
+        PipelineState { pc: 0, instruction: Instruction::nop(), valid: false }
     }
 }
 
@@ -74,6 +105,9 @@ pub struct Machine {
     pub mem2wb: WriteBackData,
 
     csr_file: [i32; 4096],
+
+    tracer: Option<Tracer>,
+    htif: Option<Htif>,
 }
 
 impl IntegerMachine for Machine {
@@ -85,6 +119,15 @@ impl IntegerMachine for Machine {
     fn cycle(&mut self, mem : &mut dyn Memory) {
         self.do_write_back();
         self.do_mem(mem);
+        if let Some(record) = self.tracer.as_ref().and_then(|_| self.committed(0)) {
+            self.tracer.as_mut().unwrap().record(&record);
+        }
+        if let Some(htif) = self.htif.as_mut() {
+            if let Some((addr, size)) = self.ex2mem.stored() {
+                htif.on_store(addr, size);
+            }
+            htif.poll(mem);
+        }
         self.do_execute();
         self.do_decode();
         self.do_fetch(mem);
@@ -115,7 +158,7 @@ impl IntegerMachine for Machine {
     fn get_pc(&self) -> i32 { self.pc }
     fn set_pc(&mut self, value:i32) { self.pc = value }
 
-    fn finished(&self) -> bool { self.pc == 0 }
+    fn finished(&self) -> bool { self.pc == 0 || self.exit_code().is_some() }
 }
 
 impl Machine {
@@ -127,15 +170,71 @@ impl Machine {
             pc: 0, 
             if2dc: PipelineState::empty(),
             dc2ex: PipelineState::empty(),
-            ex2mem: MemData { pc: 0, wb_rd: 0, wb_perform: false, perform: None, 
-                addr: 0, size: WordSize::B, value: 0 },
+            ex2mem: MemData { pc: 0, instruction: None, wb_rd: 0, wb_perform: false, perform: None, 
+                addr: 0, size: WordSize::B, signed: false, value: 0 },
             mem2wb: WriteBackData { perform: false, rd: 0, value: 0 },
+            tracer: None,
+            htif: None,
         };
 
         ret.set_csr(CsrId::MISA, 0x40002000);
         ret
     }
 
+    /// Writes the instructions committed from now on to `tracer`.
+    pub fn enable_trace(&mut self, tracer:Tracer) {
+        self.tracer = Some(tracer);
+    }
+
+    pub fn tracer(&self) -> Option<&Tracer> {
+        self.tracer.as_ref()
+    }
+
+    pub fn tracer_mut(&mut self) -> Option<&mut Tracer> {
+        self.tracer.as_mut()
+    }
+
+    /// Stops tracing, and flushes the trace.
+    pub fn finish_trace(&mut self) -> io::Result<()> {
+        self.tracer.take().map_or(Ok(()), |tracer| tracer.finish())
+    }
+
+    /// Runs the host side of `htif` after each cycle, the machine finishing
+    /// when the program exits through it.
+    pub fn enable_htif(&mut self, htif:Htif) {
+        self.htif = Some(htif);
+    }
+
+    pub fn htif(&self) -> Option<&Htif> {
+        self.htif.as_ref()
+    }
+
+    /// The code the program exited with through HTIF, once it did.
+    pub fn exit_code(&self) -> Option<u32> {
+        self.htif.as_ref().and_then(|htif| htif.exit_code())
+    }
+
+    /// The record of the instruction which just went through the `mem`
+    /// step, as committed by hart `hart`, unless it was a bubble.
+    pub fn committed(&self, hart:usize) -> Option<TraceRecord> {
+        let i = self.ex2mem.instruction?;
+        let mut record = TraceRecord::new(hart, self.ex2mem.pc, i.0);
+        if self.mem2wb.perform && self.mem2wb.rd != 0 {
+            record.writes.push(RegWrite::x(self.mem2wb.rd, self.mem2wb.value));
+        }
+        let size = self.ex2mem.size.bytes();
+        match self.ex2mem.perform {
+            Some(MemAction::Load) => record.accesses.push(MemAccess::load(self.ex2mem.addr, size)),
+            Some(MemAction::Store) => {
+                let mask = if size == 8 { !0 } else { (1u64 << (size * 8)) - 1 };
+                let value = self.ex2mem.value as u32 as u64 & mask;
+                record.accesses.push(MemAccess::store(self.ex2mem.addr, size, value))
+            },
+            None => { },
+        }
+        Some(record)
+    }
+
     pub fn get_register(&self, i:usize) -> i32 {
         if i <= 0 || i > 31 {
             0
@@ -167,8 +266,11 @@ impl Machine {
         match &self.ex2mem.perform {
             Some(MemAction::Load) => {
                 perform_wb = true;
+                let signed = self.ex2mem.signed;
                 value = match self.ex2mem.size {
+                    WordSize::B if signed => mem.get_8(self.ex2mem.addr) as i8 as i32,
                     WordSize::B => mem.get_8(self.ex2mem.addr) as i32,
+                    WordSize::H if signed => mem.get_16(self.ex2mem.addr) as i16 as i32,
                     WordSize::H => mem.get_16(self.ex2mem.addr) as i32,
                     WordSize::W => mem.get_32(self.ex2mem.addr) as i32,
                     _ => 0,
@@ -202,9 +304,10 @@ impl Machine {
 
     pub fn do_execute(&mut self) {
         let curr_pc = self.dc2ex.pc;
-        let mut to_mem = MemData { pc: curr_pc, wb_perform: false, wb_rd: 0
-            , value: 0, perform: None, addr: 0, size: WordSize::B };
         let i = self.dc2ex.instruction;
+        let fetched = if self.dc2ex.valid { Some(i) } else { None };
+        let mut to_mem = MemData { pc: curr_pc, instruction: fetched, wb_perform: false, wb_rd: 0
+            , value: 0, perform: None, addr: 0, size: WordSize::B, signed: false };
         let mut illegal = false;
 
         let (i, advance) = if i.is_compressed() { (i.uncompressed(), 2) } else { (i, 4) };
@@ -218,30 +321,25 @@ impl Machine {
             OpCode::AUIPC => {
                 to_mem.wb_perform = true;
                 to_mem.wb_rd = i.get_rd() as usize;
-                to_mem.value = curr_pc + i.get_imm_u();
-                self.if2dc.instruction = Instruction::nop();
-                self.dc2ex.instruction = Instruction::nop();
+                to_mem.value = curr_pc.wrapping_add(i.get_imm_u());
             },
             OpCode::JAL => {
-                to_mem.value = curr_pc.wrapping_add(4);
+                to_mem.value = curr_pc.wrapping_add(advance);
                 to_mem.wb_perform = true;
                 to_mem.wb_rd = i.get_rd() as usize;
                 self.pc = curr_pc.wrapping_add(i.get_imm_j());
-                self.if2dc.instruction = Instruction::nop();
-                self.dc2ex.instruction = Instruction::nop();
+                self.flush();
             },
             OpCode::JALR => {
-                to_mem.value = curr_pc.wrapping_add(4);
+                to_mem.value = curr_pc.wrapping_add(advance);
                 to_mem.wb_perform = true;
                 to_mem.wb_rd = i.get_rd() as usize;
                 self.pc = self.get_register(i.get_rs1() as usize)
-                    .wrapping_add(i.get_imm_i());
-                self.if2dc.instruction = Instruction::nop();
-                self.dc2ex.instruction = Instruction::nop();
+                    .wrapping_add(i.get_imm_i()) & !1;
+                self.flush();
             },
             OpCode::BRANCH => {
-                let  tpc = curr_pc.wrapping_add(i.get_imm_b());
-                let ntpc = curr_pc.wrapping_add(advance);
+                let tpc = curr_pc.wrapping_add(i.get_imm_b());
                 
                 let r1 = i.get_rs1() as usize;
                 let v1 = self.get_register(r1);
@@ -251,27 +349,30 @@ impl Machine {
                 let v2 = self.get_register(r2);
                 let uv2 = v2 as u32;
 
-                self.pc = match i.get_funct3() {
-                    0b000 => if  v1 ==  v2 { tpc } else { ntpc }, // BEQ
-                    0b001 => if  v1 !=  v2 { tpc } else { ntpc }, // BNE
-                    0b100 => if  v1 <   v2 { tpc } else { ntpc }, // BLT
-                    0b101 => if  v1 >=  v2 { tpc } else { ntpc }, // BGE
-                    0b110 => if uv1 <  uv2 { tpc } else { ntpc }, // BLTU
-                    0b111 => if uv1 >= uv2 { tpc } else { ntpc }, // BGEU
-                    _ => curr_pc,
+                let taken = match i.get_funct3() {
+                    0b000 =>  v1 ==  v2, // BEQ
+                    0b001 =>  v1 !=  v2, // BNE
+                    0b100 =>  v1 <   v2, // BLT
+                    0b101 =>  v1 >=  v2, // BGE
+                    0b110 => uv1 <  uv2, // BLTU
+                    0b111 => uv1 >= uv2, // BGEU
+                    _ => false,
                 };
 
-                if self.pc == tpc {
-                    self.if2dc.instruction = Instruction::nop();
-                    self.dc2ex.instruction = Instruction::nop();
+                // the instructions after a branch not taken are already
+                // fetched
+                if taken {
+                    self.pc = tpc;
+                    self.flush();
                 }
             },
             OpCode::LOAD => {
-                let width = WordSize::from(i.get_funct3());
+                let width = WordSize::from(i.get_funct3() & 0b11);
                 let base = self.get_register(i.get_rs1() as usize) as usize;
                 to_mem.perform = Some(MemAction::Load);
                 to_mem.addr = (i.get_imm_i() as usize).wrapping_add(base);
                 to_mem.size = width;
+                to_mem.signed = i.get_funct3() & 0b100 == 0;
                 to_mem.wb_rd = i.get_rd() as usize;
             },
             OpCode::STORE => {
@@ -299,9 +400,9 @@ impl Machine {
                     0b100 => v1 ^ v2,
                     0b110 => v1 | v2,
                     0b111 => v1 & v2,
-                    0b001 => v1 << v2,
-                    0b101 => if i.get_funct7() != 0 { v1 >> v2 }
-                             else { ((v1 as u32) >> v2) as i32 },
+                    0b001 => v1 << (v2 & 0x1f),
+                    0b101 => if i.get_funct7() != 0 { v1 >> (v2 & 0x1f) }
+                             else { ((v1 as u32) >> (v2 & 0x1f)) as i32 },
                     _ => unreachable!(),
                 };
             },
@@ -319,39 +420,46 @@ impl Machine {
                 let uv2_64 = uv2 as u64;
 
                 let allset = 0xFFFFFFFFu32 as i32;
+                let shamt = uv2 & 0x1f;
 
                 to_mem.value = match i.get_funct7() {
                     0b0000000 => match i.get_funct3() {
                         0b000 => v1.wrapping_add(v2),
-                        0b001 => v1 >> v2,
+                        0b001 => v1 << shamt,
                         0b010 => (v1 < v2) as i32,
                         0b011 => ((v1 as u32) < v2 as u32) as i32,
                         0b100 => v1 ^ v2,
-                        0b101 => ((v1 as u32) >> v2) as i32,
+                        0b101 => (uv1 >> shamt) as i32,
                         0b110 => v1 | v2,
                         0b111 => v1 & v2,
                         _ => unreachable!(),
                     },
                     0b0000001 => match i.get_funct3() { // M Extension
-                        0b000 => v1 * v2,
+                        0b000 => v1.wrapping_mul(v2),
                         0b001 => ((v1_64 * v2_64) >> 32) as i32,
                         0b010 => ((v1_64 * (uv2_64 as i64)) >> 32) as i32,
                         0b011 => ((uv1_64 * uv2_64) >> 32) as i32,
-                        0b100 => if v2 == 0 { allset } else { v1 / v2 }, // DIV
+                        0b100 => if v2 == 0 { allset } else { v1.wrapping_div(v2) }, // DIV
                         0b101 => if v2 == 0 { allset } else { (uv1 / uv2) as i32 }, // DIVU
-                        0b110 => if v2 == 0 { v1 } else { v1 % v2 }, // REM
+                        0b110 => if v2 == 0 { v1 } else { v1.wrapping_rem(v2) }, // REM
                         0b111 => if v2 == 0 { v1 } else { (uv1 % uv2) as i32 }, // REMU
                         _ => unreachable!(),
                     },
                     0b0100000 => match i.get_funct3() {
                         0b000 => v1.wrapping_sub(v2),
-                        0b101 => v1 >> v2,
+                        0b101 => v1 >> shamt,
                         _ => unreachable!(),
                     },
                     _ => unimplemented!(), // TODO add other extensions (F has priority)
                 };
 
             },
+            OpCode::FENCE if i.get_funct3() == 0b001 => {
+                // FENCE.I: what follows may have been fetched before the
+                // stores it orders
+                self.pc = curr_pc.wrapping_add(advance);
+                self.flush();
+            },
             OpCode::SYSTEM => {
                 match i.get_funct3() {
                     0b000 => {
@@ -360,19 +468,18 @@ impl Machine {
                                 match i.get_rs2() {
                                     0b00000 => { /* ECALL */
                                         self.raise_exception(false, self.get_privilege() as i32 + 8, 0, curr_pc);
-                                        self.dc2ex.instruction = Instruction::nop();
-                                        self.if2dc.instruction = Instruction::nop();
+                                        self.flush();
                                     },
                                     0b00001 => { /* EBREAK */
                                         self.raise_exception(false, 3, 0, curr_pc);
-                                        self.dc2ex.instruction = Instruction::nop();
-                                        self.if2dc.instruction = Instruction::nop();
+                                        self.flush();
                                     },
                                     0b00010 => { /* URET */ 
                                         let mpie = self.get_csr_field(CsrField::UPIE);
                                         self.set_csr_field(CsrField::UIE, mpie);
                                         self.set_csr_field(CsrField::UPIE, 1);
                                         self.set_pc(self.get_csr(CsrId::UEPC).unwrap());
+                                        self.flush();
                                     },
                                     _ => { }
                                 }
@@ -392,6 +499,7 @@ impl Machine {
                                             self.set_csr_field(CsrField::SPP, 0);
                                             self.set_privilege(mpp as u8);
                                             self.set_pc(self.get_csr(CsrId::SEPC).unwrap());
+                                            self.flush();
                                         }
                                     },
                                     0b00101 => { /* TODO WFI */ },
@@ -411,6 +519,7 @@ impl Machine {
                                             self.set_csr_field(CsrField::MPP, 0);
                                             self.set_privilege(mpp as u8);
                                             self.set_pc(self.get_csr(CsrId::MEPC).unwrap());
+                                            self.flush();
                                         }
                                     },
                                     _ => { }
@@ -419,91 +528,9 @@ impl Machine {
                             _ => {}
                         }
                     },
-                    0b001 => { /* CSRRW */
-                        let csr = CsrId::from(i.get_imm_i() as u16);
-                        let rs1 = i.get_rs1() as usize;
-                        let rd = i.get_rd() as usize;
-
-                        illegal = 
-                            self.get_csr(csr).and_then(| v | {
-                                to_mem.wb_rd = rd;
-                                to_mem.wb_perform = true;
-                                to_mem.value = v;
-                                
-                                self.set_csr(csr, self.get_i_register(rs1))
-                            }).is_none();
+                    _ => { /* CSRRW, CSRRS, CSRRC, and their immediate forms */
+                        illegal = self.access_csr(i, &mut to_mem).is_none();
                     },
-                    0b010 => { /* CSRRS */
-                        let csr = CsrId::from(i.get_imm_i() as u16);
-                        let rs1 = i.get_rs1() as usize;
-                        let rd = i.get_rd() as usize;
-
-                        illegal =
-                            self.get_csr(csr).and_then(| v | {
-                                to_mem.wb_rd = rd;
-                                to_mem.wb_perform = true;
-                                to_mem.value = v;
-
-                                self.set_csr(csr, v | self.get_i_register(rs1))
-                            }).is_none();
-                    },
-                    0b011 => { /* CSRRC */ 
-                        let csr = CsrId::from(i.get_imm_i() as u16);
-                        let rs1 = i.get_rs1() as usize;
-                        let rd = i.get_rd() as usize;
-
-                        illegal =
-                            self.get_csr(csr).and_then(| v | {
-                                to_mem.wb_rd = rd;
-                                to_mem.wb_perform = true;
-                                to_mem.value = v;
-
-                                self.set_csr(csr, v & !self.get_i_register(rs1))
-                            }).is_none();
-                    },
-                    0b101 => { /* CSRRWI */
-                        let csr = CsrId::from(i.get_imm_i() as u16);
-                        let rs1 = i.get_rs1() as usize;
-                        let rd = i.get_rd() as usize;
-
-                        illegal = 
-                            self.get_csr(csr).and_then(| v | {
-                                to_mem.wb_rd = rd;
-                                to_mem.wb_perform = true;
-                                to_mem.value = v;
-                                
-                                self.set_csr(csr, rs1 as i32)
-                            }).is_none();
-                    },
-                    0b110 => { /* CSRRSI */
-                        let csr = CsrId::from(i.get_imm_i() as u16);
-                        let rs1 = i.get_rs1() as usize;
-                        let rd = i.get_rd() as usize;
-
-                        illegal = 
-                            self.get_csr(csr).and_then(| v | {
-                                to_mem.wb_rd = rd;
-                                to_mem.wb_perform = true;
-                                to_mem.value = v;
-                                
-                                self.set_csr(csr, v | (rs1 as i32))
-                            }).is_none();
-                    },
-                    0b111 => { /* CSRRCI */
-                        let csr = CsrId::from(i.get_imm_i() as u16);
-                        let rs1 = i.get_rs1() as usize;
-                        let rd = i.get_rd() as usize;
-
-                        illegal = 
-                            self.get_csr(csr).and_then(| v | {
-                                to_mem.wb_rd = rd;
-                                to_mem.wb_perform = true;
-                                to_mem.value = v;
-                                
-                                self.set_csr(csr, v | !(rs1 as i32))
-                            }).is_none();
-                    },
-                    _ => {}
                 }
             },
             _ => {}
@@ -511,13 +538,42 @@ impl Machine {
 
         if illegal {
             self.raise_exception(false, 2, 0, curr_pc);
-            self.dc2ex.instruction = Instruction::nop();
-            self.if2dc.instruction = Instruction::nop();
+            self.flush();
         }
 
         self.ex2mem = to_mem
     }
 
+    // Runs the CSR instruction `i`, `None` when it is illegal: an unknown
+    // CSR, a read-only one written, or a reserved `funct3`. CSRRS and CSRRC
+    // of `x0`, and their immediate forms of 0, only read.
+    fn access_csr(&mut self, i:Instruction, to_mem:&mut MemData) -> Option<()> {
+        let csr = CsrId::from_number((i.get_imm_i() & 0xfff) as u16)?;
+        let old = self.get_csr(csr)?;
+        let rs1 = i.get_rs1() as usize;
+        let operand = if i.get_funct3() & 0b100 == 0 { self.get_register(rs1) } else { rs1 as i32 };
+        let new = match i.get_funct3() & 0b11 {
+            0b00 => return None,
+            0b01 => Some(operand),
+            _ if rs1 == 0 => None,
+            0b10 => Some(old | operand),
+            _ => Some(old & !operand),
+        };
+        if let Some(value) = new {
+            self.set_csr(csr, value)?;
+        }
+        to_mem.wb_perform = true;
+        to_mem.wb_rd = i.get_rd() as usize;
+        to_mem.value = old;
+        Some(())
+    }
+
+    /// Replaces the instructions being fetched and decoded by bubbles.
+    pub fn flush(&mut self) {
+        self.if2dc = PipelineState::empty();
+        self.dc2ex = PipelineState::empty();
+    }
+
     pub fn do_decode(&mut self) {
         self.dc2ex = self.if2dc
     }
@@ -525,7 +581,7 @@ impl Machine {
     pub fn do_fetch(&mut self, mem:&mut dyn Memory) {
         if self.pc % 2 != 0 { self.raise_exception(false, 0, 0, self.pc); }
 
-        let first = mem.get_16(self.pc as usize) as u32;
+        let first = mem.fetch_16(self.pc as usize) as u32;
 
         let ic = Instruction(first);
 
@@ -533,12 +589,12 @@ impl Machine {
             if ic.is_compressed() {
                 (2, ic)
             } else {
-                let second = mem.get_16((self.pc + 2) as usize) as u32;
+                let second = mem.fetch_16((self.pc + 2) as usize) as u32;
                 (4, Instruction((second << 16) | first))
             };
 
         //println!("fetched {}", i);
-        self.if2dc = PipelineState { pc: self.pc, instruction: i };
+        self.if2dc = PipelineState { pc: self.pc, instruction: i, valid: true };
         self.pc += advance
     }
 }
@@ -1,8 +1,13 @@
 use machine::{MultiCoreIMachine, IntegerMachine};
 use isa::{Instruction, OpCode, CsrField};
 use memory::Memory;
-use machine::rv32imc::{self, Machine as RV32I};
+use cache::CacheConfig;
+use cache::coherence::{Coherence, Protocol};
+use machine::rv32imc::{self, Machine as RV32I, PipelineState};
+use trace::Tracer;
+use htif::Htif;
 use std::collections::HashMap;
+use std::io;
 use std::sync::{Arc, Mutex};
 
 pub struct Machine {
@@ -14,6 +19,9 @@ pub struct Machine {
     cycles : i32,
     plt_addresses : HashMap<i32, String>,
     reservations : [ i32 ; 4 ],
+    coherence : Option<Coherence>,
+    tracer : Option<Tracer>,
+    htif : Option<Htif>,
 }
 
 impl Machine {
@@ -27,6 +35,9 @@ impl Machine {
             cycles : 0,
             plt_addresses : plt,
             reservations : [ 0 ; 4 ],
+            coherence : None,
+            tracer : None,
+            htif : None,
         };
 
         let mut i = 0;
@@ -59,6 +70,73 @@ impl Machine {
         machine
     }
 
+    /// Gives each core a private data cache of `config`, kept coherent with
+    /// `protocol`. The loads and stores of the program go through the cache
+    /// of their core.
+    pub fn enable_coherence(&mut self, config:CacheConfig, protocol:Protocol) {
+        self.coherence = Some(Coherence::new(self.cores.len(), config, protocol));
+    }
+
+    pub fn coherence(&self) -> Option<&Coherence> {
+        self.coherence.as_ref()
+    }
+
+    /// Prints the hits and misses of each cache, the coherence traffic, and
+    /// the lines which moved between the caches, false sharing first.
+    pub fn print_coherence_report(&self) {
+        let coherence = match self.coherence.as_ref() {
+            Some(coherence) => coherence,
+            None => return,
+        };
+        println!("=== {:?} caches ===", coherence.protocol());
+        for core in 0..coherence.cores() {
+            println!("core {}: {}", core, coherence.cache_stats(core));
+        }
+        println!("total: {}", coherence.total());
+
+        let mut lines : Vec<_> = coherence.per_line().iter()
+            .filter(|(_, s)| s.invalidations > 0)
+            .collect();
+        lines.sort_by_key(|(addr, s)| (std::cmp::Reverse(s.false_sharing), std::cmp::Reverse(s.invalidations), **addr));
+        for (addr, stats) in lines {
+            println!("line 0x{:x}: {}", addr, stats);
+        }
+    }
+
+    /// Writes the instructions committed by the cores from now on to
+    /// `tracer`, the hart of a record being its core.
+    pub fn enable_trace(&mut self, tracer:Tracer) {
+        self.tracer = Some(tracer);
+    }
+
+    pub fn tracer(&self) -> Option<&Tracer> {
+        self.tracer.as_ref()
+    }
+
+    pub fn tracer_mut(&mut self) -> Option<&mut Tracer> {
+        self.tracer.as_mut()
+    }
+
+    /// Stops tracing, and flushes the trace.
+    pub fn finish_trace(&mut self) -> io::Result<()> {
+        self.tracer.take().map_or(Ok(()), |tracer| tracer.finish())
+    }
+
+    /// Runs the host side of `htif` after each cycle (or step), the machine
+    /// finishing when the program exits through it.
+    pub fn enable_htif(&mut self, htif:Htif) {
+        self.htif = Some(htif);
+    }
+
+    pub fn htif(&self) -> Option<&Htif> {
+        self.htif.as_ref()
+    }
+
+    /// The code the program exited with through HTIF, once it did.
+    pub fn exit_code(&self) -> Option<u32> {
+        self.htif.as_ref().and_then(|htif| htif.exit_code())
+    }
+
     fn schedule_next_core(&mut self) {
         let mut i = (self.current_core + 1) % self.active_threads;
         let mut num = 0;
@@ -75,13 +153,27 @@ impl Machine {
     }
 
     fn do_mem(&mut self, core:usize, mem:&mut dyn Memory) {
-        self.cores[core].do_mem(mem)
+        match self.coherence.as_mut() {
+            Some(coherence) => self.cores[core].do_mem(&mut coherence.view(core, mem)),
+            None => self.cores[core].do_mem(mem),
+        }
+        if let Some(record) = self.tracer.as_ref().and_then(|_| self.cores[core].committed(core)) {
+            self.tracer.as_mut().unwrap().record(&record);
+        }
+    }
+
+    // Tells the HTIF of the store the memory stage of `core` just did
+    fn notify_store(&mut self, core:usize) {
+        if let (Some(htif), Some((addr, size))) = (self.htif.as_mut(), self.cores[core].ex2mem.stored()) {
+            htif.on_store(addr, size);
+        }
     }
 
     fn do_execute(&mut self, core:usize, mem:&mut dyn Memory) {
         let curr = core;
         let curr_pc = self.cores[curr].dc2ex.pc;
         let i = self.cores[curr].dc2ex.instruction;
+        let fetched = if self.cores[curr].dc2ex.valid { Some(i) } else { None };
 
         let (advance, i) = if i.is_compressed() {
             (2, i.uncompressed())
@@ -91,7 +183,6 @@ impl Machine {
 
         let address = curr_pc.wrapping_add(i.get_imm_j());
 
-        println!("Core {}   0x{:x}: {}", core, curr_pc, i);
         if i.get_opcode() == OpCode::JAL.into() {
             //println!("jump addr = {:x}", address);
             if let Some(func_name) = self.plt_addresses.get(&address) {
@@ -120,8 +211,7 @@ impl Machine {
                     let to_wait = self.get_i_register_of(curr, 10);
                     self.joining[curr] = to_wait;
                     self.cores[curr].set_pc(curr_pc + advance);
-                    self.cores[curr].if2dc.instruction = Instruction::nop();
-                    self.cores[curr].dc2ex.instruction = Instruction::nop();
+                    self.cores[curr].flush();
                     //println!("[SIM] thread {} waiting for {} to join", curr, to_wait)
                 } else if func_name.contains("puts") {
                     let mut str_addr = self.get_i_register_of(curr, 10) as usize;
@@ -146,9 +236,10 @@ impl Machine {
                 self.cores[curr].ex2mem = rv32imc::MemData {
                     value: curr_pc.wrapping_add(advance),
                     pc: curr_pc,
+                    instruction: fetched,
                     wb_perform: true,
                     wb_rd: i.get_rd() as usize,
-                    perform: None, addr: 0, size: rv32imc::WordSize::B,
+                    perform: None, addr: 0, size: rv32imc::WordSize::B, signed: false,
                 }
             } else {
                 self.cores[curr].do_execute()
@@ -183,7 +274,7 @@ impl Machine {
             if self.cores[self.joining[core] as usize].finished() {
                 self.joining[core] = -1;
             } else {
-                self.cores[core].if2dc.instruction = Instruction::nop();
+                self.cores[core].if2dc = PipelineState::empty();
                 return
             }
         }
@@ -217,6 +308,10 @@ impl IntegerMachine for Machine {
         let curr = self.current_core;
         self.do_write_back(curr);
         self.do_mem(curr, mem);
+        self.notify_store(curr);
+        if let Some(htif) = self.htif.as_mut() {
+            htif.poll(mem);
+        }
         self.do_execute(curr, mem);
         self.do_decode(curr);
         self.do_fetch(curr, mem);
@@ -242,6 +337,7 @@ impl IntegerMachine for Machine {
     fn set_pc(&mut self, value:i32) { self.cores[self.current_core].set_pc(value) }
 
     fn finished(&self) -> bool {
+        if self.exit_code().is_some() { return true }
         for core in &self.cores {
             if !core.finished() { return false }
         }
@@ -256,22 +352,27 @@ impl MultiCoreIMachine for Machine {
     fn step(&mut self, memory:Arc<Mutex<dyn Memory + std::marker::Send>>) {
         let len = self.active_threads;
         crossbeam::thread::scope(|s| {
-            let selfref = Arc::new(Mutex::new(self));
+            let selfref = Arc::new(Mutex::new(&mut *self));
             for i in 0..len {
                 let mem = memory.clone();
                 let selfref = selfref.clone();
                 s.spawn(move |_| {
                     selfref.lock().unwrap().do_write_back(i);
                     selfref.lock().unwrap().do_mem(i, mem.lock().unwrap().deref_mut());
+                    selfref.lock().unwrap().notify_store(i);
                     selfref.lock().unwrap().do_execute(i, mem.lock().unwrap().deref_mut());
                     selfref.lock().unwrap().do_decode(i);
                     selfref.lock().unwrap().do_fetch(i, mem.lock().unwrap().deref_mut());
                 });
             }
         }).expect("Execution step failed");
+        if let Some(htif) = self.htif.as_mut() {
+            htif.poll(memory.lock().unwrap().deref_mut());
+        }
     }
 
     fn finished(&self) -> bool {
+        if self.exit_code().is_some() { return true }
         for core in &self.cores {
             if !core.finished() { return false }
         }
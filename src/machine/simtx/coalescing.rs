@@ -0,0 +1,161 @@
+//! Analysis of the memory accesses of the warps: how the addresses of the
+//! lanes of a load or a store relate, and how many memory transactions
+//! (cache lines) they need.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// How the addresses of the lanes of a memory instruction relate, in lane
+/// order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AccessPattern {
+    /// Every lane accesses the same address.
+    Uniform,
+    /// Consecutive lanes access consecutive elements.
+    UnitStride,
+    /// Consecutive lanes are a constant number of bytes apart.
+    Strided(i64),
+    /// Anything else.
+    Scattered,
+}
+
+impl fmt::Display for AccessPattern {
+    fn fmt(&self, f:&mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AccessPattern::Uniform => write!(f, "uniform"),
+            AccessPattern::UnitStride => write!(f, "unit-stride"),
+            AccessPattern::Strided(stride) => write!(f, "strided ({} bytes)", stride),
+            AccessPattern::Scattered => write!(f, "scattered"),
+        }
+    }
+}
+
+/// Classifies the `addresses` of the lanes (in lane order) of an access of
+/// `size` bytes.
+pub fn classify(addresses:&[usize], size:usize) -> AccessPattern {
+    if addresses.windows(2).all(|w| w[0] == w[1]) {
+        return AccessPattern::Uniform
+    }
+    let stride = addresses[1] as i64 - addresses[0] as i64;
+    if addresses.windows(2).any(|w| w[1] as i64 - w[0] as i64 != stride) {
+        AccessPattern::Scattered
+    } else if stride == size as i64 {
+        AccessPattern::UnitStride
+    } else {
+        AccessPattern::Strided(stride)
+    }
+}
+
+/// The number of `line_size` lines touched by accesses of `size` bytes at
+/// `addresses`.
+pub fn transactions(addresses:&[usize], size:usize, line_size:usize) -> usize {
+    let mut lines : Vec<usize> = addresses.iter()
+        .flat_map(|a| a / line_size..=(a + size.max(1) - 1) / line_size)
+        .collect();
+    lines.sort();
+    lines.dedup();
+    lines.len()
+}
+
+// The fewest lines which can hold the distinct bytes accessed
+fn ideal_transactions(addresses:&[usize], size:usize, line_size:usize) -> usize {
+    let mut bytes : Vec<usize> = addresses.iter()
+        .flat_map(|a| *a..*a + size.max(1))
+        .collect();
+    bytes.sort();
+    bytes.dedup();
+    (bytes.len() + line_size - 1) / line_size
+}
+
+/// The accesses of a memory instruction, added over all its executions.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PcCoalescing {
+    pub write:bool,
+    /// Warp executions, and lane accesses.
+    pub executions:u64,
+    pub accesses:u64,
+    pub transactions:u64,
+    /// Transactions the accesses would need if perfectly coalesced.
+    pub ideal_transactions:u64,
+    pub uniform:u64,
+    pub unit_stride:u64,
+    pub strided:u64,
+    pub scattered:u64,
+}
+
+impl PcCoalescing {
+    /// Ideal over actual transactions, 1 when perfectly coalesced.
+    pub fn efficiency(&self) -> f64 {
+        if self.transactions == 0 { 1. } else { self.ideal_transactions as f64 / self.transactions as f64 }
+    }
+
+    pub fn transactions_per_execution(&self) -> f64 {
+        if self.executions == 0 { 0. } else { self.transactions as f64 / self.executions as f64 }
+    }
+}
+
+impl fmt::Display for PcCoalescing {
+    fn fmt(&self, f:&mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} times, {} transactions ({:.2} per execution), efficiency {:.1}% (uniform {}, unit-stride {}, strided {}, scattered {})"
+               , if self.write { "store" } else { "load" }
+               , self.executions, self.transactions, self.transactions_per_execution()
+               , self.efficiency() * 100., self.uniform, self.unit_stride, self.strided, self.scattered)
+    }
+}
+
+/// Classifies every memory instruction executed by the warps.
+#[derive(Clone, Debug)]
+pub struct CoalescingAnalyzer {
+    line_size:usize,
+    per_pc:BTreeMap<i32, PcCoalescing>,
+}
+
+impl CoalescingAnalyzer {
+    /// Counts transactions of `line_size` bytes.
+    pub fn new(line_size:usize) -> CoalescingAnalyzer {
+        CoalescingAnalyzer { line_size:line_size.max(1), per_pc:BTreeMap::new() }
+    }
+
+    pub fn line_size(&self) -> usize {
+        self.line_size
+    }
+
+    /// Records the execution by a warp of the memory instruction at `pc`,
+    /// which accesses `size` bytes at `addresses` (one per active lane, in
+    /// lane order).
+    pub fn record(&mut self, pc:i32, write:bool, size:usize, addresses:&[usize]) -> AccessPattern {
+        let pattern = classify(addresses, size);
+        let stats = self.per_pc.entry(pc).or_insert(PcCoalescing { write, ..PcCoalescing::default() });
+        stats.executions += 1;
+        stats.accesses += addresses.len() as u64;
+        stats.transactions += transactions(addresses, size, self.line_size) as u64;
+        stats.ideal_transactions += ideal_transactions(addresses, size, self.line_size) as u64;
+        match pattern {
+            AccessPattern::Uniform => stats.uniform += 1,
+            AccessPattern::UnitStride => stats.unit_stride += 1,
+            AccessPattern::Strided(_) => stats.strided += 1,
+            AccessPattern::Scattered => stats.scattered += 1,
+        }
+        pattern
+    }
+
+    /// The statistics of every memory instruction, by PC.
+    pub fn per_pc(&self) -> &BTreeMap<i32, PcCoalescing> {
+        &self.per_pc
+    }
+
+    /// The statistics of all the memory instructions together.
+    pub fn total(&self) -> PcCoalescing {
+        self.per_pc.values().fold(PcCoalescing::default(), |mut t, s| {
+            t.executions += s.executions;
+            t.accesses += s.accesses;
+            t.transactions += s.transactions;
+            t.ideal_transactions += s.ideal_transactions;
+            t.uniform += s.uniform;
+            t.unit_stride += s.unit_stride;
+            t.strided += s.strided;
+            t.scattered += s.scattered;
+            t
+        })
+    }
+}
@@ -0,0 +1,27 @@
+use std::fmt;
+
+/// An error caused by the simulated program. When such an error happens, the
+/// machine stops and reports it through `Machine::error()`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GuestError {
+    /// The program called an imported function which has no host call
+    /// handler.
+    UnknownHostCall { name:String, pc:i32 },
+    /// `free` or `realloc` of a pointer which was already freed.
+    DoubleFree { ptr:usize, pc:i32 },
+    /// `free` or `realloc` of a pointer which was never allocated.
+    InvalidFree { ptr:usize, pc:i32 },
+}
+
+impl fmt::Display for GuestError {
+    fn fmt(&self, f:&mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GuestError::UnknownHostCall { name, pc } =>
+                write!(f, "call to unknown imported function `{}` at 0x{:x}", name, pc),
+            GuestError::DoubleFree { ptr, pc } =>
+                write!(f, "double free of 0x{:x} at 0x{:x}", ptr, pc),
+            GuestError::InvalidFree { ptr, pc } =>
+                write!(f, "free of 0x{:x}, which was not allocated, at 0x{:x}", ptr, pc),
+        }
+    }
+}
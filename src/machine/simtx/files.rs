@@ -0,0 +1,364 @@
+//! The file descriptor table shared by all the warps of a SIMT-X machine.
+//!
+//! Descriptors follow POSIX: 0, 1 and 2 are the standard streams of the
+//! simulator and files get the lowest free descriptor. A `FILE*` of the
+//! simulated program is its descriptor plus one (see `stream`), so that a
+//! valid stream is never `NULL`.
+//!
+//! Every path is resolved inside the sandbox directory, as if the program ran
+//! chrooted in it: `/data/in.txt` and `../../data/in.txt` both name
+//! `<sandbox>/data/in.txt`. Symbolic links are followed only as long as
+//! they stay in the sandbox.
+
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write, Seek, SeekFrom};
+use std::path::{Path, PathBuf, Component};
+
+pub const STDIN:i32 = 0;
+pub const STDOUT:i32 = 1;
+pub const STDERR:i32 = 2;
+
+/// The `errno` values reported by the host calls (Linux numbering).
+pub mod errno {
+    pub const ENOENT:i32 = 2;
+    pub const EIO:i32 = 5;
+    pub const EBADF:i32 = 9;
+    pub const ENOMEM:i32 = 12;
+    pub const EACCES:i32 = 13;
+    pub const EEXIST:i32 = 17;
+    pub const EISDIR:i32 = 21;
+    pub const EINVAL:i32 = 22;
+    pub const ESPIPE:i32 = 29;
+}
+
+// open(2) flags, with the values of the generic Linux ABI used by RISC-V
+const O_ACCMODE:i32 = 0o3;
+const O_WRONLY:i32 = 0o1;
+const O_RDWR:i32 = 0o2;
+const O_CREAT:i32 = 0o100;
+const O_EXCL:i32 = 0o200;
+const O_TRUNC:i32 = 0o1000;
+const O_APPEND:i32 = 0o2000;
+
+const SEEK_SET:i32 = 0;
+const SEEK_CUR:i32 = 1;
+const SEEK_END:i32 = 2;
+
+/// The most bytes a host call reads at once: `read` returns less, and
+/// `fread` reads the rest in several steps.
+pub const CHUNK:usize = 64 * 1024;
+
+/// Returns the `FILE*` value of the descriptor `fd`.
+pub fn stream(fd:i32) -> u32 {
+    fd as u32 + 1
+}
+
+/// Returns the descriptor of the `FILE*` value `stream`.
+pub fn fd_of(stream:u32) -> i32 {
+    stream as i32 - 1
+}
+
+enum Handle {
+    Stdin,
+    Stdout,
+    Stderr,
+    File(File),
+}
+
+struct OpenFile {
+    handle:Handle,
+    readable:bool,
+    writable:bool,
+    eof:bool,
+    // bytes given back by `ungetc`, the last one is read first
+    pushback:Vec<u8>,
+}
+
+impl OpenFile {
+    fn new(handle:Handle, readable:bool, writable:bool) -> OpenFile {
+        OpenFile { handle, readable, writable, eof:false, pushback:Vec::new() }
+    }
+}
+
+fn errno_of(e:io::Error) -> i32 {
+    match e.kind() {
+        io::ErrorKind::NotFound => errno::ENOENT,
+        io::ErrorKind::PermissionDenied => errno::EACCES,
+        io::ErrorKind::AlreadyExists => errno::EEXIST,
+        io::ErrorKind::InvalidInput => errno::EINVAL,
+        _ => e.raw_os_error().unwrap_or(errno::EIO),
+    }
+}
+
+/// Every error is reported as the `errno` value the guest should see.
+pub type FileResult<T> = Result<T, i32>;
+
+pub struct FileTable {
+    sandbox:PathBuf,
+    files:BTreeMap<i32, OpenFile>,
+}
+
+impl FileTable {
+    /// Creates a table with the three standard streams opened, confined to
+    /// the current directory.
+    pub fn new() -> FileTable {
+        let mut files = BTreeMap::new();
+        files.insert(STDIN, OpenFile::new(Handle::Stdin, true, false));
+        files.insert(STDOUT, OpenFile::new(Handle::Stdout, false, true));
+        files.insert(STDERR, OpenFile::new(Handle::Stderr, false, true));
+        FileTable { sandbox:PathBuf::from("."), files }
+    }
+
+    pub fn set_sandbox<P:AsRef<Path>>(&mut self, dir:P) {
+        self.sandbox = dir.as_ref().to_path_buf()
+    }
+
+    pub fn sandbox(&self) -> &Path {
+        &self.sandbox
+    }
+
+    /// Returns the host path of the guest path `path`. The path never leaves
+    /// the sandbox: `..` stops at its root.
+    pub fn resolve(&self, path:&str) -> PathBuf {
+        let mut ret = self.sandbox.clone();
+        let mut depth = 0;
+        for c in Path::new(path).components() {
+            match c {
+                Component::Normal(name) => {
+                    ret.push(name);
+                    depth += 1;
+                },
+                Component::ParentDir if depth > 0 => {
+                    ret.pop();
+                    depth -= 1;
+                },
+                _ => {},
+            }
+        }
+        ret
+    }
+
+    // The real path of `path`, once its symbolic links are followed, or of
+    // its closest existing parent for a file to create. It must still be in
+    // the sandbox. A dangling link could point anywhere, so it is refused.
+    fn confine(&self, path:&Path) -> FileResult<PathBuf> {
+        let root = self.sandbox.canonicalize().map_err(errno_of)?;
+        let mut existing = path.to_path_buf();
+        let mut missing = Vec::new();
+        let mut real = loop {
+            match existing.canonicalize() {
+                Ok(real) => break real,
+                Err(_) if existing.symlink_metadata().is_ok() => return Err(errno::EACCES),
+                Err(e) => {
+                    match existing.file_name() {
+                        Some(name) => missing.push(name.to_os_string()),
+                        None => return Err(errno_of(e)),
+                    }
+                    existing.pop();
+                },
+            }
+        };
+        if !real.starts_with(&root) {
+            return Err(errno::EACCES)
+        }
+        for name in missing.iter().rev() {
+            real.push(name);
+        }
+        Ok(real)
+    }
+
+    fn insert(&mut self, file:OpenFile) -> i32 {
+        let mut fd = 0;
+        while self.files.contains_key(&fd) { fd += 1 }
+        self.files.insert(fd, file);
+        fd
+    }
+
+    fn get(&mut self, fd:i32) -> FileResult<&mut OpenFile> {
+        self.files.get_mut(&fd).ok_or(errno::EBADF)
+    }
+
+    fn open_with(&mut self, path:&str, options:&OpenOptions, readable:bool, writable:bool) -> FileResult<i32> {
+        let path = self.confine(&self.resolve(path))?;
+        if writable && path.is_dir() {
+            return Err(errno::EISDIR)
+        }
+        let file = options.open(path).map_err(errno_of)?;
+        Ok(self.insert(OpenFile::new(Handle::File(file), readable, writable)))
+    }
+
+    /// Opens `path` following the `fopen` `mode` string ("r", "w+", "ab"...)
+    /// and returns its descriptor.
+    pub fn fopen(&mut self, path:&str, mode:&str) -> FileResult<i32> {
+        let mut chars = mode.chars();
+        let mut options = OpenOptions::new();
+        let (mut readable, mut writable) = match chars.next() {
+            Some('r') => (true, false),
+            Some('w') => { options.create(true).truncate(true); (false, true) },
+            Some('a') => { options.create(true).append(true); (false, true) },
+            _ => return Err(errno::EINVAL),
+        };
+        for c in chars {
+            match c {
+                '+' => { readable = true; writable = true },
+                'x' if writable => { options.create_new(true); },
+                // binary mode, close on exec and mmap hints mean nothing here
+                'b' | 'e' | 'm' | 'c' | 'x' => {},
+                _ => break,
+            }
+        }
+        options.read(readable).write(writable);
+        self.open_with(path, &options, readable, writable)
+    }
+
+    /// Opens `path` following the `open(2)` `flags` and returns its
+    /// descriptor.
+    pub fn open(&mut self, path:&str, flags:i32) -> FileResult<i32> {
+        let (readable, writable) = match flags & O_ACCMODE {
+            O_WRONLY => (false, true),
+            O_RDWR => (true, true),
+            _ => (true, false),
+        };
+        let mut options = OpenOptions::new();
+        options
+            .read(readable)
+            .write(writable)
+            .append(flags & O_APPEND != 0)
+            .truncate(writable && flags & O_TRUNC != 0);
+        if flags & O_CREAT != 0 {
+            if flags & O_EXCL != 0 {
+                options.create_new(true);
+            } else {
+                options.create(true);
+            }
+        }
+        self.open_with(path, &options, readable, writable)
+    }
+
+    pub fn close(&mut self, fd:i32) -> FileResult<()> {
+        let mut file = self.files.remove(&fd).ok_or(errno::EBADF)?;
+        if let Handle::File(ref mut f) = file.handle {
+            f.flush().map_err(errno_of)?;
+        }
+        Ok(())
+    }
+
+    /// Reads at most `buf.len()` bytes at once, as `read(2)`: it may read
+    /// less without being at the end of the file, which only reading nothing
+    /// tells (setting the end of file indicator).
+    pub fn read(&mut self, fd:i32, buf:&mut [u8]) -> FileResult<usize> {
+        let file = self.get(fd)?;
+        if !file.readable { return Err(errno::EBADF) }
+        if buf.is_empty() { return Ok(0) }
+
+        // the bytes given back by `ungetc`, without waiting for more
+        if !file.pushback.is_empty() {
+            let mut n = 0;
+            while n < buf.len() {
+                match file.pushback.pop() {
+                    Some(b) => { buf[n] = b; n += 1 },
+                    None => break,
+                }
+            }
+            return Ok(n)
+        }
+        let read = match file.handle {
+            Handle::Stdin => io::stdin().read(buf),
+            Handle::File(ref mut f) => f.read(buf),
+            _ => return Err(errno::EBADF),
+        }.map_err(errno_of)?;
+        if read == 0 {
+            file.eof = true;
+        }
+        Ok(read)
+    }
+
+    /// Reads until `buf` is full or the end of the file, as `fread`. Reading
+    /// less than asked sets the end of file indicator.
+    pub fn read_full(&mut self, fd:i32, buf:&mut [u8]) -> FileResult<usize> {
+        let mut n = 0;
+        while n < buf.len() {
+            let read = self.read(fd, &mut buf[n..])?;
+            if read == 0 { break }
+            n += read;
+        }
+        Ok(n)
+    }
+
+    /// Reads one byte, `None` at the end of the file.
+    pub fn getc(&mut self, fd:i32) -> FileResult<Option<u8>> {
+        let mut b = [0];
+        Ok(if self.read(fd, &mut b)? == 1 { Some(b[0]) } else { None })
+    }
+
+    /// Gives back `byte` to the stream, it will be the next byte read.
+    pub fn ungetc(&mut self, fd:i32, byte:u8) -> FileResult<()> {
+        let file = self.get(fd)?;
+        file.pushback.push(byte);
+        file.eof = false;
+        Ok(())
+    }
+
+    pub fn write(&mut self, fd:i32, bytes:&[u8]) -> FileResult<usize> {
+        let file = self.get(fd)?;
+        if !file.writable { return Err(errno::EBADF) }
+        match file.handle {
+            Handle::Stdout => io::stdout().write_all(bytes),
+            Handle::Stderr => io::stderr().write_all(bytes),
+            Handle::File(ref mut f) => f.write_all(bytes),
+            Handle::Stdin => return Err(errno::EBADF),
+        }.map_err(errno_of)?;
+        Ok(bytes.len())
+    }
+
+    pub fn flush(&mut self, fd:i32) -> FileResult<()> {
+        match self.get(fd)?.handle {
+            Handle::Stdout => io::stdout().flush(),
+            Handle::Stderr => io::stderr().flush(),
+            Handle::File(ref mut f) => f.flush(),
+            Handle::Stdin => Ok(()),
+        }.map_err(errno_of)
+    }
+
+    /// Moves the position of `fd` like `lseek`/`fseek` and returns the new
+    /// position. Clears the end of file indicator.
+    pub fn seek(&mut self, fd:i32, offset:i64, whence:i32) -> FileResult<u64> {
+        let file = self.get(fd)?;
+        let pending = file.pushback.len() as i64;
+        let f = match file.handle {
+            Handle::File(ref mut f) => f,
+            _ => return Err(errno::ESPIPE),
+        };
+        let from = match whence {
+            SEEK_SET if offset >= 0 => SeekFrom::Start(offset as u64),
+            SEEK_CUR => SeekFrom::Current(offset - pending),
+            SEEK_END => SeekFrom::End(offset),
+            _ => return Err(errno::EINVAL),
+        };
+        let pos = f.seek(from).map_err(errno_of)?;
+        file.pushback.clear();
+        file.eof = false;
+        Ok(pos)
+    }
+
+    /// Position of `fd`, as seen by the guest.
+    pub fn tell(&mut self, fd:i32) -> FileResult<u64> {
+        let file = self.get(fd)?;
+        let pending = file.pushback.len() as u64;
+        match file.handle {
+            Handle::File(ref mut f) => f.stream_position()
+                .map(|pos| pos.saturating_sub(pending))
+                .map_err(errno_of),
+            _ => Err(errno::ESPIPE),
+        }
+    }
+
+    pub fn eof(&mut self, fd:i32) -> FileResult<bool> {
+        Ok(self.get(fd)?.eof)
+    }
+}
+
+impl Default for FileTable {
+    fn default() -> FileTable { FileTable::new() }
+}
@@ -0,0 +1,175 @@
+//! Dynamic warp formation and thread block compaction: the paths of
+//! different warps at the same PC are issued together, their threads packed
+//! into as few warps as the register file allows.
+//!
+//! The threads keep their warp (and thread id): the warps of a group run
+//! their path at the PC of the most threads of the group, and the threads
+//! issued at the same PC are counted as the fewest warps they fit in. A
+//! warp falls back to its scheduler when this path is blocked in a host
+//! call, or another of its paths waited too long (as the holder of a lock
+//! the majority spins on). A thread keeps its registers in bank
+//! `lane % banks`, and can only be issued in the lanes of this bank.
+
+use types::{BitSet, BoolIterator};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// Which warps are formed together, given as `dwf[:banks]` or
+/// `tbc:warps[:banks]`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FormationConfig {
+    /// Warps of a thread block, compacted together (thread block
+    /// compaction), or `None` for all the warps (dynamic warp formation).
+    pub block:Option<usize>,
+    /// Register banks of a warp, or `None` for one per lane: a thread is
+    /// then always issued in its own lane.
+    pub banks:Option<usize>,
+}
+
+impl FormationConfig {
+    /// Dynamic warp formation, over all the warps.
+    pub fn dwf() -> FormationConfig {
+        FormationConfig { block:None, banks:None }
+    }
+
+    /// Thread block compaction, over blocks of `warps` warps.
+    pub fn tbc(warps:usize) -> FormationConfig {
+        FormationConfig { block:Some(warps.max(1)), banks:None }
+    }
+
+    /// The group of warp `wid`.
+    pub fn group_of(&self, wid:usize) -> usize {
+        self.block.map_or(0, |warps| wid / warps)
+    }
+}
+
+impl FromStr for FormationConfig {
+    type Err = String;
+
+    fn from_str(s:&str) -> Result<FormationConfig, String> {
+        let number = |s:&str| s.parse::<usize>().ok().filter(|n| *n > 0)
+            .ok_or_else(|| format!("`{}` is not a positive number", s));
+        let fields : Vec<&str> = s.split(':').collect();
+        let (mut config, banks) = match (fields[0], fields.len()) {
+            ("dwf", 1) | ("dwf", 2) => (FormationConfig::dwf(), fields.get(1)),
+            ("tbc", 2) | ("tbc", 3) => (FormationConfig::tbc(number(fields[1])?), fields.get(2)),
+            _ => return Err(format!("`{}` is not dwf[:banks] or tbc:warps[:banks]", s)),
+        };
+        if let Some(banks) = banks {
+            config.banks = Some(number(banks)?);
+        }
+        Ok(config)
+    }
+}
+
+impl fmt::Display for FormationConfig {
+    fn fmt(&self, f:&mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.block {
+            Some(warps) => write!(f, "thread block compaction, {} warps per block", warps)?,
+            None => write!(f, "dynamic warp formation")?,
+        }
+        match self.banks {
+            Some(banks) => write!(f, ", {} register banks", banks),
+            None => write!(f, ", a register bank per lane"),
+        }
+    }
+}
+
+/// The fewest warps of `tpw` lanes holding the threads of `masks` (one per
+/// warp, issued at the same PC), as the threads can only move to the lanes
+/// of their register bank (see `FormationConfig::banks`).
+pub fn compacted_warps<BV:BitSet>(masks:&[BV], tpw:usize, banks:Option<usize>) -> usize {
+    let banks = banks.unwrap_or(tpw).max(1).min(tpw.max(1));
+    let lanes_per_bank = (tpw / banks).max(1);
+    let mut threads = vec![0; banks];
+    for mask in masks {
+        for lane in mask.bits().ones() {
+            threads[lane as usize % banks] += 1;
+        }
+    }
+    threads.iter().map(|t| (t + lanes_per_bank - 1) / lanes_per_bank).max().unwrap_or(0)
+}
+
+/// The PC of the most threads among the `paths` (PC and mask) of a group,
+/// the lowest one on ties.
+pub fn majority_pc<BV:BitSet, I:Iterator<Item=(i32, BV)>>(paths:I) -> Option<i32> {
+    let mut threads = BTreeMap::new();
+    for (pc, mask) in paths.filter(|(pc, mask)| *pc != 0 && mask.any()) {
+        *threads.entry(pc).or_insert(0) += mask.count();
+    }
+    let most = threads.values().cloned().max()?;
+    threads.into_iter().find(|(_, t)| *t == most).map(|(pc, _)| pc)
+}
+
+/// What the formation of the warps gained.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FormationStats {
+    /// Instructions run by a thread.
+    pub thread_instructions:u64,
+    /// Instructions issued by the warps on their own, and once formed.
+    pub warp_instructions:u64,
+    pub formed_instructions:u64,
+    /// Formed instructions of the paths which fit in fewer warps than they
+    /// came from.
+    pub merged:u64,
+}
+
+impl FormationStats {
+    /// Active lanes over issued lanes, without and with the formation.
+    pub fn simd_efficiency(&self, tpw:usize) -> f64 {
+        efficiency(self.thread_instructions, self.warp_instructions, tpw)
+    }
+
+    pub fn formed_simd_efficiency(&self, tpw:usize) -> f64 {
+        efficiency(self.thread_instructions, self.formed_instructions, tpw)
+    }
+}
+
+fn efficiency(threads:u64, warps:u64, tpw:usize) -> f64 {
+    if warps == 0 { 1. } else { threads as f64 / (warps * tpw as u64) as f64 }
+}
+
+/// The warp formation of a machine, see `Machine::enable_warp_formation`.
+#[derive(Clone, Debug)]
+pub struct WarpFormation {
+    config:FormationConfig,
+    tpw:usize,
+    stats:FormationStats,
+}
+
+impl WarpFormation {
+    pub fn new(config:FormationConfig, tpw:usize) -> WarpFormation {
+        WarpFormation { config, tpw, stats:FormationStats::default() }
+    }
+
+    pub fn config(&self) -> &FormationConfig {
+        &self.config
+    }
+
+    pub fn tpw(&self) -> usize {
+        self.tpw
+    }
+
+    pub fn stats(&self) -> &FormationStats {
+        &self.stats
+    }
+
+    /// Accounts for the instructions `issued` in a cycle: the warp, PC and
+    /// mask of each.
+    pub fn record<BV:BitSet>(&mut self, issued:&[(usize, i32, BV)]) {
+        let mut formed : BTreeMap<(usize, i32), Vec<BV>> = BTreeMap::new();
+        for (wid, pc, mask) in issued {
+            formed.entry((self.config.group_of(*wid), *pc)).or_default().push(*mask);
+            self.stats.thread_instructions += mask.count() as u64;
+            self.stats.warp_instructions += 1;
+        }
+        for masks in formed.values() {
+            let warps = compacted_warps(masks, self.tpw, self.config.banks) as u64;
+            self.stats.formed_instructions += warps;
+            if warps < masks.len() as u64 {
+                self.stats.merged += warps;
+            }
+        }
+    }
+}
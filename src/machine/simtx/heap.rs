@@ -0,0 +1,251 @@
+//! The heap of the simulated program, behind `malloc` and friends.
+//!
+//! The heap is a contiguous list of chunks, used or free, from its start to
+//! its top. Allocations take the first free chunk large enough (splitting
+//! it), or grow the top; freed chunks are merged with their free neighbours.
+//! Only the bookkeeping lives here: the guest memory is left to the caller.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Alignment (and granularity) of every allocation, like glibc on RV32.
+pub const MIN_ALIGN:usize = 16;
+
+fn align_up(x:usize, align:usize) -> usize {
+    (x + align - 1) & !(align - 1)
+}
+
+/// The misuses of the heap detected by `free` and `realloc`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HeapError {
+    /// The pointer was already freed.
+    DoubleFree(usize),
+    /// The pointer was never returned by an allocation.
+    InvalidFree(usize),
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HeapStats {
+    pub allocations:usize,
+    pub frees:usize,
+    pub reallocs:usize,
+    /// Allocations which failed because the heap is full.
+    pub failures:usize,
+    /// Bytes asked by the program and not freed yet.
+    pub in_use:usize,
+    pub peak_in_use:usize,
+    /// Bytes between the start and the top of the heap.
+    pub heap_size:usize,
+    pub live_chunks:usize,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Chunk {
+    size:usize,
+    // size asked by the program, 0 if the chunk is free
+    requested:usize,
+    used:bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct Heap {
+    start:usize,
+    top:usize,
+    limit:usize,
+    chunks:BTreeMap<usize, Chunk>,
+    // freed addresses not given back by an allocation since
+    freed:BTreeSet<usize>,
+    stats:HeapStats,
+}
+
+impl Heap {
+    /// Creates an empty heap at `start`, which can grow up to `limit` bytes.
+    pub fn new(start:usize, limit:usize) -> Heap {
+        let start = align_up(start, MIN_ALIGN);
+        Heap {
+            start, top:start, limit,
+            chunks:BTreeMap::new(),
+            freed:BTreeSet::new(),
+            stats:HeapStats::default(),
+        }
+    }
+
+    pub fn start(&self) -> usize { self.start }
+
+    pub fn top(&self) -> usize { self.top }
+
+    pub fn stats(&self) -> &HeapStats { &self.stats }
+
+    /// Returns the start and requested size of the live allocation containing
+    /// `addr`.
+    pub fn allocation_of(&self, addr:usize) -> Option<(usize, usize)> {
+        self.chunks.range(..=addr).next_back()
+            .filter(|(start, c)| c.used && addr < *start + c.requested)
+            .map(|(start, c)| (*start, c.requested))
+    }
+
+    /// Returns the requested size of the allocation starting at `ptr`.
+    pub fn size_of(&self, ptr:usize) -> Option<usize> {
+        self.chunks.get(&ptr).filter(|c| c.used).map(|c| c.requested)
+    }
+
+    /// Iterates over the live allocations (address, requested size).
+    pub fn allocations(&self) -> impl Iterator<Item=(usize, usize)> + '_ {
+        self.chunks.iter()
+            .filter(|(_, c)| c.used)
+            .map(|(addr, c)| (*addr, c.requested))
+    }
+
+    /// Bytes taken by an allocation of `size` bytes, padding included.
+    pub fn chunk_size(size:usize) -> usize {
+        align_up(size.max(1), MIN_ALIGN)
+    }
+
+    // inserts a free chunk, merged with its free neighbours
+    fn insert_free(&mut self, addr:usize, size:usize) {
+        let mut addr = addr;
+        let mut size = size;
+
+        let next = addr + size;
+        if let Some(c) = self.chunks.get(&next).cloned() {
+            if !c.used {
+                self.chunks.remove(&next);
+                size += c.size;
+            }
+        }
+        if let Some((&prev, c)) = self.chunks.range(..addr).next_back() {
+            if !c.used && prev + c.size == addr {
+                addr = prev;
+                size += c.size;
+            }
+        }
+        self.chunks.insert(addr, Chunk { size, requested:0, used:false });
+    }
+
+    fn take(&mut self, addr:usize, size:usize, requested:usize) {
+        self.chunks.insert(addr, Chunk { size, requested, used:true });
+        let freed : Vec<usize> = self.freed.range(addr..addr + size).cloned().collect();
+        for f in freed {
+            self.freed.remove(&f);
+        }
+        self.stats.allocations += 1;
+        self.stats.live_chunks += 1;
+        self.stats.in_use += requested;
+        self.stats.peak_in_use = self.stats.peak_in_use.max(self.stats.in_use);
+    }
+
+    /// Allocates `size` bytes aligned on `align` (a power of two). Returns
+    /// `None` when the heap is full.
+    pub fn alloc(&mut self, size:usize, align:usize) -> Option<usize> {
+        let align = align.max(MIN_ALIGN);
+        let csize = Heap::chunk_size(size);
+
+        // first fit among the free chunks
+        let fit = self.chunks.iter()
+            .filter(|(_, c)| !c.used)
+            .map(|(addr, c)| (*addr, c.size, align_up(*addr, align)))
+            .find(|(addr, s, aligned)| aligned + csize <= addr + s);
+
+        let ptr = match fit {
+            Some((addr, s, aligned)) => {
+                self.chunks.remove(&addr);
+                if aligned > addr {
+                    self.chunks.insert(addr, Chunk { size:aligned - addr, requested:0, used:false });
+                }
+                let end = aligned + csize;
+                if end < addr + s {
+                    self.chunks.insert(end, Chunk { size:addr + s - end, requested:0, used:false });
+                }
+                aligned
+            },
+            None => {
+                let aligned = align_up(self.top, align);
+                if aligned + csize - self.start > self.limit {
+                    self.stats.failures += 1;
+                    return None
+                }
+                if aligned > self.top {
+                    let top = self.top;
+                    self.insert_free(top, aligned - top);
+                }
+                self.top = aligned + csize;
+                self.stats.heap_size = self.top - self.start;
+                aligned
+            },
+        };
+
+        self.take(ptr, csize, size);
+        Some(ptr)
+    }
+
+    /// Frees the allocation starting at `ptr`.
+    pub fn free(&mut self, ptr:usize) -> Result<(), HeapError> {
+        let chunk = match self.chunks.get(&ptr) {
+            Some(c) if c.used => *c,
+            _ if self.freed.contains(&ptr) => return Err(HeapError::DoubleFree(ptr)),
+            _ => return Err(HeapError::InvalidFree(ptr)),
+        };
+
+        self.chunks.remove(&ptr);
+        self.insert_free(ptr, chunk.size);
+        self.freed.insert(ptr);
+        self.stats.frees += 1;
+        self.stats.live_chunks -= 1;
+        self.stats.in_use -= chunk.requested;
+        Ok(())
+    }
+
+    /// Resizes the allocation at `ptr` (which must not be null). Returns the
+    /// new address; when it moved, the caller must copy the
+    /// `min(old size, size)` first bytes, given as the second value.
+    pub fn realloc(&mut self, ptr:usize, size:usize) -> Result<Option<(usize, usize)>, HeapError> {
+        let chunk = match self.chunks.get(&ptr) {
+            Some(c) if c.used => *c,
+            _ if self.freed.contains(&ptr) => return Err(HeapError::DoubleFree(ptr)),
+            _ => return Err(HeapError::InvalidFree(ptr)),
+        };
+        self.stats.reallocs += 1;
+        let csize = Heap::chunk_size(size);
+
+        // in place: shrink, or grow over a free next chunk or the top
+        let next = ptr + chunk.size;
+        let room = match self.chunks.get(&next) {
+            Some(c) if !c.used => chunk.size + c.size,
+            None if next == self.top => self.limit + self.start - ptr,
+            _ => chunk.size,
+        };
+        if csize <= room {
+            if let Some(c) = self.chunks.get(&next).cloned() {
+                if !c.used { self.chunks.remove(&next); }
+            }
+            let end = ptr + csize;
+            if end > self.top {
+                self.top = end;
+                self.stats.heap_size = self.top - self.start;
+            } else if end < ptr + room {
+                let free_end = (ptr + room).min(self.top);
+                if end < free_end {
+                    self.insert_free(end, free_end - end);
+                }
+            }
+            self.chunks.insert(ptr, Chunk { size:csize, requested:size, used:true });
+            let freed : Vec<usize> = self.freed.range(ptr..end).cloned().collect();
+            for f in freed {
+                self.freed.remove(&f);
+            }
+            self.stats.in_use = self.stats.in_use - chunk.requested + size;
+            self.stats.peak_in_use = self.stats.peak_in_use.max(self.stats.in_use);
+            return Ok(Some((ptr, 0)))
+        }
+
+        match self.alloc(size, MIN_ALIGN) {
+            Some(new) => {
+                // the move is counted as a realloc, not as a malloc and a free
+                self.stats.allocations -= 1;
+                self.free(ptr)?;
+                self.stats.frees -= 1;
+                Ok(Some((new, chunk.requested.min(size))))
+            },
+            None => Ok(None),
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use machine::simtx::{Machine, Core, scheduler::SimtxScheduler};
+use memory::Memory;
+use types::BitSet;
+use std::collections::HashMap;
+
+/// What the machine must do with the calling path once a host call returns.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HostCallAction {
+    /// The call is over, the path continues right after the `jal`.
+    Return,
+    /// The call cannot complete yet (e.g. `pthread_join` on a running thread)
+    /// and will be issued again the next time the path is scheduled.
+    Retry,
+    /// The handler already moved the calling path (e.g. `exit`).
+    Jumped,
+}
+
+/// Describes a single call from the simulated program to a host function.
+#[derive(Clone, Debug)]
+pub struct HostCallContext {
+    /// Symbol name of the called function, without its version suffix.
+    pub name:String,
+    /// Address of the `jal` instruction which issued the call.
+    pub pc:i32,
+    /// Warp and path which issued the call.
+    pub warp:usize,
+    pub path:usize,
+    /// Size of the `jal` instruction (2 if compressed, 4 otherwise).
+    pub advance:i32,
+    /// Threads per warp of the machine.
+    pub tpw:usize,
+    lanes:Vec<usize>,
+}
+
+impl HostCallContext {
+    pub fn new(name:&str, pc:i32, warp:usize, path:usize, advance:i32
+               , tpw:usize, lanes:Vec<usize>) -> HostCallContext {
+        HostCallContext { name:String::from(name), pc, warp, path, advance, tpw, lanes }
+    }
+
+    /// Ids (inside the warp) of the lanes taking part in the call, in thread
+    /// order.
+    pub fn lanes(&self) -> impl Iterator<Item=usize> + '_ {
+        self.lanes.iter().cloned()
+    }
+
+    /// Returns the only lane making the call. Panics if more than one lane
+    /// is active, for functions which cannot be called in SIMT.
+    pub fn single_lane(&self) -> usize {
+        assert!(self.lanes.len() == 1, "{} must be called by only 1 thread", self.name);
+        self.lanes[0]
+    }
+
+    /// Global thread id of `lane`.
+    pub fn tid(&self, lane:usize) -> usize {
+        self.warp * self.tpw + lane
+    }
+}
+
+/// Reads the arguments and writes the return value of one lane, following the
+/// RV32 calling convention (`a0`-`a7` and `fa0`-`fa7`).
+pub struct LaneArgs<'a> {
+    core:&'a mut Core,
+}
+
+impl<'a> LaneArgs<'a> {
+    pub fn new(core:&'a mut Core) -> LaneArgs<'a> {
+        LaneArgs { core }
+    }
+
+    /// `n`-th integer argument.
+    pub fn int(&self, n:usize) -> i32 {
+        self.core.registers[10 + n]
+    }
+
+    /// `n`-th integer argument, seen as a guest address.
+    pub fn ptr(&self, n:usize) -> usize {
+        self.int(n) as u32 as usize
+    }
+
+    /// `n`-th single precision argument.
+    pub fn float(&self, n:usize) -> f32 {
+        self.core.get_f32_register(10 + n)
+    }
+
+    /// `n`-th double precision argument.
+    pub fn double(&self, n:usize) -> f64 {
+        self.core.get_f64_register(10 + n)
+    }
+
+    /// Stack pointer of the lane, where the arguments which do not fit in
+    /// registers are.
+    pub fn sp(&self) -> usize {
+        self.core.registers[2] as u32 as usize
+    }
+
+    pub fn ret(&mut self, value:i32) {
+        self.core.set_ri(10, value)
+    }
+
+    pub fn ret_float(&mut self, value:f32) {
+        self.core.set_f32_register(10, value)
+    }
+
+    pub fn ret_double(&mut self, value:f64) {
+        self.core.set_f64_register(10, value)
+    }
+}
+
+/// Signature of a host call handler. Handlers are plain functions: they get
+/// the machine, the description of the call and the guest memory.
+pub type HostCall<S, BV=u32> = fn(&mut Machine<S, BV>, &HostCallContext, &mut dyn Memory) -> HostCallAction;
+
+/// The table of host calls known by a machine, keyed by exact symbol name.
+pub struct HostCalls<S:SimtxScheduler, BV:BitSet=u32> {
+    handlers:HashMap<String, HostCall<S, BV>>,
+}
+
+impl<S:SimtxScheduler, BV:BitSet> HostCalls<S, BV> {
+    pub fn new() -> HostCalls<S, BV> {
+        HostCalls { handlers:HashMap::new() }
+    }
+
+    /// Registers `handler` for `name`, replacing any previous handler.
+    pub fn register(&mut self, name:&str, handler:HostCall<S, BV>) {
+        self.handlers.insert(String::from(name), handler);
+    }
+
+    pub fn get(&self, name:&str) -> Option<HostCall<S, BV>> {
+        self.handlers.get(symbol_name(name)).cloned()
+    }
+
+    pub fn contains(&self, name:&str) -> bool {
+        self.handlers.contains_key(symbol_name(name))
+    }
+}
+
+impl<S:SimtxScheduler, BV:BitSet> Default for HostCalls<S, BV> {
+    fn default() -> HostCalls<S, BV> { HostCalls::new() }
+}
+
+impl<S:SimtxScheduler, BV:BitSet> Clone for HostCalls<S, BV> {
+    fn clone(&self) -> HostCalls<S, BV> {
+        HostCalls { handlers:self.handlers.clone() }
+    }
+}
+
+/// Strips the symbol version of a dynamic symbol (`printf@@GLIBC_2.29`).
+pub fn symbol_name(name:&str) -> &str {
+    name.split('@').next().unwrap_or(name)
+}
@@ -1,43 +1,44 @@
 use machine::{
     MultiCoreIMachine,
 };
-use machine::simtx::scheduler::SimtxScheduler;
+use machine::simtx::scheduler::{SimtxScheduler, ProgramInfo, Rng};
+use machine::simtx::hostcall::{HostCall, HostCalls, HostCallContext, HostCallAction, LaneArgs, symbol_name};
+use machine::simtx::error::GuestError;
+use machine::simtx::files::FileTable;
+use machine::simtx::heap::{Heap, HeapError, HeapStats, MIN_ALIGN};
+use machine::simtx::memcheck::{MemChecker, MemError, Access, Site, WriteLog};
+use machine::simtx::timing::{Timing, TimingConfig};
+use machine::simtx::coalescing::{self, CoalescingAnalyzer};
+use machine::simtx::formation::{self, FormationConfig, WarpFormation};
+use machine::simtx::stats::{SimtStats, WarpStats, BranchStats};
+use machine::simtx::ipdom::ReconvergencePoints;
+use machine::simtx::predictor::{BranchOutcome, DivergencePredictor, CounterPredictor, TableConfig, PredictionStats};
 use isa::{Instruction, OpCode, CsrId};
 use memory::*;
+use symbolizer::Symbolizer;
+use cfg::Cfg;
+use trace::{Tracer, TraceRecord, RegWrite, MemAccess};
+use htif::Htif;
 use types::{MachineInteger, BitSet, BoolIterator};
 use std::{
     sync::{Arc, Mutex},
     fmt,
     ops::DerefMut,
     collections::{HashMap, BTreeMap},
-    fs::{File, OpenOptions},
-    io::{Write, Read, Seek, SeekFrom},
-    ffi::{CString},
-    marker::PhantomData,
+    io,
 };
-use libc::{c_char, strtok};
-
-macro_rules! syscall (
-    {$fnv:ident > $func:tt} => {
-        if $fnv.contains(stringify!($func)) {
-            include!(concat!("syscalls/", stringify!($func), ".rs"));
-            break
-        }
-    };
-    {$fnv:ident > ($file:expr) $($func:ident),+} => {
-        if $($fnv.contains(stringify!($func)))||* {
-            include!(concat!("syscalls/", $file));
-            break
-        }
-    };
-);
 
+#[path = "syscalls/mod.rs"]
+mod syscalls;
 
-static mut STRTOK_VEC : * mut c_char = 0 as * mut c_char;
 
+/// The number of threads per warp allowed by the default `u32` execution
+/// masks. Machines built over `u64` or `u128` masks allow 64 or 128.
+pub const MAX_TPW : usize = <u32 as BitSet>::SIZE as usize;
 
-type BitVec = u32;
-pub const MAX_TPW : usize = core::mem::size_of::<BitVec>() * 8;
+// Schedules a path can wait before the warp formation gives way to the
+// scheduler of its warp
+const FORMATION_PATIENCE : usize = 64;
 
 #[repr(C)]
 #[derive(Clone, Copy)]
@@ -78,6 +79,77 @@ impl MachineF32 {
         ret
     }
     //fn from_f64(double:f64) -> Self { Self { double } }
+
+    /// The 64 bits of the register, NaN-boxing single precision values.
+    pub fn bits(&self) -> u64 {
+        unsafe { ((self.bits.hi as u64) << 32) | self.bits.lo as u64 }
+    }
+}
+
+/// The CSRs of a hardware thread. The threads only run in machine mode,
+/// without interrupts: `mtvec` is always in direct mode, and `mstatus` only
+/// has `MIE` and `MPIE`, `MPP` staying machine mode.
+#[derive(Clone, Default)]
+pub struct Csrs {
+    pub mhartid: i32,
+    pub mstatus: i32,
+    pub mtvec: i32,
+    pub mscratch: i32,
+    pub mepc: i32,
+    pub mcause: i32,
+    pub mtval: i32,
+}
+
+const MSTATUS_MIE : i32 = 1 << 3;
+const MSTATUS_MPIE : i32 = 1 << 7;
+const MSTATUS_MPP : i32 = 0b11 << 11;
+
+impl Csrs {
+    // The value of `csr`, `None` for the CSRs the threads do not have
+    fn get(&self, csr:CsrId) -> Option<i32> {
+        match csr {
+            CsrId::MVENDORID | CsrId::MARCHID | CsrId::MIMPID => Some(0),
+            CsrId::MHARTID => Some(self.mhartid),
+            CsrId::MSTATUS => Some(self.mstatus | MSTATUS_MPP),
+            CsrId::MIE | CsrId::MIP => Some(0),
+            CsrId::MTVEC => Some(self.mtvec),
+            CsrId::MSCRATCH => Some(self.mscratch),
+            CsrId::MEPC => Some(self.mepc),
+            CsrId::MCAUSE => Some(self.mcause),
+            CsrId::MTVAL => Some(self.mtval),
+            _ => None,
+        }
+    }
+
+    fn set(&mut self, csr:CsrId, value:i32) {
+        match csr {
+            CsrId::MSTATUS => self.mstatus = value & (MSTATUS_MIE | MSTATUS_MPIE),
+            CsrId::MTVEC => self.mtvec = value & !0b11,
+            CsrId::MSCRATCH => self.mscratch = value,
+            CsrId::MEPC => self.mepc = value & !1,
+            CsrId::MCAUSE => self.mcause = value,
+            CsrId::MTVAL => self.mtval = value,
+            _ => { },
+        }
+    }
+
+    // Takes the exception `cause` of the instruction at `pc`, giving the
+    // address of the handler
+    fn trap(&mut self, cause:i32, pc:i32) -> i32 {
+        let mie = self.mstatus & MSTATUS_MIE;
+        self.mstatus = (self.mstatus & !(MSTATUS_MIE | MSTATUS_MPIE)) | (mie << 4);
+        self.mepc = pc;
+        self.mcause = cause;
+        self.mtval = 0;
+        self.mtvec
+    }
+
+    // `mret`, giving the address to return to
+    fn mret(&mut self) -> i32 {
+        let mpie = self.mstatus & MSTATUS_MPIE;
+        self.mstatus = (self.mstatus & !MSTATUS_MIE) | (mpie >> 4) | MSTATUS_MPIE;
+        self.mepc
+    }
 }
 
 /// Defines the state of a single hardware thread.
@@ -85,27 +157,83 @@ impl MachineF32 {
 pub struct Core {
     pub registers: [ i32; 32 ],
     pub fregisters: [ MachineF32; 32 ],
+    pub csrs: Csrs,
+    // the registers written, floating-point ones flagged, while tracing
+    writes: Option<Vec<(bool, usize)>>,
 }
 
 impl Core {
-    fn set_f32_register(&mut self, reg:usize, value:f32) {
-        self.fregisters[reg] = MachineF32::from_f32(value);
+    fn log_write(&mut self, fp:bool, reg:usize) {
+        if let Some(writes) = self.writes.as_mut() {
+            if !writes.contains(&(fp, reg)) {
+                writes.push((fp, reg));
+            }
+        }
+    }
+    fn set_fr(&mut self, reg:usize, value:MachineF32) {
+        self.log_write(true, reg);
+        self.fregisters[reg] = value
     }
-    fn get_f32_register(&mut self, reg:usize) -> f32 {
+    pub fn set_f32_register(&mut self, reg:usize, value:f32) {
+        self.set_fr(reg, MachineF32::from_f32(value));
+    }
+    pub fn get_f32_register(&self, reg:usize) -> f32 {
         unsafe { self.fregisters[reg].float.lo }
     }
-    fn set_f64_register(&mut self, reg:usize, value:f64) {
-        self.fregisters[reg].double = value
+    pub fn set_f64_register(&mut self, reg:usize, value:f64) {
+        self.set_fr(reg, MachineF32 { double:value })
     }
-    fn get_f64_register(&self, reg:usize) -> f64 {
+    pub fn get_f64_register(&self, reg:usize) -> f64 {
         unsafe { self.fregisters[reg].double }
     }
-    fn set_ri(&mut self, reg:usize, value:i32) {
-        #[cfg(debug_assertions)]
-        /*if reg >= 18 && reg <= 27*/ {
-            println!("WRITE {:x} TO x{}", value, reg);
+    /// Writes to `x0` are ignored.
+    pub fn set_ri(&mut self, reg:usize, value:i32) {
+        if reg != 0 {
+            self.log_write(false, reg);
+            self.registers[reg] = value
         }
-        self.registers[reg] = value
+    }
+
+    // Runs the SYSTEM instruction `inst` at `pc`, giving the address of the
+    // next instruction of the thread: the one after `pc`, its trap handler,
+    // or the address `mret` returns to.
+    fn system(&mut self, inst:Instruction, pc:i32, next_pc:i32) -> i32 {
+        if inst.get_funct3() != 0 {
+            return match self.access_csr(inst) {
+                Some(()) => next_pc,
+                None => self.csrs.trap(2, pc),
+            }
+        }
+        match inst.0 >> 20 {
+            0x000 => self.csrs.trap(11, pc), // ECALL
+            0x001 => self.csrs.trap(3, pc),  // EBREAK
+            0x302 => self.csrs.mret(),       // MRET
+            0x105 => next_pc,                // WFI
+            _ => self.csrs.trap(2, pc),
+        }
+    }
+
+    // The CSR instructions, `None` when illegal: an unknown CSR, a read-only
+    // one written, or a reserved `funct3`. CSRRS and CSRRC of `x0`, and
+    // their immediate forms of 0, only read.
+    fn access_csr(&mut self, inst:Instruction) -> Option<()> {
+        let csr = CsrId::from_number((inst.get_imm_i() & 0xfff) as u16)?;
+        let old = self.csrs.get(csr)?;
+        let rs1 = inst.get_rs1() as usize;
+        let operand = if inst.get_funct3() & 0b100 == 0 { self.registers[rs1] } else { rs1 as i32 };
+        let new = match inst.get_funct3() & 0b11 {
+            0b00 => return None,
+            0b01 => Some(operand),
+            _ if rs1 == 0 => None,
+            0b10 => Some(old | operand),
+            _ => Some(old & !operand),
+        };
+        if let Some(value) = new {
+            if csr.mode() == 0b11 { return None }
+            self.csrs.set(csr, value);
+        }
+        self.set_ri(inst.get_rd() as usize, old);
+        Some(())
     }
 }
 
@@ -113,169 +241,113 @@ impl Core {
 /// we handle divergence by remembering where all threads are with a
 /// `(fetch_pc, execution_mask)` tuple. Before fetching instructions, we chose
 /// a `Path` to advance.
+///
+/// The mask type `BV` bounds the number of threads of a warp.
 #[derive(Clone, Copy, Debug)]
-pub struct Path {
+pub struct Path<BV:BitSet=u32> {
     pub fetch_pc : i32,
-    pub execution_mask : BitVec,
-    pub waiting_for_sync : BitVec,
+    pub execution_mask : BV,
+    pub waiting_for_sync : BV,
     pub time_since_scheduled : usize,
+    /// With IPDOM reconvergence, where the path gives its threads back to
+    /// the entry below it in the stack.
+    pub reconvergence_pc : Option<i32>,
 }
 
-impl Path {
-    pub fn from_pc_mask(pc:i32, mask:BitVec) -> Path {
+impl<BV:BitSet> Path<BV> {
+    pub fn from_pc_mask(pc:i32, mask:BV) -> Path<BV> {
         Path {
             fetch_pc: pc,
             execution_mask: mask,
-            waiting_for_sync: 0,
+            waiting_for_sync: BV::empty(),
             time_since_scheduled: 0,
+            reconvergence_pc: None,
         }
     }
 
     pub fn is_single(&self) -> bool {
-        self.execution_mask.bits().ones().count() == 1
+        self.execution_mask.count() == 1
     }
 }
 
-impl fmt::Display for Path {
+impl<BV:BitSet> fmt::Display for Path<BV> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "(0x{:x}, {:b})", self.fetch_pc, self.execution_mask)
     }
 }
 
 #[derive(Debug, Clone)]
-pub enum BranchOutcome<BV:BitSet> {
-    Uniform(bool, BV),
-    Divergent(BV, BV),
-}
-
-impl<BV:BitSet> std::fmt::Display for BranchOutcome<BV> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            BranchOutcome::Uniform(taken, mask) => {
-                for b in mask.bits() {
-                    write!(f, "{}", if b { if *taken { 'T' } else { 'N' } } else { ' ' })?;
-                }
-            },
-            BranchOutcome::Divergent(tmask, ntmask) => {
-                for (t, nt) in tmask.bits().zip(ntmask.bits()) {
-                    write!(f, "{}", if t { 'T' } else if nt { 'N' } else { ' ' })?;
-                }
-            },
-        }
-        Ok(())
-    }
-}
-
-#[derive(Debug, Clone, Copy)]
-struct PredData {
-    good_uni:usize,
-    good_div:usize,
-    bad_uni:usize,
-    bad_div:usize,
-}
-
-impl PredData {
-    fn new() -> Self { Self { good_uni:0, good_div:0, bad_uni:0, bad_div:0 } }
-    fn update<BV:BitSet>(&mut self, pred_div:bool, actual_div:&BranchOutcome<BV>) {
-        match (pred_div, actual_div) {
-            (false, BranchOutcome::Divergent(..)) => self.bad_div += 1,
-            (true, BranchOutcome::Divergent(..)) => self.good_div += 1,
-            (true, _) => self.bad_uni += 1,
-            (false, _) => self.good_uni += 1,
-        }
-    }
-}
-
-#[derive(Debug, Clone)]
-pub struct CondBranchData {
+pub struct CondBranchData<BV:BitSet=u32> {
     times_passed:usize,
-    taken_hist:Vec<BranchOutcome<BitVec>>,
-    pred_data:PredData,
+    taken_hist:Vec<BranchOutcome<BV>>,
+    pred_data:PredictionStats,
 }
 
-impl CondBranchData {
-    fn new() -> Self { Self { times_passed : 0, taken_hist : Vec::new(), pred_data:PredData::new() } }
-}
-
-#[derive(Debug, Clone)]
-struct DivergencePredictor<T : BitSet> {
-    counter : Vec<i8>,
-    phantom: PhantomData<T>,
+impl<BV:BitSet> CondBranchData<BV> {
+    fn new() -> Self { Self { times_passed : 0, taken_hist : Vec::new(), pred_data:PredictionStats::default() } }
 }
 
-impl<BV:BitSet> DivergencePredictor<BV> {
-    fn new() -> Self { Self { counter : vec![1;BV::SIZE as usize], phantom: PhantomData, } }
-
-    fn predict_divergence(&self, mask:BV) -> bool {
-        let nbt = mask.bits().ones().count() - 1;
-        let predictor = &self.counter[nbt];
-        let counter_prediction = *predictor > 1;
-        let uniform_probability = (0.5f32).powi(nbt as i32);
-
-        if uniform_probability == 1.0 {
-            false
-        } else {
-            counter_prediction
-        }
-    }
-
-    fn update(&mut self, outcome:&BranchOutcome<BV>) {
-        match outcome {
-            BranchOutcome::Uniform(_, mask) => {
-                let nbt = mask.bits().ones().count() - 1;
-                let predictor = &mut self.counter[nbt];
-                *predictor = (*predictor - 1).max(0);
-            },
-            BranchOutcome::Divergent(m1, m2) => {
-                let nbt = m1.bits().ones().chain(m2.bits().ones()).count() - 1;
-                let predictor = &mut self.counter[nbt];
-                *predictor = (*predictor + 1).min(3);
-            },
-        }
-    }
-
-    fn predict_and_update(&mut self, mask:BV, outcome:&BranchOutcome<BV>) -> bool {
-        let nbth = mask.bits().ones().count();
-        let ret = self.predict_divergence(mask);
-        if nbth > 1 {
-            self.update(outcome);
-        }
-        ret
-    }
+// The scheduling state of a warp, see `Warp::save_schedule`
+struct SavedSchedule<S> {
+    scheduler:S,
+    rng:Rng,
+    current_path:Option<usize>,
+    invalidated:bool,
+    ages:Vec<usize>,
 }
 
 /// Defines a hardware warp (a group of threads) which all execute instructions
 /// in an SIMD fasion.
 #[derive(Clone)]
-pub struct Warp<S:SimtxScheduler> {
+pub struct Warp<S:SimtxScheduler, BV:BitSet=u32> {
     pub cores: Vec<Core>,
-    pub paths: Vec<Path>,
+    pub paths: Vec<Path<BV>>,
     pub current_path: Option<usize>,
     pub cycles_since_last_schedule: usize,
 
     // Log variables
-    pub branch_mask_hist: HashMap<i32, Vec<BitVec>>,
+    pub branch_mask_hist: HashMap<i32, Vec<BV>>,
     pub thresholds: usize,
     pub fusions: usize,
-    pub cond_branch_data: HashMap<i32, CondBranchData>,
+    pub cond_branch_data: HashMap<i32, CondBranchData<BV>>,
 
     // Scheduler black box 
     pub scheduler:S,
     pub schedule_invalidated:bool,
 
     // divergence prediction
-    div_pred:Vec<DivergencePredictor<u32>>,
+    div_pred:Box<dyn DivergencePredictor<BV>>,
+
+    // IPDOM reconvergence when enabled: `paths` is then a stack
+    ipdom:Option<Arc<ReconvergencePoints>>,
+    // the host calls retried since the warp last issued anything else: as
+    // many blocked paths to skip from the top of the IPDOM stack, and the
+    // warp formation leaving the choice of the path to the scheduler
+    retries:usize,
+
+    // what is known of the program, shared by the warps, and the cycle
+    program:Arc<ProgramInfo>,
+    cycle:u64,
+
+    // see `Machine::simt_stats`, with the divergences whose threads did not
+    // reconverge yet (branch, cycle and threads), and the threads issued last
+    stats:WarpStats,
+    branch_stats:BTreeMap<i32, BranchStats>,
+    divergences:Vec<(i32, u64, BV)>,
+    last_issued:BV,
+
+    /// For the random choices of the scheduler
+    pub rng:Rng,
 }
 
-impl<S:SimtxScheduler> Warp<S> {
-    pub fn new(tpw:usize) -> Warp<S> {
+impl<S:SimtxScheduler, BV:BitSet> Warp<S, BV> {
+    pub fn new(tpw:usize) -> Warp<S, BV> {
         let mut cores = Vec::new();
-        cores.resize(tpw, Core { registers : [ 0; 32 ], fregisters: [ MachineF32::new(); 32 ] });
-
-        let div_pred = vec![DivergencePredictor::new(); 1<<16];
+        cores.resize(tpw, Core { registers : [ 0; 32 ], fregisters: [ MachineF32::new(); 32 ]
+            , csrs: Csrs::default(), writes: None });
 
         Warp {
-            div_pred,
+            div_pred:Box::new(CounterPredictor::new(TableConfig::default())),
             schedule_invalidated:true,
             cores,
             paths: Vec::new(),
@@ -286,14 +358,44 @@ impl<S:SimtxScheduler> Warp<S> {
             cond_branch_data: HashMap::new(),
             cycles_since_last_schedule: 0,
             scheduler:S::default(),
+            ipdom:None,
+            retries:0,
+            program:Arc::new(ProgramInfo::default()),
+            cycle:0,
+            stats:WarpStats::default(),
+            branch_stats:BTreeMap::new(),
+            divergences:Vec::new(),
+            last_issued:BV::empty(),
+            rng:Rng::new(0),
         }
     }
 
-    pub fn _get_path_of_core_mut(&mut self, cid:usize) -> Option<&mut Path> {
+    /// What is known of the program before running it, as given by
+    /// `Machine::load_program`.
+    pub fn program(&self) -> &ProgramInfo {
+        &self.program
+    }
+
+    /// The number of steps of the machine so far.
+    pub fn cycle(&self) -> u64 {
+        self.cycle
+    }
+
+    /// Whether the divergence predictor of the warp expects the threads of
+    /// `mask` to diverge at the branch at `pc`.
+    pub fn predict_divergence(&self, pc:i32, mask:BV) -> bool {
+        mask.count() > 1 && self.div_pred.predict(pc, mask)
+    }
+
+    pub fn predictor(&self) -> &dyn DivergencePredictor<BV> {
+        self.div_pred.as_ref()
+    }
+
+    pub fn _get_path_of_core_mut(&mut self, cid:usize) -> Option<&mut Path<BV>> {
         self.paths.iter_mut().filter(|p| p.execution_mask.at(cid)).next()
     }
 
-    pub fn _get_path_of_core(&self, cid:usize) -> Option<&Path> {
+    pub fn _get_path_of_core(&self, cid:usize) -> Option<&Path<BV>> {
         self.paths.iter().filter(|p| p.execution_mask.at(cid)).next()
     }
 
@@ -306,49 +408,86 @@ impl<S:SimtxScheduler> Warp<S> {
             .expect("Current path is empty")
     }
 
-    fn _probas(&self) -> Vec<f32> {
-        let ps = &self.paths;
-
-        let raw : Vec<f32> = ps
-            .into_iter()
-            .enumerate()
-            .map(|(i,p)| {
-                ps
-                    .into_iter()
-                    .enumerate()
-                    .filter(|(j,_)| i != *j)
-                    .fold(0., |v, (_,pp)| {
-                        let decrease_factor = 1;
-                        v + 1. / ((pp.fetch_pc as f32) - p.fetch_pc as f32)
-                            .abs()
-                            .powi(decrease_factor)
-                    })
-            }).collect();
-
-        let total : f32 = (&raw).into_iter().sum();
-        raw
-            .into_iter()
-            .map(|v| v/total)
-            .collect()
-    }
-
     /// This function should be called right before any FETCH step of the `Warp`
     /// to ensure we always execute a valid path.
     ///
     /// This function sets the `current_path` of the `Warp` correcly according
     /// to the scheduling rule, but also fusion paths with the same PC.
+    ///
+    /// The `time_since_scheduled` of the other paths grows by one.
     pub fn schedule_path(&mut self) -> Option<usize> {
+        let path = if self.ipdom.is_some() {
+            self.schedule_ipdom()
+        } else {
+            S::schedule(self)
+        };
+        self.age_paths(path);
+        path
+    }
 
+    // Runs the path at `pc` when there is one, as asked by the warp
+    // formation, else the one of the scheduler. The scheduler also chooses
+    // after a blocked host call, and while a path of the warp waited
+    // FORMATION_PATIENCE schedules (e.g. the holder of a lock the others
+    // spin on). The IPDOM stack is kept in order.
+    fn schedule_at(&mut self, pc:Option<i32>) -> Option<usize> {
+        let starving = self.paths.iter().any(|p| p.fetch_pc != 0 && p.execution_mask.any()
+            && p.time_since_scheduled >= FORMATION_PATIENCE);
+        let formed = pc.filter(|_| self.ipdom.is_none() && self.retries == 0 && !starving)
+            .and_then(|pc| self.paths.iter().position(|p| p.fetch_pc == pc && p.execution_mask.any()));
+        if formed.is_none() {
+            return self.schedule_path()
+        }
+        self.current_path = formed;
+        self.schedule_invalidated = true;
+        self.age_paths(formed);
+        formed
+    }
 
-        //**TODO: scheduling priority timing window
-        //* avoir une boite "priorite" qui calcule la prio de scheduling des chemins
-        //* definir les bons criteres pour cette boite
-        //*
-        //* scheduler avec une "fenetre de temps" qu'on peuple et parcour en fct de cette
-        //* fonction de probabilite/priorite
-        //*/
+    // What scheduling a path changes in the warp, to take the choice back
+    // when the path cannot issue
+    fn save_schedule(&self) -> SavedSchedule<S> {
+        SavedSchedule {
+            scheduler:self.scheduler.clone(),
+            rng:self.rng.clone(),
+            current_path:self.current_path,
+            invalidated:self.schedule_invalidated,
+            ages:self.paths.iter().map(|p| p.time_since_scheduled).collect(),
+        }
+    }
 
-        S::schedule(self)
+    fn restore_schedule(&mut self, saved:SavedSchedule<S>) {
+        self.scheduler = saved.scheduler;
+        self.rng = saved.rng;
+        self.current_path = saved.current_path;
+        self.schedule_invalidated = saved.invalidated;
+        for (p, age) in self.paths.iter_mut().zip(saved.ages) {
+            p.time_since_scheduled = age;
+        }
+    }
+
+    fn age_paths(&mut self, path:Option<usize>) {
+        for (pid, p) in self.paths.iter_mut().enumerate() {
+            p.time_since_scheduled = if Some(pid) == path { 0 } else { p.time_since_scheduled + 1 };
+        }
+    }
+
+    // The path on top of the IPDOM stack, or a path below it when the ones
+    // above are blocked in a host call.
+    fn schedule_ipdom(&mut self) -> Option<usize> {
+        // reconvergence entries whose threads all exited
+        while self.paths.last().map_or(false, |p| !p.execution_mask.any()) {
+            self.paths.pop();
+        }
+        let ready : Vec<usize> = (0..self.paths.len()).rev()
+            .filter(|p| self.paths[*p].execution_mask.any())
+            .collect();
+        self.current_path = if ready.is_empty() {
+            None
+        } else {
+            Some(ready[self.retries % ready.len()])
+        };
+        self.current_path
     }
 
     pub fn cores(&self) -> impl Iterator<Item=(usize, &Core)> {
@@ -366,7 +505,7 @@ impl<S:SimtxScheduler> Warp<S> {
         self.paths[self.current_path.unwrap()].execution_mask.bits().ones().map(|id| id as usize)
     }
 
-    pub fn update_branch_hist(&mut self, pc:i32, mask:BitVec) {
+    pub fn update_branch_hist(&mut self, pc:i32, mask:BV) {
         if let Some(hist) = self.branch_mask_hist.get_mut(&pc) {
             hist.push(mask);
         } else {
@@ -376,19 +515,29 @@ impl<S:SimtxScheduler> Warp<S> {
 
     /// Fully executes an instruction (from Fetch to Commit)
     /// 
-    /// This operation is NOT cycle accurate. Will be later when needed.
+    /// This operation is NOT cycle accurate: the timing of the machine is
+    /// modeled apart, see `Machine::enable_timing`.
     pub fn execute(&mut self, mem:&mut dyn Memory) {
         if self.current_path.is_none() { return }
-        let pid = self.current_path.unwrap();
-        let mask : u32 = self.paths[pid].execution_mask;
-        let pc : i32 = self.paths[pid].fetch_pc;
-        let inst = Instruction(mem.get_32(pc as usize));
+        let pc : i32 = self.paths[self.current_path.unwrap()].fetch_pc;
+        let inst = Instruction(mem.fetch_32(pc as usize));
 
         let (inst, advance) = if inst.is_compressed() {
             (inst.uncompressed(), 2)
         } else {
             (inst, 4)
         };
+        self.execute_fetched(inst, advance, mem)
+    }
+
+    // Executes `inst` (uncompressed, `advance` bytes long), already fetched
+    // at the PC of the current path.
+    fn execute_fetched(&mut self, inst:Instruction, advance:i32, mem:&mut dyn Memory) {
+        if self.current_path.is_none() { return }
+        self.retries = 0;
+        let pid = self.current_path.unwrap();
+        let mask : BV = self.paths[pid].execution_mask;
+        let pc : i32 = self.paths[pid].fetch_pc;
 
         let next_pc = pc.wrapping_add(advance);
         let mut update_pc = true;
@@ -415,46 +564,34 @@ impl<S:SimtxScheduler> Warp<S> {
                 update_pc = false
             },
             OpCode::JALR => { // indirect jump can be divergent multiple times
-                let mut nph : HashMap<i32, BitVec> = HashMap::new();
+                let mut nph : HashMap<i32, BV> = HashMap::new();
 
                 // Compute new self.paths[pid]s based on the new thread PCs
                 for (i, core) in self.cores_mut() {
                     let new_pc = inst.get_imm_i().
-                        wrapping_add(core.registers[inst.get_rs1() as usize]);
+                        wrapping_add(core.registers[inst.get_rs1() as usize]) & !1;
                     if let Some(bv) = nph.get_mut(&new_pc) {
                         bv.set(i);
                     } else {
-                        nph.insert(new_pc, BitVec::singleton(i));
+                        nph.insert(new_pc, BV::singleton(i));
                     }
                     if inst.get_rd() != 0 {
                         core.set_ri(inst.get_rd() as usize, pc.wrapping_add(advance));
                     }
                 }
 
-                // Check if it's a uniform jump
-                // and just update the pc of the current self.paths[pid] if it is
-                if nph.len() == 1 {
-                    self.set_pc(pid, *nph.keys().next().unwrap());
-                } else {
-                    // If not, create as many self.paths[pid]s as needed and inject them
-                    let old_pc = self.paths[pid].fetch_pc;
-                    self.remove_path(pid);
-                    for (pc, mask) in nph {
-                        if old_pc == pc { self.current_path = Some(self.paths.len()) }
-                        self.push_path(Path::from_pc_mask(pc, mask));
-                    }
-                }
-
+                self.record_branch(pc, mask, nph.len() > 1);
+                self.jump(pid, pc, nph);
                 update_pc = false
             },
             OpCode::BRANCH => { // conditional branch
                 let  tpc = pc.wrapping_add(inst.get_imm_b());
                 let ntpc = pc.wrapping_add(advance);
 
+                // asked before the branch runs, and the paths change
+                let prediction = self.predict_divergence(pc, mask);
 
-                let pred_id = (pc & 0xffff) ^ ((pc >> 16) & 0xffff);
-
-                let mut taken_mask = 0;
+                let mut taken_mask = BV::empty();
 
                 // compute taken/not_taken masks for each alive thread
                 for (i, core) in self.cores_mut() {
@@ -482,17 +619,24 @@ impl<S:SimtxScheduler> Warp<S> {
                 }
 
                 let not_taken_mask = (!taken_mask) & self.paths[self.current_path.unwrap()].execution_mask;
+                self.record_branch(pc, mask, taken_mask.any() && not_taken_mask.any());
 
                 // update self.paths[pid], and add new paths if divergent
                 if !not_taken_mask.any() {    // uniform taken
                     self.set_pc(pid, tpc);
                 } else if !taken_mask.any() { // uniform not taken
                     self.set_pc(pid, ntpc);
+                } else if self.ipdom.is_some() {
+                    let targets = [Path::from_pc_mask(ntpc, not_taken_mask), Path::from_pc_mask(tpc, taken_mask)];
+                    self.diverge(pid, pc, targets.to_vec());
+                    S::on_diverge(self, pc, &targets);
                 } else {                      // divergent
 
                     self.remove_path(self.current_path.unwrap());
-                    self.push_path(Path::from_pc_mask(tpc, taken_mask));
-                    self.push_path(Path::from_pc_mask(ntpc, not_taken_mask));
+                    let targets = [Path::from_pc_mask(tpc, taken_mask), Path::from_pc_mask(ntpc, not_taken_mask)];
+                    self.push_path(targets[0]);
+                    self.push_path(targets[1]);
+                    S::on_diverge(self, pc, &targets);
                 }
 
                 let outcome = 
@@ -504,11 +648,17 @@ impl<S:SimtxScheduler> Warp<S> {
                             BranchOutcome::Divergent(taken_mask, not_taken_mask)
                         };
 
-                let prediction = self.div_pred[pred_id as usize].predict_and_update(mask, &outcome);
+                // a lone thread cannot diverge, its branches are not predicted
+                let predicted = mask.count() > 1;
+                if predicted {
+                    self.div_pred.update(pc, &outcome);
+                }
 
                 let dat = self.cond_branch_data.entry(pc).or_insert(CondBranchData::new());
                 dat.times_passed += 1;
-                dat.pred_data.update(prediction, &outcome);
+                if predicted {
+                    dat.pred_data.record(prediction, &outcome);
+                }
                 dat.taken_hist.push(outcome);
 
                 update_pc = false
@@ -522,19 +672,14 @@ impl<S:SimtxScheduler> Warp<S> {
 
                     let addr = (base.wrapping_add(imm) as usize) & 0xffffffff;
 
-                    #[cfg(debug_assertion)]
-                    println!("loading at address {:x}+{} = {:x}", base, imm, addr);
-                    let value = match width & 0b11 {
-                            0 => mem.get_8(addr) as i32,
-                            1 => mem.get_16(addr) as i32,
-                            2 => mem.get_32(addr) as i32,
+                    let value = match width {
+                            0 => mem.get_8(addr) as i8 as i32,   // LB
+                            1 => mem.get_16(addr) as i16 as i32, // LH
+                            2 => mem.get_32(addr) as i32,        // LW
+                            4 => mem.get_8(addr) as i32,         // LBU
+                            5 => mem.get_16(addr) as i32,        // LHU
                             _ => panic!("LOAD: bad word width {}", width), // ERROR
                         };
-
-                    #[cfg(debug_assertions)]
-                    /*if pc == 0x10ab8*/ {
-                        println!("{} : loaded {} at {:x}", inst, value, addr);
-                    }
                     core.set_ri(inst.get_rd() as usize, value);
                 }
             },
@@ -551,12 +696,6 @@ impl<S:SimtxScheduler> Warp<S> {
                         2 => mem.set_32(addr, src as u32),
                         _ => panic!("STORE: Bad word width"), // ERROR
                     };
-                    #[cfg(debug_assertions)]
-                    {
-                    if (pc & 0xfffff0) == 0x011110 {
-                        println!("STORE = {:x} AT {:x}", src, addr);
-                    }
-                    }
                 }
             },
             OpCode::OPIMM => {
@@ -598,37 +737,34 @@ impl<S:SimtxScheduler> Warp<S> {
                     let uv2_64 = uv2 as u64;
 
                     let allset = i32::all_set();
+                    let shamt = uv2 & 0x1f;
 
                     core.set_ri(dst, match inst.get_funct7() {
                         0b0000000 => match inst.get_funct3() {
-                            0b000 => { 
-                                let v=v1.wrapping_add(v2);
-                                #[cfg(debug_assertions)]
-                                println!("{:x}(x{})+{:x}(x{})={:x}(x{})", v1, rs1, v2, rs2, v, dst);
-                                v },
-                            0b001 => v1 << v2, // SLL
+                            0b000 => v1.wrapping_add(v2), // ADD
+                            0b001 => v1 << shamt, // SLL
                             0b010 => (v1 < v2) as i32, // SLT
                             0b011 => ((v1 as u32) < v2 as u32) as i32, // SLTU
                             0b100 => v1 ^ v2, // XOR
-                            0b101 => ((v1 as u32) >> v2) as i32, // SRL
+                            0b101 => (uv1 >> shamt) as i32, // SRL
                             0b110 => v1 | v2, // OR
                             0b111 => v1 & v2, // AND
                             _ => 0, // Cannot be here, because funct3 is on 3 bits
                         },
                         0b0000001 => match inst.get_funct3() {
-                            0b000 => v1 * v2,
+                            0b000 => v1.wrapping_mul(v2),
                             0b001 => ((v1_64 * v2_64) >> 32) as i32,
                             0b010 => ((v1_64 * (uv2_64 as i64)) >> 32) as i32,
                             0b011 => ((uv1_64 * uv2_64) >> 32) as i32,
-                            0b100 => if v2 == 0 { allset } else { v1 / v2 }, // DIV
+                            0b100 => if v2 == 0 { allset } else { v1.wrapping_div(v2) }, // DIV
                             0b101 => if v2 == 0 { allset } else { (uv1 / uv2) as i32 }, // DIVU
-                            0b110 => if v2 == 0 { v1 } else { v1 % v2 }, // REM
+                            0b110 => if v2 == 0 { v1 } else { v1.wrapping_rem(v2) }, // REM
                             0b111 => if v2 == 0 { v1 } else { (uv1 % uv2) as i32 }, // REMU
                             _ => 0,
                         },
                         0b0100000 => match inst.get_funct3() {
                             0b000 => v1.wrapping_sub(v2), // SUB
-                            0b101 => v1 >> v2, // SRA
+                            0b101 => v1 >> shamt, // SRA
                             _ => unreachable!("OPREG SUB OR SHIFT"),
                         }
                         _ => unreachable!("OPREG FUNCT7"),
@@ -643,14 +779,6 @@ impl<S:SimtxScheduler> Warp<S> {
                     let imm = inst.get_imm_i();
 
                     let addr = (base.wrapping_add(imm) as usize) & 0xffffffff;
-
-                    #[cfg(debug_assertions)]
-                    println!("{:x} : {:x}(x{}) + {:x} = {:x}",
-                        pc,
-                        base,
-                        rbase,
-                        imm,
-                        addr);
                     let value = match width {
                             0 | 1 => unreachable!("LOAD: float values are 32bits wide at least"),
                             2 => MachineF32::from_u64(mem.get_32(addr) as u64),
@@ -662,7 +790,7 @@ impl<S:SimtxScheduler> Warp<S> {
                             },
                             _ => unreachable!("LOAD @ 0x{:x}: illegal word width {}", pc, width),
                         };
-                    core.fregisters[inst.get_rd() as usize] = value;
+                    core.set_fr(inst.get_rd() as usize, value);
                 }
             },
             OpCode::FSW => {
@@ -831,6 +959,17 @@ impl<S:SimtxScheduler> Warp<S> {
                 }
             },
 
+            OpCode::FENCE => { }, // the threads share a single memory, in order
+            OpCode::SYSTEM => { // traps and `mret` may send the threads apart
+                let mut nph : HashMap<i32, BV> = HashMap::new();
+                for (i, core) in self.cores_mut() {
+                    nph.entry(core.system(inst, pc, next_pc)).or_insert_with(BV::empty).set(i);
+                }
+                if nph.len() > 1 || !nph.contains_key(&next_pc) {
+                    self.jump(pid, pc, nph);
+                    update_pc = false
+                }
+            },
             _ => unimplemented!(),
         }
 
@@ -845,12 +984,50 @@ impl<S:SimtxScheduler> Warp<S> {
         }
     }
 
+    // Sends the threads of path `pid`, at `pc`, to the targets of `nph`, the
+    // path diverging when there are several of them.
+    fn jump(&mut self, pid:usize, pc:i32, nph:HashMap<i32, BV>) {
+        // Check if it's a uniform jump
+        // and just update the pc of the current self.paths[pid] if it is
+        if nph.len() == 1 {
+            self.set_pc(pid, *nph.keys().next().unwrap());
+        } else if self.ipdom.is_some() {
+            let mut targets : Vec<(i32, BV)> = nph.into_iter().collect();
+            targets.sort_by_key(|(pc, _)| std::cmp::Reverse(*pc));
+            let targets : Vec<Path<BV>> = targets.into_iter()
+                .map(|(pc, mask)| Path::from_pc_mask(pc, mask))
+                .collect();
+            self.diverge(pid, pc, targets.clone());
+            S::on_diverge(self, pc, &targets);
+        } else {
+            // If not, create as many self.paths[pid]s as needed and inject them
+            let old_pc = self.paths[pid].fetch_pc;
+            self.remove_path(pid);
+            let targets : Vec<Path<BV>> = nph.into_iter()
+                .map(|(pc, mask)| Path::from_pc_mask(pc, mask))
+                .collect();
+            for target in &targets {
+                if old_pc == target.fetch_pc { self.current_path = Some(self.paths.len()) }
+                self.push_path(*target);
+            }
+            S::on_diverge(self, pc, &targets);
+        }
+    }
+
     fn advance_pc(&mut self, pid:usize, advance:i32) {
         let old_pc = self.paths[pid].fetch_pc;
         self.set_pc(pid, old_pc.wrapping_add(advance))
     }
 
     fn set_pc(&mut self, pid:usize, pc:i32) {
+        if self.ipdom.is_some() {
+            self.paths[pid].fetch_pc = pc;
+            if self.paths[pid].reconvergence_pc == Some(pc) {
+                self.reconverge(pid);
+            }
+            return
+        }
+
         let mask = self.paths[pid].execution_mask;
 
         // search for a path already at given pc. if found, update its mask
@@ -860,6 +1037,8 @@ impl<S:SimtxScheduler> Warp<S> {
             if p.fetch_pc == pc {
                 p.execution_mask |= mask;
                 self.remove_path(pid);
+                self.close_divergences();
+                S::on_merge(self, pc, mask);
                 return
             }
         }
@@ -868,12 +1047,17 @@ impl<S:SimtxScheduler> Warp<S> {
         self.paths[pid].fetch_pc = pc;
     }
 
-    fn push_path(&mut self, path:Path) {
+    fn push_path(&mut self, path:Path<BV>) {
         // if we already have a path at path.fetch_pc, merge the given mask with
-        // the current mask
+        // the current mask (IPDOM stack entries must reconverge at the same
+        // place too)
+        let ipdom = self.ipdom.is_some();
         for p in &mut self.paths {
-            if p.fetch_pc == path.fetch_pc {
+            if p.fetch_pc == path.fetch_pc
+                && (!ipdom || p.reconvergence_pc == path.reconvergence_pc) {
                 p.execution_mask |= path.execution_mask;
+                self.close_divergences();
+                S::on_merge(self, path.fetch_pc, path.execution_mask);
                 return
             }
         }
@@ -881,6 +1065,49 @@ impl<S:SimtxScheduler> Warp<S> {
         self.paths.push(path)
     }
 
+    // Replaces path `pid`, which diverged at `pc`, by a reconvergence entry
+    // at the immediate post-dominator of `pc` with `targets` on top of it.
+    // Without a post-dominator, the targets reconverge where the path would.
+    fn diverge(&mut self, pid:usize, pc:i32, targets:Vec<Path<BV>>) {
+        let parent = self.paths[pid];
+        let ipdom = self.ipdom.as_ref().unwrap().get(pc);
+        let mut entries = Vec::new();
+        if let Some(ipdom) = ipdom {
+            entries.push(Path { reconvergence_pc:parent.reconvergence_pc
+                              , ..Path::from_pc_mask(ipdom, BV::empty()) });
+        }
+        let reconvergence_pc = ipdom.or(parent.reconvergence_pc);
+        entries.extend(targets.into_iter().map(|t| Path { reconvergence_pc, ..t }));
+
+        let count = entries.len();
+        self.paths.splice(pid..pid + 1, entries);
+        self.invalidate();
+        self.current_path = None;
+
+        // targets which are already at their reconvergence point
+        for p in (pid..pid + count).rev() {
+            if self.paths[p].reconvergence_pc == Some(self.paths[p].fetch_pc) {
+                self.reconverge(p);
+            }
+        }
+    }
+
+    // Gives the threads of path `pid`, at its reconvergence point, back to
+    // the entry waiting there below it in the stack.
+    fn reconverge(&mut self, pid:usize) {
+        let pc = self.paths[pid].fetch_pc;
+        match (0..pid).rev().find(|p| self.paths[*p].fetch_pc == pc) {
+            Some(entry) => {
+                let mask = self.paths[pid].execution_mask;
+                self.paths[entry].execution_mask |= mask;
+                self.remove_path(pid);
+                self.close_divergences();
+                S::on_merge(self, pc, mask);
+            },
+            None => self.paths[pid].reconvergence_pc = None,
+        }
+    }
+
     fn remove_path(&mut self, pid:usize) {
         self.invalidate();
         self.paths.remove(pid);
@@ -891,25 +1118,56 @@ impl<S:SimtxScheduler> Warp<S> {
 
     fn clean_idles(&mut self, offset:usize) -> Vec<usize> {
         self.current_path = None;
-        let mut inv = false;
-        let ret = self.paths.drain_filter(|p| {
-            let b = p.fetch_pc == 0;
-            if b { inv = true }
-            b
-        })
-        .flat_map(|p| p.execution_mask.bits().ones())
-        .map(|cid| cid as usize + offset)
-        .collect();
-        self.schedule_invalidated |= inv;
-        ret
+        let exited : Vec<Path<BV>> = self.paths.drain_filter(|p| p.fetch_pc == 0).collect();
+        self.schedule_invalidated |= !exited.is_empty();
+        if !exited.is_empty() {
+            self.close_divergences();
+        }
+        for p in &exited {
+            S::on_path_exit(self, p.execution_mask);
+        }
+        exited.iter()
+            .flat_map(|p| p.execution_mask.bits().ones())
+            .map(|cid| cid as usize + offset)
+            .collect()
     }
 
     pub fn invalidate(&mut self) {
         self.schedule_invalidated = true
     }
+
+    // Accounts for the branch at `pc` run by the threads of `mask`
+    fn record_branch(&mut self, pc:i32, mask:BV, divergent:bool) {
+        let stats = self.branch_stats.entry(pc).or_default();
+        stats.executions += 1;
+        if divergent {
+            stats.divergences += 1;
+            self.divergences.push((pc, self.cycle, mask));
+        }
+    }
+
+    // Closes the divergences whose threads still running are in the same
+    // path again
+    fn close_divergences(&mut self) {
+        let alive = self.paths.iter()
+            .filter(|p| p.fetch_pc != 0)
+            .fold(BV::empty(), |alive, p| alive | p.execution_mask);
+        let open = std::mem::take(&mut self.divergences);
+        for (pc, start, mask) in open {
+            let mask = mask & alive;
+            if mask.none() { continue }
+            if self.paths.iter().any(|p| p.fetch_pc != 0 && (p.execution_mask & mask) == mask) {
+                let stats = self.branch_stats.entry(pc).or_default();
+                stats.reconvergences += 1;
+                stats.reconvergence_cycles += self.cycle - start;
+            } else {
+                self.divergences.push((pc, start, mask));
+            }
+        }
+    }
 }
 
-impl<S:SimtxScheduler> fmt::Display for Warp<S> {
+impl<S:SimtxScheduler, BV:BitSet> fmt::Display for Warp<S, BV> {
     fn fmt(&self, f:&mut fmt::Formatter<'_>) -> fmt::Result {
         let prelude = write!(f, "Warp {}c, \n", self.cores.len());
         self.paths.iter().fold(prelude, |res, p| {
@@ -929,10 +1187,12 @@ struct Barrier {
     current_cap:i32,
 }
 
+/// How often the backward branch of a loop was issued, and by how many
+/// threads in total.
 #[derive(Debug)]
-struct LoopData {
-    times_passed:usize,
-    num_threads_passed:usize,
+pub struct LoopData {
+    pub times_passed:usize,
+    pub num_threads_passed:usize,
 }
 
 impl LoopData {
@@ -941,19 +1201,14 @@ impl LoopData {
     }
 }
 
-static mut LOOPS : Option<HashMap<i32, i32>> = None;
-pub fn loops() -> &'static mut HashMap<i32, i32> {
-    if unsafe { LOOPS.is_none() } {
-       unsafe { LOOPS = Some(HashMap::new()) }
-    }
-    unsafe { LOOPS.as_mut().unwrap() }
-}
-
 /// The SIMT-X machine. To handle `pthread` or `omp` system calls, we detect them
 /// using plt information, and emulate them.
-pub struct Machine<S:SimtxScheduler> {
+///
+/// The execution masks are `u32` by default, which limits warps to 32
+/// threads: use `u64` or `u128` as `BV` for wider warps.
+pub struct Machine<S:SimtxScheduler, BV:BitSet=u32> {
     // Our cores
-    warps:Vec<Warp<S>>,
+    warps:Vec<Warp<S, BV>>,
 
     // For dynamic library calls emulation
     plt_addresses:HashMap<i32, String>,
@@ -966,11 +1221,12 @@ pub struct Machine<S:SimtxScheduler> {
     barriers:HashMap<i32, Barrier>,
 
     // For malloc
-    heap_ptr:usize,
-    heap_elements:BTreeMap<usize, (usize, bool)>,
+    heap:Heap,
 
     // For loop detection
     detected_loops: HashMap<i32, i32>,
+    program: Arc<ProgramInfo>,
+    cycle: u64,
 
     // For execution analysis
     loop_data: HashMap<i32, LoopData>,
@@ -979,20 +1235,67 @@ pub struct Machine<S:SimtxScheduler> {
     stack_start: usize,
     stack_size: usize,
 
-    // For files and IO purposes
-    file_handles: BTreeMap<i32, File>,
-    next_fid: i32,
+    // For files and IO purposes, shared by all the warps
+    files: FileTable,
+    // Address of the errno of each thread which asked for it
+    errno_locations: HashMap<usize, usize>,
+    // Rest of the string being split by strtok
+    strtok_next: usize,
+
+    // For human readable PCs in stats and traces
+    symbolizer: Symbolizer,
+
+    // Emulated library functions, by symbol name
+    host_calls: HostCalls<S, BV>,
+
+    // Set when the guest program did something we cannot simulate
+    error: Option<GuestError>,
+
+    // Checks every memory access when enabled
+    memcheck: Option<MemChecker>,
+
+    // Cycle-level timing model, when enabled
+    timing: Option<Timing>,
+
+    // Classifies the memory accesses of the warps, when enabled
+    coalescing: Option<CoalescingAnalyzer>,
+
+    // Issues the paths of different warps at the same PC together, when
+    // enabled
+    formation: Option<WarpFormation>,
+
+    // Paths of all the warps every `stats_period` cycles
+    path_samples: Vec<(u64, usize)>,
+    stats_period: u64,
+
+    // Writes the instructions committed by the threads, when enabled
+    tracer: Option<Tracer>,
+
+    // The host side of `tohost` and `fromhost`, when enabled
+    htif: Option<Htif>,
 }
 
-impl<S:SimtxScheduler> Machine<S> {
-    pub fn new(tpw:usize, nb_warps:usize, plt_addresses:HashMap<i32, String>) -> Machine<S> {
+impl<S:SimtxScheduler, BV:BitSet> Machine<S, BV> {
+    /// The largest number of threads per warp of this machine.
+    pub fn max_tpw() -> usize {
+        BV::SIZE as usize
+    }
+
+    pub fn new(tpw:usize, nb_warps:usize, plt_addresses:HashMap<i32, String>) -> Machine<S, BV> {
 
-        if tpw > MAX_TPW {
-            panic!("This version of SIMTX is compiled with MAX_TPW={}", MAX_TPW)
+        if tpw > Self::max_tpw() {
+            panic!("This machine uses {} bits execution masks, it cannot have {} threads per warp", BV::SIZE, tpw)
         }
 
         let mut warps = Vec::new();
         warps.resize(nb_warps, Warp::new(tpw));
+        // as seeded by `seed(0)`
+        for (wid, warp) in warps.iter_mut().enumerate() {
+            warp.rng = Rng::new(wid as u64);
+            for (cid, core) in warp.cores.iter_mut().enumerate() {
+                core.csrs.mhartid = (wid * tpw + cid) as i32;
+            }
+        }
 
         let mut idle_threads = Vec::new();
         for i in (0..tpw*nb_warps).rev() {
@@ -1004,54 +1307,501 @@ impl<S:SimtxScheduler> Machine<S> {
 
         Machine {
             detected_loops: HashMap::new(),
+            program: Arc::new(ProgramInfo::default()),
+            cycle: 0,
             warps,
             plt_addresses,
             idle_threads,
             barriers:HashMap::new(),
             in_barrier,
-            heap_ptr:0x10000000,
-            heap_elements:BTreeMap::new(),
+            // 256 Mio heap, right above the stacks
+            heap:Heap::new(0x20000000, 0x10000000),
             loop_data:HashMap::new(),
 
-            // default stack size and place
-            // it is 128 * 2Kio bytes stacks starting at 0x10000000
+            // default stack size and place: 2 Mio stacks, growing down
+            // from 0x20000000 one after the other
             stack_start: 0x20000000,
             stack_size: 0x00200000,
 
-            file_handles: BTreeMap::new(),
-            next_fid: 3,
+            files: FileTable::new(),
+            errno_locations: HashMap::new(),
+            strtok_next: 0,
+
+            symbolizer: Symbolizer::new(),
+
+            host_calls: syscalls::builtins(),
+            error: None,
+
+            memcheck: None,
+            timing: None,
+            coalescing: None,
+            formation: None,
+            path_samples: Vec::new(),
+            stats_period: 1024,
+            tracer: None,
+            htif: None,
         }
     }
 
+    /// Confines the files opened by the simulated program to `dir`.
+    pub fn set_sandbox<P:AsRef<std::path::Path>>(&mut self, dir:P) {
+        self.files.set_sandbox(dir)
+    }
+
+    /// The file descriptor table of the simulated program.
+    pub fn files(&mut self) -> &mut FileTable {
+        &mut self.files
+    }
+
+    /// Registers (or replaces) the handler called when the simulated program
+    /// calls the imported function `name`.
+    pub fn register_host_call(&mut self, name:&str, handler:HostCall<S, BV>) {
+        self.host_calls.register(name, handler)
+    }
+
+    /// Lists the imported functions of the program which have no host call
+    /// handler. Calling one of them stops the machine with an error.
+    pub fn unresolved_host_calls(&self) -> Vec<&str> {
+        let mut ret : Vec<&str> = self.plt_addresses.values()
+            .filter(|name| !self.host_calls.contains(name))
+            .map(|name| name.as_str())
+            .collect();
+        ret.sort();
+        ret
+    }
+
+    /// Gives access to the arguments and return value of `lane` during the
+    /// host call `call`.
+    pub fn lane(&mut self, call:&HostCallContext, lane:usize) -> LaneArgs<'_> {
+        LaneArgs::new(&mut self.warps[call.warp].cores[lane])
+    }
+
+    /// Returns the error which stopped the machine, if any.
+    pub fn error(&self) -> Option<&GuestError> {
+        self.error.as_ref()
+    }
+
+    /// Sets the symbolizer used to print PCs as `function+offset (file:line)`
+    /// in every stats and trace output of the machine.
+    pub fn set_symbolizer(&mut self, symbolizer:Symbolizer) {
+        self.symbolizer = symbolizer;
+    }
+
+    pub fn symbolizer(&self) -> &Symbolizer {
+        &self.symbolizer
+    }
+
     pub fn place_stack(&mut self, text_end:usize, stack_size:usize) {
         self.stack_start =
             text_end +
             self.warps.len() * self.warps[0].cores.len() * stack_size;
         self.stack_size = stack_size;
+        assert!(self.stack_start <= self.heap.start(), "the stacks of the threads run into the heap at {:x}", self.heap.start());
     }
 
     fn malloc(&mut self, mem:&mut dyn Memory, size:usize) -> usize {
-        mem.allocate_at(self.heap_ptr, size);
+        self.aligned_alloc(mem, size, MIN_ALIGN)
+    }
+
+    // Returns 0 when the heap is full, like malloc
+    fn aligned_alloc(&mut self, mem:&mut dyn Memory, size:usize, align:usize) -> usize {
+        match self.heap.alloc(size, align) {
+            Some(ptr) => {
+                // the padding is mapped too, so that overflows into it can be
+                // reported by the memory checker
+                mem.allocate_at(ptr, Heap::chunk_size(size));
+                ptr
+            },
+            None => 0,
+        }
+    }
+
+    fn heap_error(&mut self, e:HeapError, pc:i32) {
+        self.error = Some(match e {
+            HeapError::DoubleFree(ptr) => GuestError::DoubleFree { ptr, pc },
+            HeapError::InvalidFree(ptr) => GuestError::InvalidFree { ptr, pc },
+        });
+    }
+
+    /// Enables the memory checker. It must be enabled before the program
+    /// allocates anything.
+    pub fn enable_memcheck(&mut self) {
+        self.memcheck = Some(MemChecker::new());
+    }
 
-        for (ptr, (chunk_size, used)) in &mut self.heap_elements {
-            if !(*used) && *chunk_size < size {
-                *used = true;
-                return *ptr
+    /// The errors found by the memory checker, if enabled.
+    pub fn memcheck_errors(&self) -> &[MemError] {
+        self.memcheck.as_ref().map_or(&[], |c| c.errors())
+    }
+
+    pub fn print_memcheck_report(&self) {
+        let errors = self.memcheck_errors();
+        println!("=== MEMCHECK: {} ERRORS ===", errors.len());
+        for e in errors {
+            println!("{} of {} bytes at 0x{:08x} by thread {} (lane {}) at {} ({} times)"
+                     , e.kind, e.size, e.addr, e.tid, e.lane, self.symbolizer.describe(e.pc as usize), e.count);
+            if let Some(block) = e.block {
+                println!("\tblock 0x{:08x} of {} bytes allocated by thread {} at {}"
+                         , block.start, block.size, block.allocated.tid
+                         , self.symbolizer.describe(block.allocated.pc as usize));
+                if let Some(freed) = block.freed {
+                    println!("\tfreed by thread {} at {}", freed.tid, self.symbolizer.describe(freed.pc as usize));
+                }
             }
         }
+    }
 
-        self.heap_elements.insert(self.heap_ptr, (size, true));
+    fn track_alloc(&mut self, ptr:usize, size:usize, pc:i32, tid:usize) {
+        if let Some(c) = self.memcheck.as_mut() {
+            c.on_alloc(ptr, size, Site { pc, tid }, false);
+        }
+    }
 
-        let ret = self.heap_ptr;
-        self.heap_ptr += size;
+    fn track_free(&mut self, ptr:usize, pc:i32, tid:usize) {
+        if let Some(c) = self.memcheck.as_mut() {
+            c.on_free(ptr, Site { pc, tid });
+        }
+    }
 
-        println!("mallo {} returns 0x{:08x}", size, ret);
-        ret
+    fn track_resize(&mut self, ptr:usize, size:usize) {
+        if let Some(c) = self.memcheck.as_mut() {
+            c.on_resize(ptr, size);
+        }
+    }
+
+    // The (lane, address, size, write) of the accesses of instruction `i`,
+    // about to be executed by the current path of warp `wid`.
+    fn memory_accesses(&self, wid:usize, i:&Instruction) -> Vec<(usize, usize, usize, bool)> {
+        let (base, offset, size, write) = match i.get_opcode_enum() {
+            OpCode::LOAD => (i.get_rs1(), i.get_imm_i(), 1 << (i.get_funct3() & 0b11), false),
+            OpCode::FLW => (i.get_rs1(), i.get_imm_i(), 1 << i.get_funct3(), false),
+            OpCode::STORE => (i.get_rs1(), i.get_imm_s(), 1 << i.get_funct3(), true),
+            OpCode::FSW => (i.get_rs1(), i.get_imm_s(), 1 << i.get_funct3(), true),
+            _ => return Vec::new(),
+        };
+        let warp = &self.warps[wid];
+        warp.alive_cores_ids()
+            .map(|lane| {
+                let addr = warp.cores[lane].registers[base as usize].wrapping_add(offset);
+                (lane, addr as u32 as usize, size, write)
+            })
+            .collect()
+    }
+
+    // Checks the stack pointers and the memory accesses of instruction `i`,
+    // about to be executed by the current path of warp `wid`.
+    fn check_memory(&mut self, wid:usize, pc:i32, i:&Instruction) {
+        let tpw = self.warps[0].cores.len();
+        let (stack_start, stack_size) = (self.stack_start, self.stack_size);
+        let (heap_start, heap_top) = (self.heap.start(), self.heap.top());
+        let accesses = self.memory_accesses(wid, i);
+        let checker = self.memcheck.as_mut().unwrap();
+        checker.set_heap(heap_start, heap_top);
+
+        let warp = &self.warps[wid];
+        for lane in warp.alive_cores_ids() {
+            let tid = wid * tpw + lane;
+            let bottom = stack_start.wrapping_sub((tid + 1) * stack_size);
+            checker.check_stack(pc, tid, lane, warp.cores[lane].registers[2] as u32 as usize, bottom);
+        }
+        for (lane, addr, size, write) in accesses {
+            let tid = wid * tpw + lane;
+            checker.check_access(Access { pc, tid, lane, addr, size, write });
+        }
+    }
+
+    /// Reconverges divergent paths at the immediate post-dominator of their
+    /// branch, found in `points`, with a stack of paths per warp (see
+    /// `ipdom`). The scheduler is then not used.
+    pub fn enable_ipdom(&mut self, points:ReconvergencePoints) {
+        let points = Arc::new(points);
+        for warp in &mut self.warps {
+            warp.ipdom = Some(points.clone());
+        }
+    }
+
+    /// Gives the schedulers the basic blocks, backward branches and loops of
+    /// `cfg`, instead of waiting for the branches to be taken.
+    pub fn load_program(&mut self, cfg:&Cfg) {
+        self.program = Arc::new(ProgramInfo::from_cfg(cfg));
+        for warp in &mut self.warps {
+            warp.program = self.program.clone();
+        }
+    }
+
+    pub fn program(&self) -> &ProgramInfo {
+        &self.program
+    }
+
+    /// The number of steps of the machine so far.
+    pub fn cycle(&self) -> u64 {
+        self.cycle
     }
 
-    fn free(&mut self, ptr:usize) {
-        self.heap_elements.get_mut(&ptr)
-            .expect("Free unalocated pointer").1 = false;
+    /// Seeds the random numbers of the schedulers, each warp drawing its own
+    /// sequence. Runs with the same seed make the same choices.
+    pub fn seed(&mut self, seed:u64) {
+        for (wid, warp) in self.warps.iter_mut().enumerate() {
+            warp.rng = Rng::new(seed.wrapping_add(wid as u64));
+        }
+    }
+
+    /// Sets the parameter `name` of the scheduler of every warp.
+    pub fn set_scheduler_param(&mut self, name:&str, value:&str) -> Result<(), String> {
+        for warp in &mut self.warps {
+            warp.scheduler.set_param(name, value)?;
+        }
+        Ok(())
+    }
+
+    /// The warp `wid`, with the state of its scheduler.
+    pub fn warp(&self, wid:usize) -> &Warp<S, BV> {
+        &self.warps[wid]
+    }
+
+    /// Enables the cycle-level timing model: from now on, every `step` is a
+    /// cycle, during which a warp may not issue anything.
+    pub fn enable_timing(&mut self, config:TimingConfig) {
+        self.timing = Some(Timing::new(config, self.warps.len()));
+    }
+
+    /// The timing model, if enabled.
+    pub fn timing(&self) -> Option<&Timing> {
+        self.timing.as_ref()
+    }
+
+    pub fn print_timing_report(&self) {
+        if let Some(timing) = &self.timing {
+            println!("=== TIMING: {} CYCLES ===", timing.cycle());
+            for wid in 0..self.warps.len() {
+                println!("warp {}: {}", wid, timing.warp(wid));
+            }
+            println!("total: {}", timing.total());
+        }
+    }
+
+    /// Enables the analysis of the memory accesses of the warps, counting
+    /// transactions of `line_size` bytes.
+    pub fn enable_coalescing(&mut self, line_size:usize) {
+        self.coalescing = Some(CoalescingAnalyzer::new(line_size));
+    }
+
+    /// The coalescing analysis, if enabled.
+    pub fn coalescing(&self) -> Option<&CoalescingAnalyzer> {
+        self.coalescing.as_ref()
+    }
+
+    pub fn print_coalescing_report(&self) {
+        if let Some(analyzer) = &self.coalescing {
+            let total = analyzer.total();
+            println!("=== COALESCING: {} MEMORY INSTRUCTIONS, {:.1}% EFFICIENCY ({}-BYTE LINES) ==="
+                     , total.executions, total.efficiency() * 100., analyzer.line_size());
+            for (pc, stats) in analyzer.per_pc() {
+                println!("{}: {}", self.symbolizer.describe(*pc as usize), stats);
+            }
+        }
+    }
+
+    /// Forms the warps dynamically, or compacts the thread blocks, as given
+    /// by `config` (see the `formation` module). A warp still runs the path
+    /// of its scheduler after a blocked host call, or when one of its paths
+    /// waited too long for the majority PC.
+    pub fn enable_warp_formation(&mut self, config:FormationConfig) {
+        self.formation = Some(WarpFormation::new(config, self.warps[0].cores.len()));
+    }
+
+    /// The warp formation, if enabled.
+    pub fn warp_formation(&self) -> Option<&WarpFormation> {
+        self.formation.as_ref()
+    }
+
+    pub fn print_formation_report(&self) {
+        if let Some(formation) = &self.formation {
+            let stats = formation.stats();
+            println!("=== WARP FORMATION: {} ===", formation.config());
+            println!("{} thread instructions in {} warp instructions, {} once formed ({} merged)"
+                     , stats.thread_instructions, stats.warp_instructions
+                     , stats.formed_instructions, stats.merged);
+            println!("SIMD efficiency: {:.1}% -> {:.1}%"
+                     , stats.simd_efficiency(formation.tpw()) * 100.
+                     , stats.formed_simd_efficiency(formation.tpw()) * 100.);
+        }
+    }
+
+    /// Writes the instructions committed from now on to `tracer`: a record
+    /// per thread of the issued paths, the hart of a record being its thread
+    /// id.
+    pub fn enable_trace(&mut self, tracer:Tracer) {
+        self.tracer = Some(tracer);
+    }
+
+    pub fn tracer(&self) -> Option<&Tracer> {
+        self.tracer.as_ref()
+    }
+
+    pub fn tracer_mut(&mut self) -> Option<&mut Tracer> {
+        self.tracer.as_mut()
+    }
+
+    /// Stops tracing, and flushes the trace.
+    pub fn finish_trace(&mut self) -> io::Result<()> {
+        self.tracer.take().map_or(Ok(()), |tracer| tracer.finish())
+    }
+
+    /// Runs the host side of `htif` after each step, the machine finishing
+    /// when the program exits through it.
+    pub fn enable_htif(&mut self, htif:Htif) {
+        self.htif = Some(htif);
+    }
+
+    pub fn htif(&self) -> Option<&Htif> {
+        self.htif.as_ref()
+    }
+
+    /// The code the program exited with through HTIF, once it did.
+    pub fn exit_code(&self) -> Option<u32> {
+        self.htif.as_ref().and_then(|htif| htif.exit_code())
+    }
+
+    // Logs the registers written by the threads of `mask` in warp `wid` if
+    // its instruction at `pc` is traced, and tells whether it is
+    fn start_trace(&mut self, wid:usize, pc:i32, mask:BV) -> bool {
+        if !self.tracer.as_ref().map_or(false, |t| t.wants(pc, wid)) {
+            return false
+        }
+        for lane in mask.bits().ones() {
+            self.warps[wid].cores[lane as usize].writes = Some(Vec::new());
+        }
+        true
+    }
+
+    // The value each lane of the current path of warp `wid` stores with
+    // instruction `i`, read from its source register before `i` executes
+    fn stored_values(&self, wid:usize, i:&Instruction) -> Vec<(usize, u64)> {
+        let src = i.get_rs2() as usize;
+        let warp = &self.warps[wid];
+        warp.alive_cores_ids()
+            .filter_map(|lane| {
+                let core = &warp.cores[lane];
+                let value = match (i.get_opcode_enum(), i.get_funct3()) {
+                    (OpCode::STORE, 0) => core.registers[src] as u8 as u64,
+                    (OpCode::STORE, 1) => core.registers[src] as u16 as u64,
+                    (OpCode::STORE, _) => core.registers[src] as u32 as u64,
+                    // `fsd` stores the high word first
+                    (OpCode::FSW, 3) => core.fregisters[src].bits().rotate_left(32),
+                    (OpCode::FSW, _) => core.fregisters[src].bits() as u32 as u64,
+                    _ => return None,
+                };
+                Some((lane, value))
+            })
+            .collect()
+    }
+
+    // Writes the records of instruction `inst` at `pc`, just issued by the
+    // threads of `mask` in warp `wid` with the memory `accesses`, storing the
+    // `stored` values
+    fn trace_issue(&mut self, wid:usize, pc:i32, inst:u32, mask:BV
+                   , accesses:&[(usize, usize, usize, bool)], stored:&[(usize, u64)]) {
+        let tpw = self.warps[wid].cores.len();
+        let lanes = mask.bits().enumerate()
+            .filter(|(_, active)| *active)
+            .fold(0u128, |bits, (lane, _)| bits | 1 << lane);
+        for lane in mask.bits().ones() {
+            let lane = lane as usize;
+            let core = &mut self.warps[wid].cores[lane];
+            let mut record = TraceRecord::new(wid * tpw + lane, pc, inst);
+            record.warp = Some((wid, lanes));
+            for (fp, reg) in core.writes.take().unwrap_or_default() {
+                if fp {
+                    record.writes.push(RegWrite::f(reg, core.fregisters[reg].bits()));
+                } else if reg != 0 {
+                    record.writes.push(RegWrite::x(reg, core.registers[reg]));
+                }
+            }
+            for &(_, addr, size, write) in accesses.iter().filter(|a| a.0 == lane) {
+                record.accesses.push(if write {
+                    let value = stored.iter().find(|s| s.0 == lane).map_or(0, |s| s.1);
+                    MemAccess::store(addr, size, value)
+                } else {
+                    MemAccess::load(addr, size)
+                });
+            }
+            self.tracer.as_mut().unwrap().record(&record);
+        }
+    }
+
+    // Accounts for the paths of the warps and the threads in barriers this
+    // cycle
+    fn sample_paths(&mut self) {
+        let tpw = self.warps[0].cores.len();
+        let mut total = 0;
+        for (wid, warp) in self.warps.iter_mut().enumerate() {
+            let paths = warp.paths.iter().filter(|p| p.fetch_pc != 0 && p.execution_mask.any()).count();
+            warp.stats.path_cycles += paths as u64;
+            warp.stats.max_paths = warp.stats.max_paths.max(paths);
+            let waiting = self.in_barrier[wid * tpw..(wid + 1) * tpw].iter().filter(|b| **b != 0).count();
+            warp.stats.barrier_wait += waiting as u64;
+            total += paths;
+        }
+        if self.cycle % self.stats_period == 0 {
+            self.path_samples.push((self.cycle, total));
+        }
+    }
+
+    // The PC each warp runs this cycle when the warps are formed: the one of
+    // the most threads of its group
+    fn formation_pcs(&self) -> Vec<Option<i32>> {
+        let config = match &self.formation {
+            Some(formation) => *formation.config(),
+            None => return vec![None; self.warps.len()],
+        };
+        let groups = config.group_of(self.warps.len() - 1) + 1;
+        let majorities : Vec<Option<i32>> = (0..groups).map(|g| {
+            formation::majority_pc((0..self.warps.len())
+                .filter(|wid| config.group_of(*wid) == g)
+                .flat_map(|wid| self.warps[wid].paths.iter().map(|p| (p.fetch_pc, p.execution_mask))))
+        }).collect();
+        (0..self.warps.len()).map(|wid| majorities[config.group_of(wid)]).collect()
+    }
+
+    fn record_accesses(&mut self, wid:usize, pc:i32, i:&Instruction) {
+        let accesses = self.memory_accesses(wid, i);
+        if let Some(&(_, _, size, write)) = accesses.first() {
+            let addresses : Vec<usize> = accesses.iter().map(|(_, addr, _, _)| *addr).collect();
+            self.coalescing.as_mut().unwrap().record(pc, write, size, &addresses);
+        }
+    }
+
+    // Returns whether warp `wid` issues `i` this cycle, and accounts for it
+    fn time_issue(&mut self, wid:usize, pathid:usize, pc:i32, i:&Instruction) -> bool {
+        let tpw = self.warps[wid].cores.len();
+        let mask = self.warps[wid].paths[pathid].execution_mask;
+        let lanes : Vec<bool> = (0..tpw).map(|lane| mask.at(lane)).collect();
+        if !self.timing.as_mut().unwrap().can_issue(wid, pc, &lanes, i) {
+            return false
+        }
+        let accesses = self.memory_accesses(wid, i);
+        let addresses : Vec<usize> = accesses.iter().map(|(_, addr, _, _)| *addr).collect();
+        let size = accesses.first().map_or(0, |(_, _, size, _)| *size);
+        let timing = self.timing.as_mut().unwrap();
+        let transactions = coalescing::transactions(&addresses, size, timing.config().line_size.max(1));
+        timing.issue(wid, i, mask.count(), transactions);
+        true
+    }
+
+    /// Usage statistics of the heap of the simulated program.
+    pub fn heap_stats(&self) -> &HeapStats {
+        self.heap.stats()
+    }
+
+    pub fn print_heap_stats(&self) {
+        let stats = self.heap.stats();
+        println!("=== HEAP STATS ===");
+        println!("allocations: {}, frees: {}, reallocs: {}, failures: {}"
+                 , stats.allocations, stats.frees, stats.reallocs, stats.failures);
+        println!("in use: {} bytes in {} chunks (peak: {} bytes)"
+                 , stats.in_use, stats.live_chunks, stats.peak_in_use);
+        println!("heap size: {} bytes", stats.heap_size);
     }
 
     pub fn pop_first_idle(&mut self) -> usize {
@@ -1073,7 +1823,7 @@ impl<S:SimtxScheduler> Machine<S> {
     /// nothing.
     pub fn print_stats_for_pc(&self, pc:usize) {
         for wid in 0..self.warps.len() {
-            println!("=== STATS FOR WARP {} AT PC {} ===", wid, pc);
+            println!("=== STATS FOR WARP {} AT PC {} ===", wid, self.symbolizer.describe(pc));
             let warp = &self.warps[wid];
             warp.branch_mask_hist.get(&(pc as i32)).map( |hist| {
                 for bv in hist {
@@ -1121,17 +1871,55 @@ impl<S:SimtxScheduler> Machine<S> {
 //        }
         println!("detected loops:");
         for (end, start) in &self.detected_loops {
-            println!("{:08x} -> {:08x}", start, end);
-
+            println!("{} -> {}",
+                self.symbolizer.describe(*start as usize),
+                self.symbolizer.describe(*end as usize));
         }
     }
 
     pub fn print_relevant_pcs(&self) {
         println!("=== DETECTED LOOPS ===");
         for (end, start) in &self.detected_loops {
-            println!("{:08x} -> {:08x}", start, end);
+            println!("{} -> {}",
+                self.symbolizer.describe(*start as usize),
+                self.symbolizer.describe(*end as usize));
+        }
+    }
+
+    /// Samples the number of paths of the warps every `period` cycles (1024
+    /// by default) for `simt_stats`.
+    pub fn set_stats_period(&mut self, period:u64) {
+        self.stats_period = period.max(1);
+    }
 
+    /// The SIMD efficiency of the warps, the divergences of the branches and
+    /// the paths over time, so far.
+    pub fn simt_stats(&self) -> SimtStats {
+        let mut stats = SimtStats {
+            tpw: self.warps[0].cores.len(),
+            cycles: self.cycle,
+            warps: self.warps.iter().map(|w| w.stats).collect(),
+            path_samples: self.path_samples.clone(),
+            ..SimtStats::default()
+        };
+        for w in &self.warps {
+            for (pc, b) in &w.branch_stats {
+                stats.branches.entry(*pc).or_insert(BranchStats::default()).add(b);
+            }
         }
+        for pc in stats.branches.keys() {
+            stats.locations.insert(*pc, self.symbolizer.describe(*pc as usize));
+        }
+        stats
+    }
+
+    pub fn print_simt_stats(&self) {
+        print!("{}", self.simt_stats());
+    }
+
+    /// The issues of the backward branches so far, by address.
+    pub fn loop_data(&self) -> &HashMap<i32, LoopData> {
+        &self.loop_data
     }
 
     pub fn print_branch_stats(&self) {
@@ -1139,21 +1927,48 @@ impl<S:SimtxScheduler> Machine<S> {
         for (pc, stats) in &self.loop_data {
             let ratio = (stats.num_threads_passed as f32)
                 / (stats.times_passed as f32);
-            println!("{:08x}: {:?} (ratio: {})", pc, stats, ratio);
+            println!("{}: {:?} (ratio: {})", self.symbolizer.describe(*pc as usize), stats, ratio);
         }
+    }
 
-        println!("=== PREDICTOR STATS ===");
+    /// Replaces the divergence predictor of every warp by a copy of
+    /// `predictor`.
+    pub fn set_divergence_predictor(&mut self, predictor:Box<dyn DivergencePredictor<BV>>) {
+        for warp in &mut self.warps {
+            warp.div_pred = predictor.clone();
+        }
+    }
+
+    /// How well the divergence of each conditional branch was predicted, over
+    /// all the warps, by PC.
+    pub fn prediction_stats(&self) -> BTreeMap<i32, PredictionStats> {
+        let mut stats = BTreeMap::new();
         for w in &self.warps {
             for (pc, dat) in &w.cond_branch_data {
-                println!("Branch {:08x} : {:?}", pc, dat.pred_data);
+                stats.entry(*pc).or_insert(PredictionStats::default()).add(&dat.pred_data);
             }
         }
+        stats
+    }
+
+    pub fn print_prediction_report(&self) {
+        let stats = self.prediction_stats();
+        let mut total = PredictionStats::default();
+        for s in stats.values() {
+            total.add(s);
+        }
+        println!("=== DIVERGENCE PREDICTION ({}): {} BRANCHES, {:.1}% ACCURACY ==="
+                 , self.warps[0].div_pred.name(), stats.len(), total.accuracy() * 100.);
+        for (pc, s) in &stats {
+            println!("{}: {}", self.symbolizer.describe(*pc as usize), s);
+        }
     }
 
     pub fn print_branch_hist(&self, branch:i32) {
         for w in &self.warps {
             for (pc, dat) in &w.cond_branch_data {
                 if *pc == branch {
+                    println!("% {}", self.symbolizer.describe(*pc as usize));
                     println!("\\begin{{hist}}");
                     for entry in &dat.taken_hist {
                         println!("{}", entry);
@@ -1164,6 +1979,49 @@ impl<S:SimtxScheduler> Machine<S> {
         }
     }
 
+    // Executes instruction `i` of path `pathid` in warp `wid`, or the host
+    // call it jumps to.
+    fn issue(&mut self, wid:usize, pathid:usize, pc:i32, advance:i32, i:Instruction
+             , mem:&mut dyn Memory) {
+        if i.get_opcode_enum() == OpCode::JAL {
+            let address = pc.wrapping_add(i.get_imm_j());
+            if let Some(func_name) = self.plt_addresses.get(&address).cloned() {
+                self.host_call(&func_name, wid, pathid, pc, advance, mem);
+            } else {
+                self.warps[wid].execute_fetched(i, advance, mem)
+            }
+        } else {
+            self.warps[wid].execute_fetched(i, advance, mem)
+        }
+    }
+
+    fn host_call(&mut self, name:&str, wid:usize, pathid:usize, pc:i32, advance:i32
+                 , mem:&mut dyn Memory) {
+        let handler = match self.host_calls.get(name) {
+            Some(handler) => handler,
+            None => {
+                self.error = Some(GuestError::UnknownHostCall {
+                    name: String::from(symbol_name(name)), pc });
+                return
+            },
+        };
+
+        let call = HostCallContext::new(symbol_name(name), pc, wid, pathid, advance
+            , self.warps[0].cores.len(), self.warps[wid].alive_cores_ids().collect());
+
+        let action = handler(self, &call, mem);
+        if action == HostCallAction::Return {
+            self.warps[wid].advance_pc(pathid, advance);
+        }
+        // a blocked path lets the ones below it in the IPDOM stack, or the
+        // scheduler of the formed warps, run
+        if action == HostCallAction::Retry {
+            self.warps[wid].retries += 1;
+        } else {
+            self.warps[wid].retries = 0;
+        }
+    }
+
     fn free_barrier(&mut self, barr:i32, advance:i32) {
         for wid in 0..self.warps.len() {
             let warp = &mut self.warps[wid];
@@ -1172,8 +2030,8 @@ impl<S:SimtxScheduler> Machine<S> {
             while pid < warp.paths.len() {
                 let pc = warp.paths[pid].fetch_pc;
                 let mask = warp.paths[pid].execution_mask;
-                let mut bv_cont = 0;
-                let mut bv_barr = 0;
+                let mut bv_cont = BV::empty();
+                let mut bv_barr = BV::empty();
                 for cid in (0..tpw).filter(|i| mask.at(*i)) {
                     let tid = wid * tpw + cid;
                     if self.in_barrier[tid] == barr {
@@ -1185,11 +2043,12 @@ impl<S:SimtxScheduler> Machine<S> {
                 }
 
                 if bv_cont.any() {
+                    let reconvergence_pc = warp.paths[pid].reconvergence_pc;
                     warp.paths[pid].execution_mask = bv_cont;
                     warp.advance_pc(pid, advance);
                     
                     if bv_barr.any() {
-                        warp.push_path(Path::from_pc_mask(pc, bv_barr));
+                        warp.push_path(Path { reconvergence_pc, ..Path::from_pc_mask(pc, bv_barr) });
                     }
                 }
                 pid += 1;
@@ -1198,16 +2057,22 @@ impl<S:SimtxScheduler> Machine<S> {
     }
 }
 
-impl<S:SimtxScheduler> MultiCoreIMachine for Machine<S> {
+impl<S:SimtxScheduler, BV:BitSet> MultiCoreIMachine for Machine<S, BV> {
     type IntegerType = i32;
 
     fn step(&mut self, mem:Arc<Mutex<dyn Memory + std::marker::Send>>) {
         let mut mem = mem.lock().unwrap();
-        let tpw = self.warps[0].cores.len();
 
+        for warp in &mut self.warps {
+            warp.cycle = self.cycle;
+        }
+        self.sample_paths();
+        let formation_pcs = self.formation_pcs();
+        let mut issued = Vec::new();
         for wid in 0..self.warps.len() {
             self.clean_idles();
-            let pathid = self.warps[wid].schedule_path();
+            let saved = self.timing.as_ref().map(|_| self.warps[wid].save_schedule());
+            let pathid = self.warps[wid].schedule_at(formation_pcs[wid]);
 
             if pathid.is_none() ||
                 self.warps[wid].paths[pathid.unwrap()].fetch_pc == 0 { continue }
@@ -1215,22 +2080,20 @@ impl<S:SimtxScheduler> MultiCoreIMachine for Machine<S> {
             let pathid = pathid.unwrap();
             let pc = self.warps[wid].paths[pathid].fetch_pc;
 
-            let i = Instruction(mem.get_32(pc as usize));
+            let fetched = Instruction(mem.fetch_32(pc as usize));
 
-            let (advance, i) = if i.is_compressed() {
-                #[cfg(debug_assertions)]
-                print!("{:8x} :: ", i.0 & 0xffff);
-                (2, i.uncompressed())
+            let (advance, i) = if fetched.is_compressed() {
+                (2, fetched.uncompressed())
             } else {
-                #[cfg(debug_assertions)]
-                print!("{:08x} :: ", i.0);
-                (4, i)
+                (4, fetched)
             };
 
-            // DEBUG
-            #[cfg(debug_assertions)]
-            println!("warp {} mask {:x} 0x{:x} :: {} {:x}", wid, self.warps[wid].paths[pathid].execution_mask, pc, i, i.0);
-        
+            // a stalled path neither counts nor uses up a scheduling decision
+            if self.timing.is_some() && !self.time_issue(wid, pathid, pc, &i) {
+                self.warps[wid].restore_schedule(saved.unwrap());
+                continue
+            }
+
             // Update back-branch stats
             if i.get_opcode_enum() == OpCode::BRANCH || (i.get_opcode_enum() == OpCode::JAL && i.get_rd() == 0) {
                 if i.jump_offset() < 0 {
@@ -1244,54 +2107,69 @@ impl<S:SimtxScheduler> MultiCoreIMachine for Machine<S> {
                 }
             }
 
-            if i.get_opcode_enum() == OpCode::JAL {
-                let address = pc.wrapping_add(i.get_imm_j());
-                if let Some(func_name) = self.plt_addresses.get(&address).cloned() {
-                    loop {
-                        syscall!{func_name > pthread_create}
-                        syscall!{func_name > pthread_join}
-                        syscall!{func_name > pthread_barrier_init}
-                        syscall!{func_name > pthread_barrier_wait}
-                        syscall!{func_name > ("puts.rs") puts, printf}
-                        syscall!{func_name > malloc}
-                        syscall!{func_name > free}
-                        syscall!{func_name > GOMP_parallel}
-                        syscall!{func_name > omp_get_num_threads}
-                        syscall!{func_name > omp_get_thread_num}
-                        syscall!{func_name > exit}
-                        syscall!{func_name > strtof}
-                        syscall!{func_name > strtol}
-                        syscall!{func_name > ("open.rs") fopen, open}
-                        syscall!{func_name > read}
-                        syscall!{func_name > strtok}
-                        syscall!{func_name > rewind}
-                        syscall!{func_name > fgets}
-                        syscall!{func_name > fwrite}
-                        syscall!{func_name > printf}
-
-                        self.warps[wid].advance_pc(pathid, advance);
-                        break
-                    }
-                } else {
-                    self.warps[wid].execute(mem.deref_mut())
-                }
-            } else if i.get_opcode_enum() == OpCode::SYSTEM {
-                let csr = CsrId::from((i.get_imm_i() & 0xfff) as u16);
-                //let rs1 = i.get_rs1() as usize;
-                let rd = i.get_rd() as usize;
-                for (i, c) in self.warps[wid].cores_mut() {
-                    let v = match csr {
-                        CsrId::MHARTID => { i + wid*tpw },
-                        _ => 0,
-                    };
-                    println!("csrr {:?} = {}", csr, v);
-                    c.set_ri(rd, v as i32);
-                }
-                self.warps[wid].advance_pc(pathid, advance);
+            if self.coalescing.is_some() {
+                self.record_accesses(wid, pc, &i);
+            }
+            let accesses = self.memory_accesses(wid, &i);
+            if let Some(&(_, _, _, write)) = accesses.first() {
+                let addresses : Vec<usize> = accesses.iter().map(|(_, addr, _, _)| *addr).collect();
+                S::on_memory_op(&mut self.warps[wid], pc, write, &addresses);
+            }
+            let mask = self.warps[wid].paths[pathid].execution_mask;
+            if self.formation.is_some() {
+                issued.push((wid, pc, mask));
+            }
+            let warp = &mut self.warps[wid];
+            warp.stats.instructions += 1;
+            warp.stats.active_lanes += mask.count() as u64;
+            if warp.last_issued.any() && (warp.last_issued & mask).none() {
+                warp.stats.scheduler_switches += 1;
+            }
+            warp.last_issued = mask;
+            let first_lane = if self.timing.is_some() {
+                self.warps[wid].paths[pathid].execution_mask.bits().ones().next()
             } else {
-                self.warps[wid].execute(mem.deref_mut())
+                None
+            };
+            let traced = self.start_trace(wid, pc, mask);
+            let stored = if traced { self.stored_values(wid, &i) } else { Vec::new() };
+
+            if self.memcheck.is_some() {
+                self.check_memory(wid, pc, &i);
+                let mut log = WriteLog::new(mem.deref_mut());
+                self.issue(wid, pathid, pc, advance, i, &mut log);
+                let writes = log.into_writes();
+                self.memcheck.as_mut().unwrap().on_writes(&writes);
+            } else {
+                self.issue(wid, pathid, pc, advance, i, mem.deref_mut());
+            }
+            if traced {
+                self.trace_issue(wid, pc, fetched.0, mask, &accesses, &stored);
             }
+            if let Some(htif) = self.htif.as_mut() {
+                for &(_, addr, size, _) in accesses.iter().filter(|a| a.3) {
+                    htif.on_store(addr, size);
+                }
+            }
+
+            // where the path went, to tell path switches from jumps
+            if let Some(lane) = first_lane {
+                let tpw = self.warps[wid].cores.len();
+                let next_pc = self.get_pc_of(wid * tpw + lane as usize);
+                self.timing.as_mut().unwrap().set_next_pc(wid, next_pc);
+            }
+        }
+
+        if let Some(formation) = self.formation.as_mut() {
+            formation.record(&issued);
+        }
+        if let Some(timing) = self.timing.as_mut() {
+            timing.tick();
+        }
+        if let Some(htif) = self.htif.as_mut() {
+            htif.poll(mem.deref_mut());
         }
+        self.cycle += 1;
     }
 
     fn get_i_register_of(&self, coreid:usize, id:usize) -> i32 {
@@ -1329,8 +2207,8 @@ impl<S:SimtxScheduler> MultiCoreIMachine for Machine<S> {
         for pid in 0..self.warps[wid].paths.len() {
             let ex = &self.warps[wid].paths[pid].execution_mask;
             if ex.at(cid) {
-                let new_mask : BitVec = BitSet::singleton(cid);
-                let modified_mask = ex & !new_mask;
+                let new_mask = BV::singleton(cid);
+                let modified_mask = *ex & !new_mask;
 
                 self.warps[wid].push_path(Path::from_pc_mask(value, new_mask));
                 self.warps[wid].paths[pid].execution_mask = modified_mask;
@@ -1343,6 +2221,7 @@ impl<S:SimtxScheduler> MultiCoreIMachine for Machine<S> {
     }
 
     fn finished(&self) -> bool {
+        if self.error.is_some() || self.exit_code().is_some() { return true }
         for warp in &self.warps {
             for path in &warp.paths {
                 if path.fetch_pc != 0 {
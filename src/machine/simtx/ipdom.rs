@@ -0,0 +1,59 @@
+//! Reconvergence at immediate post-dominators, the stack-based scheme of
+//! classic GPUs.
+//!
+//! A divergent branch replaces its path with a reconvergence entry at the
+//! immediate post-dominator of the branch, with the paths of the targets on
+//! top of it. The warp always runs the path on top of the stack, and a path
+//! reaching its reconvergence point gives its threads back to the entry
+//! below, which runs once all the paths above it are done.
+
+use cfg::{Cfg, Terminator};
+
+use std::collections::HashMap;
+
+/// The reconvergence point of each branch which can diverge.
+#[derive(Clone, Debug, Default)]
+pub struct ReconvergencePoints {
+    points:HashMap<i32, i32>,
+}
+
+impl ReconvergencePoints {
+    pub fn new() -> ReconvergencePoints {
+        ReconvergencePoints::default()
+    }
+
+    /// The immediate post-dominators of the branches, indirect jumps and
+    /// indirect calls of `cfg`.
+    pub fn from_cfg(cfg:&Cfg) -> ReconvergencePoints {
+        let mut ret = ReconvergencePoints::new();
+        for block in cfg.blocks() {
+            match block.terminator {
+                Terminator::Branch | Terminator::Call | Terminator::Indirect => {
+                    if let Some(ipdom) = cfg.immediate_post_dominator(block.last) {
+                        ret.insert(block.last as i32, ipdom as i32);
+                    }
+                },
+                _ => {},
+            }
+        }
+        ret
+    }
+
+    pub fn insert(&mut self, branch:i32, reconvergence:i32) {
+        self.points.insert(branch, reconvergence);
+    }
+
+    /// Where the paths of `branch` reconverge, `None` if only at the end of
+    /// the function.
+    pub fn get(&self, branch:i32) -> Option<i32> {
+        self.points.get(&branch).cloned()
+    }
+
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+}
@@ -0,0 +1,294 @@
+//! An optional memory checker for the simulated program, in the spirit of
+//! valgrind's memcheck.
+//!
+//! The checker is told about every heap allocation and every load and store
+//! before it executes. It reports accesses out of the heap blocks, accesses to
+//! freed blocks, loads of heap bytes never written (tracked one bit per byte
+//! in a shadow memory) and threads whose stack pointer left their stack.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use memory::Memory;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MemErrorKind {
+    /// Access to heap memory which belongs to no live block.
+    OutOfBounds,
+    /// Access to a block which was freed.
+    UseAfterFree,
+    /// Load of heap bytes which were never written.
+    UninitializedRead,
+    /// The stack pointer of a thread went below its stack.
+    StackOverflow,
+}
+
+/// Where a heap block was allocated or freed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Site {
+    pub pc:i32,
+    pub tid:usize,
+}
+
+/// A heap block, as seen by the checker.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Block {
+    pub start:usize,
+    pub size:usize,
+    pub allocated:Site,
+    pub freed:Option<Site>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MemError {
+    pub kind:MemErrorKind,
+    pub pc:i32,
+    /// Global id of the faulting thread, and its lane in its warp.
+    pub tid:usize,
+    pub lane:usize,
+    pub addr:usize,
+    pub size:usize,
+    pub write:bool,
+    /// The block the access was meant for: the one containing the address,
+    /// or the closest one below it.
+    pub block:Option<Block>,
+    /// How many times the same error happened at this PC.
+    pub count:usize,
+}
+
+impl fmt::Display for MemErrorKind {
+    fn fmt(&self, f:&mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", match self {
+            MemErrorKind::OutOfBounds => "out of bounds access",
+            MemErrorKind::UseAfterFree => "use after free",
+            MemErrorKind::UninitializedRead => "read of uninitialized memory",
+            MemErrorKind::StackOverflow => "stack overflow",
+        })
+    }
+}
+
+/// One bit per byte, set when the byte is undefined. Only pages which hold
+/// undefined bytes at some point are stored, everything else is defined.
+#[derive(Clone, Debug, Default)]
+struct Shadow {
+    pages:HashMap<usize, Vec<u64>>,
+}
+
+const PAGE_BITS:usize = 12;
+const PAGE_SIZE:usize = 1 << PAGE_BITS;
+
+impl Shadow {
+    fn set(&mut self, start:usize, size:usize, undefined:bool) {
+        for addr in start..start + size {
+            let (page, offset) = (addr >> PAGE_BITS, addr & (PAGE_SIZE - 1));
+            let words = if undefined {
+                self.pages.entry(page).or_insert_with(|| vec![0; PAGE_SIZE / 64])
+            } else {
+                match self.pages.get_mut(&page) {
+                    Some(words) => words,
+                    None => continue,
+                }
+            };
+            if undefined {
+                words[offset / 64] |= 1 << (offset % 64);
+            } else {
+                words[offset / 64] &= !(1 << (offset % 64));
+            }
+        }
+    }
+
+    fn is_undefined(&self, addr:usize) -> bool {
+        let (page, offset) = (addr >> PAGE_BITS, addr & (PAGE_SIZE - 1));
+        self.pages.get(&page)
+            .map_or(false, |words| words[offset / 64] & (1 << (offset % 64)) != 0)
+    }
+}
+
+/// A memory which remembers the ranges written through it, so that host
+/// calls and instructions mark the bytes they store as defined.
+pub struct WriteLog<'a> {
+    mem:&'a mut dyn Memory,
+    writes:Vec<(usize, usize)>,
+}
+
+impl<'a> WriteLog<'a> {
+    pub fn new(mem:&'a mut dyn Memory) -> WriteLog<'a> {
+        WriteLog { mem, writes:Vec::new() }
+    }
+
+    /// The (address, size) of every store done through this memory.
+    pub fn into_writes(self) -> Vec<(usize, usize)> {
+        self.writes
+    }
+}
+
+impl<'a> Memory for WriteLog<'a> {
+    fn get_8(&self, addr:usize) -> u8 { self.mem.get_8(addr) }
+    fn get_16(&self, addr:usize) -> u16 { self.mem.get_16(addr) }
+    fn get_32(&self, addr:usize) -> u32 { self.mem.get_32(addr) }
+    fn fetch_16(&self, addr:usize) -> u16 { self.mem.fetch_16(addr) }
+    fn fetch_32(&self, addr:usize) -> u32 { self.mem.fetch_32(addr) }
+
+    fn set_8(&mut self, addr:usize, value:u8) {
+        self.writes.push((addr, 1));
+        self.mem.set_8(addr, value)
+    }
+    fn set_16(&mut self, addr:usize, value:u16) {
+        self.writes.push((addr, 2));
+        self.mem.set_16(addr, value)
+    }
+    fn set_32(&mut self, addr:usize, value:u32) {
+        self.writes.push((addr, 4));
+        self.mem.set_32(addr, value)
+    }
+
+    fn allocate_at(&mut self, start:usize, size:usize) -> bool {
+        self.mem.allocate_at(start, size)
+    }
+}
+
+/// A load or a store of one lane, about to be executed.
+#[derive(Clone, Copy, Debug)]
+pub struct Access {
+    pub pc:i32,
+    pub tid:usize,
+    pub lane:usize,
+    pub addr:usize,
+    pub size:usize,
+    pub write:bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct MemChecker {
+    live:BTreeMap<usize, Block>,
+    freed:BTreeMap<usize, Block>,
+    shadow:Shadow,
+    // the heap area: anything in it which is not a live block is an error
+    heap:(usize, usize),
+    overflowed:HashSet<usize>,
+    errors:Vec<MemError>,
+    seen:HashMap<(MemErrorKind, i32), usize>,
+}
+
+impl MemChecker {
+    pub fn new() -> MemChecker {
+        MemChecker {
+            live:BTreeMap::new(),
+            freed:BTreeMap::new(),
+            shadow:Shadow::default(),
+            heap:(0, 0),
+            overflowed:HashSet::new(),
+            errors:Vec::new(),
+            seen:HashMap::new(),
+        }
+    }
+
+    /// The errors found so far, one per kind and PC.
+    pub fn errors(&self) -> &[MemError] {
+        &self.errors
+    }
+
+    /// Sets the bounds of the heap area.
+    pub fn set_heap(&mut self, start:usize, top:usize) {
+        self.heap = (start, top);
+    }
+
+    pub fn on_alloc(&mut self, start:usize, size:usize, site:Site, defined:bool) {
+        let end = start + size;
+        let reused : Vec<usize> = self.freed.range(..end)
+            .filter(|(s, b)| **s + b.size > start)
+            .map(|(s, _)| *s)
+            .collect();
+        for s in reused {
+            self.freed.remove(&s);
+        }
+        self.live.insert(start, Block { start, size, allocated:site, freed:None });
+        self.shadow.set(start, size, !defined);
+    }
+
+    pub fn on_free(&mut self, start:usize, site:Site) {
+        if let Some(mut block) = self.live.remove(&start) {
+            block.freed = Some(site);
+            self.freed.insert(start, block);
+        }
+    }
+
+    /// The block at `start` was resized in place.
+    pub fn on_resize(&mut self, start:usize, size:usize) {
+        if let Some(block) = self.live.get_mut(&start) {
+            if size > block.size {
+                self.shadow.set(start + block.size, size - block.size, true);
+            }
+            block.size = size;
+        }
+    }
+
+    /// Marks as defined the bytes written since the last call.
+    pub fn on_writes(&mut self, writes:&[(usize, usize)]) {
+        for (addr, size) in writes {
+            self.shadow.set(*addr, *size, false);
+        }
+    }
+
+    fn report(&mut self, kind:MemErrorKind, access:&Access, block:Option<Block>) {
+        let count = self.seen.entry((kind, access.pc)).or_insert(0);
+        *count += 1;
+        if *count == 1 {
+            self.errors.push(MemError {
+                kind, pc:access.pc, tid:access.tid, lane:access.lane,
+                addr:access.addr, size:access.size, write:access.write,
+                block, count:1,
+            });
+        } else if let Some(e) = self.errors.iter_mut().find(|e| e.kind == kind && e.pc == access.pc) {
+            e.count += 1;
+        }
+    }
+
+    // the block containing `addr` in `blocks`, or the closest one below
+    fn block_near(blocks:&BTreeMap<usize, Block>, addr:usize) -> Option<(Block, bool)> {
+        blocks.range(..=addr).next_back()
+            .map(|(_, b)| (*b, addr < b.start + b.size))
+    }
+
+    /// Checks a load or a store before it happens.
+    pub fn check_access(&mut self, access:Access) {
+        let (start, top) = self.heap;
+        if access.addr + access.size <= start || access.addr >= top {
+            return
+        }
+
+        match MemChecker::block_near(&self.live, access.addr) {
+            Some((block, true)) if access.addr + access.size <= block.start + block.size => {
+                let undefined = (access.addr..access.addr + access.size)
+                    .any(|a| self.shadow.is_undefined(a));
+                if !access.write && undefined {
+                    self.report(MemErrorKind::UninitializedRead, &access, Some(block));
+                    // only report it once, as valgrind does
+                    self.shadow.set(access.addr, access.size, false);
+                }
+            },
+            Some((block, true)) => self.report(MemErrorKind::OutOfBounds, &access, Some(block)),
+            live => match MemChecker::block_near(&self.freed, access.addr) {
+                Some((block, true)) => self.report(MemErrorKind::UseAfterFree, &access, Some(block)),
+                _ => {
+                    let block = live.map(|(b, _)| b);
+                    self.report(MemErrorKind::OutOfBounds, &access, block)
+                },
+            },
+        }
+    }
+
+    /// Checks the stack pointer `sp` of thread `tid`, whose stack starts at
+    /// `bottom`. A thread overflowing is reported once.
+    pub fn check_stack(&mut self, pc:i32, tid:usize, lane:usize, sp:usize, bottom:usize) {
+        if sp == 0 || sp >= bottom || self.overflowed.contains(&tid) {
+            return
+        }
+        self.overflowed.insert(tid);
+        let access = Access { pc, tid, lane, addr:sp, size:0, write:false };
+        self.report(MemErrorKind::StackOverflow, &access, None);
+    }
+}
+
+impl Default for MemChecker {
+    fn default() -> MemChecker { MemChecker::new() }
+}
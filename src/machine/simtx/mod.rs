@@ -1,7 +1,21 @@
 pub mod scheduler;
+pub mod hostcall;
+pub mod error;
+pub mod files;
+pub mod heap;
+pub mod memcheck;
+pub mod timing;
+pub mod coalescing;
+pub mod formation;
+pub mod stats;
+pub mod ipdom;
+pub mod predictor;
 mod implem;
 
 pub use machine::simtx::implem::Machine as Machine;
 pub use machine::simtx::implem::Warp as Warp;
 pub use machine::simtx::implem::Path as Path;
+pub use machine::simtx::implem::Core as Core;
+pub use machine::simtx::implem::Csrs as Csrs;
+pub use machine::simtx::implem::LoopData as LoopData;
 pub use machine::simtx::implem::MAX_TPW as MAX_TPW;
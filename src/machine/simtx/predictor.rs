@@ -0,0 +1,440 @@
+//! Prediction of the divergence of the conditional branches: whether the
+//! threads of a warp running a branch will go different ways. Every warp
+//! asks its predictor before running a branch, and teaches it the outcome.
+//! The schedulers can ask it as well, see `Warp::predict_divergence`.
+
+use types::BitSet;
+use std::fmt;
+use std::marker::PhantomData;
+use std::str::FromStr;
+
+/// Where the threads running a conditional branch went.
+#[derive(Debug, Clone)]
+pub enum BranchOutcome<BV:BitSet> {
+    /// All taken or all not taken, by the threads of the mask.
+    Uniform(bool, BV),
+    /// The threads which took the branch, and the ones which did not.
+    Divergent(BV, BV),
+}
+
+impl<BV:BitSet> BranchOutcome<BV> {
+    pub fn is_divergent(&self) -> bool {
+        matches!(self, BranchOutcome::Divergent(..))
+    }
+
+    /// The threads which ran the branch.
+    pub fn mask(&self) -> BV {
+        match self {
+            BranchOutcome::Uniform(_, mask) => *mask,
+            BranchOutcome::Divergent(taken, not_taken) => *taken | *not_taken,
+        }
+    }
+}
+
+impl<BV:BitSet> fmt::Display for BranchOutcome<BV> {
+    fn fmt(&self, f:&mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BranchOutcome::Uniform(taken, mask) => {
+                for b in mask.bits() {
+                    write!(f, "{}", if b { if *taken { 'T' } else { 'N' } } else { ' ' })?;
+                }
+            },
+            BranchOutcome::Divergent(tmask, ntmask) => {
+                for (t, nt) in tmask.bits().zip(ntmask.bits()) {
+                    write!(f, "{}", if t { 'T' } else if nt { 'N' } else { ' ' })?;
+                }
+            },
+        }
+        Ok(())
+    }
+}
+
+/// Predicts whether a branch diverges. Only asked, and taught, for the
+/// branches run by more than one thread: a single thread never diverges.
+pub trait DivergencePredictor<BV:BitSet> : Send {
+    /// The name of the predictor, for the reports.
+    fn name(&self) -> &'static str;
+
+    /// Whether the threads of `mask` will diverge at the branch at `pc`.
+    fn predict(&self, pc:i32, mask:BV) -> bool;
+
+    /// Learns the `outcome` of the branch at `pc`.
+    fn update(&mut self, pc:i32, outcome:&BranchOutcome<BV>);
+
+    fn box_clone(&self) -> Box<dyn DivergencePredictor<BV>>;
+}
+
+impl<BV:BitSet> Clone for Box<dyn DivergencePredictor<BV>> {
+    fn clone(&self) -> Self {
+        self.box_clone()
+    }
+}
+
+/// How a PC is turned into an entry of a predictor table.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Indexing {
+    /// The PC cut in pieces of as many bits as the index, xored together:
+    /// `(pc & 0xffff) ^ (pc >> 16)` for 65536 entries.
+    Folded,
+    /// The low bits of the PC, but the first one, always 0.
+    Direct,
+}
+
+/// The most entries a predictor table may have.
+pub const MAX_ENTRIES : usize = 1 << 24;
+
+/// The size of a predictor table, and how it is indexed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TableConfig {
+    /// A power of two.
+    pub entries:usize,
+    pub indexing:Indexing,
+}
+
+impl TableConfig {
+    /// `entries` is rounded up to a power of two, up to `MAX_ENTRIES`.
+    pub fn new(entries:usize, indexing:Indexing) -> TableConfig {
+        TableConfig { entries:entries.clamp(1, MAX_ENTRIES).next_power_of_two(), indexing }
+    }
+
+    /// The entry of the branch at `pc`.
+    pub fn index(&self, pc:i32) -> usize {
+        let bits = self.entries.trailing_zeros();
+        let mask = (self.entries - 1) as u32;
+        match self.indexing {
+            Indexing::Folded => {
+                let mut pc = pc as u32;
+                let mut index = 0;
+                while pc != 0 && bits > 0 {
+                    index ^= pc & mask;
+                    pc = pc.checked_shr(bits).unwrap_or(0);
+                }
+                index as usize
+            },
+            Indexing::Direct => ((pc as u32 >> 1) & mask) as usize,
+        }
+    }
+}
+
+impl Default for TableConfig {
+    fn default() -> TableConfig {
+        TableConfig::new(1 << 16, Indexing::Folded)
+    }
+}
+
+fn saturating_update(counter:&mut u8, up:bool) {
+    *counter = if up { (*counter + 1).min(3) } else { counter.saturating_sub(1) };
+}
+
+/// A 2-bit counter per number of active threads, in every entry: the
+/// branches are predicted to diverge as they did with as many threads.
+#[derive(Clone)]
+pub struct CounterPredictor<BV:BitSet> {
+    table:TableConfig,
+    counters:Vec<u8>,
+    phantom:PhantomData<BV>,
+}
+
+impl<BV:BitSet> CounterPredictor<BV> {
+    pub fn new(table:TableConfig) -> CounterPredictor<BV> {
+        CounterPredictor { table, counters:vec![1; table.entries * BV::SIZE as usize], phantom:PhantomData }
+    }
+
+    fn slot(&self, pc:i32, mask:BV) -> usize {
+        self.table.index(pc) * BV::SIZE as usize + mask.count().saturating_sub(1)
+    }
+}
+
+impl<BV:BitSet> DivergencePredictor<BV> for CounterPredictor<BV> {
+    fn name(&self) -> &'static str { "counter" }
+
+    fn predict(&self, pc:i32, mask:BV) -> bool {
+        self.counters[self.slot(pc, mask)] > 1
+    }
+
+    fn update(&mut self, pc:i32, outcome:&BranchOutcome<BV>) {
+        let slot = self.slot(pc, outcome.mask());
+        saturating_update(&mut self.counters[slot], outcome.is_divergent());
+    }
+
+    fn box_clone(&self) -> Box<dyn DivergencePredictor<BV>> {
+        Box::new(self.clone())
+    }
+}
+
+// Outcomes remembered by each branch of the history predictor
+const LOCAL_HISTORY : u32 = 8;
+
+/// The last divergences of each branch, which select a 2-bit counter: the
+/// branches diverging every other time, or once every loop, are learnt.
+#[derive(Clone)]
+pub struct HistoryPredictor<BV:BitSet> {
+    table:TableConfig,
+    history_bits:u32,
+    histories:Vec<u32>,
+    counters:Vec<u8>,
+    phantom:PhantomData<BV>,
+}
+
+impl<BV:BitSet> HistoryPredictor<BV> {
+    pub fn new(table:TableConfig) -> HistoryPredictor<BV> {
+        HistoryPredictor::with_history(table, LOCAL_HISTORY)
+    }
+
+    /// Remembers `bits` outcomes per branch, up to 16.
+    pub fn with_history(table:TableConfig, bits:u32) -> HistoryPredictor<BV> {
+        HistoryPredictor {
+            table, history_bits:bits.min(16),
+            histories:vec![0; table.entries], counters:vec![1; table.entries],
+            phantom:PhantomData,
+        }
+    }
+
+    fn slot(&self, index:usize) -> usize {
+        (index ^ self.histories[index] as usize) & (self.table.entries - 1)
+    }
+}
+
+impl<BV:BitSet> DivergencePredictor<BV> for HistoryPredictor<BV> {
+    fn name(&self) -> &'static str { "history" }
+
+    fn predict(&self, pc:i32, _mask:BV) -> bool {
+        self.counters[self.slot(self.table.index(pc))] > 1
+    }
+
+    fn update(&mut self, pc:i32, outcome:&BranchOutcome<BV>) {
+        let index = self.table.index(pc);
+        let slot = self.slot(index);
+        let divergent = outcome.is_divergent();
+        saturating_update(&mut self.counters[slot], divergent);
+        let history = &mut self.histories[index];
+        *history = ((*history << 1) | divergent as u32) & ((1 << self.history_bits) - 1);
+    }
+
+    fn box_clone(&self) -> Box<dyn DivergencePredictor<BV>> {
+        Box::new(self.clone())
+    }
+}
+
+/// The way each thread went the last time it ran the branch: the threads
+/// of a branch are predicted to diverge when some of them went one way and
+/// some the other.
+#[derive(Clone)]
+pub struct PatternPredictor<BV:BitSet> {
+    table:TableConfig,
+    taken:Vec<BV>,
+    not_taken:Vec<BV>,
+}
+
+impl<BV:BitSet> PatternPredictor<BV> {
+    pub fn new(table:TableConfig) -> PatternPredictor<BV> {
+        PatternPredictor { table, taken:vec![BV::empty(); table.entries], not_taken:vec![BV::empty(); table.entries] }
+    }
+}
+
+impl<BV:BitSet> DivergencePredictor<BV> for PatternPredictor<BV> {
+    fn name(&self) -> &'static str { "pattern" }
+
+    fn predict(&self, pc:i32, mask:BV) -> bool {
+        let index = self.table.index(pc);
+        (mask & self.taken[index]).any() && (mask & self.not_taken[index]).any()
+    }
+
+    fn update(&mut self, pc:i32, outcome:&BranchOutcome<BV>) {
+        let index = self.table.index(pc);
+        let (taken, not_taken) = match outcome {
+            BranchOutcome::Uniform(true, mask) => (*mask, BV::empty()),
+            BranchOutcome::Uniform(false, mask) => (BV::empty(), *mask),
+            BranchOutcome::Divergent(taken, not_taken) => (*taken, *not_taken),
+        };
+        let others = !outcome.mask();
+        self.taken[index] = (self.taken[index] & others) | taken;
+        self.not_taken[index] = (self.not_taken[index] & others) | not_taken;
+    }
+
+    fn box_clone(&self) -> Box<dyn DivergencePredictor<BV>> {
+        Box::new(self.clone())
+    }
+}
+
+// Outcomes of the last branches of the warp seen by the perceptrons
+const GLOBAL_HISTORY : usize = 16;
+
+/// A perceptron per entry, whose inputs are the divergences of the last
+/// branches of the warp (Jiménez and Lin, 2001).
+#[derive(Clone)]
+pub struct PerceptronPredictor<BV:BitSet> {
+    table:TableConfig,
+    // the bias, then a weight per outcome of the history, for every entry
+    weights:Vec<i16>,
+    // the last outcome first
+    history:Vec<bool>,
+    threshold:i32,
+    phantom:PhantomData<BV>,
+}
+
+impl<BV:BitSet> PerceptronPredictor<BV> {
+    pub fn new(table:TableConfig) -> PerceptronPredictor<BV> {
+        PerceptronPredictor::with_history(table, GLOBAL_HISTORY)
+    }
+
+    /// Sees the outcomes of the last `length` branches.
+    pub fn with_history(table:TableConfig, length:usize) -> PerceptronPredictor<BV> {
+        PerceptronPredictor {
+            table,
+            weights:vec![0; table.entries * (length + 1)],
+            history:vec![false; length],
+            // the training threshold given in the paper
+            threshold:(1.93 * length as f64 + 14.) as i32,
+            phantom:PhantomData,
+        }
+    }
+
+    fn output(&self, index:usize) -> i32 {
+        let weights = &self.weights[index * (self.history.len() + 1)..];
+        self.history.iter().zip(&weights[1..])
+            .fold(weights[0] as i32, |y, (d, w)| if *d { y + *w as i32 } else { y - *w as i32 })
+    }
+}
+
+impl<BV:BitSet> DivergencePredictor<BV> for PerceptronPredictor<BV> {
+    fn name(&self) -> &'static str { "perceptron" }
+
+    fn predict(&self, pc:i32, _mask:BV) -> bool {
+        self.output(self.table.index(pc)) >= 0
+    }
+
+    fn update(&mut self, pc:i32, outcome:&BranchOutcome<BV>) {
+        let index = self.table.index(pc);
+        let divergent = outcome.is_divergent();
+        let y = self.output(index);
+        if (y >= 0) != divergent || y.abs() <= self.threshold {
+            let t = if divergent { 1 } else { -1 };
+            let start = index * (self.history.len() + 1);
+            let train = |w:&mut i16, x:i16| *w = (*w + t * x).clamp(-128, 127);
+            train(&mut self.weights[start], 1);
+            for (i, d) in self.history.iter().enumerate() {
+                train(&mut self.weights[start + 1 + i], if *d { 1 } else { -1 });
+            }
+        }
+        if !self.history.is_empty() {
+            self.history.pop();
+            self.history.insert(0, divergent);
+        }
+    }
+
+    fn box_clone(&self) -> Box<dyn DivergencePredictor<BV>> {
+        Box::new(self.clone())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PredictorKind {
+    Counter,
+    History,
+    Pattern,
+    Perceptron,
+}
+
+/// A predictor and its table, given as `kind[:entries[:folded|direct]]`
+/// (e.g. `perceptron:4096:direct`) where kind is `counter`, `history`,
+/// `pattern` or `perceptron`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PredictorConfig {
+    pub kind:PredictorKind,
+    pub table:TableConfig,
+}
+
+impl PredictorConfig {
+    pub fn build<BV:BitSet>(&self) -> Box<dyn DivergencePredictor<BV>> {
+        match self.kind {
+            PredictorKind::Counter => Box::new(CounterPredictor::new(self.table)),
+            PredictorKind::History => Box::new(HistoryPredictor::new(self.table)),
+            PredictorKind::Pattern => Box::new(PatternPredictor::new(self.table)),
+            PredictorKind::Perceptron => Box::new(PerceptronPredictor::new(self.table)),
+        }
+    }
+}
+
+impl FromStr for PredictorConfig {
+    type Err = String;
+
+    fn from_str(s:&str) -> Result<PredictorConfig, String> {
+        let fields : Vec<&str> = s.split(':').collect();
+        if fields.len() > 3 {
+            return Err(format!("`{}` is not kind[:entries[:indexing]]", s))
+        }
+        let kind = match fields[0] {
+            "counter" => PredictorKind::Counter,
+            "history" => PredictorKind::History,
+            "pattern" => PredictorKind::Pattern,
+            "perceptron" => PredictorKind::Perceptron,
+            k => return Err(format!("unknown divergence predictor `{}`", k)),
+        };
+        let mut table = TableConfig::default();
+        if let Some(entries) = fields.get(1) {
+            let entries = entries.parse::<usize>().ok().filter(|&n| n <= MAX_ENTRIES)
+                .ok_or_else(|| format!("`{}` is not a number of entries, up to {}", entries, MAX_ENTRIES))?;
+            table = TableConfig::new(entries, table.indexing);
+        }
+        if let Some(indexing) = fields.get(2) {
+            table.indexing = match *indexing {
+                "folded" => Indexing::Folded,
+                "direct" => Indexing::Direct,
+                i => return Err(format!("unknown indexing `{}`", i)),
+            };
+        }
+        Ok(PredictorConfig { kind, table })
+    }
+}
+
+/// How well the divergence of a branch was predicted.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PredictionStats {
+    /// Predicted uniform and divergent, rightly.
+    pub good_uniform:u64,
+    pub good_divergent:u64,
+    /// Predicted divergent but uniform, and uniform but divergent.
+    pub bad_uniform:u64,
+    pub bad_divergent:u64,
+}
+
+impl PredictionStats {
+    pub fn record<BV:BitSet>(&mut self, predicted:bool, outcome:&BranchOutcome<BV>) {
+        match (predicted, outcome.is_divergent()) {
+            (false, true) => self.bad_divergent += 1,
+            (true, true) => self.good_divergent += 1,
+            (true, false) => self.bad_uniform += 1,
+            (false, false) => self.good_uniform += 1,
+        }
+    }
+
+    pub fn predictions(&self) -> u64 {
+        self.good_uniform + self.good_divergent + self.bad_uniform + self.bad_divergent
+    }
+
+    /// The branches which actually diverged.
+    pub fn divergent(&self) -> u64 {
+        self.good_divergent + self.bad_divergent
+    }
+
+    pub fn accuracy(&self) -> f64 {
+        let predictions = self.predictions();
+        if predictions == 0 { 1. } else { (self.good_uniform + self.good_divergent) as f64 / predictions as f64 }
+    }
+
+    pub fn add(&mut self, other:&PredictionStats) {
+        self.good_uniform += other.good_uniform;
+        self.good_divergent += other.good_divergent;
+        self.bad_uniform += other.bad_uniform;
+        self.bad_divergent += other.bad_divergent;
+    }
+}
+
+impl fmt::Display for PredictionStats {
+    fn fmt(&self, f:&mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} predictions, {} divergent, accuracy {:.1}% (mispredicted {} uniform, {} divergent)"
+               , self.predictions(), self.divergent(), self.accuracy() * 100.
+               , self.bad_uniform, self.bad_divergent)
+    }
+}
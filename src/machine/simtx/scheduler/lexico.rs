@@ -1,38 +1,57 @@
 use machine::simtx::{Warp, Path, scheduler::SimtxScheduler};
+use types::BitSet;
 
 const SCHEDULE_THRESHOLD : usize = 256;
 const RR_ROUNDS : usize = 256;
 
+/// MinPC, with a round robin over the paths every `schedule_threshold`
+/// cycles, giving `rr_rounds` cycles to each path.
 #[derive(Clone)]
 pub struct Scheduler {
     cycles_until_rr:usize,
     rr_pointer:Option<usize>,
     rr_time:usize,
+
+    schedule_threshold:usize,
+    rr_rounds:usize,
 }
 
 impl std::default::Default for Scheduler {
     fn default() -> Self {
-        Scheduler { cycles_until_rr:SCHEDULE_THRESHOLD, rr_pointer:None, rr_time:RR_ROUNDS }
+        Scheduler {
+            cycles_until_rr:SCHEDULE_THRESHOLD, rr_pointer:None, rr_time:RR_ROUNDS,
+            schedule_threshold:SCHEDULE_THRESHOLD, rr_rounds:RR_ROUNDS,
+        }
     }
 }
 
 impl SimtxScheduler for Scheduler {
-    fn schedule(simulator:&mut Warp<Self>) -> Option<usize> {
+    fn set_param(&mut self, name:&str, value:&str) -> Result<(), String> {
+        let value : usize = value.parse().map_err(|_| format!("{} must be a number", name))?;
+        match name {
+            "schedule_threshold" => { self.schedule_threshold = value; self.cycles_until_rr = value },
+            "rr_rounds" => { self.rr_rounds = value; self.rr_time = value },
+            _ => return Err(format!("unknown parameter {} of the lexico scheduler", name)),
+        }
+        Ok(())
+    }
+
+    fn schedule<BV:BitSet>(simulator:&mut Warp<Self, BV>) -> Option<usize> {
         simulator.current_path =
         if simulator.paths.is_empty() { 
             simulator.current_path = None;
             simulator.scheduler.rr_pointer = None;
-            simulator.scheduler.cycles_until_rr = SCHEDULE_THRESHOLD;
+            simulator.scheduler.cycles_until_rr = simulator.scheduler.schedule_threshold;
             None
         } else {
 
             //println!("SCHEDULING {:x?}", simulator.paths);
             // If it is RR time, start RR at path 0
             let next = if simulator.scheduler.cycles_until_rr == 0 {
-                simulator.scheduler.cycles_until_rr = SCHEDULE_THRESHOLD;
+                simulator.scheduler.cycles_until_rr = simulator.scheduler.schedule_threshold;
 
                 simulator.scheduler.rr_pointer = Some(0);
-                simulator.scheduler.rr_time = RR_ROUNDS;
+                simulator.scheduler.rr_time = simulator.scheduler.rr_rounds;
 
                 //println!("START RR");
                 Some(0)
@@ -44,7 +63,7 @@ impl SimtxScheduler for Scheduler {
                     simulator.scheduler.rr_time -= 1;
                     ptr
                 } else {
-                    simulator.scheduler.rr_time = RR_ROUNDS;
+                    simulator.scheduler.rr_time = simulator.scheduler.rr_rounds;
                     ptr + 1
                 };
 
@@ -62,7 +81,7 @@ impl SimtxScheduler for Scheduler {
                 simulator.scheduler.cycles_until_rr -= 1;
 
                 //println!("FUCK THE SYSTEM");
-                let mut copy : Vec<(usize, Path)>
+                let mut copy : Vec<(usize, Path<BV>)>
                     = simulator.paths.iter().cloned().enumerate().collect();
                 copy.sort_by_key(|(_,p)| p.fetch_pc);
                 copy.first().map(|(i,_)| *i)
@@ -1,4 +1,5 @@
-use machine::simtx::{Warp, scheduler::SimtxScheduler, implem::loops};
+use machine::simtx::{Warp, scheduler::SimtxScheduler};
+use types::BitSet;
 use std::{i32, collections::HashMap};
 
 #[derive(Clone, Default)]
@@ -6,7 +7,7 @@ pub struct Scheduler {
 }
 
 impl SimtxScheduler for Scheduler {
-    fn schedule(simulator:&mut Warp<Self>) -> Option<usize> {
+    fn schedule<BV:BitSet>(simulator:&mut Warp<Self, BV>) -> Option<usize> {
         let mut grouped : HashMap<(i32, i32), Vec<(usize, i32)>> = HashMap::new();
         let mut copied : Vec<(usize, i32)> = simulator.paths.iter()
             .cloned()
@@ -15,26 +16,24 @@ impl SimtxScheduler for Scheduler {
             .collect();
 
         // group paths by loop
-        for (beg, end) in loops() {
+        for (beg, end) in simulator.program().loops.iter() {
             grouped.insert((*beg, *end), copied.drain_filter(|(_,x)| {
                 *x >= *beg && *x <= *end
             }).collect());
         }
 
-        // iterator over minimum of each groupe
+        // iterator over minimum of each groupe, loops without paths have none
         let grouped_mins = grouped
             .iter()
-            .map(|((beg,end),vec)| {
+            .filter_map(|((beg,end),vec)| {
                 vec
                     .iter()
                     .min_by_key(|(pid1,pc1)| {
                         vec
                             .iter()
                             .filter(|(pid2,_)| pid2 != pid1)
-                            .min_by_key(|(_,pc2)| (pc2 - pc1) % (end - beg))
+                            .min_by_key(|(_,pc2)| (pc2 - pc1) % (end - beg).max(1))
                     })
-                    .or(vec.first())
-                    .unwrap()
             });
 
         // find min pc between single pc and "grouped min pc"
@@ -1,4 +1,8 @@
-use machine::simtx::Warp;
+use machine::simtx::{Warp, Path};
+use cfg::{Cfg, Terminator};
+use types::BitSet;
+pub use types::Rng;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 
 mod loopaware;
 pub use machine::simtx::scheduler::loopaware::Scheduler as LoopAwareScheduler;
@@ -7,9 +11,75 @@ pub use machine::simtx::scheduler::timeshare::Scheduler as TimeShareScheduler;
 mod lexico;
 pub use machine::simtx::scheduler::lexico::Scheduler as LexicoScheduler;
 
-//mod proba;
-//pub use proba::Scheduler as ProbaScheduler;
+mod proba;
+pub use machine::simtx::scheduler::proba::Scheduler as ProbaScheduler;
+mod registry;
+pub use machine::simtx::scheduler::registry::{SchedulerRegistry, SchedulerVisitor};
 
+/// Picks the path a warp runs next. The state of the scheduler lives in
+/// `Warp::scheduler`, one per warp.
+///
+/// Besides `schedule`, a scheduler is told what happens to the paths of the
+/// warp through the `on_*` hooks, which do nothing by default. They are
+/// called once the paths of the warp are up to date. `Warp::program()` and
+/// `Warp::cycle()` give what is known of the program and the current cycle.
 pub trait SimtxScheduler : Clone + Default {
-    fn schedule(simulator:&mut Warp<Self>) -> Option<usize>;
+    fn schedule<BV:BitSet>(simulator:&mut Warp<Self, BV>) -> Option<usize>;
+
+    /// Sets the parameter `name` of the scheduler, see
+    /// `Machine::set_scheduler_param`. There are none by default.
+    fn set_param(&mut self, name:&str, _value:&str) -> Result<(), String> {
+        Err(format!("unknown scheduler parameter {}", name))
+    }
+
+    /// The path running the branch at `pc` split into `targets`.
+    fn on_diverge<BV:BitSet>(_simulator:&mut Warp<Self, BV>, _pc:i32, _targets:&[Path<BV>]) {}
+
+    /// The threads of `mask` joined the path at `pc`.
+    fn on_merge<BV:BitSet>(_simulator:&mut Warp<Self, BV>, _pc:i32, _mask:BV) {}
+
+    /// The threads of `mask` wait in a barrier, called at `pc`.
+    fn on_barrier<BV:BitSet>(_simulator:&mut Warp<Self, BV>, _pc:i32, _mask:BV) {}
+
+    /// The threads of `mask` exited.
+    fn on_path_exit<BV:BitSet>(_simulator:&mut Warp<Self, BV>, _mask:BV) {}
+
+    /// The current path is about to run the load or store at `pc`, one
+    /// address per thread.
+    fn on_memory_op<BV:BitSet>(_simulator:&mut Warp<Self, BV>, _pc:i32, _write:bool, _addresses:&[usize]) {}
+}
+
+/// What the schedulers know of the program before running it, from its
+/// control-flow graph (see `Machine::load_program`).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ProgramInfo {
+    /// Start addresses of the basic blocks.
+    pub blocks:BTreeSet<i32>,
+    /// Target of every branch or jump going backward, by address.
+    pub backward_branches:BTreeMap<i32, i32>,
+    /// Address of the first and last instructions of every loop.
+    pub loops:HashMap<i32, i32>,
+}
+
+impl ProgramInfo {
+    pub fn from_cfg(cfg:&Cfg) -> ProgramInfo {
+        let mut info = ProgramInfo::default();
+        for block in cfg.blocks() {
+            info.blocks.insert(block.start as i32);
+            let jumps = block.terminator == Terminator::Branch || block.terminator == Terminator::Jump;
+            if let Some(target) = block.successors.first().filter(|t| jumps && **t <= block.last) {
+                info.backward_branches.insert(block.last as i32, *target as i32);
+            }
+        }
+        for l in cfg.loops() {
+            let last = l.blocks.iter().map(|b| cfg.block_of(*b).unwrap().last).max().unwrap();
+            info.loops.insert(l.start as i32, last as i32);
+        }
+        info
+    }
+
+    /// Whether a basic block starts at `pc`.
+    pub fn is_block_start(&self, pc:i32) -> bool {
+        self.blocks.contains(&pc)
+    }
 }
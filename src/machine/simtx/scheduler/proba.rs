@@ -1,106 +1,115 @@
-use machine::simtx::{Warp, Path, scheduler::SimtxScheduler};
-use std::cmp::Reverse;
-
-static WINDOW_SIZE : f32 = 256.;
-
-#[derive(Clone, Default, Debug)]
-struct TimeSlice {
-    path:usize,
-    cycles:usize,
-}
-
-impl TimeSlice {
-    fn new() -> TimeSlice { TimeSlice::default() }
-}
-
+use machine::simtx::{Warp, scheduler::SimtxScheduler};
+use types::BitSet;
+
+// Number of schedules drawn at once
+const WINDOW_SIZE : usize = 64;
+// Cycles without being scheduled which double the priority of a path
+const AGE_SCALE : f64 = 32.;
+// Paths in a loop are favoured, as the threads which left it wait for them
+const LOOP_BOOST : f64 = 2.;
+
+/// Draws the paths to run in a fetch window, each path being drawn with a
+/// probability given by its priority.
+///
+/// The priority halves with the rank of the PC of the path among the others
+/// (the lowest PC comes first, as with MinPC), grows with the cycles since
+/// the path was last scheduled, and is doubled inside the loops of the
+/// program. The window is drawn again when it is used up or when the paths
+/// change. The draws come from `Warp::rng`, see `Machine::seed`.
+///
+/// Its parameters are `window`, `age_scale` and `loop_boost`.
 #[derive(Clone)]
-pub struct SchedulerData {
-    fetch_window:Vec<TimeSlice>,
+pub struct Scheduler {
+    // path ids, in the order they run
+    fetch_window:Vec<usize>,
     window_location:usize,
-}
 
-impl Default for SchedulerData {
-    fn default() -> SchedulerData { SchedulerData::new() }
+    window:usize,
+    age_scale:f64,
+    loop_boost:f64,
 }
 
-impl SchedulerData {
-    pub fn new() -> SchedulerData {
-        SchedulerData { fetch_window:Vec::new(), window_location:0 }
-    }
-
-    fn probas(ps:&Vec<Path>) -> Vec<(usize,f32)> {
-        let mut with_id : Vec<(usize, Path)> = ps.into_iter()
-            .cloned()
-            .enumerate()
-            .collect();
-
-        // Sort by min-pc
-        with_id.sort_unstable_by_key(|(_,p)| Reverse(p.fetch_pc));
-
-        with_id.into_iter()
-            .map(|(i,_)| {
-                (i, 1. / (2f32).powi(i as i32))
-            })
-            .collect()
+impl std::default::Default for Scheduler {
+    fn default() -> Self {
+        Scheduler {
+            fetch_window:Vec::new(), window_location:0,
+            window:WINDOW_SIZE, age_scale:AGE_SCALE, loop_boost:LOOP_BOOST,
+        }
     }
 }
 
-impl SimtxScheduler for SchedulerData {
-
-    fn next_path_to_fetch_id(warp:&mut Warp<Self>) -> Option<usize> {
-        let sd = &mut warp.scheduler_data;
-
-        let l = sd.fetch_window.len();
-
-        // no path to fetch, nothing to execute on warp
-        if l <= 0 { return None }
+impl Scheduler {
+    fn priorities<BV:BitSet>(simulator:&Warp<Self, BV>) -> Vec<f64> {
+        let loops = &simulator.program().loops;
+        let scheduler = &simulator.scheduler;
+        let mut ranks : Vec<usize> = (0..simulator.paths.len()).collect();
+        ranks.sort_by_key(|p| simulator.paths[*p].fetch_pc);
+
+        let mut priorities = vec![0.; simulator.paths.len()];
+        for (rank, p) in ranks.into_iter().enumerate() {
+            let path = &simulator.paths[p];
+            let in_loop = loops.iter().any(|(beg, end)| path.fetch_pc >= *beg && path.fetch_pc <= *end);
+            priorities[p] = 0.5f64.powi(rank as i32)
+                * (1. + path.time_since_scheduled as f64 / scheduler.age_scale)
+                * if in_loop { scheduler.loop_boost } else { 1. };
+        }
+        priorities
+    }
 
-        // find next path with cycles to execute (from the fetch barrel)
-        let mut clock = 0;
-        while sd.fetch_window[sd.window_location].cycles <= 0 {
-            sd.window_location =  (sd.window_location + 1) % l;
-            clock += 1;
-            if clock == l {
-                warp.trigger_schedule();
-                return None
+    fn fill_window<BV:BitSet>(simulator:&mut Warp<Self, BV>) {
+        let priorities = Scheduler::priorities(simulator);
+        let total : f64 = priorities.iter().sum();
+        let size = simulator.scheduler.window.max(1);
+        let mut window = Vec::with_capacity(size);
+        for _ in 0..size {
+            let mut draw = simulator.rng.next_f64() * total;
+            let mut path = priorities.len() - 1;
+            for (p, priority) in priorities.iter().enumerate() {
+                if draw < *priority {
+                    path = p;
+                    break
+                }
+                draw -= priority;
             }
+            window.push(path);
         }
-
-        // advance current path of 1 cycle, and return its id
-        sd.fetch_window[sd.window_location].cycles -= 1;
-        Some(sd.fetch_window[sd.window_location].path)
+        simulator.scheduler.fetch_window = window;
+        simulator.scheduler.window_location = 0;
     }
+}
 
-    fn schedule(warp:&mut Warp<Self>) {
-        let sd = &mut warp.scheduler_data;
-        if warp.must_schedule {
-            // Get the probability list and sort it by decreasing probability
-            let probas = SchedulerData::probas(&warp.paths);
-
-            // Share cycles for each path given their runtime probability
-            let mut probas : Vec<(usize, usize)> = probas
-                .into_iter()
-                .map(|(i,p)| (i, 1 + (p * WINDOW_SIZE) as usize))
-                .collect();
-            probas.sort_unstable_by_key(|(_,p)| Reverse(p.clone()));
-
-            let l = probas.len();
-            let off = sd.window_location;
+impl SimtxScheduler for Scheduler {
+    fn set_param(&mut self, name:&str, value:&str) -> Result<(), String> {
+        let bad = format!("{} must be a number", name);
+        match name {
+            "window" => self.window = value.parse().map_err(|_| bad)?,
+            // the ages are divided by it
+            "age_scale" => self.age_scale = value.parse().ok().filter(|s:&f64| s.is_finite() && *s > 0.)
+                .ok_or_else(|| format!("{} must be a positive number", name))?,
+            "loop_boost" => self.loop_boost = value.parse().map_err(|_| bad)?,
+            _ => return Err(format!("unknown parameter {} of the proba scheduler", name)),
+        }
+        Ok(())
+    }
 
-            // clear the fetch_window, and re-populate it with as many timeslices
-            // as there are paths
-            sd.fetch_window.clear();
-            sd.fetch_window.resize(l, TimeSlice::new());
-            probas
-                .into_iter()
-                .enumerate()
-                .for_each(|(j, (i, n))| {
-                    sd.fetch_window[(off + j)%l] = TimeSlice { path:i, cycles:n }
-                });
+    fn schedule<BV:BitSet>(simulator:&mut Warp<Self, BV>) -> Option<usize> {
+        if simulator.paths.is_empty() {
+            simulator.scheduler.fetch_window.clear();
+            simulator.current_path = None;
+            return None
+        }
 
-            // relocate the window pointer
-            sd.window_location = sd.window_location % l;
-            warp.must_schedule = false;
+        let location = simulator.scheduler.window_location;
+        let used_up = simulator.scheduler.fetch_window.get(location)
+            .map_or(true, |p| *p >= simulator.paths.len());
+        if simulator.schedule_invalidated || used_up {
+            Scheduler::fill_window(simulator);
+            simulator.schedule_invalidated = false;
         }
+
+        let path = simulator.scheduler.fetch_window[simulator.scheduler.window_location];
+        simulator.scheduler.window_location += 1;
+        simulator.current_path = Some(path);
+        simulator.current_path
     }
 }
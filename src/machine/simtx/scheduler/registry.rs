@@ -0,0 +1,79 @@
+use machine::simtx::scheduler::{SimtxScheduler, LexicoScheduler, TimeShareScheduler, LoopAwareScheduler, ProbaScheduler};
+use std::collections::BTreeMap;
+
+/// Code to run with a scheduler chosen at runtime: as the scheduler is a
+/// type parameter of the machine, the registry calls `visit` with the type
+/// registered under the chosen name.
+pub trait SchedulerVisitor {
+    type Output;
+    fn visit<S:SimtxScheduler>(self) -> Self::Output;
+}
+
+/// The schedulers known by name, for the visitors of type `V`.
+///
+/// ```ignore
+/// let mut registry = SchedulerRegistry::with_builtins();
+/// registry.register::<MyScheduler>("mine", "My own scheduler");
+/// let output = registry.visit("mine", MyVisitor { .. })?;
+/// ```
+pub struct SchedulerRegistry<V:SchedulerVisitor> {
+    schedulers:BTreeMap<String, Entry<V>>,
+}
+
+struct Entry<V:SchedulerVisitor> {
+    description:String,
+    visit:fn(V) -> V::Output,
+}
+
+fn visit<S:SimtxScheduler, V:SchedulerVisitor>(visitor:V) -> V::Output {
+    visitor.visit::<S>()
+}
+
+impl<V:SchedulerVisitor> SchedulerRegistry<V> {
+    /// A registry without any scheduler.
+    pub fn new() -> SchedulerRegistry<V> {
+        SchedulerRegistry { schedulers:BTreeMap::new() }
+    }
+
+    /// The schedulers of the crate: `lexico`, `timeshare`, `loopaware` and
+    /// `proba`.
+    pub fn with_builtins() -> SchedulerRegistry<V> {
+        let mut registry = SchedulerRegistry::new();
+        registry.register::<LexicoScheduler>("lexico", "MinPC, with a round robin over the paths from time to time");
+        registry.register::<TimeShareScheduler>("timeshare", "Time slices shared by PC order");
+        registry.register::<LoopAwareScheduler>("loopaware", "MinPC, keeping the paths in a loop together");
+        registry.register::<ProbaScheduler>("proba", "Draws the paths by priority, from a seedable generator");
+        registry
+    }
+
+    /// Registers (or replaces) the scheduler `S` under `name`.
+    pub fn register<S:SimtxScheduler>(&mut self, name:&str, description:&str) {
+        self.schedulers.insert(String::from(name), Entry { description:String::from(description), visit:visit::<S, V> });
+    }
+
+    /// The names and descriptions of the schedulers, by name.
+    pub fn schedulers(&self) -> impl Iterator<Item=(&str, &str)> {
+        self.schedulers.iter().map(|(n, e)| (n.as_str(), e.description.as_str()))
+    }
+
+    pub fn contains(&self, name:&str) -> bool {
+        self.schedulers.contains_key(name)
+    }
+
+    /// Runs `visitor` with the scheduler named `name`.
+    pub fn visit(&self, name:&str, visitor:V) -> Result<V::Output, String> {
+        match self.schedulers.get(name) {
+            Some(entry) => Ok((entry.visit)(visitor)),
+            None => {
+                let names : Vec<&str> = self.schedulers.keys().map(|n| n.as_str()).collect();
+                Err(format!("unknown scheduler {} (known: {})", name, names.join(", ")))
+            },
+        }
+    }
+}
+
+impl<V:SchedulerVisitor> Default for SchedulerRegistry<V> {
+    fn default() -> Self {
+        SchedulerRegistry::new()
+    }
+}
@@ -1,13 +1,37 @@
-use machine::simtx::{MAX_TPW, Warp, Path, scheduler::SimtxScheduler};
+use machine::simtx::{Warp, Path, scheduler::SimtxScheduler};
+use types::BitSet;
 
-#[derive(Clone, Default)]
+const POOL_SIZE : usize = 512;
+
+/// Shares a pool of `pool` cycles between the paths, halving the share with
+/// the rank of the PC of the path.
+#[derive(Clone)]
 pub struct Scheduler {
-    slices:[usize;MAX_TPW],
+    // one slice per path, there are at most as many paths as threads
+    slices:Vec<usize>,
     pointer:usize,
+
+    pool:usize,
+}
+
+impl std::default::Default for Scheduler {
+    fn default() -> Self {
+        Scheduler { slices:Vec::new(), pointer:0, pool:POOL_SIZE }
+    }
 }
 
 impl SimtxScheduler for Scheduler {
-    fn schedule(simulator:&mut Warp<Self>) -> Option<usize> {
+    fn set_param(&mut self, name:&str, value:&str) -> Result<(), String> {
+        match name {
+            "pool" => self.pool = value.parse().map_err(|_| String::from("pool must be a number"))?,
+            _ => return Err(format!("unknown parameter {} of the timeshare scheduler", name)),
+        }
+        Ok(())
+    }
+
+    fn schedule<BV:BitSet>(simulator:&mut Warp<Self, BV>) -> Option<usize> {
+        let max_paths = BV::SIZE as usize;
+        simulator.scheduler.slices.resize(max_paths, 0);
 
         //
         // This is a first attempt
@@ -25,23 +49,23 @@ impl SimtxScheduler for Scheduler {
                 *remaining -= 1;
                 break Some(pointer)
             }
-            simulator.scheduler.pointer = (pointer + 1) % MAX_TPW;
+            simulator.scheduler.pointer = (pointer + 1) % max_paths;
             clock += 1;
-            if clock == MAX_TPW { break None }
+            if clock == max_paths { break None }
         };
 
         // If there are no more path to run
         if path.is_none() || simulator.schedule_invalidated {
 
             // Compute a new time share
-            let mut paths : Vec<(usize, Path)> = simulator.paths.iter()
+            let mut paths : Vec<(usize, Path<BV>)> = simulator.paths.iter()
                 .cloned()
                 .enumerate()
                 .collect();
             paths.sort_by_key(|(_, p)| p.fetch_pc);
 
             let mut decreasing = 1f32;
-            let pool_size = 512f32;
+            let pool_size = simulator.scheduler.pool as f32;
             let mut pool = pool_size as usize;
             let probas : Vec<(usize, f32)> = paths.iter()
                 .map(|(i,_)| { decreasing /= 2f32; (*i, decreasing) })
@@ -72,9 +96,9 @@ impl SimtxScheduler for Scheduler {
                     *remaining -= 1;
                     break Some(pointer)
                 }
-                simulator.scheduler.pointer = (pointer + 1) % MAX_TPW;
+                simulator.scheduler.pointer = (pointer + 1) % max_paths;
                 clock += 1;
-                if clock == MAX_TPW { break None }
+                if clock == max_paths { break None }
             };
             simulator.schedule_invalidated = false;
         }
@@ -0,0 +1,197 @@
+//! How well the warps ran in SIMD: the active lanes of the instructions
+//! issued, the divergences of the branches and how long their threads took
+//! to reconverge, the paths of the warps over time, the switches of the
+//! schedulers and the waits in barriers. See `Machine::simt_stats`.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fmt::Write;
+
+/// What a warp did.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WarpStats {
+    /// Instructions issued, and the lanes active in them.
+    pub instructions:u64,
+    pub active_lanes:u64,
+    /// Paths of the warp, added over the cycles, and the most at once.
+    pub path_cycles:u64,
+    pub max_paths:usize,
+    /// Instructions issued for other threads than the previous one of the
+    /// warp.
+    pub scheduler_switches:u64,
+    /// Cycles spent in a barrier, added over the threads.
+    pub barrier_wait:u64,
+}
+
+impl WarpStats {
+    /// Active lanes over the lanes of the instructions issued.
+    pub fn simd_efficiency(&self, tpw:usize) -> f64 {
+        if self.instructions == 0 { 1. } else { self.active_lanes as f64 / (self.instructions * tpw as u64) as f64 }
+    }
+
+    /// Paths per cycle, over `cycles` cycles.
+    pub fn average_paths(&self, cycles:u64) -> f64 {
+        if cycles == 0 { 0. } else { self.path_cycles as f64 / cycles as f64 }
+    }
+
+    pub fn add(&mut self, other:&WarpStats) {
+        self.instructions += other.instructions;
+        self.active_lanes += other.active_lanes;
+        self.path_cycles += other.path_cycles;
+        self.max_paths = self.max_paths.max(other.max_paths);
+        self.scheduler_switches += other.scheduler_switches;
+        self.barrier_wait += other.barrier_wait;
+    }
+}
+
+/// What happened at a conditional branch or an indirect jump.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BranchStats {
+    pub executions:u64,
+    pub divergences:u64,
+    /// Divergences whose threads met again in a path, and the cycles it
+    /// took them, added.
+    pub reconvergences:u64,
+    pub reconvergence_cycles:u64,
+}
+
+impl BranchStats {
+    pub fn divergence_rate(&self) -> f64 {
+        if self.executions == 0 { 0. } else { self.divergences as f64 / self.executions as f64 }
+    }
+
+    /// Cycles from a divergence to the reconvergence of its threads.
+    pub fn reconvergence_distance(&self) -> f64 {
+        if self.reconvergences == 0 { 0. } else { self.reconvergence_cycles as f64 / self.reconvergences as f64 }
+    }
+
+    pub fn add(&mut self, other:&BranchStats) {
+        self.executions += other.executions;
+        self.divergences += other.divergences;
+        self.reconvergences += other.reconvergences;
+        self.reconvergence_cycles += other.reconvergence_cycles;
+    }
+}
+
+/// The statistics of a run.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SimtStats {
+    pub tpw:usize,
+    pub cycles:u64,
+    pub warps:Vec<WarpStats>,
+    /// By PC, over all the warps.
+    pub branches:BTreeMap<i32, BranchStats>,
+    /// Where the branches are, as given by the symbolizer.
+    pub locations:BTreeMap<i32, String>,
+    /// Paths of all the warps, sampled every few cycles.
+    pub path_samples:Vec<(u64, usize)>,
+}
+
+fn escape(s:&str) -> String {
+    let mut escaped = String::new();
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => { let _ = write!(escaped, "\\u{:04x}", c as u32); },
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+impl SimtStats {
+    /// All the warps together.
+    pub fn total(&self) -> WarpStats {
+        self.warps.iter().fold(WarpStats::default(), |mut t, w| { t.add(w); t })
+    }
+
+    pub fn simd_efficiency(&self) -> f64 {
+        self.total().simd_efficiency(self.tpw)
+    }
+
+    fn warp_json(&self, w:&WarpStats) -> String {
+        format!("{{\"instructions\":{},\"active_lanes\":{},\"simd_efficiency\":{},\"average_paths\":{},\"max_paths\":{},\"scheduler_switches\":{},\"barrier_wait\":{}}}"
+                , w.instructions, w.active_lanes, w.simd_efficiency(self.tpw), w.average_paths(self.cycles)
+                , w.max_paths, w.scheduler_switches, w.barrier_wait)
+    }
+
+    pub fn to_json(&self) -> String {
+        let warps : Vec<String> = self.warps.iter().map(|w| self.warp_json(w)).collect();
+        let branches : Vec<String> = self.branches.iter().map(|(pc, b)| {
+            format!("{{\"pc\":\"0x{:x}\",\"location\":\"{}\",\"executions\":{},\"divergences\":{},\"divergence_rate\":{},\"reconvergences\":{},\"reconvergence_distance\":{}}}"
+                    , *pc as u32, escape(self.locations.get(pc).map_or("", |l| l.as_str()))
+                    , b.executions, b.divergences, b.divergence_rate()
+                    , b.reconvergences, b.reconvergence_distance())
+        }).collect();
+        let samples : Vec<String> = self.path_samples.iter()
+            .map(|(cycle, paths)| format!("[{},{}]", cycle, paths))
+            .collect();
+        format!("{{\"tpw\":{},\"cycles\":{},\"total\":{},\"warps\":[{}],\"branches\":[{}],\"path_samples\":[{}]}}\n"
+                , self.tpw, self.cycles, self.warp_json(&self.total())
+                , warps.join(","), branches.join(","), samples.join(","))
+    }
+
+    /// One value per line: `scope,id,metric,value`, the scope being
+    /// `global`, `warp` (by id), `branch` (by PC) or `paths` (by cycle).
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("scope,id,metric,value\n");
+        let warp = |csv:&mut String, scope:&str, id:String, w:&WarpStats| {
+            let metrics : [(&str, String); 7] = [
+                ("instructions", w.instructions.to_string()),
+                ("active_lanes", w.active_lanes.to_string()),
+                ("simd_efficiency", w.simd_efficiency(self.tpw).to_string()),
+                ("average_paths", w.average_paths(self.cycles).to_string()),
+                ("max_paths", w.max_paths.to_string()),
+                ("scheduler_switches", w.scheduler_switches.to_string()),
+                ("barrier_wait", w.barrier_wait.to_string()),
+            ];
+            for (metric, value) in &metrics {
+                let _ = writeln!(csv, "{},{},{},{}", scope, id, metric, value);
+            }
+        };
+        let _ = writeln!(csv, "global,,tpw,{}", self.tpw);
+        let _ = writeln!(csv, "global,,cycles,{}", self.cycles);
+        warp(&mut csv, "global", String::new(), &self.total());
+        for (wid, w) in self.warps.iter().enumerate() {
+            warp(&mut csv, "warp", wid.to_string(), w);
+        }
+        for (pc, b) in &self.branches {
+            let metrics : [(&str, String); 6] = [
+                ("executions", b.executions.to_string()),
+                ("divergences", b.divergences.to_string()),
+                ("divergence_rate", b.divergence_rate().to_string()),
+                ("reconvergences", b.reconvergences.to_string()),
+                ("reconvergence_distance", b.reconvergence_distance().to_string()),
+                ("reconvergence_cycles", b.reconvergence_cycles.to_string()),
+            ];
+            for (metric, value) in &metrics {
+                let _ = writeln!(csv, "branch,0x{:x},{},{}", *pc as u32, metric, value);
+            }
+        }
+        for (cycle, paths) in &self.path_samples {
+            let _ = writeln!(csv, "paths,{},paths,{}", cycle, paths);
+        }
+        csv
+    }
+}
+
+impl fmt::Display for SimtStats {
+    fn fmt(&self, f:&mut fmt::Formatter<'_>) -> fmt::Result {
+        let total = self.total();
+        writeln!(f, "=== SIMT STATS: {} CYCLES, {:.1}% SIMD EFFICIENCY ===", self.cycles, self.simd_efficiency() * 100.)?;
+        for (wid, w) in self.warps.iter().enumerate() {
+            writeln!(f, "warp {}: {} instructions, {:.1}% SIMD efficiency, {:.2} paths on average ({} at most), {} scheduler switches, {} cycles in barriers"
+                     , wid, w.instructions, w.simd_efficiency(self.tpw) * 100., w.average_paths(self.cycles)
+                     , w.max_paths, w.scheduler_switches, w.barrier_wait)?;
+        }
+        writeln!(f, "total: {} instructions, {} active lanes, {} scheduler switches, {} cycles in barriers"
+                 , total.instructions, total.active_lanes, total.scheduler_switches, total.barrier_wait)?;
+        for (pc, b) in self.branches.iter().filter(|(_, b)| b.divergences > 0) {
+            let location = self.locations.get(pc).cloned().unwrap_or_else(|| format!("0x{:x}", pc));
+            writeln!(f, "{}: diverged {} times out of {}, reconverged after {:.1} cycles on average"
+                     , location, b.divergences, b.executions, b.reconvergence_distance())?;
+        }
+        Ok(())
+    }
+}
@@ -1,8 +1,8 @@
-{
-    let ids : Vec<usize> = self.warps[wid].alive_cores_ids().collect();
-    if ids.len() != 1 { panic!("GOMP_parallel() called by more than one thread") }
+use super::*;
 
-    let id = ids[0];
-    let core = &self.warps[wid].cores[id];
+pub fn GOMP_parallel<S:SimtxScheduler, BV:BitSet>(m:&mut Machine<S, BV>, call:&HostCallContext, _mem:&mut dyn Memory) -> HostCallAction {
+    let id = call.single_lane();
+    let core = &m.warps[call.warp].cores[id];
     let _function = core.registers[10];
+    HostCallAction::Return
 }
@@ -0,0 +1,18 @@
+use super::*;
+
+pub fn __errno_location<S:SimtxScheduler, BV:BitSet>(m:&mut Machine<S, BV>, call:&HostCallContext, mem:&mut dyn Memory) -> HostCallAction {
+    for i in call.lanes() {
+        let tid = call.tid(i);
+        let addr = match m.errno_locations.get(&tid) {
+            Some(addr) => *addr,
+            None => {
+                let addr = m.malloc(mem, 4);
+                mem.set_32(addr, 0);
+                m.errno_locations.insert(tid, addr);
+                addr
+            },
+        };
+        m.lane(call, i).ret(addr as i32);
+    }
+    HostCallAction::Return
+}
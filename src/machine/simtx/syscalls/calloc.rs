@@ -0,0 +1,26 @@
+use super::*;
+
+pub fn calloc<S:SimtxScheduler, BV:BitSet>(m:&mut Machine<S, BV>, call:&HostCallContext, mem:&mut dyn Memory) -> HostCallAction {
+    for i in call.lanes() {
+        let nmemb = m.lane(call, i).ptr(0);
+        let size = m.lane(call, i).ptr(1);
+
+        // the guest size_t is 32 bits wide
+        let ptr = match nmemb.checked_mul(size).filter(|s| *s <= u32::MAX as usize) {
+            Some(total) => {
+                let ptr = m.malloc(mem, total);
+                if ptr != 0 {
+                    m.track_alloc(ptr, total, call.pc, call.tid(i));
+                    write_bytes(mem, ptr, &vec![0; total]);
+                }
+                ptr
+            },
+            None => 0,
+        };
+        if ptr == 0 {
+            set_errno(m, mem, call.tid(i), errno::ENOMEM);
+        }
+        m.lane(call, i).ret(ptr as i32);
+    }
+    HostCallAction::Return
+}
@@ -0,0 +1,10 @@
+use super::*;
+
+pub fn close<S:SimtxScheduler, BV:BitSet>(m:&mut Machine<S, BV>, call:&HostCallContext, mem:&mut dyn Memory) -> HostCallAction {
+    for i in call.lanes() {
+        let fd = m.lane(call, i).int(0);
+        let ret = m.files.close(fd).map(|_| 0);
+        ret_result(m, call, i, mem, ret, -1);
+    }
+    HostCallAction::Return
+}
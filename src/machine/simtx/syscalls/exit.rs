@@ -1,3 +1,6 @@
-{
-    self.warps[wid].set_pc(pathid, 0);
+use super::*;
+
+pub fn exit<S:SimtxScheduler, BV:BitSet>(m:&mut Machine<S, BV>, call:&HostCallContext, _mem:&mut dyn Memory) -> HostCallAction {
+    m.warps[call.warp].set_pc(call.path, 0);
+    HostCallAction::Jumped
 }
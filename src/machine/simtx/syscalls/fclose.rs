@@ -0,0 +1,10 @@
+use super::*;
+
+pub fn fclose<S:SimtxScheduler, BV:BitSet>(m:&mut Machine<S, BV>, call:&HostCallContext, mem:&mut dyn Memory) -> HostCallAction {
+    for i in call.lanes() {
+        let fd = files::fd_of(m.lane(call, i).ptr(0) as u32);
+        let ret = m.files.close(fd).map(|_| 0);
+        ret_result(m, call, i, mem, ret, EOF);
+    }
+    HostCallAction::Return
+}
@@ -0,0 +1,10 @@
+use super::*;
+
+pub fn feof<S:SimtxScheduler, BV:BitSet>(m:&mut Machine<S, BV>, call:&HostCallContext, mem:&mut dyn Memory) -> HostCallAction {
+    for i in call.lanes() {
+        let fd = files::fd_of(m.lane(call, i).ptr(0) as u32);
+        let ret = m.files.eof(fd).map(|eof| eof as i32);
+        ret_result(m, call, i, mem, ret, 0);
+    }
+    HostCallAction::Return
+}
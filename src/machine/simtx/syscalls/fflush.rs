@@ -0,0 +1,15 @@
+use super::*;
+
+pub fn fflush<S:SimtxScheduler, BV:BitSet>(m:&mut Machine<S, BV>, call:&HostCallContext, mem:&mut dyn Memory) -> HostCallAction {
+    for i in call.lanes() {
+        let stream = m.lane(call, i).ptr(0) as u32;
+        // fflush(NULL) flushes every stream, which are never buffered here
+        let ret = if stream == 0 {
+            Ok(0)
+        } else {
+            m.files.flush(files::fd_of(stream)).map(|_| 0)
+        };
+        ret_result(m, call, i, mem, ret, EOF);
+    }
+    HostCallAction::Return
+}
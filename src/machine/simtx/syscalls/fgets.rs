@@ -1,50 +1,36 @@
-{
-    let _cid = self.warps[wid].get_single_core_id();
-    let core = &mut self.warps[wid].cores[wid];
-    let mut addr = core.registers[10] as usize;
-    let size = core.registers[11] as usize;
-    let fp = core.registers[12];
+use super::*;
 
-    match fp {
-        0 | 2 => panic!("reading from stdout or stderr"),
-        1 => {
-            let mut buf = String::new();
-            std::io::stdin().read_line(&mut buf).unwrap();
-            for byte in buf.bytes() {
-                mem.set_8(addr, byte);
-                addr += 1;
+pub fn fgets<S:SimtxScheduler, BV:BitSet>(m:&mut Machine<S, BV>, call:&HostCallContext, mem:&mut dyn Memory) -> HostCallAction {
+    for i in call.lanes() {
+        let buf = m.lane(call, i).ptr(0);
+        let size = m.lane(call, i).int(1);
+        let fd = files::fd_of(m.lane(call, i).ptr(2) as u32);
+
+        let mut line = Vec::new();
+        let mut error = None;
+        while (line.len() as i32) < size - 1 {
+            match m.files.getc(fd) {
+                Ok(Some(b)) => {
+                    line.push(b);
+                    if b == b'\n' { break }
+                },
+                Ok(None) => break,
+                Err(e) => { error = Some(e); break },
             }
-            mem.set_8(addr, 0);
-        },
-        n => {
-            self.file_handles.get_mut(&n).map(|file| {
-                let mut bytes = Read::by_ref(file).bytes();
-                    let mut i = 0;
-                    while i < size {
-                        match bytes.next() {
-                            Some(Ok(b)) => {
-                                mem.set_8(addr + i, b);
-                                if b as char == '\n' { break }
-                            },
-                            _ => break,
-                        }
-                        i += 1;
-                    }
-                    mem.set_8(addr + i, 0);
+        }
 
-                    core.registers[10] =
-                        if i == 0 {
-                            0
-                        } else {
-                            addr as i32
-                        };
-            })
-            .or_else(|| {
-                println!("File finished");
-                Some(core.registers[10] = 0)
-            });
-        },
+        if let Some(e) = error {
+            ret_result(m, call, i, mem, Err(e), 0);
+        } else if line.is_empty() && size > 1 {
+            // end of file before anything was read
+            m.lane(call, i).ret(0);
+        } else {
+            if size > 0 {
+                write_bytes(mem, buf, &line);
+                mem.set_8(buf + line.len(), 0);
+            }
+            m.lane(call, i).ret(buf as i32);
+        }
     }
-
-    self.warps[wid].advance_pc(pathid, advance);
+    HostCallAction::Return
 }
@@ -0,0 +1,326 @@
+//! C `printf` format strings, as understood by the printf family of host
+//! calls.
+
+use memory::Memory;
+
+/// Walks through the variadic arguments of a call, following the RV32
+/// ILP32/ILP32D calling convention: variadic arguments are always passed like
+/// integers, in `a0`-`a7` then on the stack, and 64 bit values (`double`,
+/// `long long`) use an aligned pair of registers or an 8 bytes aligned stack
+/// slot.
+pub struct VarArgs<'a> {
+    regs:[i32;8],
+    sp:usize,
+    // byte offset in a0-a7 followed by the stack
+    offset:usize,
+    mem:&'a dyn Memory,
+}
+
+impl<'a> VarArgs<'a> {
+    /// `first` is the index of the first variadic argument register (1 for
+    /// `printf`, which has a single fixed argument).
+    pub fn new(regs:[i32;8], sp:usize, first:usize, mem:&'a dyn Memory) -> VarArgs<'a> {
+        VarArgs { regs, sp, offset:first * 4, mem }
+    }
+
+    /// Next 32 bits argument (`int`, `long` or pointer).
+    pub fn next_32(&mut self) -> u32 {
+        let offset = self.offset;
+        self.offset += 4;
+        if offset < 32 {
+            self.regs[offset / 4] as u32
+        } else {
+            self.mem.get_32(self.sp + offset - 32)
+        }
+    }
+
+    fn next_64(&mut self) -> u64 {
+        self.offset = (self.offset + 7) & !7;
+        let lo = self.next_32() as u64;
+        let hi = self.next_32() as u64;
+        (hi << 32) | lo
+    }
+
+    fn next_double(&mut self) -> f64 {
+        f64::from_bits(self.next_64())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Length { Char, Short, Int, LongLong }
+
+struct Spec {
+    left:bool,
+    plus:bool,
+    space:bool,
+    alt:bool,
+    zero:bool,
+    width:usize,
+    precision:Option<usize>,
+    length:Length,
+}
+
+/// Formats `fmt` with the arguments of `args`. `%n` stores are returned with
+/// the output (address, count) since the memory is only borrowed here.
+pub fn format(fmt:&[u8], args:&mut VarArgs) -> (Vec<u8>, Vec<(usize, i32)>) {
+    let mut out = Vec::new();
+    let mut stores = Vec::new();
+    let mut i = 0;
+
+    while i < fmt.len() {
+        if fmt[i] != b'%' {
+            out.push(fmt[i]);
+            i += 1;
+            continue
+        }
+        let start = i;
+        i += 1;
+
+        let mut spec = Spec {
+            left:false, plus:false, space:false, alt:false, zero:false,
+            width:0, precision:None, length:Length::Int,
+        };
+
+        // flags
+        while i < fmt.len() {
+            match fmt[i] {
+                b'-' => spec.left = true,
+                b'+' => spec.plus = true,
+                b' ' => spec.space = true,
+                b'#' => spec.alt = true,
+                b'0' => spec.zero = true,
+                _ => break,
+            }
+            i += 1;
+        }
+
+        // width
+        if i < fmt.len() && fmt[i] == b'*' {
+            let w = args.next_32() as i32;
+            if w < 0 { spec.left = true }
+            spec.width = w.unsigned_abs() as usize;
+            i += 1;
+        } else {
+            while i < fmt.len() && fmt[i].is_ascii_digit() {
+                spec.width = spec.width * 10 + (fmt[i] - b'0') as usize;
+                i += 1;
+            }
+        }
+
+        // precision
+        if i < fmt.len() && fmt[i] == b'.' {
+            i += 1;
+            if i < fmt.len() && fmt[i] == b'*' {
+                let p = args.next_32() as i32;
+                spec.precision = if p < 0 { None } else { Some(p as usize) };
+                i += 1;
+            } else {
+                let mut p = 0;
+                while i < fmt.len() && fmt[i].is_ascii_digit() {
+                    p = p * 10 + (fmt[i] - b'0') as usize;
+                    i += 1;
+                }
+                spec.precision = Some(p);
+            }
+        }
+
+        // length modifier (long, size_t and pointers are 32 bits wide on RV32)
+        while i < fmt.len() {
+            match fmt[i] {
+                b'h' => spec.length = if spec.length == Length::Short { Length::Char } else { Length::Short },
+                b'l' => spec.length = if spec.length == Length::Int && fmt[i-1] == b'l' { Length::LongLong } else { Length::Int },
+                b'q' | b'j' => spec.length = Length::LongLong,
+                b'z' | b't' | b'L' => {},
+                _ => break,
+            }
+            i += 1;
+        }
+
+        if i >= fmt.len() {
+            out.extend_from_slice(&fmt[start..]);
+            break
+        }
+        let conv = fmt[i];
+        i += 1;
+
+        match conv {
+            b'%' => out.push(b'%'),
+            b'd' | b'i' => {
+                let value = match spec.length {
+                    Length::LongLong => args.next_64() as i64,
+                    Length::Int => args.next_32() as i32 as i64,
+                    Length::Short => args.next_32() as i16 as i64,
+                    Length::Char => args.next_32() as i8 as i64,
+                };
+                let sign = if value < 0 { "-" } else if spec.plus { "+" } else if spec.space { " " } else { "" };
+                let digits = integer_digits(value.unsigned_abs(), 10, false, spec.precision);
+                pad_number(&mut out, &spec, sign, &digits, spec.precision.is_none());
+            },
+            b'u' | b'o' | b'x' | b'X' => {
+                let value = match spec.length {
+                    Length::LongLong => args.next_64(),
+                    Length::Int => args.next_32() as u64,
+                    Length::Short => args.next_32() as u16 as u64,
+                    Length::Char => args.next_32() as u8 as u64,
+                };
+                let radix = match conv { b'o' => 8, b'u' => 10, _ => 16 };
+                let mut digits = integer_digits(value, radix, conv == b'X', spec.precision);
+                let mut prefix = "";
+                if spec.alt {
+                    match conv {
+                        b'o' if !digits.starts_with('0') => digits.insert(0, '0'),
+                        b'x' if value != 0 => prefix = "0x",
+                        b'X' if value != 0 => prefix = "0X",
+                        _ => {},
+                    }
+                }
+                pad_number(&mut out, &spec, prefix, &digits, spec.precision.is_none());
+            },
+            b'c' => {
+                let c = [args.next_32() as u8];
+                pad(&mut out, &spec, &c);
+            },
+            b's' => {
+                let addr = args.next_32() as usize;
+                let mut s = Vec::new();
+                if addr == 0 {
+                    s.extend_from_slice(b"(null)");
+                } else {
+                    let mut addr = addr;
+                    loop {
+                        if spec.precision.map_or(false, |p| s.len() >= p) { break }
+                        let byte = args.mem.get_8(addr);
+                        if byte == 0 { break }
+                        s.push(byte);
+                        addr += 1;
+                    }
+                }
+                pad(&mut out, &spec, &s);
+            },
+            b'p' => {
+                let value = args.next_32();
+                if value == 0 {
+                    pad(&mut out, &spec, b"(nil)");
+                } else {
+                    let digits = integer_digits(value as u64, 16, false, spec.precision);
+                    pad_number(&mut out, &spec, "0x", &digits, spec.precision.is_none());
+                }
+            },
+            b'f' | b'F' | b'e' | b'E' | b'g' | b'G' => {
+                let value = args.next_double();
+                let sign = if value.is_sign_negative() { "-" } else if spec.plus { "+" } else if spec.space { " " } else { "" };
+                let upper = conv.is_ascii_uppercase();
+                if !value.is_finite() {
+                    let s = match (value.is_nan(), upper) {
+                        (true, false) => "nan", (true, true) => "NAN",
+                        (false, false) => "inf", (false, true) => "INF",
+                    };
+                    pad_number(&mut out, &spec, sign, s, false);
+                } else {
+                    let value = value.abs();
+                    let precision = spec.precision.unwrap_or(6);
+                    let digits = match conv {
+                        b'f' | b'F' => fixed(value, precision, spec.alt),
+                        b'e' | b'E' => exponent(value, precision, spec.alt, upper),
+                        _ => general(value, precision, spec.alt, upper),
+                    };
+                    pad_number(&mut out, &spec, sign, &digits, true);
+                }
+            },
+            b'n' => {
+                let addr = args.next_32() as usize;
+                stores.push((addr, out.len() as i32));
+            },
+            _ => out.extend_from_slice(&fmt[start..i]),
+        }
+    }
+
+    (out, stores)
+}
+
+fn integer_digits(value:u64, radix:u32, upper:bool, precision:Option<usize>) -> String {
+    let mut digits = match radix {
+        8 => format!("{:o}", value),
+        16 if upper => format!("{:X}", value),
+        16 => format!("{:x}", value),
+        _ => format!("{}", value),
+    };
+    match precision {
+        Some(0) if value == 0 => digits.clear(),
+        Some(p) => while digits.len() < p { digits.insert(0, '0') },
+        None => {},
+    }
+    digits
+}
+
+fn fixed(value:f64, precision:usize, alt:bool) -> String {
+    let mut s = format!("{:.*}", precision, value);
+    if alt && precision == 0 { s.push('.') }
+    s
+}
+
+// Rust writes "1.5e2" where C writes "1.5e+02"
+fn exponent(value:f64, precision:usize, alt:bool, upper:bool) -> String {
+    let s = format!("{:.*e}", precision, value);
+    let (mantissa, exp) = s.split_at(s.find('e').unwrap());
+    let exp : i32 = exp[1..].parse().unwrap();
+    let mut ret = String::from(mantissa);
+    if alt && precision == 0 { ret.push('.') }
+    ret.push(if upper { 'E' } else { 'e' });
+    ret.push(if exp < 0 { '-' } else { '+' });
+    ret.push_str(&format!("{:02}", exp.abs()));
+    ret
+}
+
+fn general(value:f64, precision:usize, alt:bool, upper:bool) -> String {
+    let p = if precision == 0 { 1 } else { precision };
+    // the exponent once rounded to p significant digits
+    let e = format!("{:.*e}", p - 1, value);
+    let x : i32 = e[e.find('e').unwrap() + 1..].parse().unwrap();
+
+    let mut s = if x < -4 || x >= p as i32 {
+        exponent(value, p - 1, alt, upper)
+    } else {
+        fixed(value, (p as i32 - 1 - x) as usize, alt)
+    };
+
+    if !alt {
+        let exp_at = s.find(['e', 'E']).unwrap_or(s.len());
+        let (mantissa, exp) = s.split_at(exp_at);
+        let mantissa = if mantissa.contains('.') {
+            mantissa.trim_end_matches('0').trim_end_matches('.')
+        } else {
+            mantissa
+        };
+        s = format!("{}{}", mantissa, exp);
+    }
+    s
+}
+
+fn pad(out:&mut Vec<u8>, spec:&Spec, s:&[u8]) {
+    let fill = spec.width.saturating_sub(s.len());
+    if !spec.left { out.extend(std::iter::repeat(b' ').take(fill)) }
+    out.extend_from_slice(s);
+    if spec.left { out.extend(std::iter::repeat(b' ').take(fill)) }
+}
+
+// `zeroable` is false when the '0' flag does not apply (integers with a
+// precision, inf and nan)
+fn pad_number(out:&mut Vec<u8>, spec:&Spec, prefix:&str, digits:&str, zeroable:bool) {
+    let len = prefix.len() + digits.len();
+    let fill = spec.width.saturating_sub(len);
+    let zero = zeroable && spec.zero && !spec.left;
+    if spec.left {
+        out.extend_from_slice(prefix.as_bytes());
+        out.extend_from_slice(digits.as_bytes());
+        out.extend(std::iter::repeat(b' ').take(fill));
+    } else if zero {
+        out.extend_from_slice(prefix.as_bytes());
+        out.extend(std::iter::repeat(b'0').take(fill));
+        out.extend_from_slice(digits.as_bytes());
+    } else {
+        out.extend(std::iter::repeat(b' ').take(fill));
+        out.extend_from_slice(prefix.as_bytes());
+        out.extend_from_slice(digits.as_bytes());
+    }
+}
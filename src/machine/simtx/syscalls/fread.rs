@@ -0,0 +1,31 @@
+use super::*;
+
+pub fn fread<S:SimtxScheduler, BV:BitSet>(m:&mut Machine<S, BV>, call:&HostCallContext, mem:&mut dyn Memory) -> HostCallAction {
+    for i in call.lanes() {
+        let buf = m.lane(call, i).ptr(0);
+        let size = m.lane(call, i).ptr(1);
+        let nmemb = m.lane(call, i).ptr(2);
+        let fd = files::fd_of(m.lane(call, i).ptr(3) as u32);
+
+        // read by chunks, the guest giving any size
+        let table = &mut m.files;
+        let ret = size.checked_mul(nmemb).ok_or(errno::EINVAL).and_then(|total| {
+            let mut bytes = vec![0; total.min(files::CHUNK)];
+            let mut done = 0;
+            while done < total {
+                let want = (total - done).min(bytes.len());
+                let n = match table.read_full(fd, &mut bytes[..want]) {
+                    Ok(n) => n,
+                    Err(e) if done == 0 => return Err(e),
+                    Err(_) => break,
+                };
+                write_bytes(mem, buf + done, &bytes[..n]);
+                done += n;
+                if n < want { break }
+            }
+            Ok(done.checked_div(size).unwrap_or(0) as i32)
+        });
+        ret_result(m, call, i, mem, ret, 0);
+    }
+    HostCallAction::Return
+}
@@ -1,7 +1,14 @@
-{
-    for i in self.warps[wid].alive_cores_ids() {
-        let ptr = self.warps[wid].cores[i].registers[10];
-        self.free(ptr as usize);
+use super::*;
+
+pub fn free<S:SimtxScheduler, BV:BitSet>(m:&mut Machine<S, BV>, call:&HostCallContext, _mem:&mut dyn Memory) -> HostCallAction {
+    for i in call.lanes() {
+        let ptr = m.lane(call, i).ptr(0);
+        if ptr == 0 { continue }
+        if let Err(e) = m.heap.free(ptr) {
+            m.heap_error(e, call.pc);
+            break
+        }
+        m.track_free(ptr, call.pc, call.tid(i));
     }
-    self.warps[wid].advance_pc(pathid, advance);
+    HostCallAction::Return
 }
@@ -0,0 +1,63 @@
+use super::*;
+use super::format::VarArgs;
+use super::scan::{scan, ScanInput, Stored};
+use machine::simtx::files::FileTable;
+
+struct FileInput<'a> {
+    files:&'a mut FileTable,
+    fd:i32,
+    error:Option<i32>,
+}
+
+impl<'a> ScanInput for FileInput<'a> {
+    fn getc(&mut self) -> Option<u8> {
+        match self.files.getc(self.fd) {
+            Ok(c) => c,
+            Err(e) => { self.error = Some(e); None },
+        }
+    }
+
+    fn ungetc(&mut self, byte:u8) {
+        let _ = self.files.ungetc(self.fd, byte);
+    }
+}
+
+pub fn fscanf<S:SimtxScheduler, BV:BitSet>(m:&mut Machine<S, BV>, call:&HostCallContext, mem:&mut dyn Memory) -> HostCallAction {
+    for i in call.lanes() {
+        let (count, stores, error) = {
+            let fmt = read_c_bytes(mem, m.lane(call, i).ptr(1));
+            let fd = files::fd_of(m.lane(call, i).ptr(0) as u32);
+            let (regs, sp) = {
+                let args = m.lane(call, i);
+                let mut regs = [0;8];
+                for (n, reg) in regs.iter_mut().enumerate() {
+                    *reg = args.int(n);
+                }
+                (regs, args.sp())
+            };
+            let mut input = FileInput { files:&mut m.files, fd, error:None };
+            let mut args = VarArgs::new(regs, sp, 2, mem);
+            let (count, stores) = scan(&fmt, &mut input, &mut args);
+            (count, stores, input.error)
+        };
+
+        for (addr, value) in stores {
+            match value {
+                Stored::Byte(v) => mem.set_8(addr, v),
+                Stored::Half(v) => mem.set_16(addr, v),
+                Stored::Word(v) => mem.set_32(addr, v),
+                Stored::Double(v) => {
+                    mem.set_32(addr, v as u32);
+                    mem.set_32(addr + 4, (v >> 32) as u32);
+                },
+                Stored::Bytes(v) => write_bytes(mem, addr, &v),
+            }
+        }
+
+        match error {
+            Some(e) if count <= 0 => ret_result(m, call, i, mem, Err(e), EOF),
+            _ => m.lane(call, i).ret(count),
+        }
+    }
+    HostCallAction::Return
+}
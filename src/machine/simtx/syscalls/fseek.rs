@@ -0,0 +1,12 @@
+use super::*;
+
+pub fn fseek<S:SimtxScheduler, BV:BitSet>(m:&mut Machine<S, BV>, call:&HostCallContext, mem:&mut dyn Memory) -> HostCallAction {
+    for i in call.lanes() {
+        let fd = files::fd_of(m.lane(call, i).ptr(0) as u32);
+        let offset = m.lane(call, i).int(1) as i64;
+        let whence = m.lane(call, i).int(2);
+        let ret = m.files.seek(fd, offset, whence).map(|_| 0);
+        ret_result(m, call, i, mem, ret, -1);
+    }
+    HostCallAction::Return
+}
@@ -1,26 +1,16 @@
-{
-    for i in self.warps[wid].alive_cores_ids() {
-        let core = &mut self.warps[wid].cores[i];
-        let file_desc = core.registers[13];
-        let mut str_addr = core.registers[10];
-        let mut to_write = String::new();
-        let mut byte = mem.get_8(str_addr as usize);
-        while byte != 0 {
-            to_write.push(byte as char);
-            str_addr += 1;
-            byte = mem.get_8(str_addr as usize);
-        }
-        match file_desc {
-            0 => print!("{}", to_write),
-            1 => panic!("writing to stdin"),
-            2 => eprint!("{}", to_write),
-            n => self.file_handles.get_mut(&n).map(|f| {
-                match f.write(to_write.as_bytes()) {
-                    Err(_) => {},
-                    Ok(_size) => {},
-                }
-            }).expect("[SIM] Cannot open file"),
-        };
+use super::*;
+
+pub fn fwrite<S:SimtxScheduler, BV:BitSet>(m:&mut Machine<S, BV>, call:&HostCallContext, mem:&mut dyn Memory) -> HostCallAction {
+    for i in call.lanes() {
+        let buf = m.lane(call, i).ptr(0);
+        let size = m.lane(call, i).ptr(1);
+        let nmemb = m.lane(call, i).ptr(2);
+        let fd = files::fd_of(m.lane(call, i).ptr(3) as u32);
+
+        let ret = size.checked_mul(nmemb).ok_or(errno::EINVAL)
+            .and_then(|total| write_chunks(&mut m.files, mem, fd, buf, total))
+            .map(|n| n.checked_div(size).unwrap_or(0) as i32);
+        ret_result(m, call, i, mem, ret, 0);
     }
-    self.warps[wid].advance_pc(pathid, advance);
+    HostCallAction::Return
 }
@@ -1,10 +1,15 @@
-{
-    for i in self.warps[wid].alive_cores_ids() {
-        let size = self.warps[wid].cores[i].registers[10];
-        let ptr = self.malloc(mem.deref_mut(), size as usize);
-        self.warps[wid].cores[i].set_ri(10, ptr as i32);
-        #[cfg(debug_assertions)]
-        println!("MALLOC CALLED ADRESSE EST {:x}", ptr);
+use super::*;
+
+pub fn malloc<S:SimtxScheduler, BV:BitSet>(m:&mut Machine<S, BV>, call:&HostCallContext, mem:&mut dyn Memory) -> HostCallAction {
+    for i in call.lanes() {
+        let size = m.lane(call, i).ptr(0);
+        let ptr = m.malloc(mem, size);
+        if ptr == 0 {
+            set_errno(m, mem, call.tid(i), errno::ENOMEM);
+        } else {
+            m.track_alloc(ptr, size, call.pc, call.tid(i));
+        }
+        m.lane(call, i).ret(ptr as i32);
     }
-    self.warps[wid].advance_pc(pathid, advance);
+    HostCallAction::Return
 }
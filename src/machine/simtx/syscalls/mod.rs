@@ -0,0 +1,169 @@
+//! Emulation of the library functions called through the `.plt` of the
+//! simulated program. Every function is registered by `builtins()` under its
+//! exact symbol name.
+
+use machine::simtx::implem::{Machine, Path, Barrier, MachineF32};
+use machine::simtx::hostcall::{HostCalls, HostCallContext, HostCallAction};
+use machine::simtx::scheduler::SimtxScheduler;
+use types::BitSet;
+use machine::simtx::files::{self, errno, FileResult, FileTable};
+use memory::Memory;
+
+mod pthread_create;
+mod pthread_join;
+mod pthread_barrier_init;
+mod pthread_barrier_wait;
+mod puts;
+mod format;
+mod printf;
+mod malloc;
+mod calloc;
+mod realloc;
+mod posix_memalign;
+mod free;
+#[allow(non_snake_case)]
+mod GOMP_parallel;
+mod omp_get_num_threads;
+mod omp_get_thread_num;
+mod exit;
+mod strtof;
+mod strtol;
+mod strtok;
+mod scan;
+mod __errno_location;
+mod open;
+mod close;
+mod read;
+mod write;
+mod lseek;
+mod fopen;
+mod fclose;
+mod fread;
+mod fwrite;
+mod fgets;
+mod fscanf;
+mod fseek;
+mod ftell;
+mod feof;
+mod rewind;
+mod fflush;
+
+/// Returns the table of every host call emulated by the crate.
+pub fn builtins<S:SimtxScheduler, BV:BitSet>() -> HostCalls<S, BV> {
+    let mut calls = HostCalls::new();
+    calls.register("pthread_create", pthread_create::pthread_create);
+    calls.register("pthread_join", pthread_join::pthread_join);
+    calls.register("pthread_barrier_init", pthread_barrier_init::pthread_barrier_init);
+    calls.register("pthread_barrier_wait", pthread_barrier_wait::pthread_barrier_wait);
+    calls.register("puts", puts::puts);
+    calls.register("printf", printf::printf);
+    calls.register("fprintf", printf::fprintf);
+    calls.register("sprintf", printf::sprintf);
+    calls.register("snprintf", printf::snprintf);
+    calls.register("malloc", malloc::malloc);
+    calls.register("calloc", calloc::calloc);
+    calls.register("realloc", realloc::realloc);
+    calls.register("posix_memalign", posix_memalign::posix_memalign);
+    calls.register("free", free::free);
+    calls.register("GOMP_parallel", GOMP_parallel::GOMP_parallel);
+    calls.register("omp_get_num_threads", omp_get_num_threads::omp_get_num_threads);
+    calls.register("omp_get_thread_num", omp_get_thread_num::omp_get_thread_num);
+    calls.register("exit", exit::exit);
+    calls.register("strtof", strtof::strtof);
+    calls.register("strtol", strtol::strtol);
+    calls.register("strtok", strtok::strtok);
+    calls.register("__errno_location", __errno_location::__errno_location);
+    calls.register("open", open::open);
+    calls.register("close", close::close);
+    calls.register("read", read::read);
+    calls.register("write", write::write);
+    calls.register("lseek", lseek::lseek);
+    calls.register("fopen", fopen::fopen);
+    calls.register("fclose", fclose::fclose);
+    calls.register("fread", fread::fread);
+    calls.register("fwrite", fwrite::fwrite);
+    calls.register("fgets", fgets::fgets);
+    calls.register("fscanf", fscanf::fscanf);
+    calls.register("fseek", fseek::fseek);
+    calls.register("ftell", ftell::ftell);
+    calls.register("feof", feof::feof);
+    calls.register("rewind", rewind::rewind);
+    calls.register("fflush", fflush::fflush);
+    calls
+}
+
+/// Reads the NUL terminated string at `addr` in guest memory.
+fn read_string(mem:&dyn Memory, addr:usize) -> String {
+    let mut addr = addr;
+    let mut s = String::new();
+    let mut byte = mem.get_8(addr);
+    while byte != 0 {
+        s.push(byte as char);
+        addr += 1;
+        byte = mem.get_8(addr);
+    }
+    s
+}
+
+/// Reads the bytes of the NUL terminated string at `addr` in guest memory,
+/// as they are: the ones past 0x7f are no UTF-8.
+fn read_c_bytes(mem:&dyn Memory, addr:usize) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let mut byte = mem.get_8(addr);
+    while byte != 0 {
+        bytes.push(byte);
+        byte = mem.get_8(addr + bytes.len());
+    }
+    bytes
+}
+
+fn read_bytes(mem:&dyn Memory, addr:usize, size:usize) -> Vec<u8> {
+    (0..size).map(|i| mem.get_8(addr + i)).collect()
+}
+
+fn write_bytes(mem:&mut dyn Memory, addr:usize, bytes:&[u8]) {
+    for (i, b) in bytes.iter().enumerate() {
+        mem.set_8(addr + i, *b);
+    }
+}
+
+/// Writes the `size` bytes at `addr` in guest memory to `fd`, copying them
+/// by chunks, the guest giving any size. Returns how many were written, an
+/// error past the first chunk ending the write short.
+fn write_chunks(table:&mut FileTable, mem:&dyn Memory, fd:i32, addr:usize, size:usize) -> FileResult<usize> {
+    let mut done = 0;
+    while done < size {
+        let bytes = read_bytes(mem, addr + done, (size - done).min(files::CHUNK));
+        match table.write(fd, &bytes) {
+            Ok(n) => done += n,
+            Err(e) if done == 0 => return Err(e),
+            Err(_) => break,
+        }
+    }
+    Ok(done)
+}
+
+/// The `EOF` value returned by stdio functions.
+const EOF:i32 = -1;
+
+/// Sets `errno` for the thread `tid`. Nothing is written until the program
+/// asked for its `errno` through `__errno_location`.
+fn set_errno<S:SimtxScheduler, BV:BitSet>(m:&mut Machine<S, BV>, mem:&mut dyn Memory, tid:usize, value:i32) {
+    if let Some(addr) = m.errno_locations.get(&tid) {
+        mem.set_32(*addr, value as u32);
+    }
+}
+
+/// Returns the value of `result` to `lane`, or sets `errno` and returns
+/// `error` if it failed.
+fn ret_result<S:SimtxScheduler, BV:BitSet>(m:&mut Machine<S, BV>, call:&HostCallContext, lane:usize
+                                , mem:&mut dyn Memory, result:FileResult<i32>, error:i32) {
+    let ret = match result {
+        Ok(value) => value,
+        Err(e) => {
+            set_errno(m, mem, call.tid(lane), e);
+            error
+        },
+    };
+    m.lane(call, lane).ret(ret);
+}
@@ -1,8 +1,9 @@
-{
-    for i in self.warps[wid].alive_cores_ids() {
-        let num_warps = self.warps.len();
-        let core = &mut self.warps[wid].cores[i];
-        core.set_ri(10, (num_warps * tpw) as i32);
+use super::*;
+
+pub fn omp_get_num_threads<S:SimtxScheduler, BV:BitSet>(m:&mut Machine<S, BV>, call:&HostCallContext, _mem:&mut dyn Memory) -> HostCallAction {
+    let num_warps = m.warps.len();
+    for i in call.lanes() {
+        m.lane(call, i).ret((num_warps * call.tpw) as i32);
     }
-    self.warps[wid].advance_pc(pathid, advance);
+    HostCallAction::Return
 }
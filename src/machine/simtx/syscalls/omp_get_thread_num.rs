@@ -1,9 +1,10 @@
-{
-    for i in self.warps[wid].alive_cores_ids() {
-        let core = &mut self.warps[wid].cores[i];
-        let tid = wid*tpw + i;
+use super::*;
+
+pub fn omp_get_thread_num<S:SimtxScheduler, BV:BitSet>(m:&mut Machine<S, BV>, call:&HostCallContext, _mem:&mut dyn Memory) -> HostCallAction {
+    for i in call.lanes() {
+        let tid = call.tid(i);
         //println!("core {} omp_get_thread_num = {}", wid, tid);
-        core.set_ri(10, tid as i32);
+        m.lane(call, i).ret(tid as i32);
     }
-    self.warps[wid].advance_pc(pathid, advance);
+    HostCallAction::Return
 }
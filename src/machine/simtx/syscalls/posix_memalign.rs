@@ -0,0 +1,25 @@
+use super::*;
+
+pub fn posix_memalign<S:SimtxScheduler, BV:BitSet>(m:&mut Machine<S, BV>, call:&HostCallContext, mem:&mut dyn Memory) -> HostCallAction {
+    for i in call.lanes() {
+        let memptr = m.lane(call, i).ptr(0);
+        let align = m.lane(call, i).ptr(1);
+        let size = m.lane(call, i).ptr(2);
+
+        // the error is returned, errno is left untouched
+        let ret = if !align.is_power_of_two() || align % 4 != 0 {
+            errno::EINVAL
+        } else {
+            match m.aligned_alloc(mem, size, align) {
+                0 => errno::ENOMEM,
+                ptr => {
+                    m.track_alloc(ptr, size, call.pc, call.tid(i));
+                    mem.set_32(memptr, ptr as u32);
+                    0
+                },
+            }
+        };
+        m.lane(call, i).ret(ret);
+    }
+    HostCallAction::Return
+}
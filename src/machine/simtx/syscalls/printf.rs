@@ -1,4 +1,78 @@
-{
-    println!("<printf>");
-    self.warps[wid].advance_pc(pathid, advance);
+use super::*;
+use super::format::{format, VarArgs};
+use std::io::Write;
+
+// Formats the call of `lane`, whose format string is argument `fmt_arg`.
+fn format_lane<S:SimtxScheduler, BV:BitSet>(m:&mut Machine<S, BV>, call:&HostCallContext, lane:usize
+                                 , mem:&mut dyn Memory, fmt_arg:usize) -> Vec<u8> {
+    let (out, stores) = {
+        let args = m.lane(call, lane);
+        let mut regs = [0;8];
+        for (n, reg) in regs.iter_mut().enumerate() {
+            *reg = args.int(n);
+        }
+        let fmt = read_c_bytes(mem, args.ptr(fmt_arg));
+        format(&fmt, &mut VarArgs::new(regs, args.sp(), fmt_arg + 1, mem))
+    };
+    for (addr, count) in stores {
+        mem.set_32(addr, count as u32);
+    }
+    out
+}
+
+fn write_stream<S:SimtxScheduler, BV:BitSet>(m:&mut Machine<S, BV>, stream:u32, bytes:&[u8]) -> bool {
+    m.files.write(files::fd_of(stream), bytes).is_ok()
+}
+
+// Writes at most `size` bytes (NUL included) of `bytes` at `addr`.
+fn store_string(mem:&mut dyn Memory, addr:usize, size:usize, bytes:&[u8]) {
+    if size == 0 { return }
+    let len = bytes.len().min(size - 1);
+    for (i, b) in bytes[..len].iter().enumerate() {
+        mem.set_8(addr + i, *b);
+    }
+    mem.set_8(addr + len, 0);
+}
+
+pub fn printf<S:SimtxScheduler, BV:BitSet>(m:&mut Machine<S, BV>, call:&HostCallContext, mem:&mut dyn Memory) -> HostCallAction {
+    for i in call.lanes() {
+        let out = format_lane(m, call, i, mem, 0);
+        let ret = if write_stream(m, files::stream(files::STDOUT), &out) { out.len() as i32 } else { -1 };
+        m.lane(call, i).ret(ret);
+    }
+    std::io::stdout().flush().unwrap_or(());
+    HostCallAction::Return
+}
+
+pub fn fprintf<S:SimtxScheduler, BV:BitSet>(m:&mut Machine<S, BV>, call:&HostCallContext, mem:&mut dyn Memory) -> HostCallAction {
+    for i in call.lanes() {
+        let stream = m.lane(call, i).ptr(0) as u32;
+        let out = format_lane(m, call, i, mem, 1);
+        let ret = if write_stream(m, stream, &out) { out.len() as i32 } else { -1 };
+        m.lane(call, i).ret(ret);
+    }
+    std::io::stdout().flush().unwrap_or(());
+    HostCallAction::Return
+}
+
+pub fn sprintf<S:SimtxScheduler, BV:BitSet>(m:&mut Machine<S, BV>, call:&HostCallContext, mem:&mut dyn Memory) -> HostCallAction {
+    for i in call.lanes() {
+        let buf = m.lane(call, i).ptr(0);
+        let out = format_lane(m, call, i, mem, 1);
+        store_string(mem, buf, out.len() + 1, &out);
+        m.lane(call, i).ret(out.len() as i32);
+    }
+    HostCallAction::Return
+}
+
+pub fn snprintf<S:SimtxScheduler, BV:BitSet>(m:&mut Machine<S, BV>, call:&HostCallContext, mem:&mut dyn Memory) -> HostCallAction {
+    for i in call.lanes() {
+        let buf = m.lane(call, i).ptr(0);
+        let size = m.lane(call, i).ptr(1);
+        let out = format_lane(m, call, i, mem, 2);
+        store_string(mem, buf, size, &out);
+        // like C, returns the length the output would have had
+        m.lane(call, i).ret(out.len() as i32);
+    }
+    HostCallAction::Return
 }
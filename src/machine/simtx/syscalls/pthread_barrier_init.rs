@@ -1,12 +1,14 @@
-{
-let cid = self.warps[wid].get_single_core_id();
-let num = self.warps[wid].cores[cid].registers[12];
-let ptr = self.warps[wid].cores[cid].registers[10];
+use super::*;
 
-self.barriers.insert(ptr, Barrier {
-    initial_cap:num,
-    current_cap:num,
-});
+pub fn pthread_barrier_init<S:SimtxScheduler, BV:BitSet>(m:&mut Machine<S, BV>, call:&HostCallContext, _mem:&mut dyn Memory) -> HostCallAction {
+    let cid = call.single_lane();
+    let num = m.lane(call, cid).int(2);
+    let ptr = m.lane(call, cid).int(0);
 
-self.warps[wid].advance_pc(pathid, advance);
+    m.barriers.insert(ptr, Barrier {
+        initial_cap:num,
+        current_cap:num,
+    });
+
+    HostCallAction::Return
 }
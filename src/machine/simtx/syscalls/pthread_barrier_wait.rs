@@ -1,27 +1,37 @@
-{
-for i in self.warps[wid].alive_cores_ids() {
-    let core = &self.warps[wid].cores[i];
-    let tid = wid*tpw + i;
-    let ptr = core.registers[10];
+use super::*;
 
-    // don't re-execute the wait
-    if self.in_barrier[tid] == ptr { continue }
+pub fn pthread_barrier_wait<S:SimtxScheduler, BV:BitSet>(m:&mut Machine<S, BV>, call:&HostCallContext, _mem:&mut dyn Memory) -> HostCallAction {
+    let mut waiting = BV::empty();
+    for i in call.lanes() {
+        let tid = call.tid(i);
+        let ptr = m.lane(call, i).int(0);
 
-    // if the barrier exists
-    if let Some(barr) = self.barriers.get_mut(&ptr) {
+        // don't re-execute the wait
+        if m.in_barrier[tid] == ptr { continue }
 
-        // put thread in barrier
-        self.in_barrier[tid] = ptr;
-        // decrement its capacity
-        barr.current_cap -= 1;
+        // if the barrier exists
+        if let Some(barr) = m.barriers.get_mut(&ptr) {
 
-        // it the barrier must open, free all threads
-        // then stop the loop
-        if barr.current_cap == 0 {
-            barr.current_cap = barr.initial_cap;
-            self.free_barrier(ptr, advance);
-            break;
+            // put thread in barrier
+            m.in_barrier[tid] = ptr;
+            waiting.set(i);
+            // decrement its capacity
+            barr.current_cap -= 1;
+
+            // it the barrier must open, free all threads
+            // then stop the loop
+            if barr.current_cap == 0 {
+                barr.current_cap = barr.initial_cap;
+                m.free_barrier(ptr, call.advance);
+                break;
+            }
         }
     }
-}
+
+    if waiting.any() {
+        S::on_barrier(&mut m.warps[call.warp], call.pc, waiting);
+    }
+
+    // threads leave the barrier through `free_barrier`
+    HostCallAction::Retry
 }
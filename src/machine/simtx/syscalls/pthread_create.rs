@@ -1,37 +1,37 @@
-{
-assert!(self.warps[wid].paths[pathid].is_single(),
-"pthread_create must be called by only 1 thread");
+use super::*;
 
-// allocate a new thread (get its id)
-let tid = self.pop_first_idle();
-let w = tid / tpw; let t = tid % tpw;
+pub fn pthread_create<S:SimtxScheduler, BV:BitSet>(m:&mut Machine<S, BV>, call:&HostCallContext, mem:&mut dyn Memory) -> HostCallAction {
+    let cid = call.single_lane();
+    let tpw = call.tpw;
 
-let stacksize = self.stack_size;
-let stackstart = self.stack_start - stacksize * tid; 
-let stackend = stackstart - stacksize;
-mem.allocate_at(stackend, stacksize);
+    // allocate a new thread (get its id)
+    let tid = m.pop_first_idle();
+    let w = tid / tpw; let t = tid % tpw;
 
-let cid = self.warps[wid].get_single_core_id();
-// write in memory the tid
-let addr = self.warps[wid].cores[cid].registers[10] as usize;
-mem.set_32(addr, tid as u32);
+    let stacksize = m.stack_size;
+    let stackstart = m.stack_start - stacksize * tid;
+    let stackend = stackstart - stacksize;
+    mem.allocate_at(stackend, stacksize);
 
-// setup allocated core's register file
-let mut regs = [0;32];
-regs[2] = stackstart as i32;
-regs[8] = stackstart as i32;
-regs[3] = self.warps[wid].cores[cid].registers[3];
-regs[4] = tid as i32;
-regs[10] = self.warps[wid].cores[cid].registers[13];
-self.warps[w].cores[t].registers = regs;
+    // write in memory the tid
+    let addr = m.lane(call, cid).ptr(0);
+    mem.set_32(addr, tid as u32);
 
-// setup a new path with only allocated thread inside
-let npc = self.warps[wid].cores[cid].registers[12];
-let m = BitSet::singleton(t);
+    // setup allocated core's register file
+    let parent = m.warps[call.warp].cores[cid].registers;
+    let mut regs = [0;32];
+    regs[2] = stackstart as i32;
+    regs[8] = stackstart as i32;
+    regs[3] = parent[3];
+    regs[4] = tid as i32;
+    regs[10] = parent[13];
+    m.warps[w].cores[t].registers = regs;
 
-self.warps[w].push_path(Path::from_pc_mask(npc, m));
+    // setup a new path with only allocated thread inside
+    let npc = parent[12];
+    m.warps[w].push_path(Path::from_pc_mask(npc, BitSet::singleton(t)));
 
-// return 0 and advance current path
-self.warps[wid].cores[cid].set_ri(10, 0);
-self.warps[wid].advance_pc(pathid, advance);
+    // return 0 and advance current path
+    m.lane(call, cid).ret(0);
+    HostCallAction::Return
 }
@@ -1,7 +1,11 @@
-{
-let cid = self.warps[wid].get_single_core_id();
-let to_wait = self.warps[wid].cores[cid].registers[10] as usize;
-if self.idle_threads.contains(&to_wait) {
-    self.warps[wid].advance_pc(pathid, advance);
-}
+use super::*;
+
+pub fn pthread_join<S:SimtxScheduler, BV:BitSet>(m:&mut Machine<S, BV>, call:&HostCallContext, _mem:&mut dyn Memory) -> HostCallAction {
+    let cid = call.single_lane();
+    let to_wait = m.lane(call, cid).int(0) as usize;
+    if m.idle_threads.contains(&to_wait) {
+        HostCallAction::Return
+    } else {
+        HostCallAction::Retry
+    }
 }
@@ -0,0 +1,45 @@
+use super::*;
+use machine::simtx::heap::Heap;
+
+pub fn realloc<S:SimtxScheduler, BV:BitSet>(m:&mut Machine<S, BV>, call:&HostCallContext, mem:&mut dyn Memory) -> HostCallAction {
+    for i in call.lanes() {
+        let ptr = m.lane(call, i).ptr(0);
+        let size = m.lane(call, i).ptr(1);
+
+        let tid = call.tid(i);
+        let ret = if ptr == 0 {
+            let new = m.malloc(mem, size);
+            if new != 0 { m.track_alloc(new, size, call.pc, tid) }
+            new
+        } else if size == 0 {
+            // like glibc, realloc(ptr, 0) frees ptr
+            match m.heap.free(ptr) {
+                Ok(()) => { m.track_free(ptr, call.pc, tid); 0 },
+                Err(e) => { m.heap_error(e, call.pc); break },
+            }
+        } else {
+            match m.heap.realloc(ptr, size) {
+                Ok(Some((new, to_copy))) => {
+                    mem.allocate_at(new, Heap::chunk_size(size));
+                    if new != ptr {
+                        m.track_alloc(new, size, call.pc, tid);
+                        let bytes = read_bytes(mem, ptr, to_copy);
+                        write_bytes(mem, new, &bytes);
+                        m.track_free(ptr, call.pc, tid);
+                    } else {
+                        m.track_resize(ptr, size);
+                    }
+                    new
+                },
+                Ok(None) => 0,
+                Err(e) => { m.heap_error(e, call.pc); break },
+            }
+        };
+
+        if ret == 0 && size != 0 {
+            set_errno(m, mem, tid, errno::ENOMEM);
+        }
+        m.lane(call, i).ret(ret as i32);
+    }
+    HostCallAction::Return
+}
@@ -1,21 +1,12 @@
-{
-    let _cid = self.warps[wid].get_single_core_id();
-    let core = &mut self.warps[wid].cores[wid];
-    let mut addr = core.registers[10] as usize;
-    let size = core.registers[11] as usize;
-    let fp = core.registers[12];
+use super::*;
 
-    match fp {
-        0 | 1 | 2 => panic!("reading from stdout or stderr"),
-        n => {
-            self.file_handles.get_mut(&n).map(|file| {
-                let _ = file.seek(SeekFrom::Start(0))
-                    .map_err(|_| {
-                        println!("LOL")
-                    });
-            });
-        },
+pub fn rewind<S:SimtxScheduler, BV:BitSet>(m:&mut Machine<S, BV>, call:&HostCallContext, mem:&mut dyn Memory) -> HostCallAction {
+    for i in call.lanes() {
+        let fd = files::fd_of(m.lane(call, i).ptr(0) as u32);
+        // rewind returns nothing, only errno tells about errors
+        if let Err(e) = m.files.seek(fd, 0, 0) {
+            set_errno(m, mem, call.tid(i), e);
+        }
     }
-
-    self.warps[wid].advance_pc(pathid, advance);
+    HostCallAction::Return
 }
@@ -0,0 +1,274 @@
+//! C `scanf` format strings, as understood by the scanf family of host calls.
+
+use super::format::VarArgs;
+
+/// A source of bytes which can give back the last byte it read.
+pub trait ScanInput {
+    fn getc(&mut self) -> Option<u8>;
+    fn ungetc(&mut self, byte:u8);
+}
+
+/// A value to store in guest memory for a conversion.
+pub enum Stored {
+    Byte(u8),
+    Half(u16),
+    Word(u32),
+    Double(u64),
+    Bytes(Vec<u8>),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Length { Char, Short, Int, LongLong, Double }
+
+struct Reader<'a> {
+    input:&'a mut dyn ScanInput,
+    consumed:usize,
+}
+
+impl<'a> Reader<'a> {
+    fn peek(&mut self) -> Option<u8> {
+        let c = self.input.getc();
+        if let Some(b) = c { self.input.ungetc(b) }
+        c
+    }
+
+    fn bump(&mut self) -> Option<u8> {
+        let c = self.input.getc();
+        if c.is_some() { self.consumed += 1 }
+        c
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().map_or(false, |c| c.is_ascii_whitespace()) {
+            self.bump();
+        }
+    }
+
+    // takes the next byte if it is accepted by `f` and the width allows it
+    fn take_if<F:Fn(u8) -> bool>(&mut self, width:&mut usize, f:F) -> Option<u8> {
+        if *width == 0 { return None }
+        match self.peek() {
+            Some(c) if f(c) => {
+                *width -= 1;
+                self.bump()
+            },
+            _ => None,
+        }
+    }
+}
+
+fn digit_of(c:u8, base:u32) -> Option<u32> {
+    (c as char).to_digit(base)
+}
+
+// Reads an integer in `base` (0 detects the base from the prefix like `%i`)
+fn scan_integer(r:&mut Reader, width:usize, base:u32) -> Option<u64> {
+    let mut width = width;
+    let negative = r.take_if(&mut width, |c| c == b'-' || c == b'+') == Some(b'-');
+
+    let mut base = base;
+    let mut digits = 0;
+    let mut value : u64 = 0;
+    if (base == 0 || base == 16) && r.take_if(&mut width, |c| c == b'0').is_some() {
+        digits += 1;
+        if r.take_if(&mut width, |c| c == b'x' || c == b'X').is_some() {
+            base = 16;
+        } else if base == 0 {
+            base = 8;
+        }
+    }
+    if base == 0 { base = 10 }
+
+    while let Some(c) = r.take_if(&mut width, |c| digit_of(c, base).is_some()) {
+        value = value.wrapping_mul(base as u64).wrapping_add(digit_of(c, base).unwrap() as u64);
+        digits += 1;
+    }
+
+    if digits == 0 { return None }
+    Some(if negative { value.wrapping_neg() } else { value })
+}
+
+fn scan_float(r:&mut Reader, width:usize) -> Option<f64> {
+    let mut width = width;
+    let mut s = String::new();
+    if let Some(c) = r.take_if(&mut width, |c| c == b'-' || c == b'+') {
+        s.push(c as char);
+    }
+    while let Some(c) = r.take_if(&mut width, |c| c.is_ascii_digit()) {
+        s.push(c as char);
+    }
+    if let Some(c) = r.take_if(&mut width, |c| c == b'.') {
+        s.push(c as char);
+        while let Some(c) = r.take_if(&mut width, |c| c.is_ascii_digit()) {
+            s.push(c as char);
+        }
+    }
+    if !s.bytes().any(|c| c.is_ascii_digit()) { return None }
+    if let Some(c) = r.take_if(&mut width, |c| c == b'e' || c == b'E') {
+        s.push(c as char);
+        if let Some(c) = r.take_if(&mut width, |c| c == b'-' || c == b'+') {
+            s.push(c as char);
+        }
+        while let Some(c) = r.take_if(&mut width, |c| c.is_ascii_digit()) {
+            s.push(c as char);
+        }
+    }
+    // like C, an incomplete exponent ("1e") is not given back
+    s.trim_end_matches(['e', 'E', '+', '-'])
+        .parse().ok()
+}
+
+// Parses the set of a `%[` conversion, `i` is right after the '['
+fn scan_set(fmt:&[u8], i:&mut usize) -> ([bool;256], bool) {
+    let mut set = [false;256];
+    let negated = *i < fmt.len() && fmt[*i] == b'^';
+    if negated { *i += 1 }
+    let start = *i;
+    while *i < fmt.len() && (fmt[*i] != b']' || *i == start) {
+        if *i + 2 < fmt.len() && fmt[*i+1] == b'-' && fmt[*i+2] != b']' {
+            for c in fmt[*i]..=fmt[*i+2] { set[c as usize] = true }
+            *i += 3;
+        } else {
+            set[fmt[*i] as usize] = true;
+            *i += 1;
+        }
+    }
+    *i += 1;
+    (set, negated)
+}
+
+/// Reads `input` following `fmt`. Returns the number of assigned
+/// conversions (-1 if the input ended before the first one) and the values
+/// to store at the addresses given in `args`.
+pub fn scan(fmt:&[u8], input:&mut dyn ScanInput, args:&mut VarArgs) -> (i32, Vec<(usize, Stored)>) {
+    let mut r = Reader { input, consumed:0 };
+    let mut stores = Vec::new();
+    let mut assigned = 0;
+    let mut i = 0;
+
+    while i < fmt.len() {
+        let c = fmt[i];
+        i += 1;
+
+        if c.is_ascii_whitespace() {
+            r.skip_whitespace();
+            continue
+        }
+        if c != b'%' || (i < fmt.len() && fmt[i] == b'%') {
+            if c == b'%' {
+                i += 1;
+                r.skip_whitespace();
+            }
+            match r.peek() {
+                Some(x) if x == c => { r.bump(); },
+                None if assigned == 0 => return (-1, stores),
+                _ => break,
+            }
+            continue
+        }
+
+        let suppress = i < fmt.len() && fmt[i] == b'*';
+        if suppress { i += 1 }
+
+        let mut width = 0;
+        while i < fmt.len() && fmt[i].is_ascii_digit() {
+            width = width * 10 + (fmt[i] - b'0') as usize;
+            i += 1;
+        }
+
+        let mut length = Length::Int;
+        while i < fmt.len() {
+            match fmt[i] {
+                b'h' => length = if length == Length::Short { Length::Char } else { Length::Short },
+                b'l' => length = if length == Length::Double { Length::LongLong } else { Length::Double },
+                b'L' | b'q' | b'j' => length = Length::LongLong,
+                b'z' | b't' => {},
+                _ => break,
+            }
+            i += 1;
+        }
+        if i >= fmt.len() { break }
+        let conv = fmt[i];
+        i += 1;
+
+        if conv == b'n' {
+            if !suppress {
+                let addr = args.next_32() as usize;
+                stores.push((addr, Stored::Word(r.consumed as u32)));
+            }
+            continue
+        }
+
+        if conv != b'c' && conv != b'[' {
+            r.skip_whitespace();
+        }
+        if r.peek().is_none() {
+            if assigned == 0 { assigned = -1 }
+            break
+        }
+
+        let value = match conv {
+            b'd' | b'i' | b'u' | b'o' | b'x' | b'X' | b'p' => {
+                let base = match conv { b'd' | b'u' => 10, b'o' => 8, b'i' => 0, _ => 16 };
+                let width = if width == 0 { usize::MAX } else { width };
+                scan_integer(&mut r, width, base).map(|v| match (conv, length) {
+                    (b'p', _) => Stored::Word(v as u32),
+                    (_, Length::Char) => Stored::Byte(v as u8),
+                    (_, Length::Short) => Stored::Half(v as u16),
+                    (_, Length::LongLong) => Stored::Double(v),
+                    _ => Stored::Word(v as u32),
+                })
+            },
+            b'f' | b'F' | b'e' | b'E' | b'g' | b'G' | b'a' => {
+                let width = if width == 0 { usize::MAX } else { width };
+                scan_float(&mut r, width).map(|v| match length {
+                    Length::Double | Length::LongLong => Stored::Double(v.to_bits()),
+                    _ => Stored::Word((v as f32).to_bits()),
+                })
+            },
+            b'c' => {
+                let width = if width == 0 { 1 } else { width };
+                let mut bytes = Vec::new();
+                while bytes.len() < width {
+                    match r.bump() {
+                        Some(b) => bytes.push(b),
+                        None => break,
+                    }
+                }
+                if bytes.len() == width { Some(Stored::Bytes(bytes)) } else { None }
+            },
+            b's' | b'[' => {
+                let (set, negated) = if conv == b'[' {
+                    scan_set(fmt, &mut i)
+                } else {
+                    let mut set = [false;256];
+                    for c in b" \t\n\x0b\x0c\r" { set[*c as usize] = true }
+                    (set, true)
+                };
+                let mut width = if width == 0 { usize::MAX } else { width };
+                let mut bytes = Vec::new();
+                while let Some(b) = r.take_if(&mut width, |c| set[c as usize] != negated) {
+                    bytes.push(b);
+                }
+                if bytes.is_empty() {
+                    None
+                } else {
+                    bytes.push(0);
+                    Some(Stored::Bytes(bytes))
+                }
+            },
+            _ => None,
+        };
+
+        match value {
+            Some(value) => if !suppress {
+                let addr = args.next_32() as usize;
+                stores.push((addr, value));
+                assigned += 1;
+            },
+            None => break,
+        }
+    }
+
+    (assigned, stores)
+}
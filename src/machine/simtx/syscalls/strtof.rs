@@ -1,16 +1,17 @@
-{
-    for i in self.warps[wid].alive_cores_ids() {
-        let core = &mut self.warps[wid].cores[i];
-        let mut str_addr = core.registers[10];
+use super::*;
+
+pub fn strtof<S:SimtxScheduler, BV:BitSet>(m:&mut Machine<S, BV>, call:&HostCallContext, mem:&mut dyn Memory) -> HostCallAction {
+    for i in call.lanes() {
+        let mut str_addr = m.lane(call, i).ptr(0);
         let mut to_parse = String::new();
-        let mut byte = mem.get_8(str_addr as usize);
+        let mut byte = mem.get_8(str_addr);
 
         //println!("byte at 0x{:x}", str_addr);
 
         while byte != 0 && byte != ('\n' as u8) {
             to_parse.push(byte as char);
             str_addr += 1;
-            byte = mem.get_8(str_addr as usize);
+            byte = mem.get_8(str_addr);
         }
 
         //println!("strtof({})", to_parse);
@@ -19,7 +20,7 @@
         //println!("strtof(\"{}\") = {}", to_parse, parsed);
 
         let x : MachineF32 = MachineF32::from_f32(parsed);
-        unsafe { core.set_ri(10, x.bits.lo as i32); }
+        unsafe { m.lane(call, i).ret(x.bits.lo as i32); }
     }
-    self.warps[wid].advance_pc(pathid, advance);
+    HostCallAction::Return
 }
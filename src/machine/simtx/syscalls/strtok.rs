@@ -1,47 +1,40 @@
-{
-    let _cid = self.warps[wid].get_single_core_id();
-    let core = &mut self.warps[wid].cores[wid];
-    let mut addr1 = core.registers[10] as usize;
-    let mut addr2 = core.registers[11] as usize;
-
-    unsafe {
-        let mut strptr = 0 as * mut c_char;
-        let delimptr : * const c_char;
-        if addr1 != 0 {
-            // deallocate the previously allocated CString
-            if STRTOK_VEC != 0 as * mut c_char {
-                std::mem::drop(CString::from_raw(STRTOK_VEC));
-            }
-
-            // retrieve the new CString
-            let mut strvec = Vec::new();
-            let mut byte = mem.get_8(addr1 as usize);
-            while byte != 0 {
-                strvec.push(byte);
-                addr1 += 1;
-                byte = mem.get_8(addr1 as usize);
-            }
-
-            STRTOK_VEC = CString::from_vec_unchecked(strvec).into_raw();
-            strptr = STRTOK_VEC;
-        }
-
-        // retrieve the new token string
-        let mut strvec = Vec::new();
-        let mut byte = mem.get_8(addr2 as usize);
-        while byte != 0 {
-            strvec.push(byte);
-            addr2 += 1;
-            byte = mem.get_8(addr1 as usize);
-        }
-
-        delimptr = CString::from_vec_unchecked(strvec).into_raw()
-            as * const c_char;
-
+use super::*;
+
+// Works in the memory of the guest, like the libc: the delimiter ending a
+// token is overwritten by a 0, and the rest of the string is kept by the
+// machine for the next calls with a null string.
+pub fn strtok<S:SimtxScheduler, BV:BitSet>(m:&mut Machine<S, BV>, call:&HostCallContext, mem:&mut dyn Memory) -> HostCallAction {
+    let cid = call.single_lane();
+    let string = m.lane(call, cid).ptr(0);
+    let delimiters = read_c_bytes(mem, m.lane(call, cid).ptr(1));
+    let is_delimiter = |b:u8| delimiters.contains(&b);
+
+    let mut addr = if string != 0 { string } else { m.strtok_next };
+    if addr == 0 {
+        m.lane(call, cid).ret(0);
+        return HostCallAction::Return
+    }
 
-        let ret = strtok(strptr, delimptr);
-        core.registers[10] = ret as i32;
+    while mem.get_8(addr) != 0 && is_delimiter(mem.get_8(addr)) {
+        addr += 1;
+    }
+    if mem.get_8(addr) == 0 {
+        m.strtok_next = 0;
+        m.lane(call, cid).ret(0);
+        return HostCallAction::Return
     }
 
-    self.warps[wid].advance_pc(pathid, advance);
+    let token = addr;
+    while mem.get_8(addr) != 0 && !is_delimiter(mem.get_8(addr)) {
+        addr += 1;
+    }
+    m.strtok_next = if mem.get_8(addr) == 0 {
+        0
+    } else {
+        mem.set_8(addr, 0);
+        addr + 1
+    };
+    m.lane(call, cid).ret(token as i32);
+
+    HostCallAction::Return
 }
@@ -0,0 +1,12 @@
+use super::*;
+
+pub fn write<S:SimtxScheduler, BV:BitSet>(m:&mut Machine<S, BV>, call:&HostCallContext, mem:&mut dyn Memory) -> HostCallAction {
+    for i in call.lanes() {
+        let fd = m.lane(call, i).int(0);
+        let buf = m.lane(call, i).ptr(1);
+        let size = m.lane(call, i).ptr(2);
+        let ret = write_chunks(&mut m.files, mem, fd, buf, size).map(|n| n as i32);
+        ret_result(m, call, i, mem, ret, -1);
+    }
+    HostCallAction::Return
+}
@@ -0,0 +1,373 @@
+//! An optional cycle-level timing model of a SIMT-X machine.
+//!
+//! Without it, `step` executes one instruction per warp. Once enabled, a
+//! `step` is one cycle: each warp issues at most one instruction, in order,
+//! when its front-end is filled and the registers the instruction reads are
+//! ready (a scoreboard per warp). Results become ready after the latency of
+//! the functional unit executing them, and memory accesses cost one
+//! transaction per cache line touched by the lanes, so divergent accesses are
+//! slower than scalar (or coalesced) ones. Switching from a path to another
+//! refills the front-end.
+
+use isa::{Instruction, OpCode};
+use std::fmt;
+
+/// The latencies of the pipeline, in cycles.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimingConfig {
+    /// Depth of the front-end, paid at start and after each path switch.
+    pub fetch:usize,
+    pub decode:usize,
+    /// Cycles between two issues of the same warp.
+    pub issue:usize,
+    /// Latency of the integer ALU (and branches).
+    pub execute:usize,
+    /// Cycles between the end of the execution and the use of the result.
+    pub writeback:usize,
+
+    /// Latencies of the other functional units.
+    pub mul:usize,
+    pub div:usize,
+    pub fp:usize,
+    /// FP divisions and square roots.
+    pub fp_div:usize,
+
+    /// Latency of a load served in a single transaction.
+    pub memory:usize,
+    /// Size of the lines accessed by a single memory transaction.
+    pub line_size:usize,
+    /// Extra cycles for each transaction after the first one.
+    pub transaction:usize,
+
+    /// Extra cycles on top of the front-end refill when changing paths.
+    pub path_switch:usize,
+}
+
+impl Default for TimingConfig {
+    fn default() -> TimingConfig {
+        TimingConfig {
+            fetch:1, decode:1, issue:1, execute:1, writeback:0,
+            mul:3, div:20, fp:4, fp_div:16,
+            memory:20, line_size:64, transaction:4,
+            path_switch:1,
+        }
+    }
+}
+
+impl TimingConfig {
+    /// Sets the latency named `name` (the name of its field).
+    pub fn set(&mut self, name:&str, cycles:usize) -> Result<(), String> {
+        let latency = match name {
+            "fetch" => &mut self.fetch,
+            "decode" => &mut self.decode,
+            "issue" => &mut self.issue,
+            "execute" => &mut self.execute,
+            "writeback" => &mut self.writeback,
+            "mul" => &mut self.mul,
+            "div" => &mut self.div,
+            "fp" => &mut self.fp,
+            "fp_div" => &mut self.fp_div,
+            "memory" => &mut self.memory,
+            "line_size" => &mut self.line_size,
+            "transaction" => &mut self.transaction,
+            "path_switch" => &mut self.path_switch,
+            _ => return Err(format!("unknown latency {}", name)),
+        };
+        *latency = cycles;
+        Ok(())
+    }
+}
+
+/// The counters of a warp.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WarpTiming {
+    /// Cycles during which the warp had a path to run.
+    pub cycles:u64,
+    /// Warp instructions issued, and the number of lanes which executed them.
+    pub instructions:u64,
+    pub thread_instructions:u64,
+    /// Cycles waiting for a result of the ALU or of another functional unit.
+    pub data_stalls:u64,
+    /// Cycles waiting for a load, or for the memory transactions of the
+    /// previous instruction.
+    pub memory_stalls:u64,
+    /// Cycles refilling the front-end.
+    pub front_end_stalls:u64,
+    pub path_switches:u64,
+    pub memory_transactions:u64,
+}
+
+impl WarpTiming {
+    /// Warp instructions per cycle.
+    pub fn ipc(&self) -> f64 {
+        if self.cycles == 0 { 0. } else { self.instructions as f64 / self.cycles as f64 }
+    }
+
+    /// Thread instructions per cycle.
+    pub fn thread_ipc(&self) -> f64 {
+        if self.cycles == 0 { 0. } else { self.thread_instructions as f64 / self.cycles as f64 }
+    }
+
+    pub fn stalls(&self) -> u64 {
+        self.data_stalls + self.memory_stalls + self.front_end_stalls
+    }
+}
+
+impl fmt::Display for WarpTiming {
+    fn fmt(&self, f:&mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} cycles, {} instructions (IPC {:.3}, {:.3} per thread), {} stalls ({} data, {} memory, {} front-end), {} path switches, {} memory transactions"
+               , self.cycles, self.instructions, self.ipc(), self.thread_ipc()
+               , self.stalls(), self.data_stalls, self.memory_stalls, self.front_end_stalls
+               , self.path_switches, self.memory_transactions)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Reg {
+    Int(u8),
+    Float(u8),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Unit {
+    Alu,
+    Mul,
+    Div,
+    Fp,
+    FpDiv,
+    Load,
+    Store,
+}
+
+// The registers read and written by an instruction, and the unit running it
+struct Operands {
+    sources:Vec<Reg>,
+    dest:Option<Reg>,
+    unit:Unit,
+}
+
+fn int(r:u8) -> Option<Reg> {
+    if r == 0 { None } else { Some(Reg::Int(r)) }
+}
+
+fn operands(i:&Instruction) -> Operands {
+    let (rd, rs1, rs2) = (i.get_rd(), i.get_rs1(), i.get_rs2());
+    let (sources, dest, unit) = match i.get_opcode_enum() {
+        OpCode::LUI | OpCode::AUIPC | OpCode::JAL => (vec![], int(rd), Unit::Alu),
+        OpCode::JALR | OpCode::OPIMM => (vec![Reg::Int(rs1)], int(rd), Unit::Alu),
+        OpCode::BRANCH => (vec![Reg::Int(rs1), Reg::Int(rs2)], None, Unit::Alu),
+        OpCode::LOAD => (vec![Reg::Int(rs1)], int(rd), Unit::Load),
+        OpCode::STORE => (vec![Reg::Int(rs1), Reg::Int(rs2)], None, Unit::Store),
+        OpCode::FLW => (vec![Reg::Int(rs1)], Some(Reg::Float(rd)), Unit::Load),
+        OpCode::FSW => (vec![Reg::Int(rs1), Reg::Float(rs2)], None, Unit::Store),
+        OpCode::OPREG => {
+            let unit = match (i.get_funct7(), i.get_funct3()) {
+                (0b0000001, f) if f < 4 => Unit::Mul,
+                (0b0000001, _) => Unit::Div,
+                _ => Unit::Alu,
+            };
+            (vec![Reg::Int(rs1), Reg::Int(rs2)], int(rd), unit)
+        },
+        OpCode::FMADD | OpCode::FMSUB | OpCode::FNMSUB | OpCode::FNMADD =>
+            (vec![Reg::Float(rs1), Reg::Float(rs2), Reg::Float(i.get_rs3())], Some(Reg::Float(rd)), Unit::Fp),
+        OpCode::FOPREG => match i.get_funct7() & 0b1111110 {
+            // divisions and square roots
+            0b0001100 | 0b0101100 => (vec![Reg::Float(rs1), Reg::Float(rs2)], Some(Reg::Float(rd)), Unit::FpDiv),
+            // to integer registers: conversions, moves and comparisons
+            0b1100000 | 0b1110000 => (vec![Reg::Float(rs1)], int(rd), Unit::Fp),
+            0b1010000 => (vec![Reg::Float(rs1), Reg::Float(rs2)], int(rd), Unit::Fp),
+            // from integer registers
+            0b1101000 | 0b1111000 => (vec![Reg::Int(rs1)], Some(Reg::Float(rd)), Unit::Fp),
+            _ => (vec![Reg::Float(rs1), Reg::Float(rs2)], Some(Reg::Float(rd)), Unit::Fp),
+        },
+        OpCode::SYSTEM => (vec![Reg::Int(rs1)], int(rd), Unit::Alu),
+        _ => (vec![], None, Unit::Alu),
+    };
+    Operands { sources, dest, unit }
+}
+
+// When a register is ready, and whether it waits for a load
+#[derive(Clone, Copy, Debug, Default)]
+struct Ready {
+    cycle:u64,
+    load:bool,
+}
+
+#[derive(Clone, Debug)]
+struct WarpState {
+    iregs:[Ready;32],
+    fregs:[Ready;32],
+    // first cycle the warp can issue, and why it cannot before
+    issue_at:u64,
+    issue_wait:Stall,
+    // the execution mask and the next pc of the last instruction issued
+    last_mask:Vec<bool>,
+    next_pc:i32,
+    stats:WarpTiming,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Stall {
+    Data,
+    Memory,
+    FrontEnd,
+}
+
+impl WarpState {
+    fn ready(&self, r:Reg) -> Ready {
+        match r {
+            Reg::Int(n) => self.iregs[n as usize],
+            Reg::Float(n) => self.fregs[n as usize],
+        }
+    }
+
+    fn set_ready(&mut self, r:Reg, ready:Ready) {
+        match r {
+            Reg::Int(n) => self.iregs[n as usize] = ready,
+            Reg::Float(n) => self.fregs[n as usize] = ready,
+        }
+    }
+}
+
+/// The state of the timing model of a whole machine.
+#[derive(Clone, Debug)]
+pub struct Timing {
+    config:TimingConfig,
+    cycle:u64,
+    warps:Vec<WarpState>,
+}
+
+impl Timing {
+    pub fn new(config:TimingConfig, nb_warps:usize) -> Timing {
+        let front_end = (config.fetch + config.decode) as u64;
+        let warp = WarpState {
+            iregs:[Ready::default();32],
+            fregs:[Ready::default();32],
+            issue_at:front_end,
+            issue_wait:Stall::FrontEnd,
+            last_mask:Vec::new(),
+            next_pc:0,
+            stats:WarpTiming::default(),
+        };
+        Timing { config, cycle:0, warps:vec![warp; nb_warps] }
+    }
+
+    pub fn config(&self) -> &TimingConfig {
+        &self.config
+    }
+
+    /// The current cycle.
+    pub fn cycle(&self) -> u64 {
+        self.cycle
+    }
+
+    pub fn warp(&self, wid:usize) -> &WarpTiming {
+        &self.warps[wid].stats
+    }
+
+    /// The counters of all the warps added together (cycles are the cycles
+    /// of the machine).
+    pub fn total(&self) -> WarpTiming {
+        let mut total = self.warps.iter().fold(WarpTiming::default(), |mut t, w| {
+            t.instructions += w.stats.instructions;
+            t.thread_instructions += w.stats.thread_instructions;
+            t.data_stalls += w.stats.data_stalls;
+            t.memory_stalls += w.stats.memory_stalls;
+            t.front_end_stalls += w.stats.front_end_stalls;
+            t.path_switches += w.stats.path_switches;
+            t.memory_transactions += w.stats.memory_transactions;
+            t
+        });
+        total.cycles = self.cycle;
+        total
+    }
+
+    /// Ends the current cycle.
+    pub fn tick(&mut self) {
+        self.cycle += 1;
+    }
+
+    /// Tells whether warp `wid` can issue `i` this cycle, for the path at
+    /// `pc` with the lanes of `mask`. A path switch starts refilling the
+    /// front-end. Counts a stall when the warp cannot issue.
+    pub fn can_issue(&mut self, wid:usize, pc:i32, mask:&[bool], i:&Instruction) -> bool {
+        let cycle = self.cycle;
+        let config = self.config;
+        let warp = &mut self.warps[wid];
+        warp.stats.cycles += 1;
+
+        // another path than the one of the last instruction: the front-end
+        // must fetch from elsewhere
+        if !warp.last_mask.is_empty() && warp.last_mask != mask && warp.next_pc != pc {
+            warp.stats.path_switches += 1;
+            warp.issue_at = warp.issue_at.max(cycle + (config.fetch + config.decode + config.path_switch) as u64);
+            warp.issue_wait = Stall::FrontEnd;
+        }
+        warp.last_mask = mask.to_vec();
+        warp.next_pc = pc;
+
+        if cycle < warp.issue_at {
+            match warp.issue_wait {
+                Stall::FrontEnd => warp.stats.front_end_stalls += 1,
+                Stall::Memory => warp.stats.memory_stalls += 1,
+                Stall::Data => warp.stats.data_stalls += 1,
+            }
+            return false
+        }
+
+        let ops = operands(i);
+        let waiting = ops.sources.iter()
+            .map(|r| warp.ready(*r))
+            .filter(|r| r.cycle > cycle)
+            .max_by_key(|r| r.cycle);
+        match waiting {
+            Some(r) if r.load => { warp.stats.memory_stalls += 1; false },
+            Some(_) => { warp.stats.data_stalls += 1; false },
+            None => true,
+        }
+    }
+
+    /// Issues `i` on warp `wid`, for `lanes` lanes. A load or a store needs
+    /// `transactions` memory transactions (see `coalescing::transactions`).
+    pub fn issue(&mut self, wid:usize, i:&Instruction, lanes:usize, transactions:usize) {
+        let cycle = self.cycle;
+        let config = self.config;
+        let warp = &mut self.warps[wid];
+        let ops = operands(i);
+        let transactions = transactions.max(1) as u64;
+
+        let latency = match ops.unit {
+            Unit::Alu => config.execute,
+            Unit::Mul => config.mul,
+            Unit::Div => config.div,
+            Unit::Fp => config.fp,
+            Unit::FpDiv => config.fp_div,
+            Unit::Load | Unit::Store => config.memory,
+        } as u64 + (transactions - 1) * config.transaction as u64;
+
+        if let Some(dest) = ops.dest {
+            let ready = Ready { cycle:cycle + latency + config.writeback as u64, load:ops.unit == Unit::Load };
+            warp.set_ready(dest, ready);
+        }
+
+        // the memory port is busy for one cycle per transaction
+        let (busy, wait) = match ops.unit {
+            Unit::Load | Unit::Store => {
+                warp.stats.memory_transactions += transactions;
+                (transactions.max(config.issue as u64), Stall::Memory)
+            },
+            _ => (config.issue as u64, Stall::Data),
+        };
+        warp.issue_at = cycle + busy;
+        warp.issue_wait = wait;
+
+        warp.stats.instructions += 1;
+        warp.stats.thread_instructions += lanes as u64;
+    }
+
+    /// Tells the model the path of warp `wid` continues at `pc` after the
+    /// instruction just issued.
+    pub fn set_next_pc(&mut self, wid:usize, pc:i32) {
+        self.warps[wid].next_pc = pc;
+    }
+}
@@ -35,6 +35,17 @@ pub trait Memory {
         self.set_16(addr.wrapping_add(2), ((value >> 16) & 0xFFFF) as u16)
     }
 
+    /// Reads an instruction parcel. Memories which tell instruction fetches
+    /// from data loads (such as split instruction and data caches) override
+    /// these, the others read it as data.
+    fn fetch_16(&self, addr:usize) -> u16 {
+        self.get_16(addr)
+    }
+
+    fn fetch_32(&self, addr:usize) -> u32 {
+        self.get_32(addr)
+    }
+
     fn allocate_at(&mut self, start:usize, size:usize) -> bool;
 }
 
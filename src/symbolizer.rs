@@ -0,0 +1,359 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// A source location found in the DWARF line table.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SourceLocation {
+    pub file:String,
+    pub line:u32,
+}
+
+impl fmt::Display for SourceLocation {
+    fn fmt(&self, f:&mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
+/// Maps program counters back to the program they come from.
+///
+/// Function names are taken from the ELF `.symtab` and source lines from the
+/// DWARF `.debug_line` section (versions 2 to 5). Both are optional: a
+/// `Symbolizer` built from a stripped binary simply answers `None`, and
+/// `describe()` falls back to the raw hexadecimal PC.
+#[derive(Clone, Debug, Default)]
+pub struct Symbolizer {
+    // start address -> (name, size)
+    functions:BTreeMap<usize, (String, usize)>,
+
+    // start address -> row, `None` marks the end of a sequence
+    lines:BTreeMap<usize, Option<(usize, u32)>>,
+    files:Vec<String>,
+}
+
+impl Symbolizer {
+    /// Creates a symbolizer which knows nothing. Every lookup returns `None`.
+    pub fn new() -> Symbolizer {
+        Symbolizer::default()
+    }
+
+    /// Builds the symbolizer from the symbols and line information of `file`.
+    pub fn from_elf(file:&elflib::File) -> Symbolizer {
+        let mut ret = Symbolizer::new();
+
+        if let Some(symtab) = file.get_section(".symtab") {
+            if let Ok(symbols) = file.get_symbols(symtab) {
+                for sym in symbols {
+                    if sym.symtype == elflib::types::STT_FUNC && !sym.name.is_empty() {
+                        ret.add_function(sym.value as usize, sym.size as usize, &sym.name);
+                    }
+                }
+            }
+        }
+
+        if let Some(debug_line) = file.get_section(".debug_line") {
+            let line_str = file.get_section(".debug_line_str").map(|s| &s.data[..]);
+            let str_sec = file.get_section(".debug_str").map(|s| &s.data[..]);
+            ret.parse_debug_line(&debug_line.data, str_sec, line_str);
+        }
+
+        ret
+    }
+
+    /// Registers a function covering `[start, start+size)`. A size of 0 means
+    /// the function extends up to the next known function.
+    pub fn add_function(&mut self, start:usize, size:usize, name:&str) {
+        self.functions.insert(start, (String::from(name), size));
+    }
+
+    /// Returns the function containing `pc` and the offset of `pc` in it.
+    pub fn function_of(&self, pc:usize) -> Option<(&str, usize)> {
+        let (start, (name, size)) = self.functions.range(..=pc).next_back()?;
+        if *size != 0 && pc >= start + size { return None }
+        Some((name.as_str(), pc - start))
+    }
+
+    /// Returns the start address of the function named `name`.
+    pub fn address_of(&self, name:&str) -> Option<usize> {
+        self.functions.iter()
+            .find(|(_, (n, _))| n == name)
+            .map(|(addr, _)| *addr)
+    }
+
+    /// Returns the source file and line of the instruction at `pc`.
+    pub fn location_of(&self, pc:usize) -> Option<SourceLocation> {
+        let (_, row) = self.lines.range(..=pc).next_back()?;
+        let (file, line) = (*row)?;
+        Some(SourceLocation { file:self.files[file].clone(), line })
+    }
+
+    /// Formats `pc` as `0x000102a4 <main+0x14> (main.c:12)`, leaving out the
+    /// parts which are unknown.
+    pub fn describe(&self, pc:usize) -> String {
+        let mut ret = format!("0x{:08x}", pc);
+        if let Some((name, off)) = self.function_of(pc) {
+            ret += &format!(" <{}+0x{:x}>", name, off);
+        }
+        if let Some(loc) = self.location_of(pc) {
+            ret += &format!(" ({})", loc);
+        }
+        ret
+    }
+
+    fn parse_debug_line(&mut self, data:&[u8], strs:Option<&[u8]>, line_strs:Option<&[u8]>) {
+        let mut offset = 0;
+        while offset < data.len() {
+            match self.parse_unit(data, offset, strs, line_strs) {
+                Some(next) if next > offset => offset = next,
+                _ => break,
+            }
+        }
+    }
+
+    /// Parses the line number program starting at `offset` and returns the
+    /// offset of the next one.
+    fn parse_unit(&mut self, data:&[u8], offset:usize
+                  , strs:Option<&[u8]>, line_strs:Option<&[u8]>) -> Option<usize> {
+        let mut r = Reader { data, pos:offset };
+
+        let mut unit_length = r.u32()? as usize;
+        let mut offset_size = 4;
+        if unit_length == 0xffffffff {
+            unit_length = r.u64()? as usize;
+            offset_size = 8;
+        }
+        let end = r.pos.checked_add(unit_length)?.min(data.len());
+
+        let version = r.u16()?;
+        if version < 2 || version > 5 { return Some(end) }
+        let address_size = if version >= 5 {
+            let size = r.u8()? as usize;
+            let _segment_selector_size = r.u8()?;
+            size
+        } else { 4 };
+        let header_length = r.offset(offset_size)?;
+        let program = r.pos.checked_add(header_length)?;
+
+        let min_inst_length = r.u8()? as usize;
+        if version >= 4 { let _max_ops_per_inst = r.u8()?; }
+        let _default_is_stmt = r.u8()?;
+        let line_base = r.u8()? as i8 as i64;
+        let line_range = r.u8()?;
+        let opcode_base = r.u8()?;
+        if line_range == 0 || opcode_base == 0 { return Some(end) }
+        let mut standard_lengths = Vec::new();
+        for _ in 1..opcode_base {
+            standard_lengths.push(r.u8()?);
+        }
+
+        // Directory and file tables, the file indices of the program are
+        // translated into indices of `self.files`.
+        let mut dirs : Vec<String> = Vec::new();
+        let mut unit_files : Vec<usize> = Vec::new();
+        if version >= 5 {
+            let formats = r.entry_formats()?;
+            for _ in 0..r.uleb()? {
+                let (name, _) = r.entry(&formats, offset_size, strs, line_strs)?;
+                dirs.push(name);
+            }
+            let formats = r.entry_formats()?;
+            for _ in 0..r.uleb()? {
+                let (name, dir) = r.entry(&formats, offset_size, strs, line_strs)?;
+                unit_files.push(self.intern_file(&dirs, dir, name));
+            }
+        } else {
+            // the compilation directory is implicit
+            dirs.push(String::new());
+            loop {
+                let dir = r.cstr()?;
+                if dir.is_empty() { break }
+                dirs.push(dir);
+            }
+            // file indices start at 1 before DWARF 5
+            unit_files.push(usize::MAX);
+            loop {
+                let name = r.cstr()?;
+                if name.is_empty() { break }
+                let dir = r.uleb()? as usize;
+                let _mtime = r.uleb()?;
+                let _length = r.uleb()?;
+                unit_files.push(self.intern_file(&dirs, dir, name));
+            }
+        }
+
+        // Run the line number state machine
+        r.pos = program;
+        let initial_file = if version >= 5 { 0 } else { 1 };
+        let mut address = 0usize;
+        let mut file = initial_file;
+        let mut line : i64 = 1;
+
+        while r.pos < end {
+            let opcode = r.u8()?;
+            if opcode >= opcode_base {
+                let adjusted = (opcode - opcode_base) as usize;
+                address += (adjusted / line_range as usize) * min_inst_length;
+                line += line_base + (adjusted % line_range as usize) as i64;
+                self.add_row(address, &unit_files, file, line);
+            } else if opcode == 0 {
+                let len = r.uleb()? as usize;
+                let next = r.pos.checked_add(len)?;
+                if len == 0 { continue }
+                match r.u8()? {
+                    0x01 => { // DW_LNE_end_sequence
+                        self.lines.entry(address).or_insert(None);
+                        address = 0;
+                        file = initial_file;
+                        line = 1;
+                    },
+                    0x02 => { // DW_LNE_set_address
+                        let size = if len > 1 { len - 1 } else { address_size };
+                        address = r.offset(size)?;
+                    },
+                    _ => {},
+                }
+                r.pos = next;
+            } else {
+                match opcode {
+                    0x01 => self.add_row(address, &unit_files, file, line), // DW_LNS_copy
+                    0x02 => address += r.uleb()? as usize * min_inst_length, // advance_pc
+                    0x03 => line += r.sleb()?, // advance_line
+                    0x04 => file = r.uleb()? as usize, // set_file
+                    0x05 => { r.uleb()?; }, // set_column
+                    0x06 | 0x07 => {}, // negate_stmt, set_basic_block
+                    0x08 => { // const_add_pc
+                        let adjusted = (255 - opcode_base) as usize;
+                        address += (adjusted / line_range as usize) * min_inst_length;
+                    },
+                    0x09 => address += r.u16()? as usize, // fixed_advance_pc
+                    _ => {
+                        // unknown standard opcode, skip its operands
+                        for _ in 0..standard_lengths[opcode as usize - 1] {
+                            r.uleb()?;
+                        }
+                    },
+                }
+            }
+        }
+
+        Some(end)
+    }
+
+    fn add_row(&mut self, address:usize, unit_files:&[usize], file:usize, line:i64) {
+        if let Some(&id) = unit_files.get(file) {
+            if id != usize::MAX {
+                self.lines.insert(address, Some((id, line.max(0) as u32)));
+            }
+        }
+    }
+
+    fn intern_file(&mut self, dirs:&[String], dir:usize, name:String) -> usize {
+        let full = match dirs.get(dir) {
+            Some(d) if !d.is_empty() && !name.starts_with('/') => format!("{}/{}", d, name),
+            _ => name,
+        };
+        match self.files.iter().position(|f| *f == full) {
+            Some(id) => id,
+            None => { self.files.push(full); self.files.len() - 1 },
+        }
+    }
+}
+
+/// Little-endian cursor over a DWARF section.
+struct Reader<'a> {
+    data:&'a [u8],
+    pos:usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n:usize) -> Option<&'a [u8]> {
+        let end = self.pos.checked_add(n)?;
+        let ret = self.data.get(self.pos..end)?;
+        self.pos = end;
+        Some(ret)
+    }
+
+    fn offset(&mut self, size:usize) -> Option<usize> {
+        let bytes = self.bytes(size)?;
+        Some(bytes.iter().rev().fold(0usize, |acc, b| (acc << 8) | *b as usize))
+    }
+
+    fn u8(&mut self) -> Option<u8> { self.bytes(1).map(|b| b[0]) }
+    fn u16(&mut self) -> Option<u16> { self.offset(2).map(|v| v as u16) }
+    fn u32(&mut self) -> Option<u32> { self.offset(4).map(|v| v as u32) }
+    fn u64(&mut self) -> Option<u64> { self.offset(8).map(|v| v as u64) }
+
+    fn uleb(&mut self) -> Option<u64> {
+        let mut ret = 0u64;
+        let mut shift = 0;
+        loop {
+            let b = self.u8()?;
+            if shift < 64 { ret |= ((b & 0x7f) as u64) << shift }
+            shift += 7;
+            if b & 0x80 == 0 { return Some(ret) }
+        }
+    }
+
+    fn sleb(&mut self) -> Option<i64> {
+        let mut ret = 0i64;
+        let mut shift = 0;
+        loop {
+            let b = self.u8()?;
+            if shift < 64 { ret |= ((b & 0x7f) as i64) << shift }
+            shift += 7;
+            if b & 0x80 == 0 {
+                if shift < 64 && (b & 0x40) != 0 { ret |= -1i64 << shift }
+                return Some(ret)
+            }
+        }
+    }
+
+    fn cstr(&mut self) -> Option<String> {
+        let rest = self.data.get(self.pos..)?;
+        let len = rest.iter().position(|b| *b == 0)?;
+        self.pos += len + 1;
+        Some(String::from_utf8_lossy(&rest[..len]).into_owned())
+    }
+
+    fn entry_formats(&mut self) -> Option<Vec<(u64, u64)>> {
+        let count = self.u8()?;
+        let mut ret = Vec::new();
+        for _ in 0..count {
+            ret.push((self.uleb()?, self.uleb()?));
+        }
+        Some(ret)
+    }
+
+    /// Reads a DWARF 5 directory or file entry, returning its path and its
+    /// directory index.
+    fn entry(&mut self, formats:&[(u64, u64)], offset_size:usize
+             , strs:Option<&[u8]>, line_strs:Option<&[u8]>) -> Option<(String, usize)> {
+        let mut name = String::new();
+        let mut dir = 0;
+        for (content, form) in formats {
+            let mut value = 0;
+            let mut string = None;
+            match *form {
+                0x08 => string = Some(self.cstr()?), // DW_FORM_string
+                0x0e | 0x1f => { // DW_FORM_strp, DW_FORM_line_strp
+                    let off = self.offset(offset_size)?;
+                    let sec = if *form == 0x0e { strs } else { line_strs };
+                    string = sec.and_then(|s| Reader { data:s, pos:off }.cstr());
+                },
+                0x0b => value = self.u8()? as usize,  // DW_FORM_data1
+                0x05 => value = self.u16()? as usize, // DW_FORM_data2
+                0x06 => value = self.u32()? as usize, // DW_FORM_data4
+                0x07 => value = self.u64()? as usize, // DW_FORM_data8
+                0x0f => value = self.uleb()? as usize, // DW_FORM_udata
+                0x1e => { self.bytes(16)?; }, // DW_FORM_data16
+                0x09 => { let n = self.uleb()? as usize; self.bytes(n)?; }, // DW_FORM_block
+                _ => return None,
+            }
+            match *content {
+                0x1 => name = string.unwrap_or_default(), // DW_LNCT_path
+                0x2 => dir = value, // DW_LNCT_directory_index
+                _ => {},
+            }
+        }
+        Some((name, dir))
+    }
+}
@@ -0,0 +1,379 @@
+//! Execution traces: a record per instruction committed by a hart (a core,
+//! or a thread of a SIMT-X warp), written as the text of Spike's
+//! `--log-commits` to diff against Spike, or in a compact binary format.
+//!
+//! The text is the one of Spike running in machine mode on RV32: the warp
+//! and the active mask of SIMT-X records are only kept by the binary format.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+/// Starts every binary trace, followed by the records.
+pub const BINARY_MAGIC : &[u8; 8] = b"RVTRACE\x01";
+
+/// A register written by an instruction, integer (`x`) or floating-point
+/// (`f`, NaN-boxed on 64 bits).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RegWrite {
+    pub fp:bool,
+    pub reg:u8,
+    pub value:u64,
+}
+
+impl RegWrite {
+    pub fn x(reg:usize, value:i32) -> RegWrite {
+        RegWrite { fp:false, reg:reg as u8, value:value as u32 as u64 }
+    }
+
+    pub fn f(reg:usize, bits:u64) -> RegWrite {
+        RegWrite { fp:true, reg:reg as u8, value:bits }
+    }
+}
+
+/// A memory access of an instruction: `size` bytes at `addr`, with the value
+/// written for stores.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemAccess {
+    pub addr:u32,
+    pub size:u8,
+    pub store:Option<u64>,
+}
+
+impl MemAccess {
+    pub fn load(addr:usize, size:usize) -> MemAccess {
+        MemAccess { addr:addr as u32, size:size as u8, store:None }
+    }
+
+    pub fn store(addr:usize, size:usize, value:u64) -> MemAccess {
+        MemAccess { addr:addr as u32, size:size as u8, store:Some(value) }
+    }
+}
+
+/// An instruction committed by hart `hart`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TraceRecord {
+    pub hart:usize,
+    pub pc:u32,
+    /// As fetched: 16 bits for compressed instructions.
+    pub inst:u32,
+    pub writes:Vec<RegWrite>,
+    pub accesses:Vec<MemAccess>,
+    /// The warp and the active mask of the instruction, on SIMT-X.
+    pub warp:Option<(usize, u128)>,
+}
+
+impl TraceRecord {
+    /// The record of instruction `inst` at `pc`, of which only the lower
+    /// half is kept when it is compressed.
+    pub fn new(hart:usize, pc:i32, inst:u32) -> TraceRecord {
+        let inst = if inst & 0b11 != 0b11 { inst & 0xffff } else { inst };
+        TraceRecord { hart, pc:pc as u32, inst, ..TraceRecord::default() }
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        self.inst & 0b11 != 0b11
+    }
+
+    /// The warp of the record, or its hart on machines without warps.
+    pub fn warp_or_hart(&self) -> usize {
+        self.warp.map_or(self.hart, |(wid, _)| wid)
+    }
+
+    /// Appends the record to `out` in the binary format: a flag byte (bit 0
+    /// telling whether there is a warp) then LEB128 numbers, the hart, PC,
+    /// instruction, warp and mask, and the writes and accesses, each list
+    /// preceded by its length. A write starts with a byte holding the
+    /// register and `0x20` for floating-point ones, an access with one
+    /// holding its size and `0x80` for stores, followed by the address and
+    /// the value stored.
+    pub fn encode(&self, out:&mut Vec<u8>) {
+        out.push(self.warp.is_some() as u8);
+        leb128(out, self.hart as u128);
+        leb128(out, self.pc as u128);
+        leb128(out, self.inst as u128);
+        if let Some((wid, mask)) = self.warp {
+            leb128(out, wid as u128);
+            leb128(out, mask);
+        }
+        leb128(out, self.writes.len() as u128);
+        for w in &self.writes {
+            out.push(w.reg | if w.fp { 0x20 } else { 0 });
+            leb128(out, w.value as u128);
+        }
+        leb128(out, self.accesses.len() as u128);
+        for a in &self.accesses {
+            out.push(a.size | if a.store.is_some() { 0x80 } else { 0 });
+            leb128(out, a.addr as u128);
+            if let Some(value) = a.store {
+                leb128(out, value as u128);
+            }
+        }
+    }
+}
+
+fn leb128(out:&mut Vec<u8>, mut value:u128) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn hex(f:&mut fmt::Formatter<'_>, bytes:usize, value:u64) -> fmt::Result {
+    write!(f, "0x{:01$x}", value, bytes * 2)
+}
+
+impl fmt::Display for TraceRecord {
+    /// A line of Spike's `--log-commits`.
+    fn fmt(&self, f:&mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "core{:4}: 3 ", self.hart)?;
+        hex(f, 4, self.pc as u64)?;
+        write!(f, " (")?;
+        hex(f, if self.is_compressed() { 2 } else { 4 }, self.inst as u64)?;
+        write!(f, ")")?;
+        for w in &self.writes {
+            write!(f, " {}{:<2} ", if w.fp { 'f' } else { 'x' }, w.reg)?;
+            hex(f, if w.fp { 8 } else { 4 }, w.value)?;
+        }
+        for a in self.accesses.iter().filter(|a| a.store.is_none()) {
+            write!(f, " mem ")?;
+            hex(f, 4, a.addr as u64)?;
+        }
+        for a in &self.accesses {
+            if let Some(value) = a.store {
+                write!(f, " mem ")?;
+                hex(f, 4, a.addr as u64)?;
+                write!(f, " ")?;
+                hex(f, a.size as usize, value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Reads the binary records back
+struct Decoder<'a> {
+    bytes:&'a [u8],
+    at:usize,
+}
+
+impl<'a> Decoder<'a> {
+    fn byte(&mut self) -> Result<u8, String> {
+        let byte = *self.bytes.get(self.at)
+            .ok_or_else(|| format!("truncated record at byte {}", self.at))?;
+        self.at += 1;
+        Ok(byte)
+    }
+
+    fn number(&mut self) -> Result<u128, String> {
+        let mut value = 0u128;
+        let mut shift = 0;
+        loop {
+            let byte = self.byte()?;
+            if shift >= 128 {
+                return Err(format!("number too large at byte {}", self.at))
+            }
+            value |= ((byte & 0x7f) as u128) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return Ok(value)
+            }
+        }
+    }
+
+    fn record(&mut self) -> Result<TraceRecord, String> {
+        let flags = self.byte()?;
+        let hart = self.number()? as usize;
+        let pc = self.number()? as u32;
+        let inst = self.number()? as u32;
+        let warp = if flags & 1 != 0 {
+            Some((self.number()? as usize, self.number()?))
+        } else {
+            None
+        };
+        let mut writes = Vec::new();
+        for _ in 0..self.number()? {
+            let reg = self.byte()?;
+            writes.push(RegWrite { fp:reg & 0x20 != 0, reg:reg & 0x1f, value:self.number()? as u64 });
+        }
+        let mut accesses = Vec::new();
+        for _ in 0..self.number()? {
+            let size = self.byte()?;
+            let addr = self.number()? as u32;
+            let store = if size & 0x80 != 0 { Some(self.number()? as u64) } else { None };
+            accesses.push(MemAccess { addr, size:size & 0x7f, store });
+        }
+        Ok(TraceRecord { hart, pc, inst, writes, accesses, warp })
+    }
+}
+
+/// Reads the records of a binary trace.
+pub fn read_binary(bytes:&[u8]) -> Result<Vec<TraceRecord>, String> {
+    if !bytes.starts_with(BINARY_MAGIC) {
+        return Err(String::from("not a binary trace"))
+    }
+    let mut decoder = Decoder { bytes, at:BINARY_MAGIC.len() };
+    let mut records = Vec::new();
+    while decoder.at < bytes.len() {
+        records.push(decoder.record()?);
+    }
+    Ok(records)
+}
+
+/// How the records are written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TraceFormat {
+    /// A line per record, as Spike's `--log-commits`.
+    Spike,
+    /// See `TraceRecord::encode`, after `BINARY_MAGIC`.
+    Binary,
+}
+
+impl FromStr for TraceFormat {
+    type Err = String;
+
+    fn from_str(s:&str) -> Result<TraceFormat, String> {
+        match s {
+            "spike" => Ok(TraceFormat::Spike),
+            "binary" => Ok(TraceFormat::Binary),
+            _ => Err(format!("`{}` is not spike or binary", s)),
+        }
+    }
+}
+
+/// Which records are written: those in a PC range, of a warp (or of a
+/// hart, on machines without warps), and among them `count` at most after
+/// the first `skip` ones.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TraceFilter {
+    /// From the first PC, up to the second one excluded.
+    pub pcs:Option<(u32, u32)>,
+    pub warp:Option<usize>,
+    pub skip:u64,
+    pub count:Option<u64>,
+}
+
+impl TraceFilter {
+    /// Whether the instructions at `pc` of warp (or hart) `warp` are traced.
+    pub fn matches(&self, pc:u32, warp:usize) -> bool {
+        self.pcs.map_or(true, |(beg, end)| beg <= pc && pc < end)
+            && self.warp.map_or(true, |w| w == warp)
+    }
+}
+
+// Where the records go
+enum Output {
+    Writer(TraceFormat, Box<dyn Write + Send>),
+    Records(Vec<TraceRecord>),
+}
+
+/// Writes the records of a machine, see `enable_trace` on the machines.
+pub struct Tracer {
+    filter:TraceFilter,
+    out:Output,
+    // records matching the filter, and written
+    matched:u64,
+    written:u64,
+    // the first write error, after which nothing is written
+    error:Option<io::Error>,
+}
+
+impl Tracer {
+    pub fn new(format:TraceFormat, filter:TraceFilter, out:Box<dyn Write + Send>) -> Tracer {
+        let mut tracer = Tracer { filter, out:Output::Writer(format, out), matched:0, written:0, error:None };
+        if format == TraceFormat::Binary {
+            tracer.write(BINARY_MAGIC);
+        }
+        tracer
+    }
+
+    /// A tracer keeping the records, to be taken by `take_records`.
+    pub fn collecting(filter:TraceFilter) -> Tracer {
+        Tracer { filter, out:Output::Records(Vec::new()), matched:0, written:0, error:None }
+    }
+
+    /// The records kept since the last call, by a collecting tracer.
+    pub fn take_records(&mut self) -> Vec<TraceRecord> {
+        match &mut self.out {
+            Output::Records(records) => std::mem::take(records),
+            Output::Writer(_, _) => Vec::new(),
+        }
+    }
+
+    /// A tracer writing to the file at `path`, created or truncated.
+    pub fn to_file<P:AsRef<Path>>(format:TraceFormat, filter:TraceFilter, path:P) -> io::Result<Tracer> {
+        let file = File::create(path)?;
+        Ok(Tracer::new(format, filter, Box::new(BufWriter::new(file))))
+    }
+
+    /// The format of the records written, `None` when they are kept.
+    pub fn format(&self) -> Option<TraceFormat> {
+        match self.out {
+            Output::Writer(format, _) => Some(format),
+            Output::Records(_) => None,
+        }
+    }
+
+    pub fn filter(&self) -> &TraceFilter {
+        &self.filter
+    }
+
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+
+    /// Whether a record at `pc` of warp (or hart) `warp` may be written,
+    /// telling the machines when to build it.
+    pub fn wants(&self, pc:i32, warp:usize) -> bool {
+        self.error.is_none()
+            && self.filter.count.map_or(true, |count| self.written < count)
+            && self.filter.matches(pc as u32, warp)
+    }
+
+    pub fn record(&mut self, record:&TraceRecord) {
+        if !self.wants(record.pc as i32, record.warp_or_hart()) {
+            return
+        }
+        self.matched += 1;
+        if self.matched <= self.filter.skip {
+            return
+        }
+        match self.out {
+            Output::Writer(TraceFormat::Spike, _) => {
+                let line = format!("{}\n", record);
+                self.write(line.as_bytes());
+            },
+            Output::Writer(TraceFormat::Binary, _) => {
+                let mut bytes = Vec::new();
+                record.encode(&mut bytes);
+                self.write(&bytes);
+            },
+            Output::Records(ref mut records) => records.push(record.clone()),
+        }
+        self.written += 1;
+    }
+
+    fn write(&mut self, bytes:&[u8]) {
+        if let Output::Writer(_, out) = &mut self.out {
+            if let Err(e) = out.write_all(bytes) {
+                self.error = Some(e);
+            }
+        }
+    }
+
+    /// Flushes the output, and gives the first write error if any.
+    pub fn finish(mut self) -> io::Result<()> {
+        match (self.error.take(), &mut self.out) {
+            (Some(e), _) => Err(e),
+            (None, Output::Writer(_, out)) => out.flush(),
+            (None, Output::Records(_)) => Ok(()),
+        }
+    }
+}
@@ -13,6 +13,7 @@ pub trait MachineInteger
   From<i32>
 {
     const XLEN : u32;
+    /// The bits from `j` (included) to `i` (excluded), up to `XLEN` of them.
     fn bit_slice(&self, i:usize, j:usize) -> Self;
 
     fn slice_mask(a:usize, b:usize) -> Self {
@@ -27,7 +28,7 @@ pub trait MachineInteger
 impl MachineInteger for i32 {
     const XLEN : u32 = 32;
     fn bit_slice(&self, i:usize, j:usize) -> i32 {
-        (self >> j) & ((1 << (i-j)) - 1)
+        (self >> j) & 1i32.checked_shl((i-j) as u32).unwrap_or(0).wrapping_sub(1)
     }
 
     fn all_set() -> Self { -1 }
@@ -36,7 +37,7 @@ impl MachineInteger for i32 {
 impl MachineInteger for i64 {
     const XLEN : u32 = 64;
     fn bit_slice(&self, i:usize, j:usize) -> i64 {
-        (self >> j) & ((1 << (i-j)) - 1)
+        (self >> j) & 1i64.checked_shl((i-j) as u32).unwrap_or(0).wrapping_sub(1)
     }
 
     fn all_set() -> Self { -1 }
@@ -44,7 +45,7 @@ impl MachineInteger for i64 {
 impl MachineInteger for i128 {
     const XLEN : u32 = 128;
     fn bit_slice(&self, i:usize, j:usize) -> i128 {
-        (self >> j) & ((1 << (i-j)) - 1)
+        (self >> j) & 1i128.checked_shl((i-j) as u32).unwrap_or(0).wrapping_sub(1)
     }
 
     fn all_set() -> Self { -1 }
@@ -68,7 +69,24 @@ impl MachineFloat for f64 {
     const FLEN : u32 = 64;
 }
 
-pub trait BitSet : Sized + Copy {
+/// A fixed-size set of thread ids, such as the execution mask of a SIMT path.
+/// Its `SIZE` bounds the number of threads in a warp.
+pub trait BitSet
+: Sized +
+  Copy +
+  Default +
+  PartialEq +
+  Eq +
+  std::fmt::Debug +
+  std::fmt::Binary +
+  std::fmt::LowerHex +
+  BitAnd<Output=Self> +
+  BitOr<Output=Self> +
+  BitOrAssign +
+  Not<Output=Self> +
+  Send +
+  'static
+{
     const SIZE : u32;
     fn set(&mut self, id:usize);
     fn unset(&mut self, id:usize);
@@ -76,6 +94,9 @@ pub trait BitSet : Sized + Copy {
     fn singleton(id:usize) -> Self;
     fn any(&self) -> bool;
     fn none(&self) -> bool;
+    fn count(&self) -> usize;
+
+    fn empty() -> Self { Self::default() }
 
     fn bits(&self) -> Bits<Self> {
         Bits { index:0, set:*self }
@@ -162,6 +183,7 @@ impl BitSet for u32 {
     fn singleton(id:usize) -> Self { 1 << id }
     fn any(&self) -> bool { *self != 0 }
     fn none(&self) -> bool { *self == 0 }
+    fn count(&self) -> usize { self.count_ones() as usize }
 }
 
 impl BitSet for u64 {
@@ -172,6 +194,7 @@ impl BitSet for u64 {
     fn singleton(id:usize) -> Self { 1 << id }
     fn any(&self) -> bool { *self != 0 }
     fn none(&self) -> bool { *self == 0 }
+    fn count(&self) -> usize { self.count_ones() as usize }
 }
 
 impl BitSet for u128 {
@@ -182,4 +205,36 @@ impl BitSet for u128 {
     fn singleton(id:usize) -> Self { 1 << id }
     fn any(&self) -> bool { *self != 0 }
     fn none(&self) -> bool { *self == 0 }
+    fn count(&self) -> usize { self.count_ones() as usize }
+}
+
+/// The random numbers of the simulators, for the warp schedulers which draw
+/// their choices and the random cache replacement: a xorshift generator, so
+/// that a run only depends on its seed.
+#[derive(Clone, Debug)]
+pub struct Rng {
+    state:u64,
+}
+
+impl Rng {
+    pub fn new(seed:u64) -> Rng {
+        // splitmix64, so that close seeds give unrelated sequences, and the
+        // state is never 0
+        let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        Rng { state:(z ^ (z >> 31)) | 1 }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    /// Uniform in `[0, 1)`.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
 }
@@ -0,0 +1,193 @@
+extern crate riscv_sandbox;
+
+use riscv_sandbox::cache::*;
+use riscv_sandbox::machine::{IntegerMachine, MultiCoreIMachine
+    , rv32imc::Machine as RV32I
+    , rv32pthread::Machine as RV32Threaded
+    , simtx::{Machine as SIMTX, scheduler::LexicoScheduler}};
+use riscv_sandbox::isa::{Instruction, OpCode};
+use riscv_sandbox::memory::Memory;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+#[test]
+fn lru_replacement() {
+    // a single set of 2 lines of 16 bytes
+    let mut cache = Cache::new(CacheConfig::new(32, 2, 16));
+    assert!(!cache.access(0x00, false).hit);
+    assert!(!cache.access(0x10, false).hit);
+    assert!(cache.access(0x04, false).hit);
+    assert!(!cache.access(0x20, false).hit);
+
+    assert!(cache.contains(0x00));
+    assert!(!cache.contains(0x10));
+    assert!(cache.contains(0x20));
+    let stats = cache.stats();
+    assert_eq!((stats.reads, stats.hits, stats.misses, stats.evictions), (4, 1, 3, 1));
+}
+
+#[test]
+fn plru_replacement() {
+    let mut config = CacheConfig::new(64, 4, 16);
+    config.replacement = Replacement::Plru;
+    let mut cache = Cache::new(config);
+    for line in &[0, 1, 2, 3, 0] {
+        cache.access(line * 16, false);
+    }
+    cache.access(4 * 16, false);
+
+    // the tree points away from line 0 then line 3, not at the LRU line 1
+    assert!(cache.contains(0));
+    assert!(cache.contains(16));
+    assert!(!cache.contains(32));
+    assert!(cache.contains(48));
+}
+
+#[test]
+fn write_policies() {
+    let mut cache = Cache::new(CacheConfig::new(16, 1, 16));
+    let write = cache.access(0x00, true);
+    assert_eq!((write.hit, write.fill, write.writeback, write.write_through), (false, true, None, false));
+    let read = cache.access(0x40, false);
+    assert_eq!(read.writeback, Some(0x00));
+    assert_eq!((cache.stats().evictions, cache.stats().writebacks), (1, 1));
+
+    let mut config = CacheConfig::new(16, 1, 16);
+    config.write_policy = WritePolicy::WriteThrough;
+    let mut cache = Cache::new(config);
+    let write = cache.access(0x00, true);
+    assert_eq!((write.hit, write.fill, write.write_through), (false, false, true));
+    assert!(!cache.contains(0x00));
+    cache.access(0x00, false);
+    assert!(cache.access(0x00, true).write_through);
+    assert_eq!(cache.access(0x40, false).writeback, None);
+}
+
+#[test]
+fn parse_config() {
+    let config : CacheConfig = "32K:4:64".parse().unwrap();
+    assert_eq!(config, CacheConfig::new(32 * 1024, 4, 64));
+    assert_eq!(config.sets(), 128);
+
+    let config : CacheConfig = "256k:8:64:plru:wt".parse().unwrap();
+    assert_eq!((config.replacement, config.write_policy), (Replacement::Plru, WritePolicy::WriteThrough));
+
+    assert!("32K:4".parse::<CacheConfig>().is_err());
+    assert!("32K:4:48".parse::<CacheConfig>().is_err());
+    assert!("96:3:16:plru".parse::<CacheConfig>().is_err());
+    assert!("32K:4:64:fifo".parse::<CacheConfig>().is_err());
+}
+
+fn hierarchy() -> HierarchyConfig {
+    HierarchyConfig {
+        l1i:Some(CacheConfig::new(256, 2, 16)),
+        l1d:Some(CacheConfig::new(256, 2, 16)),
+        l2:Some(CacheConfig::new(1024, 4, 32)),
+    }
+}
+
+#[test]
+fn cached_memory_levels() {
+    let mut memory = CachedMemory::new(vec![0u8; 0x200], hierarchy());
+    memory.set_32(0x100, 0xdeadbeef);
+    assert_eq!(memory.get_32(0x100), 0xdeadbeef);
+    assert_eq!(memory.inner()[0x100], 0xef);
+    memory.fetch_32(0x104);
+    memory.fetch_32(0x0);
+    memory.fetch_32(0x4);
+    // crosses a line
+    memory.get_32(0x11e);
+
+    let l1i = memory.l1i_stats().unwrap();
+    assert_eq!((l1i.reads, l1i.hits, l1i.misses), (3, 1, 2));
+    let l1d = memory.l1d_stats().unwrap();
+    assert_eq!((l1d.writes, l1d.reads, l1d.hits, l1d.misses), (1, 3, 1, 3));
+    // the fills of both L1s, the L1I miss at 0x104 hitting the line of 0x100
+    let l2 = memory.l2_stats().unwrap();
+    assert_eq!((l2.reads, l2.hits, l2.misses), (5, 2, 3));
+
+    let unified = CachedMemory::new(vec![0u8; 0x100], HierarchyConfig {
+        l1d:Some(CacheConfig::new(256, 2, 16)),
+        ..HierarchyConfig::default()
+    });
+    unified.fetch_32(0);
+    unified.get_32(0);
+    assert_eq!(unified.l1i_stats(), None);
+    assert_eq!(unified.l1d_stats().unwrap().hits, 1);
+}
+
+// lw t0, 0x100(zero) ; sw t1, 0x104(zero) ; ret, from address 4
+fn program() -> Vec<u32> {
+    let mut memory = vec![Instruction::nop().le()
+        , Instruction::create_i(OpCode::LOAD, 5, 0, 0x100, 2).le()
+        , Instruction::create_s(OpCode::STORE, 0, 6, 0x104, 2).le()
+        , Instruction::create_i(OpCode::JALR, 0, 1, 0, 0).le()];
+    memory.resize(0x200 / 4, 0);
+    memory.set_32(0x100, 42);
+    memory
+}
+
+#[test]
+fn rv32imc_cached() {
+    // the core does not stop at address 0, return to a `j .` instead
+    let mut memory = program();
+    memory[0x10 / 4] = Instruction::create_j(OpCode::JAL, 0, 0).le();
+    let mut memory = CachedMemory::new(memory, hierarchy());
+    let mut machine = RV32I::new();
+    machine.set_pc(4);
+    machine.set_register(1, 0x10);
+    machine.set_register(6, 7);
+    for _ in 0..20 {
+        machine.cycle(&mut memory);
+    }
+    assert_eq!(machine.get_register(5), 42);
+    assert_eq!(memory.inner().get_32(0x104), 7);
+
+    let l1d = memory.l1d_stats().unwrap();
+    assert_eq!((l1d.reads, l1d.writes, l1d.misses), (1, 1, 1));
+    assert!(memory.l1i_stats().unwrap().reads > 0);
+}
+
+#[test]
+fn rv32pthread_cached() {
+    let memory = Arc::new(Mutex::new(CachedMemory::new(program(), hierarchy())));
+    let mut machine = RV32Threaded::new(HashMap::new());
+    machine.set_pc(4);
+    machine.set_i_register(6, 7);
+    for _ in 0..20 {
+        machine.step(memory.clone());
+    }
+    assert_eq!(machine.get_i_register(5), 42);
+
+    let memory = memory.lock().unwrap();
+    assert_eq!(memory.inner().get_32(0x104), 7);
+    let l1d = memory.l1d_stats().unwrap();
+    assert_eq!((l1d.reads, l1d.writes, l1d.misses), (1, 1, 1));
+    assert!(memory.l1i_stats().unwrap().reads > 0);
+}
+
+#[test]
+fn simtx_cached() {
+    let memory = Arc::new(Mutex::new(CachedMemory::new(program(), hierarchy())));
+    let mut machine : SIMTX<LexicoScheduler> = SIMTX::new(2, 1, HashMap::new());
+    for _ in 0..2 {
+        let c = machine.pop_first_idle();
+        machine.set_pc_of(c, 4);
+        machine.set_i_register_of(c, 1, 0);
+        machine.set_i_register_of(c, 6, 7);
+    }
+    for _ in 0..100 {
+        machine.step(memory.clone());
+        if machine.finished() { break }
+    }
+    assert!(machine.finished());
+
+    let memory = memory.lock().unwrap();
+    assert_eq!(memory.inner().get_32(0x104), 7);
+    // one fetch per warp instruction, one access per lane
+    let l1i = memory.l1i_stats().unwrap();
+    assert_eq!((l1i.reads, l1i.misses), (3, 1));
+    let l1d = memory.l1d_stats().unwrap();
+    assert_eq!((l1d.reads, l1d.writes, l1d.hits, l1d.misses), (2, 2, 3, 1));
+}
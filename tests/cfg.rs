@@ -0,0 +1,194 @@
+extern crate riscv_sandbox;
+extern crate elf as elflib;
+
+use riscv_sandbox::cfg::{Cfg, Terminator};
+use riscv_sandbox::isa::{Instruction, OpCode};
+use riscv_sandbox::symbolizer::Symbolizer;
+
+fn code(program:&[Instruction]) -> Vec<u8> {
+    program.iter().flat_map(|i| i.0.to_le_bytes().to_vec()).collect()
+}
+
+fn ret() -> Instruction {
+    Instruction::create_i(OpCode::JALR, 0, 1, 0, 0)
+}
+
+#[test]
+fn if_then_else() {
+    let cfg = Cfg::build(&code(&[
+        Instruction::create_b(OpCode::BRANCH, 10, 0, 12, 0), // 0x100: beq a0, zero, 0x10c
+        Instruction::create_i(OpCode::OPIMM, 5, 0, 1, 0),
+        Instruction::create_j(OpCode::JAL, 0, 8),             // 0x108: j 0x110
+        Instruction::create_i(OpCode::OPIMM, 5, 0, 2, 0),
+        Instruction::create_r(OpCode::OPREG, 6, 5, 5, 0),     // 0x110
+        ret(),
+    ]), 0x100);
+
+    let starts : Vec<usize> = cfg.blocks().iter().map(|b| b.start).collect();
+    assert_eq!(starts, vec![0x100, 0x104, 0x10c, 0x110]);
+    let first = &cfg.blocks()[0];
+    assert_eq!((first.terminator, first.successors.clone()), (Terminator::Branch, vec![0x10c, 0x104]));
+    assert_eq!(cfg.blocks()[1].terminator, Terminator::Jump);
+    assert_eq!(cfg.blocks()[2].terminator, Terminator::FallThrough);
+    assert_eq!(cfg.blocks()[3].predecessors, vec![0x104, 0x10c]);
+    assert_eq!(cfg.blocks()[3].terminator, Terminator::Indirect);
+
+    assert_eq!(cfg.block_of(0x108).unwrap().start, 0x104);
+    assert!(cfg.block_of(0x118).is_none());
+    assert_eq!(cfg.immediate_post_dominator(0x100), Some(0x110));
+    assert_eq!(cfg.immediate_post_dominator(0x10c), Some(0x110));
+    assert_eq!(cfg.immediate_post_dominator(0x110), None);
+}
+
+#[test]
+fn calls_and_returns() {
+    let cfg = Cfg::build(&code(&[
+        Instruction::create_b(OpCode::BRANCH, 10, 0, 12, 0), // 0x0: beq a0, zero, 0xc
+        Instruction::create_j(OpCode::JAL, 1, 0x40),          // 0x4: call, comes back
+        ret(),                                                // 0x8
+        ret(),                                                // 0xc
+    ]), 0);
+
+    assert_eq!(cfg.blocks()[1].terminator, Terminator::Call);
+    assert_eq!(cfg.blocks()[1].successors, vec![0x8]);
+    assert_eq!(cfg.immediate_post_dominator(0x4), Some(0x8));
+    // both sides return on their own
+    assert_eq!(cfg.immediate_post_dominator(0x0), None);
+}
+
+#[test]
+fn compressed_code() {
+    // c.beqz a0, +4 ; c.li a0, 1 ; c.li a0, 2 ; c.jr ra
+    let cfg = Cfg::build(&[0x11, 0xc1, 0x05, 0x45, 0x09, 0x45, 0x82, 0x80], 0x200);
+    let starts : Vec<(usize, usize)> = cfg.blocks().iter().map(|b| (b.start, b.end)).collect();
+    assert_eq!(starts, vec![(0x200, 0x202), (0x202, 0x204), (0x204, 0x208)]);
+    assert_eq!(cfg.immediate_post_dominator(0x200), Some(0x204));
+}
+
+#[test]
+fn elf_text() {
+    let file = elflib::File::open_path("resources/hartid_test").unwrap();
+    let cfg = Cfg::from_elf(&file).unwrap();
+    let text = file.get_section(".text").unwrap();
+
+    // the blocks tile the section, and link to each other
+    let blocks = cfg.blocks();
+    assert_eq!(blocks[0].start, text.shdr.addr as usize);
+    assert_eq!(blocks.last().unwrap().end, (text.shdr.addr + text.shdr.size) as usize);
+    for pair in blocks.windows(2) {
+        assert_eq!(pair[0].end, pair[1].start);
+    }
+    for block in blocks {
+        for s in &block.successors {
+            assert!(cfg.block_of(*s).unwrap().predecessors.contains(&block.start));
+        }
+    }
+
+    // every block belongs to a function, named after its symbol
+    let total : usize = cfg.functions().iter().map(|f| f.blocks.len()).sum();
+    assert_eq!(total, blocks.len());
+    let symbolizer = Symbolizer::from_elf(&file);
+    let main = symbolizer.address_of("main").unwrap();
+    assert_eq!(cfg.function_of(main).unwrap().name, Some(String::from("main")));
+    for l in cfg.loops() {
+        assert!(cfg.dominates(l.header, l.latches[0]));
+    }
+}
+
+// Two nested loops in a function called from a loop of the caller:
+//   0x00: main: call f ; bnez a0, main ; ret
+//   0x0c: f:    outer: inner: addi ; bnez a1, inner ; bnez a2, outer ; ret
+fn nested() -> Cfg {
+    Cfg::build(&code(&[
+        Instruction::create_j(OpCode::JAL, 1, 0xc),              // 0x00
+        Instruction::create_b(OpCode::BRANCH, 10, 0, -4, 1),      // 0x04: bnez a0, 0x00
+        ret(),                                                    // 0x08
+        Instruction::create_i(OpCode::OPIMM, 5, 5, 1, 0),         // 0x0c
+        Instruction::create_b(OpCode::BRANCH, 11, 0, -4, 1),      // 0x10: bnez a1, 0x0c
+        Instruction::create_b(OpCode::BRANCH, 12, 0, -8, 1),      // 0x14: bnez a2, 0x0c
+        ret(),                                                    // 0x18
+    ]), 0)
+}
+
+#[test]
+fn functions() {
+    let cfg = nested();
+    let functions : Vec<(usize, Vec<usize>)> = cfg.functions().iter()
+        .map(|f| (f.entry, f.blocks.clone()))
+        .collect();
+    assert_eq!(functions, vec![(0x0, vec![0x0, 0x4, 0x8]), (0xc, vec![0xc, 0x14, 0x18])]);
+    assert_eq!(cfg.blocks()[0].callee, Some(0xc));
+    assert_eq!(cfg.function_of(0x10).unwrap().entry, 0xc);
+    assert!(cfg.functions().iter().all(|f| f.name.is_none()));
+}
+
+#[test]
+fn dominators() {
+    let cfg = Cfg::build(&code(&[
+        Instruction::create_b(OpCode::BRANCH, 10, 0, 12, 0), // 0x100: beq a0, zero, 0x10c
+        Instruction::create_i(OpCode::OPIMM, 5, 0, 1, 0),
+        Instruction::create_j(OpCode::JAL, 0, 8),
+        Instruction::create_i(OpCode::OPIMM, 5, 0, 2, 0),     // 0x10c
+        ret(),                                                // 0x110
+    ]), 0x100);
+
+    assert_eq!(cfg.immediate_dominator(0x100), None);
+    assert_eq!(cfg.immediate_dominator(0x108), Some(0x100));
+    assert_eq!(cfg.immediate_dominator(0x110), Some(0x100));
+    assert!(cfg.dominates(0x100, 0x110));
+    assert!(cfg.dominates(0x104, 0x108));
+    assert!(!cfg.dominates(0x104, 0x110));
+    assert!(cfg.post_dominates(0x110, 0x100));
+    assert!(!cfg.post_dominates(0x10c, 0x100));
+    assert!(cfg.loops().is_empty());
+}
+
+#[test]
+fn nested_loops() {
+    let cfg = nested();
+    let loops = cfg.loops();
+    assert_eq!(loops.len(), 2);
+
+    // main calls f from its loop: the call does not make f part of it
+    assert_eq!((loops[0].header, loops[0].latches.clone(), loops[0].blocks.clone()), (0x0, vec![0x4], vec![0x0, 0x4]));
+    assert_eq!((loops[0].start, loops[0].end, loops[0].depth), (0x0, 0x8, 1));
+
+    // the inner loop is the block branching to itself, sharing its header
+    // with the outer one: both back edges make one loop
+    assert_eq!(loops[1].header, 0xc);
+    assert_eq!(loops[1].latches, vec![0xc, 0x14]);
+    assert_eq!(loops[1].blocks, vec![0xc, 0x14]);
+    assert_eq!(cfg.loop_of(0x10).unwrap().header, 0xc);
+    assert!(cfg.loop_of(0x18).is_none());
+}
+
+#[test]
+fn loop_nesting() {
+    // outer: addi ; inner: addi ; bnez a1, inner ; bnez a2, outer ; ret
+    let cfg = Cfg::build(&code(&[
+        Instruction::create_i(OpCode::OPIMM, 5, 5, 1, 0),    // 0x0
+        Instruction::create_i(OpCode::OPIMM, 6, 6, 1, 0),    // 0x4
+        Instruction::create_b(OpCode::BRANCH, 11, 0, -4, 1), // 0x8: bnez a1, 0x4
+        Instruction::create_b(OpCode::BRANCH, 12, 0, -12, 1),// 0xc: bnez a2, 0x0
+        ret(),
+    ]), 0);
+    let loops = cfg.loops();
+    assert_eq!(loops.len(), 2);
+    assert_eq!((loops[0].header, loops[0].blocks.clone(), loops[0].depth), (0x0, vec![0x0, 0x4, 0xc], 1));
+    assert_eq!((loops[1].header, loops[1].blocks.clone(), loops[1].depth), (0x4, vec![0x4], 2));
+    assert_eq!(loops[1].parent, Some(0));
+    assert_eq!(cfg.loop_of(0x8).unwrap().header, 0x4);
+    assert_eq!(cfg.loop_of(0xc).unwrap().header, 0x0);
+}
+
+#[test]
+fn dot() {
+    let dot = nested().to_dot();
+    assert!(dot.starts_with("digraph cfg {"));
+    assert!(dot.contains("subgraph cluster_1 {\n        label=\"0xc\";"));
+    assert!(dot.contains("b0 [label=\"0x0 - 0x0\", peripheries=2];"));
+    assert!(dot.contains("b4 -> b0 [label=T, style=dashed];"));
+    assert!(dot.contains("b4 -> b8 [label=F];"));
+    assert!(dot.contains("b0 -> bc [style=dotted];"));
+    assert!(dot.trim_end().ends_with('}'));
+}
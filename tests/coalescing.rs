@@ -0,0 +1,92 @@
+extern crate riscv_sandbox;
+
+use riscv_sandbox::machine::{MultiCoreIMachine, simtx::{
+    Machine as SIMTX,
+    scheduler::LexicoScheduler,
+    coalescing::{classify, transactions, AccessPattern, CoalescingAnalyzer},
+}};
+use riscv_sandbox::isa::{Instruction, OpCode};
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+#[test]
+fn patterns() {
+    assert_eq!(classify(&[0x100], 4), AccessPattern::Uniform);
+    assert_eq!(classify(&[0x100, 0x100, 0x100], 4), AccessPattern::Uniform);
+    assert_eq!(classify(&[0x100, 0x104, 0x108, 0x10c], 4), AccessPattern::UnitStride);
+    assert_eq!(classify(&[0x100, 0x101, 0x102], 1), AccessPattern::UnitStride);
+    assert_eq!(classify(&[0x100, 0x140, 0x180], 4), AccessPattern::Strided(0x40));
+    assert_eq!(classify(&[0x10c, 0x108, 0x104], 4), AccessPattern::Strided(-4));
+    assert_eq!(classify(&[0x100, 0x104, 0x200], 4), AccessPattern::Scattered);
+}
+
+#[test]
+fn line_transactions() {
+    assert_eq!(transactions(&[0x100, 0x104, 0x108, 0x13c], 4, 64), 1);
+    assert_eq!(transactions(&[0x100, 0x140, 0x180], 4, 64), 3);
+    // an access across two lines needs both
+    assert_eq!(transactions(&[0x13e], 4, 64), 2);
+    assert_eq!(transactions(&[0x100, 0x100], 4, 32), 1);
+}
+
+#[test]
+fn per_pc_efficiency() {
+    let mut analyzer = CoalescingAnalyzer::new(64);
+    let unit : Vec<usize> = (0..32).map(|i| 0x1000 + 4 * i).collect();
+    let strided : Vec<usize> = (0..32).map(|i| 0x1000 + 64 * i).collect();
+
+    assert_eq!(analyzer.record(0x10, false, 4, &unit), AccessPattern::UnitStride);
+    assert_eq!(analyzer.record(0x10, false, 4, &unit), AccessPattern::UnitStride);
+    assert_eq!(analyzer.record(0x20, true, 4, &strided), AccessPattern::Strided(64));
+
+    let load = analyzer.per_pc()[&0x10];
+    assert_eq!((load.executions, load.accesses, load.transactions), (2, 64, 4));
+    assert_eq!(load.unit_stride, 2);
+    assert_eq!(load.efficiency(), 1.);
+
+    // 128 bytes in 32 lines instead of 2
+    let store = analyzer.per_pc()[&0x20];
+    assert!(store.write);
+    assert_eq!(store.transactions, 32);
+    assert_eq!(store.efficiency(), 2. / 32.);
+
+    let total = analyzer.total();
+    assert_eq!((total.executions, total.transactions, total.ideal_transactions), (3, 36, 6));
+}
+
+#[test]
+fn simtx_memory_instructions() {
+    // a0 = 4 * lane: unit-stride load, then a uniform load from 0x300
+    // and a scattered store
+    let mut memory : Vec<u32> = vec![Instruction::nop().le()];
+    memory.push(Instruction::create_i(OpCode::LOAD, 5, 10, 0x200, 2).le());
+    memory.push(Instruction::create_i(OpCode::LOAD, 6, 0, 0x300, 2).le());
+    memory.push(Instruction::create_r(OpCode::OPREG, 7, 10, 10, 0b0000001_000).le());
+    memory.push(Instruction::create_s(OpCode::STORE, 7, 5, 0x400, 2).le());
+    memory.push(Instruction::create_i(OpCode::JALR, 0, 1, 0, 0).le());
+    memory.resize(0x1000 / 4, 0);
+    let memory = Arc::new(Mutex::new(memory));
+
+    let mut machine : SIMTX<LexicoScheduler> = SIMTX::new(4, 1, HashMap::new());
+    machine.enable_coalescing(32);
+    for lane in 0..4 {
+        let c = machine.pop_first_idle();
+        machine.set_pc_of(c, 4);
+        machine.set_i_register_of(c, 1, 0);
+        machine.set_i_register_of(c, 10, 4 * lane);
+    }
+    for _ in 0..100 {
+        machine.step(memory.clone());
+        if machine.finished() { break }
+    }
+    assert!(machine.finished());
+
+    let per_pc = machine.coalescing().unwrap().per_pc();
+    assert_eq!(per_pc.len(), 3);
+    assert_eq!((per_pc[&0x4].unit_stride, per_pc[&0x4].transactions), (1, 1));
+    assert_eq!((per_pc[&0x8].uniform, per_pc[&0x8].transactions), (1, 1));
+    // lanes store at 0x400 + 16 * lane * lane
+    assert_eq!((per_pc[&0x10].scattered, per_pc[&0x10].transactions), (1, 3));
+    assert!(per_pc[&0x10].write);
+}
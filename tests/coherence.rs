@@ -0,0 +1,125 @@
+extern crate riscv_sandbox;
+
+use riscv_sandbox::cache::CacheConfig;
+use riscv_sandbox::cache::coherence::{Coherence, CoherenceStats, LineState, Protocol};
+use riscv_sandbox::machine::{MultiCoreIMachine, rv32pthread::Machine as RV32Threaded};
+use riscv_sandbox::isa::{Instruction, OpCode};
+use riscv_sandbox::memory::Memory;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+fn config() -> CacheConfig {
+    CacheConfig::new(256, 2, 64)
+}
+
+#[test]
+fn mesi_states() {
+    let mut coherence = Coherence::new(2, config(), Protocol::Mesi);
+    coherence.access(0, 0x100, 4, false);
+    assert_eq!(coherence.state(0, 0x100), LineState::Exclusive);
+    coherence.access(1, 0x104, 4, false);
+    assert_eq!((coherence.state(0, 0x100), coherence.state(1, 0x100)), (LineState::Shared, LineState::Shared));
+
+    coherence.access(1, 0x104, 4, true);
+    assert_eq!((coherence.state(0, 0x100), coherence.state(1, 0x100)), (LineState::Invalid, LineState::Modified));
+
+    // the modified copy is written back to be shared
+    coherence.access(0, 0x100, 4, false);
+    assert_eq!((coherence.state(0, 0x100), coherence.state(1, 0x100)), (LineState::Shared, LineState::Shared));
+
+    let stats = coherence.per_line()[&0x100];
+    assert_eq!(stats, CoherenceStats {
+        bus_reads:3, upgrades:1, invalidations:1, false_sharing:1,
+        interventions:1, writebacks:1, ..CoherenceStats::default()
+    });
+    assert_eq!(coherence.cache_stats(0).misses, 2);
+}
+
+#[test]
+fn moesi_owned() {
+    let mut coherence = Coherence::new(3, config(), Protocol::Moesi);
+    coherence.access(0, 0x0, 4, true);
+    assert_eq!(coherence.state(0, 0x0), LineState::Modified);
+    coherence.access(1, 0x0, 4, false);
+    coherence.access(2, 0x0, 4, false);
+    assert_eq!(coherence.state(0, 0x0), LineState::Owned);
+    assert_eq!(coherence.state(2, 0x0), LineState::Shared);
+
+    coherence.access(0, 0x0, 4, true);
+    assert_eq!(coherence.state(0, 0x0), LineState::Modified);
+    assert_eq!(coherence.state(1, 0x0), LineState::Invalid);
+
+    let stats = coherence.total();
+    assert_eq!((stats.bus_read_exclusives, stats.upgrades, stats.interventions), (1, 1, 2));
+    assert_eq!((stats.invalidations, stats.writebacks), (2, 0));
+}
+
+#[test]
+fn false_sharing() {
+    let mut coherence = Coherence::new(2, config(), Protocol::Mesi);
+    coherence.access(0, 0x0, 4, true);
+    coherence.access(1, 0x4, 4, true);
+    // core 1 wrote these bytes
+    coherence.access(0, 0x4, 4, true);
+    // on another line
+    coherence.access(1, 0x40, 4, true);
+
+    let stats = coherence.per_line()[&0x0];
+    assert_eq!((stats.bus_read_exclusives, stats.invalidations, stats.false_sharing), (3, 2, 1));
+    assert_eq!(coherence.per_line()[&0x40].invalidations, 0);
+}
+
+#[test]
+fn dirty_eviction() {
+    let mut coherence = Coherence::new(1, CacheConfig::new(64, 1, 64), Protocol::Mesi);
+    coherence.access(0, 0x0, 4, true);
+    coherence.access(0, 0x40, 4, false);
+    assert_eq!(coherence.state(0, 0x0), LineState::Invalid);
+    assert_eq!(coherence.per_line()[&0x0].writebacks, 1);
+    assert_eq!(coherence.cache_stats(0).evictions, 1);
+}
+
+// The main thread creates a thread, then both store to their counter
+// forever, at `main` and `thread`.
+fn counters(main:i32, thread:i32) -> CoherenceStats {
+    let mut memory : HashMap<usize, u32> = HashMap::new();
+    memory.allocate_at(0, 0x400);
+    let program = [
+        (0x04, Instruction::create_i(OpCode::OPIMM, 12, 0, 0x40, 0)),
+        (0x08, Instruction::create_i(OpCode::OPIMM, 10, 0, 0x180, 0)),
+        (0x0c, Instruction::create_j(OpCode::JAL, 1, 0x200 - 0x0c)),
+        (0x10, Instruction::create_s(OpCode::STORE, 0, 6, main, 2)),
+        (0x14, Instruction::create_j(OpCode::JAL, 0, -4)),
+        (0x40, Instruction::create_s(OpCode::STORE, 0, 6, thread, 2)),
+        (0x44, Instruction::create_j(OpCode::JAL, 0, -4)),
+    ];
+    for (addr, i) in &program {
+        memory.insert(*addr, i.le());
+    }
+    let memory = Arc::new(Mutex::new(memory));
+
+    let mut plt = HashMap::new();
+    plt.insert(0x200, String::from("pthread_create@GLIBC_2.27"));
+    let mut machine = RV32Threaded::new(plt);
+    machine.enable_coherence(config(), Protocol::Mesi);
+    machine.set_pc_of(0, 4);
+    for _ in 0..100 {
+        machine.step(memory.clone());
+    }
+    machine.coherence().unwrap().total()
+}
+
+#[test]
+fn rv32pthread_counters() {
+    let shared = counters(0x100, 0x100);
+    assert!(shared.invalidations > 0);
+    assert_eq!(shared.false_sharing, 0);
+
+    let falsely_shared = counters(0x100, 0x104);
+    assert!(falsely_shared.invalidations > 0);
+    assert_eq!(falsely_shared.false_sharing, falsely_shared.invalidations);
+
+    let padded = counters(0x100, 0x140);
+    assert_eq!(padded.invalidations, 0);
+}
@@ -0,0 +1,100 @@
+extern crate riscv_sandbox;
+
+use riscv_sandbox::conformance::*;
+
+const DIR : &str = "resources/riscv-tests";
+const ISA : &str = "resources/riscv-tests/isa";
+
+// The tests of ISA which fail on every machine, by prefix, and why
+const EXPECTED_FAILURES : &[(&str, &str)] = &[
+    ("rv32ua-p-", "no A extension: rv32imc skips the AMOs, rv32pthread only runs \
+                   LR (as a load), SIMT-X does not implement them"),
+];
+
+// The rv32 tests of riscv-tests which ISA leaves out, by prefix, and why
+const NOT_VENDORED : &[(&str, &str)] = &[
+    ("rv32ui-p-ma_data", "misaligned accesses, which riscv-tests lets a machine either trap \
+                          or perform, not recreated"),
+    ("rv32ui-p-ld_st", "not recreated"),
+    ("rv32ui-p-st_ld", "not recreated"),
+    ("rv32uf-p-", "no F extension on rv32imc and rv32pthread"),
+    ("rv32ud-p-", "no D extension"),
+];
+
+fn expected_failure(test:&str) -> Option<&'static str> {
+    EXPECTED_FAILURES.iter().find(|f| test.starts_with(f.0)).map(|f| f.1)
+}
+
+#[test]
+fn discovery() {
+    let tests = discover(DIR).unwrap();
+    let names : Vec<&str> = tests.iter().map(|t| t.name.as_str()).collect();
+    assert_eq!(names, vec!["selftest-fail", "selftest-pass", "selftest-signature"]);
+    assert_eq!(tests[0].reference, None);
+    assert_eq!(tests[2].reference, Some(format!("{}/selftest-signature.reference_output", DIR).into()));
+
+    let isa = discover(ISA).unwrap();
+    assert_eq!(isa.len(), 58);
+    assert_eq!(isa[0].name, "rv32ua-p-amoadd_w");
+    assert_eq!(isa[11].name, "rv32ui-p-add");
+    assert_eq!(isa[57].name, "rv32um-p-remu");
+    assert!(isa.iter().any(|t| t.name == "rv32uc-p-rvc"));
+    assert!(isa.iter().all(|t| t.reference.is_none()));
+    for &(prefix, reason) in NOT_VENDORED {
+        assert!(!isa.iter().any(|t| t.name.starts_with(prefix)), "{} is left out: {}", prefix, reason);
+    }
+
+    assert_eq!("simtx".parse(), Ok(Target::Simtx));
+    assert!("rv64".parse::<Target>().is_err());
+}
+
+#[test]
+fn signatures() {
+    assert_eq!(check_signature(&[1, 0xdead_beef], "00000001\ndeadbeef\n"), Verdict::Pass);
+    assert_eq!(check_signature(&[1, 2], "00000001\n00000003\n")
+               , Verdict::Signature { index:1, expected:3, got:2 });
+    assert!(!check_signature(&[1], "00000001\n00000002\n").passed());
+    assert!(!check_signature(&[1], "one\n").passed());
+}
+
+#[test]
+fn suite() {
+    let tests = discover(DIR).unwrap();
+    let report = Report::run(&tests, &Target::all(), 10_000);
+    for &target in Target::all().iter() {
+        assert_eq!(report.get("selftest-pass", target).unwrap().verdict, Verdict::Pass, "{}", report);
+        assert_eq!(report.get("selftest-fail", target).unwrap().verdict, Verdict::Fail(3), "{}", report);
+        assert_eq!(report.get("selftest-signature", target).unwrap().verdict, Verdict::Pass, "{}", report);
+        assert_eq!(report.passed(target), (2, 3));
+    }
+    assert_eq!(report.failures().count(), 3);
+
+    let text = report.to_string();
+    assert!(text.starts_with("test                rv32imc           rv32pthread core  SIMT-X lane\n"), "{}", text);
+    assert!(text.contains("selftest-fail       FAIL #3           FAIL #3           FAIL #3\n"), "{}", text);
+    assert!(text.contains("rv32imc: 2/3 passed\n"));
+    assert!(text.contains("selftest-fail on SIMT-X lane: test 3 failed\n"));
+
+    let tests = discover(ISA).unwrap();
+    let report = Report::run(&tests, &Target::all(), 100_000);
+    for result in &report.results {
+        match expected_failure(&result.test) {
+            Some(reason) => assert!(!result.verdict.passed(), "{} on {} passes, but {}", result.test, result.target, reason),
+            None => assert!(result.verdict.passed(), "{} on {}: {}", result.test, result.target, result.verdict),
+        }
+    }
+    let failing = tests.iter().filter(|t| expected_failure(&t.name).is_some()).count();
+    assert_eq!(failing, 10);
+    for &target in Target::all().iter() {
+        assert_eq!(report.passed(target), (tests.len() - failing, tests.len()), "{}", report);
+    }
+}
+
+#[test]
+fn stops() {
+    let pass = Test::new(format!("{}/selftest-pass.elf", DIR));
+    assert_eq!(run(&pass, Target::Rv32imc, 5).verdict, Verdict::Timeout);
+    let missing = Test::new(format!("{}/missing.elf", DIR));
+    let result = run(&missing, Target::Simtx, 5);
+    assert!(match result.verdict { Verdict::Error(_) => true, _ => false });
+}
@@ -0,0 +1,135 @@
+extern crate riscv_sandbox;
+
+use riscv_sandbox::machine::{IntegerMachine, MultiCoreIMachine
+    , iss::{Machine as ISS, Trap}
+    , rv32imc::Machine as RV32I
+    , simtx::{Machine as SIMTX, scheduler::LexicoScheduler}};
+use riscv_sandbox::memory::Memory;
+use riscv_sandbox::isa::{Instruction, OpCode};
+use riscv_sandbox::cosim::*;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+fn memory(program:&[Instruction]) -> Vec<u32> {
+    let mut memory : Vec<u32> = program.iter().map(|i| i.le()).collect();
+    memory.resize(0x100 / 4, 0);
+    memory
+}
+
+// Sums 1 to a0 in a1, storing the partial sums at 0x80, then loads the
+// last one back in a2 and returns to 0
+fn sum() -> Vec<Instruction> {
+    vec![
+        Instruction::nop(),
+        Instruction::create_i(OpCode::OPIMM, 10, 0, 5, 0),         // 0x04: li a0, 5
+        Instruction::create_i(OpCode::OPIMM, 11, 0, 0, 0),         // 0x08: li a1, 0
+        Instruction::create_i(OpCode::OPIMM, 5, 0, 0x80, 0),       // 0x0c: li t0, 0x80
+        Instruction::create_r(OpCode::OPREG, 11, 11, 10, 0),       // 0x10: add a1, a1, a0
+        Instruction::create_s(OpCode::STORE, 5, 11, 0, 0b001),     // 0x14: sh a1, 0(t0)
+        Instruction::create_i(OpCode::OPIMM, 5, 5, 2, 0),          // 0x18: addi t0, t0, 2
+        Instruction::create_i(OpCode::OPIMM, 10, 10, -1, 0),       // 0x1c: addi a0, a0, -1
+        Instruction::create_b(OpCode::BRANCH, 0, 10, -16, 0b100),  // 0x20: blt zero, a0, 0x10
+        Instruction::create_i(OpCode::LOAD, 12, 5, -2, 0b001),     // 0x24: lh a2, -2(t0)
+        Instruction::create_i(OpCode::JALR, 0, 1, 0, 0),           // 0x28: ret
+    ]
+}
+
+#[test]
+fn rv32imc() {
+    let program = vec![
+        Instruction::create_i(OpCode::OPIMM, 10, 0, -3, 0),        // 0x00: li a0, -3
+        Instruction::create_i(OpCode::OPIMM, 5, 0, 0x80, 0),       // 0x04: li t0, 0x80
+        Instruction::create_s(OpCode::STORE, 5, 10, 1, 0b000),     // 0x08: sb a0, 1(t0)
+        Instruction::create_b(OpCode::BRANCH, 10, 0, 8, 0b100),    // 0x0c: blt a0, zero, 0x14
+        Instruction::create_i(OpCode::OPIMM, 10, 0, 1, 0),         // 0x10: skipped
+        Instruction::create_r(OpCode::OPREG, 11, 10, 10, 0),       // 0x14: add a1, a0, a0
+        Instruction::create_s(OpCode::STORE, 5, 11, 4, 0b010),     // 0x18: sw a1, 4(t0)
+        Instruction::create_i(OpCode::LOAD, 12, 5, 0, 0b010),      // 0x1c: lw a2, 0(t0)
+        Instruction::create_i(OpCode::JALR, 0, 1, 0, 0),           // 0x20: ret
+    ];
+    let mut mem = memory(&program);
+    let mut ref_mem = memory(&program);
+    let mut machine = RV32I::new();
+    let mut reference = ISS::new(0);
+
+    let retired = lockstep(&mut Rv32iDut::new(&mut machine, &mut mem), &mut reference, &mut ref_mem, 1000);
+    assert_eq!(retired, Ok(8));
+    assert_eq!(reference.get_register(11), -6i32 as u32);
+    assert_eq!(machine.get_i_register(12), 0xfd00);
+}
+
+#[test]
+fn simtx_lanes() {
+    let program = sum();
+    for thread in 0..4 {
+        let mem = Arc::new(Mutex::new(memory(&program)));
+        let mut ref_mem = memory(&program);
+        let mut machine : SIMTX<LexicoScheduler> = SIMTX::new(2, 2, HashMap::new());
+        machine.set_pc_of(thread, 4);
+        let mut reference = ISS::new(4);
+
+        let retired = lockstep(&mut SimtxLane::new(&mut machine, mem.clone(), thread), &mut reference, &mut ref_mem, 1000);
+        assert_eq!(retired, Ok(3 + 5 * 5 + 2), "thread {}", thread);
+        assert_eq!(mem.lock().unwrap().get_16(0x88), 15);
+    }
+}
+
+#[test]
+fn divergence() {
+    // the machine and the reference start with a different sum stored
+    let program = vec![
+        Instruction::create_i(OpCode::OPIMM, 5, 0, 0x80, 0),       // 0x00: li t0, 0x80
+        Instruction::create_i(OpCode::LOAD, 6, 5, 0, 0b010),       // 0x04: lw t1, 0(t0)
+        Instruction::create_i(OpCode::JALR, 0, 1, 0, 0),
+    ];
+    let mut mem = memory(&program);
+    let mut ref_mem = memory(&program);
+    ref_mem.set_32(0x80, 42);
+    let mut machine = RV32I::new();
+    let mut reference = ISS::new(0);
+
+    let divergence = lockstep(&mut Rv32iDut::new(&mut machine, &mut mem), &mut reference, &mut ref_mem, 1000).unwrap_err();
+    assert_eq!(divergence.retired, 1);
+    assert_eq!(divergence.mismatches, vec![Mismatch::Register { reg:6, expected:42, got:0 }]);
+    assert_eq!(divergence.got.as_ref().unwrap().pc, 4);
+    let report = divergence.to_string();
+    assert!(report.starts_with("=== rv32imc DIVERGES FROM THE REFERENCE AFTER 1 INSTRUCTIONS ===\n"));
+    assert!(report.contains("x6: expected 0x0000002a, got 0x00000000\n"));
+    assert!(report.contains("*x6  0x0000002a 0x00000000"));
+
+    // the reference cannot go further than an ecall
+    let mut ref_mem = memory(&[Instruction(0x00000073)]);
+    assert_eq!(ISS::new(0).step(&mut ref_mem), Err(Trap::Ecall { pc:0 }));
+}
+
+#[test]
+fn sll() {
+    let program = vec![
+        Instruction::create_i(OpCode::OPIMM, 10, 0, 1, 0),         // 0x00: li a0, 1
+        Instruction::create_i(OpCode::OPIMM, 11, 0, 3, 0),         // 0x04: li a1, 3
+        Instruction::create_r(OpCode::OPREG, 12, 10, 11, 0b001),   // 0x08: sll a2, a0, a1
+        Instruction::create_i(OpCode::JALR, 0, 1, 0, 0),
+    ];
+    let mut mem = memory(&program);
+    let mut ref_mem = memory(&program);
+    let mut machine = RV32I::new();
+    let mut reference = ISS::new(0);
+
+    assert_eq!(lockstep(&mut Rv32iDut::new(&mut machine, &mut mem), &mut reference, &mut ref_mem, 1000), Ok(4));
+    assert_eq!(machine.get_i_register(12), 8);
+}
+
+#[test]
+fn rv32imc_loop() {
+    let program = sum();
+    let mut mem = memory(&program);
+    let mut ref_mem = memory(&program);
+    let mut machine = RV32I::new();
+    let mut reference = ISS::new(0);
+
+    // the instruction after the branch not taken runs once
+    let retired = lockstep(&mut Rv32iDut::new(&mut machine, &mut mem), &mut reference, &mut ref_mem, 1000);
+    assert_eq!(retired, Ok(4 + 5 * 5 + 2));
+    assert_eq!(machine.get_i_register(12), 15);
+}
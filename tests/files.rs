@@ -0,0 +1,155 @@
+extern crate riscv_sandbox;
+
+use riscv_sandbox::machine::simtx::files::{self, FileTable, errno};
+
+use std::fs;
+use std::path::PathBuf;
+
+fn sandbox(name:&str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("riscv_sandbox_{}", name));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("data")).unwrap();
+    dir
+}
+
+#[test]
+fn resolve_stays_in_sandbox() {
+    let mut table = FileTable::new();
+    table.set_sandbox("/sandbox");
+    assert_eq!(table.resolve("in.txt"), PathBuf::from("/sandbox/in.txt"));
+    assert_eq!(table.resolve("/data/in.txt"), PathBuf::from("/sandbox/data/in.txt"));
+    assert_eq!(table.resolve("../../etc/passwd"), PathBuf::from("/sandbox/etc/passwd"));
+    assert_eq!(table.resolve("data/../../in.txt"), PathBuf::from("/sandbox/in.txt"));
+}
+
+#[test]
+fn streams() {
+    assert_eq!(files::stream(files::STDIN), 1);
+    assert_eq!(files::fd_of(files::stream(5)), 5);
+    assert_eq!(files::fd_of(0), -1);
+}
+
+#[test]
+fn fopen_modes() {
+    let dir = sandbox("fopen_modes");
+    let mut table = FileTable::new();
+    table.set_sandbox(&dir);
+
+    assert_eq!(table.fopen("data/missing.txt", "r"), Err(errno::ENOENT));
+    assert_eq!(table.fopen("data/out.txt", "z"), Err(errno::EINVAL));
+
+    // lowest free descriptor after the standard streams
+    let fd = table.fopen("/data/out.txt", "w").unwrap();
+    assert_eq!(fd, 3);
+    assert_eq!(table.write(fd, b"hello"), Ok(5));
+    assert_eq!(table.read(fd, &mut [0;4]), Err(errno::EBADF));
+    table.close(fd).unwrap();
+    assert_eq!(table.close(fd), Err(errno::EBADF));
+
+    let fd = table.fopen("data/out.txt", "a").unwrap();
+    table.write(fd, b" world").unwrap();
+    table.close(fd).unwrap();
+    assert_eq!(fs::read_to_string(dir.join("data/out.txt")).unwrap(), "hello world");
+
+    assert_eq!(table.fopen("data/out.txt", "wx"), Err(errno::EEXIST));
+
+    let fd = table.fopen("data/out.txt", "rb").unwrap();
+    assert_eq!(table.write(fd, b"x"), Err(errno::EBADF));
+    table.close(fd).unwrap();
+}
+
+#[test]
+fn read_seek_tell() {
+    let dir = sandbox("read_seek_tell");
+    fs::write(dir.join("in.txt"), "0123456789").unwrap();
+    let mut table = FileTable::new();
+    table.set_sandbox(&dir);
+
+    let fd = table.fopen("in.txt", "r+").unwrap();
+    let mut buf = [0;4];
+    assert_eq!(table.read(fd, &mut buf), Ok(4));
+    assert_eq!(&buf, b"0123");
+    assert_eq!(table.tell(fd), Ok(4));
+
+    assert_eq!(table.getc(fd), Ok(Some(b'4')));
+    table.ungetc(fd, b'4').unwrap();
+    assert_eq!(table.tell(fd), Ok(4));
+
+    assert_eq!(table.seek(fd, -2, 2), Ok(8));
+    assert_eq!(table.read_full(fd, &mut buf), Ok(2));
+    assert_eq!(&buf[..2], b"89");
+    assert_eq!(table.eof(fd), Ok(true));
+
+    assert_eq!(table.seek(fd, 0, 0), Ok(0));
+    assert_eq!(table.eof(fd), Ok(false));
+    assert_eq!(table.seek(fd, 0, 7), Err(errno::EINVAL));
+    assert_eq!(table.seek(files::STDOUT, 0, 0), Err(errno::ESPIPE));
+}
+
+#[test]
+fn open_flags() {
+    let dir = sandbox("open_flags");
+    let mut table = FileTable::new();
+    table.set_sandbox(&dir);
+
+    // O_WRONLY | O_CREAT | O_TRUNC
+    let fd = table.open("new.txt", 0o1 | 0o100 | 0o1000).unwrap();
+    table.write(fd, b"abc").unwrap();
+    table.close(fd).unwrap();
+
+    // O_RDONLY
+    let fd = table.open("new.txt", 0).unwrap();
+    let mut buf = [0;8];
+    assert_eq!(table.read(fd, &mut buf), Ok(3));
+    assert_eq!(table.open("absent.txt", 0), Err(errno::ENOENT));
+}
+
+#[test]
+fn short_reads() {
+    let dir = sandbox("short_reads");
+    fs::write(dir.join("in.txt"), "0123").unwrap();
+    let mut table = FileTable::new();
+    table.set_sandbox(&dir);
+
+    // read returns the byte given back alone, fread goes on with the file
+    let fd = table.fopen("in.txt", "r").unwrap();
+    assert_eq!(table.getc(fd), Ok(Some(b'0')));
+    table.ungetc(fd, b'0').unwrap();
+    let mut buf = [0;8];
+    assert_eq!(table.read(fd, &mut buf), Ok(1));
+    assert_eq!(table.eof(fd), Ok(false));
+    table.ungetc(fd, b'0').unwrap();
+    assert_eq!(table.read_full(fd, &mut buf), Ok(4));
+    assert_eq!(&buf[..4], b"0123");
+    assert_eq!(table.eof(fd), Ok(true));
+    assert_eq!(table.read(fd, &mut buf), Ok(0));
+}
+
+#[cfg(unix)]
+#[test]
+fn symlinks_stay_in_sandbox() {
+    use std::os::unix::fs::symlink;
+
+    let dir = sandbox("symlinks");
+    let outside = sandbox("symlinks_outside");
+    fs::write(outside.join("secret.txt"), "secret").unwrap();
+    fs::write(dir.join("data/in.txt"), "in").unwrap();
+    symlink(&outside, dir.join("out")).unwrap();
+    symlink(outside.join("secret.txt"), dir.join("secret.txt")).unwrap();
+    symlink(outside.join("new.txt"), dir.join("dangling.txt")).unwrap();
+    symlink(dir.join("data"), dir.join("inside")).unwrap();
+
+    let mut table = FileTable::new();
+    table.set_sandbox(&dir);
+    assert_eq!(table.fopen("out/secret.txt", "r"), Err(errno::EACCES));
+    assert_eq!(table.fopen("secret.txt", "r"), Err(errno::EACCES));
+    assert_eq!(table.fopen("out/new.txt", "w"), Err(errno::EACCES));
+    assert_eq!(table.fopen("dangling.txt", "w"), Err(errno::EACCES));
+    assert!(!outside.join("new.txt").exists());
+
+    // links inside the sandbox are followed
+    let fd = table.fopen("inside/in.txt", "r").unwrap();
+    assert_eq!(table.getc(fd), Ok(Some(b'i')));
+    assert!(table.fopen("inside/new.txt", "w").is_ok());
+    assert!(dir.join("data/new.txt").exists());
+}
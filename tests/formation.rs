@@ -0,0 +1,166 @@
+extern crate riscv_sandbox;
+
+use riscv_sandbox::machine::{MultiCoreIMachine, simtx::{
+    Machine as SIMTX,
+    scheduler::LexicoScheduler,
+    formation::*,
+}};
+use riscv_sandbox::isa::{Instruction, OpCode};
+use riscv_sandbox::memory::Memory;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+#[test]
+fn compaction() {
+    // complementary paths fit in a warp, with the threads in their own lane
+    assert_eq!(compacted_warps::<u32>(&[0b0011, 0b1100], 4, None), 1);
+    assert_eq!(compacted_warps::<u32>(&[0b0011, 0b0011], 4, None), 2);
+    assert_eq!(compacted_warps::<u32>(&[0b0011, 0b0011], 4, Some(4)), 2);
+    // or free to take any lane
+    assert_eq!(compacted_warps::<u32>(&[0b0011, 0b0011], 4, Some(1)), 1);
+    assert_eq!(compacted_warps::<u32>(&[0b0111, 0b0011], 4, Some(1)), 2);
+    // lanes 0 and 2 share a bank, as do lanes 1 and 3
+    assert_eq!(compacted_warps::<u32>(&[0b0011, 0b0011], 4, Some(2)), 1);
+    assert_eq!(compacted_warps::<u32>(&[0b0101, 0b0001], 4, Some(2)), 2);
+    assert_eq!(compacted_warps::<u32>(&[], 4, None), 0);
+}
+
+#[test]
+fn majority() {
+    let paths = vec![(0x10, 0b1u32), (0x20, 0b11), (0x10, 0b10), (0, 0xff), (0x8, 0)];
+    assert_eq!(majority_pc(paths.into_iter()), Some(0x10));
+    let paths = vec![(0x10, 0b1u32), (0x20, 0b111)];
+    assert_eq!(majority_pc(paths.into_iter()), Some(0x20));
+    assert_eq!(majority_pc(Vec::<(i32, u32)>::new().into_iter()), None);
+}
+
+#[test]
+fn config() {
+    assert_eq!("dwf".parse(), Ok(FormationConfig::dwf()));
+    assert_eq!("tbc:4".parse(), Ok(FormationConfig::tbc(4)));
+    assert_eq!("tbc:4:8".parse(), Ok(FormationConfig { block:Some(4), banks:Some(8) }));
+    assert_eq!("dwf:1".parse(), Ok(FormationConfig { block:None, banks:Some(1) }));
+    for bad in &["tbc", "dwf:4:2", "tbc:0", "tbc:x", "dwf:0", "swf"] {
+        assert!(bad.parse::<FormationConfig>().is_err(), "{}", bad);
+    }
+    assert_eq!(FormationConfig::tbc(4).group_of(7), 1);
+    assert_eq!(FormationConfig::dwf().group_of(7), 0);
+    assert_eq!(format!("{}", FormationConfig::tbc(2)), "thread block compaction, 2 warps per block, a register bank per lane");
+}
+
+// t1 = 2 * (a0 == 0 ? 2 : 1) on 2 warps of 4 threads, a0 being given by
+// `a0(warp, lane)`; returns the results and the formation statistics
+fn run(formation:Option<&str>, a0:fn(usize, usize) -> i32) -> (Vec<i32>, Option<FormationStats>) {
+    let program = vec![
+        Instruction::nop(),
+        Instruction::create_b(OpCode::BRANCH, 10, 0, 12, 0), // 0x04: beq a0, zero, 0x10
+        Instruction::create_i(OpCode::OPIMM, 5, 0, 1, 0),
+        Instruction::create_j(OpCode::JAL, 0, 8),             // 0x0c: j 0x14
+        Instruction::create_i(OpCode::OPIMM, 5, 0, 2, 0),     // 0x10
+        Instruction::create_r(OpCode::OPREG, 6, 5, 5, 0),     // 0x14: join
+        Instruction::create_i(OpCode::JALR, 0, 1, 0, 0),
+    ];
+    let mut memory : Vec<u32> = program.iter().map(|i| i.le()).collect();
+    memory.resize(0x100 / 4, 0);
+    let memory = Arc::new(Mutex::new(memory));
+
+    let mut machine : SIMTX<LexicoScheduler> = SIMTX::new(4, 2, HashMap::new());
+    if let Some(config) = formation {
+        machine.enable_warp_formation(config.parse().unwrap());
+    }
+    for c in 0..8 {
+        machine.set_pc_of(c, 4);
+        machine.set_i_register_of(c, 1, 0);
+        machine.set_i_register_of(c, 10, a0(c / 4, c % 4));
+    }
+    for _ in 0..100 {
+        machine.step(memory.clone());
+        if machine.finished() { break }
+    }
+    assert!(machine.finished());
+    let results = (0..8).map(|c| machine.get_i_register_of(c, 6)).collect();
+    (results, machine.warp_formation().map(|f| *f.stats()))
+}
+
+// the first half of the first warp and the second half of the second one go
+// to 0x08, the others to 0x10
+fn complementary(warp:usize, lane:usize) -> i32 {
+    ((lane < 2) != (warp == 1)) as i32
+}
+
+fn same(_warp:usize, lane:usize) -> i32 {
+    (lane < 2) as i32
+}
+
+#[test]
+fn formation() {
+    let (results, stats) = run(None, complementary);
+    assert_eq!(results, vec![2, 2, 4, 4, 4, 4, 2, 2]);
+    assert_eq!(stats, None);
+
+    // each warp runs beq, addi, j, addi, add, ret: the 2 sides of the
+    // branch fit in a warp
+    let (results, stats) = run(Some("dwf"), complementary);
+    assert_eq!(results, vec![2, 2, 4, 4, 4, 4, 2, 2]);
+    let stats = stats.unwrap();
+    assert_eq!(stats, FormationStats { thread_instructions:36, warp_instructions:12, formed_instructions:9, merged:3 });
+    assert_eq!(stats.simd_efficiency(4), 0.75);
+    assert_eq!(stats.formed_simd_efficiency(4), 1.);
+
+    // a block per warp
+    let (_, stats) = run(Some("tbc:1"), complementary);
+    assert_eq!(stats.unwrap().formed_instructions, 12);
+    let (_, stats) = run(Some("tbc:2"), complementary);
+    assert_eq!(stats.unwrap().formed_instructions, 9);
+}
+
+#[test]
+fn bank_conflicts() {
+    // the threads of the two warps going the same way sit in the same lanes
+    let (results, stats) = run(Some("dwf"), same);
+    assert_eq!(results, vec![2, 2, 4, 4, 2, 2, 4, 4]);
+    assert_eq!(stats.unwrap().formed_instructions, 12);
+
+    let (_, stats) = run(Some("dwf:1"), same);
+    assert_eq!(stats.unwrap().formed_instructions, 9);
+    let (_, stats) = run(Some("dwf:2"), same);
+    assert_eq!(stats.unwrap().formed_instructions, 9);
+}
+
+#[test]
+fn contended_mutex() {
+    // lane 0 holds the lock at 0x100, which the 3 other lanes spin on: the
+    // majority PC alone would never let it release the lock
+    let program = vec![
+        Instruction::nop(),
+        Instruction::create_b(OpCode::BRANCH, 10, 0, 20, 0), // 0x04: beq a0, zero, 0x18
+        Instruction::lw(5, 0, 0x100),                         // 0x08: lw t0, 0x100(zero)
+        Instruction::create_b(OpCode::BRANCH, 5, 0, -4, 1),   // 0x0c: bne t0, zero, 0x08
+        Instruction::create_i(OpCode::OPIMM, 11, 0, 1, 0),    // 0x10: li a1, 1
+        Instruction::create_i(OpCode::JALR, 0, 1, 0, 0),      // 0x14: ret
+        Instruction::create_i(OpCode::OPIMM, 6, 0, 42, 0),    // 0x18: li t1, 42
+        Instruction::sw(0, 6, 0x104),                         // 0x1c: sw t1, 0x104(zero)
+        Instruction::sw(0, 0, 0x100),                         // 0x20: sw zero, 0x100(zero)
+        Instruction::create_i(OpCode::JALR, 0, 1, 0, 0),      // 0x24: ret
+    ];
+    let mut memory : Vec<u32> = program.iter().map(|i| i.le()).collect();
+    memory.resize(0x200 / 4, 0);
+    memory.set_32(0x100, 1);
+    let memory = Arc::new(Mutex::new(memory));
+
+    let mut machine : SIMTX<LexicoScheduler> = SIMTX::new(4, 1, HashMap::new());
+    machine.enable_warp_formation("dwf".parse().unwrap());
+    for c in 0..4 {
+        machine.set_pc_of(c, 4);
+        machine.set_i_register_of(c, 1, 0);
+        machine.set_i_register_of(c, 10, c as i32);
+    }
+    for _ in 0..5000 {
+        machine.step(memory.clone());
+        if machine.finished() { break }
+    }
+    assert!(machine.finished());
+    assert_eq!(memory.lock().unwrap().get_32(0x104), 42);
+    assert_eq!((1..4).map(|c| machine.get_i_register_of(c, 11)).collect::<Vec<_>>(), vec![1, 1, 1]);
+}
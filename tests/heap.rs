@@ -0,0 +1,89 @@
+extern crate riscv_sandbox;
+
+use riscv_sandbox::machine::simtx::heap::{Heap, HeapError, MIN_ALIGN};
+
+#[test]
+fn alloc_is_aligned() {
+    let mut heap = Heap::new(0x1000, 0x10000);
+    let a = heap.alloc(3, MIN_ALIGN).unwrap();
+    let b = heap.alloc(20, MIN_ALIGN).unwrap();
+    let c = heap.alloc(8, 256).unwrap();
+    assert_eq!(a, 0x1000);
+    assert_eq!(b, 0x1010);
+    assert_eq!(c % 256, 0);
+    assert_eq!(heap.size_of(b), Some(20));
+    assert_eq!(heap.allocation_of(b + 19), Some((b, 20)));
+    assert_eq!(heap.allocation_of(b + 20), None);
+}
+
+#[test]
+fn reuse_split_and_coalesce() {
+    let mut heap = Heap::new(0x1000, 0x10000);
+    let a = heap.alloc(64, MIN_ALIGN).unwrap();
+    let b = heap.alloc(64, MIN_ALIGN).unwrap();
+    let c = heap.alloc(64, MIN_ALIGN).unwrap();
+    let top = heap.top();
+
+    // a smaller allocation reuses (and splits) the first free chunk
+    heap.free(a).unwrap();
+    assert_eq!(heap.alloc(16, MIN_ALIGN), Some(a));
+    assert_eq!(heap.alloc(16, MIN_ALIGN), Some(a + 16));
+
+    // b merges with the rest of a, still too small for 128 bytes
+    heap.free(b).unwrap();
+    assert_eq!(heap.alloc(128, MIN_ALIGN), Some(c + 64));
+    // with c, it is large enough
+    heap.free(c).unwrap();
+    assert_eq!(heap.alloc(128, MIN_ALIGN), Some(a + 32));
+    assert_eq!(heap.top(), top + 128);
+}
+
+#[test]
+fn double_and_invalid_free() {
+    let mut heap = Heap::new(0x1000, 0x10000);
+    let a = heap.alloc(32, MIN_ALIGN).unwrap();
+    assert_eq!(heap.free(a + 4), Err(HeapError::InvalidFree(a + 4)));
+    assert_eq!(heap.free(0x8000), Err(HeapError::InvalidFree(0x8000)));
+    heap.free(a).unwrap();
+    assert_eq!(heap.free(a), Err(HeapError::DoubleFree(a)));
+    assert_eq!(heap.realloc(a, 8), Err(HeapError::DoubleFree(a)));
+
+    // once given back by malloc, the address can be freed again
+    assert_eq!(heap.alloc(32, MIN_ALIGN), Some(a));
+    assert_eq!(heap.free(a), Ok(()));
+}
+
+#[test]
+fn realloc_in_place_or_move() {
+    let mut heap = Heap::new(0x1000, 0x10000);
+    let a = heap.alloc(32, MIN_ALIGN).unwrap();
+    // last chunk: grows over the top
+    assert_eq!(heap.realloc(a, 100), Ok(Some((a, 0))));
+    let b = heap.alloc(16, MIN_ALIGN).unwrap();
+    // shrinking never moves
+    assert_eq!(heap.realloc(a, 10), Ok(Some((a, 0))));
+    // too large to fit before b: moved, 10 bytes to copy
+    let (c, copy) = heap.realloc(a, 200).unwrap().unwrap();
+    assert!(c > b);
+    assert_eq!(copy, 10);
+    assert_eq!(heap.free(a), Err(HeapError::DoubleFree(a)));
+}
+
+#[test]
+fn limit_and_stats() {
+    let mut heap = Heap::new(0x1000, 0x100);
+    let a = heap.alloc(0x80, MIN_ALIGN).unwrap();
+    assert_eq!(heap.alloc(0x100, MIN_ALIGN), None);
+    let b = heap.alloc(0x20, MIN_ALIGN).unwrap();
+    heap.free(a).unwrap();
+
+    let stats = heap.stats();
+    assert_eq!(stats.allocations, 2);
+    assert_eq!(stats.frees, 1);
+    assert_eq!(stats.failures, 1);
+    assert_eq!(stats.in_use, 0x20);
+    assert_eq!(stats.peak_in_use, 0xa0);
+    assert_eq!(stats.live_chunks, 1);
+    assert_eq!(stats.heap_size, 0xa0);
+    assert_eq!(heap.allocations().collect::<Vec<_>>(), vec![(b, 0x20)]);
+}
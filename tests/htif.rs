@@ -0,0 +1,247 @@
+extern crate riscv_sandbox;
+extern crate elf as elflib;
+
+use riscv_sandbox::machine::{IntegerMachine, MultiCoreIMachine
+    , rv32imc::Machine as RV32I
+    , rv32pthread::Machine as Pthread
+    , simtx::{Machine as SIMTX, scheduler::LexicoScheduler}};
+use riscv_sandbox::memory::Memory;
+use riscv_sandbox::isa::{Instruction, OpCode};
+use riscv_sandbox::htif::Htif;
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+const TOHOST : usize = 0x100;
+const FROMHOST : usize = 0x140;
+
+// The console output, shared with the test
+#[derive(Clone, Default)]
+struct Console(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Console {
+    fn write(&mut self, buf:&[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Console {
+    fn text(&self) -> String {
+        String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+    }
+}
+
+fn htif(input:&[u8]) -> (Htif, Console) {
+    let console = Console::default();
+    let mut htif = Htif::new(TOHOST, Some(FROMHOST));
+    htif.set_console(Box::new(io::Cursor::new(input.to_vec())), Box::new(console.clone()));
+    (htif, console)
+}
+
+fn set_64(mem:&mut dyn Memory, addr:usize, value:u64) {
+    mem.set_32(addr, value as u32);
+    mem.set_32(addr + 4, (value >> 32) as u32);
+}
+
+fn get_64(mem:&dyn Memory, addr:usize) -> u64 {
+    mem.get_32(addr) as u64 | (mem.get_32(addr + 4) as u64) << 32
+}
+
+// Writes `command` to tohost as an RV32 program does, the high word last
+fn send(htif:&mut Htif, mem:&mut Vec<u32>, command:u64) {
+    set_64(mem, TOHOST, command);
+    htif.on_store(TOHOST, 4);
+    htif.on_store(TOHOST + 4, 4);
+}
+
+#[test]
+fn commands() {
+    let mut mem : Vec<u32> = vec![0; 0x200 / 4];
+    let (mut htif, console) = htif(b"xy");
+
+    // putchar, taken once the high word of tohost was stored to
+    set_64(&mut mem, TOHOST, 0x0101_0000_0000_0000 | b'h' as u64);
+    assert_eq!(htif.poll(&mut mem), None);
+    htif.on_store(TOHOST, 4);
+    assert_eq!(htif.poll(&mut mem), None);
+    assert_eq!(console.text(), "");
+    htif.on_store(TOHOST + 6, 1);
+    assert_eq!(htif.poll(&mut mem), None);
+    assert_eq!(console.text(), "h");
+    assert_eq!(get_64(&mem, TOHOST), 0);
+
+    // getchar
+    send(&mut htif, &mut mem, 0x0100_0000_0000_0000);
+    htif.poll(&mut mem);
+    assert_eq!(get_64(&mem, FROMHOST), 0x0100_0000_0000_0100 | b'x' as u64);
+
+    // write(1, "hello", 5), then read(0, 0x80, 4) with a byte left
+    let syscall = |htif:&mut Htif, mem:&mut Vec<u32>, args:[u64; 4]| {
+        for (i, &arg) in args.iter().enumerate() {
+            set_64(mem, 0x40 + 8 * i, arg);
+        }
+        set_64(mem, FROMHOST, 0);
+        send(htif, mem, 0x40);
+        htif.poll(mem);
+    };
+    for (i, &byte) in b"hello".iter().enumerate() {
+        mem.set_8(0x80 + i, byte);
+    }
+    syscall(&mut htif, &mut mem, [64, 1, 0x80, 5]);
+    assert_eq!(console.text(), "hhello");
+    assert_eq!(get_64(&mem, 0x40), 5);
+    assert_eq!(get_64(&mem, FROMHOST), 1);
+
+    syscall(&mut htif, &mut mem, [63, 0, 0x80, 4]);
+    assert_eq!(get_64(&mem, 0x40), 1);
+    assert_eq!(mem.get_8(0x80), b'y');
+
+    // unknown syscalls fail with -ENOSYS
+    syscall(&mut htif, &mut mem, [1234, 0, 0, 0]);
+    assert_eq!(get_64(&mem, 0x40) as i64, -38);
+
+    // exit(5)
+    send(&mut htif, &mut mem, (5 << 1) | 1);
+    assert_eq!(htif.exit_code(), None);
+    assert_eq!(htif.poll(&mut mem), Some(5));
+    assert_eq!(htif.exit_code(), Some(5));
+}
+
+#[test]
+fn long_syscalls() {
+    let mut mem : Vec<u32> = vec![0; 0x30000 / 4];
+    let (mut htif, console) = htif(b"xy");
+    for i in 0..0x20000 {
+        mem.set_8(0x1000 + i, b'a' + (i % 26) as u8);
+    }
+
+    // write(1, 0x1000, 0x20000) goes through by chunks, read(0, 0x1000,
+    // 0x20000) gives what the console has
+    for &(number, ret) in &[(64, 0x20000), (63, 2)] {
+        set_64(&mut mem, 0x40, number);
+        set_64(&mut mem, 0x48, if number == 64 { 1 } else { 0 });
+        set_64(&mut mem, 0x50, 0x1000);
+        set_64(&mut mem, 0x58, 0x20000);
+        send(&mut htif, &mut mem, 0x40);
+        htif.poll(&mut mem);
+        assert_eq!(get_64(&mem, 0x40), ret);
+    }
+    let text = console.text();
+    assert_eq!(text.len(), 0x20000);
+    assert!(text.starts_with("abc") && text.ends_with("zabcdef"));
+    assert_eq!((mem.get_8(0x1000), mem.get_8(0x1001), mem.get_8(0x1002)), (b'x', b'y', b'c'));
+}
+
+#[test]
+fn from_elf() {
+    let file = elflib::File::open_path("resources/riscv-tests/selftest-pass.elf").unwrap();
+    let htif = Htif::from_elf(&file).unwrap();
+    assert_eq!(htif.tohost(), 0x8000_1000u32 as i32 as usize);
+    assert_eq!(htif.fromhost(), Some(0x8000_1040u32 as i32 as usize));
+}
+
+// Prints "hi" with the console device, waiting for tohost to be cleared
+// after each byte, then exits with 7. `between` runs between the stores of
+// the two words of each command.
+fn program(between:&[Instruction]) -> Vec<u32> {
+    let mut program = vec![
+        Instruction::nop(),
+        Instruction::create_i(OpCode::OPIMM, 6, 0, TOHOST as i32, 0),  // 0x04: li t1, TOHOST
+        Instruction::create_u(OpCode::LUI, 7, 0x0101_0000),            // 0x08: lui t2, 0x01010
+    ];
+    for &byte in b"hi".iter() {
+        program.extend(vec![
+            Instruction::create_i(OpCode::OPIMM, 5, 0, byte as i32, 0),  // li t0, byte
+            Instruction::create_s(OpCode::STORE, 6, 5, 0, 0b010),        // sw t0, 0(t1)
+        ]);
+        program.extend(between);
+        program.extend(vec![
+            Instruction::create_s(OpCode::STORE, 6, 7, 4, 0b010),        // sw t2, 4(t1)
+            Instruction::create_i(OpCode::LOAD, 28, 6, 0, 0b010),        // lw t3, 0(t1)
+            Instruction::create_b(OpCode::BRANCH, 28, 0, -4, 0b001),     // bnez t3, -4
+        ]);
+    }
+    program.extend(vec![
+        Instruction::create_i(OpCode::OPIMM, 5, 0, (7 << 1) | 1, 0),   // li t0, 15
+        Instruction::create_s(OpCode::STORE, 6, 5, 0, 0b010),          // sw t0, 0(t1)
+    ]);
+    program.extend(between);
+    program.extend(vec![
+        Instruction::create_s(OpCode::STORE, 6, 0, 4, 0b010),          // sw zero, 4(t1)
+        Instruction::create_j(OpCode::JAL, 0, 0),                      // j .
+    ]);
+    let mut memory : Vec<u32> = program.iter().map(|i| i.le()).collect();
+    memory.resize(0x200 / 4, 0);
+    memory
+}
+
+#[test]
+fn rv32imc() {
+    let mut mem = program(&[]);
+    let (htif, console) = htif(b"");
+    let mut machine = RV32I::new();
+    machine.set_pc(4);
+    machine.enable_htif(htif);
+    for _ in 0..1000 {
+        if machine.finished() { break }
+        machine.cycle(&mut mem);
+    }
+    assert!(machine.finished());
+    assert_eq!(machine.exit_code(), Some(7));
+    assert_eq!(console.text(), "hi");
+}
+
+// The low word of a command alone is not taken, however long it stays
+#[test]
+fn instructions_between_the_stores() {
+    let between = vec![Instruction::create_i(OpCode::OPIMM, 29, 29, 1, 0); 8];  // addi t4, t4, 1
+    let mut mem = program(&between);
+    let (htif, console) = htif(b"");
+    let mut machine = RV32I::new();
+    machine.set_pc(4);
+    machine.enable_htif(htif);
+    for _ in 0..1000 {
+        if machine.finished() { break }
+        machine.cycle(&mut mem);
+    }
+    assert_eq!(machine.exit_code(), Some(7));
+    assert_eq!(console.text(), "hi");
+    assert_eq!(machine.get_i_register(29), 24);
+}
+
+#[test]
+fn rv32pthread() {
+    let mut mem = program(&[]);
+    let (htif, console) = htif(b"");
+    let mut machine = Pthread::new(HashMap::new());
+    machine.set_pc_of(0, 4);
+    machine.enable_htif(htif);
+    for _ in 0..1000 {
+        if MultiCoreIMachine::finished(&machine) { break }
+        machine.cycle(&mut mem);
+    }
+    assert!(MultiCoreIMachine::finished(&machine));
+    assert_eq!(machine.exit_code(), Some(7));
+    assert_eq!(console.text(), "hi");
+}
+
+#[test]
+fn simtx() {
+    let mem = Arc::new(Mutex::new(program(&[])));
+    let (htif, console) = htif(b"");
+    let mut machine : SIMTX<LexicoScheduler> = SIMTX::new(1, 1, HashMap::new());
+    machine.set_pc_of(0, 4);
+    machine.enable_htif(htif);
+    for _ in 0..1000 {
+        if machine.finished() { break }
+        machine.step(mem.clone());
+    }
+    assert!(machine.finished());
+    assert_eq!(machine.exit_code(), Some(7));
+    assert_eq!(console.text(), "hi");
+}
@@ -0,0 +1,117 @@
+extern crate riscv_sandbox;
+
+use riscv_sandbox::cfg::Cfg;
+use riscv_sandbox::machine::{MultiCoreIMachine, simtx::{
+    Machine as SIMTX,
+    scheduler::LexicoScheduler,
+    ipdom::ReconvergencePoints,
+    timing::TimingConfig,
+}};
+use riscv_sandbox::isa::{Instruction, OpCode};
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+// An if-then-else whose else block sits after the join, from address 4:
+// t1 = 2 * (a0 == 0 ? 2 : 1)
+fn program() -> Vec<Instruction> {
+    vec![
+        Instruction::nop(),
+        Instruction::create_b(OpCode::BRANCH, 10, 0, 0x14, 0), // 0x04: beq a0, zero, 0x18
+        Instruction::create_i(OpCode::OPIMM, 5, 0, 1, 0),       // 0x08
+        Instruction::create_r(OpCode::OPREG, 6, 5, 5, 0),       // 0x0c: join
+        Instruction::create_i(OpCode::JALR, 0, 1, 0, 0),        // 0x10
+        Instruction::nop(),
+        Instruction::create_i(OpCode::OPIMM, 5, 0, 2, 0),       // 0x18
+        Instruction::create_j(OpCode::JAL, 0, -0x10),           // 0x1c: j 0x0c
+    ]
+}
+
+// Runs the program on a warp of `lanes` lanes, `a0` being the lane id, and
+// returns the warp instructions issued and the result of each lane.
+fn run(ipdom:bool, lanes:usize) -> (u64, Vec<i32>) {
+    let program = program();
+    let mut memory : Vec<u32> = program.iter().map(|i| i.le()).collect();
+    memory.resize(0x100 / 4, 0);
+    let memory = Arc::new(Mutex::new(memory));
+
+    let mut machine : SIMTX<LexicoScheduler> = SIMTX::new(lanes, 1, HashMap::new());
+    machine.enable_timing(TimingConfig::default());
+    if ipdom {
+        let code : Vec<u8> = program.iter().flat_map(|i| i.0.to_le_bytes().to_vec()).collect();
+        let points = ReconvergencePoints::from_cfg(&Cfg::build(&code, 0));
+        assert_eq!(points.get(0x04), Some(0x0c));
+        machine.enable_ipdom(points);
+    }
+    for lane in 0..lanes {
+        let c = machine.pop_first_idle();
+        machine.set_pc_of(c, 4);
+        machine.set_i_register_of(c, 1, 0);
+        machine.set_i_register_of(c, 10, lane as i32);
+    }
+    for _ in 0..100 {
+        machine.step(memory.clone());
+        if machine.finished() { break }
+    }
+    assert!(machine.finished());
+
+    let results = (0..lanes).map(|c| machine.get_i_register_of(c, 6)).collect();
+    (machine.timing().unwrap().warp(0).instructions, results)
+}
+
+#[test]
+fn reconverges_at_post_dominator() {
+    // MinPC runs the join first with the then lane, then again with the else
+    // lane: beq, addi, (add, ret), addi, j, (add, ret)
+    let (minpc, results) = run(false, 2);
+    assert_eq!(results, vec![4, 2]);
+    assert_eq!(minpc, 8);
+
+    // the stack holds the join until both sides reached it
+    let (ipdom, results) = run(true, 2);
+    assert_eq!(results, vec![4, 2]);
+    assert_eq!(ipdom, 6);
+}
+
+#[test]
+fn uniform_branches() {
+    let (minpc, _) = run(false, 1);
+    let (ipdom, results) = run(true, 1);
+    assert_eq!(results, vec![4]);
+    assert_eq!((minpc, ipdom), (5, 5));
+}
+
+#[test]
+fn no_post_dominator() {
+    // both sides return: they run one after the other until they exit
+    let program = vec![
+        Instruction::nop(),
+        Instruction::create_b(OpCode::BRANCH, 10, 0, 0xc, 0), // 0x04: beq a0, zero, 0x10
+        Instruction::create_i(OpCode::OPIMM, 6, 0, 1, 0),
+        Instruction::create_i(OpCode::JALR, 0, 1, 0, 0),
+        Instruction::create_i(OpCode::OPIMM, 6, 0, 2, 0),     // 0x10
+        Instruction::create_i(OpCode::JALR, 0, 1, 0, 0),
+    ];
+    let code : Vec<u8> = program.iter().flat_map(|i| i.0.to_le_bytes().to_vec()).collect();
+    let points = ReconvergencePoints::from_cfg(&Cfg::build(&code, 0));
+    assert_eq!(points.get(0x04), None);
+
+    let mut memory : Vec<u32> = program.iter().map(|i| i.le()).collect();
+    memory.resize(0x100 / 4, 0);
+    let memory = Arc::new(Mutex::new(memory));
+    let mut machine : SIMTX<LexicoScheduler> = SIMTX::new(4, 1, HashMap::new());
+    machine.enable_ipdom(points);
+    for lane in 0..4 {
+        let c = machine.pop_first_idle();
+        machine.set_pc_of(c, 4);
+        machine.set_i_register_of(c, 1, 0);
+        machine.set_i_register_of(c, 10, lane as i32 % 2);
+    }
+    for _ in 0..100 {
+        machine.step(memory.clone());
+        if machine.finished() { break }
+    }
+    assert!(machine.finished());
+    let results : Vec<i32> = (0..4).map(|c| machine.get_i_register_of(c, 6)).collect();
+    assert_eq!(results, vec![2, 1, 2, 1]);
+}
@@ -212,6 +212,18 @@ fn compressed() {
     assert_eq!(
         Instruction(0x8ff1).uncompressed()
         , Instruction::and(15, 15, 12)); // and r15,r15,r12
+    println!("uncompress : {i:016b} ({i:04x})", i = 0x8fb1);
+    assert_eq!(
+        Instruction(0x8fb1).uncompressed()
+        , Instruction::xor(15, 15, 12)); // xor r15,r15,r12
+    println!("uncompress : {i:016b} ({i:04x})", i = 0x8ca9);
+    assert_eq!(
+        Instruction(0x8ca9).uncompressed()
+        , Instruction::xor(9, 9, 10)); // xor r9,r9,r10
+    println!("uncompress : {i:016b} ({i:04x})", i = 0x8cc9);
+    assert_eq!(
+        Instruction(0x8cc9).uncompressed()
+        , Instruction::or(9, 9, 10)); // or r9,r9,r10
     println!("uncompress : {i:016b} ({i:04x})", i = 0x4462);
     assert_eq!(
         Instruction(0x4462).uncompressed()
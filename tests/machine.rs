@@ -5,13 +5,20 @@ use riscv_sandbox::machine::{rv32imc::Machine as RV32I
     //, rv32pthread::Machine as RV32Threaded
     , simtx::{
         Machine as SIMTX,
-        scheduler::LexicoScheduler,
+        scheduler::{LexicoScheduler, LoopAwareScheduler},
+        hostcall::{HostCallContext, HostCallAction, symbol_name},
+        error::GuestError,
+        files,
+        memcheck::MemErrorKind,
       }
     , *
     , self};
 use riscv_sandbox::isa::{Instruction, OpCode};
+use riscv_sandbox::memory::Memory;
+use riscv_sandbox::cfg::Cfg;
 
 use std::collections::{HashMap};
+use std::sync::{Arc, Mutex};
 
 #[test]
 fn registers() {
@@ -151,3 +158,452 @@ fn fibonacci() {
 fn simtx_too_many_tpw() {
     let _ : SIMTX<LexicoScheduler> = SIMTX::new(machine::simtx::MAX_TPW + 1, 1, HashMap::new());
 }
+
+#[test]
+#[should_panic]
+fn simtx_too_many_tpw_u64() {
+    let _ : SIMTX<LexicoScheduler, u64> = SIMTX::new(65, 1, HashMap::new());
+}
+
+#[test]
+fn simtx_wide_warp() {
+    // lanes with a0 >= 60 set a1, then every lane adds 2 to a2
+    let mut memory : Vec<u32> = Vec::new();
+    memory.push(Instruction::nop().le());
+    memory.push(Instruction::create_i(OpCode::OPIMM, 6, 0, 60, 0).le());
+    memory.push(Instruction::create_b(OpCode::BRANCH, 10, 6, 8, 4).le());
+    memory.push(Instruction::create_i(OpCode::OPIMM, 11, 0, 1, 0).le());
+    memory.push(Instruction::create_i(OpCode::OPIMM, 12, 12, 2, 0).le());
+    memory.push(Instruction::create_i(OpCode::JALR, 0, 1, 0, 0).le());
+    let memory = Arc::new(Mutex::new(memory));
+
+    let tpw = 100;
+    let mut machine : SIMTX<LexicoScheduler, u128> = SIMTX::new(tpw, 1, HashMap::new());
+    for _ in 0..tpw {
+        let c = machine.pop_first_idle();
+        machine.set_pc_of(c, 4);
+        machine.set_i_register_of(c, 1, 0);
+        machine.set_i_register_of(c, 10, c as i32);
+    }
+    for _ in 0..100 {
+        machine.step(memory.clone());
+        if machine.finished() { break }
+    }
+    assert!(machine.finished());
+
+    for c in 0..tpw {
+        assert_eq!(machine.get_i_register_of(c, 11), (c >= 60) as i32);
+        assert_eq!(machine.get_i_register_of(c, 12), 2);
+    }
+}
+
+fn thread_id(m:&mut SIMTX<LexicoScheduler>, call:&HostCallContext, _:&mut dyn Memory) -> HostCallAction {
+    for lane in call.lanes() {
+        let tid = call.tid(lane) as i32;
+        m.lane(call, lane).ret(100 + tid);
+    }
+    HostCallAction::Return
+}
+
+// starting at 4: jal to the PLT entry at 0x104, then return to address 0
+fn host_call_program() -> Vec<u32> {
+    let mut memory : Vec<u32> = Vec::new();
+    memory.push(Instruction::nop().le());
+    memory.push(Instruction::create_j(OpCode::JAL, 1, 0x100).le());
+    memory.push(Instruction::create_i(OpCode::JALR, 0, 1, 0, 0).le());
+    memory.resize(0x400 / 4, Instruction::nop().le());
+    memory
+}
+
+fn run_host_call_program<F>(machine:&mut SIMTX<LexicoScheduler>, memory:Vec<u32>, mut setup:F) -> Vec<u32>
+    where F:FnMut(&mut SIMTX<LexicoScheduler>, usize) {
+    let memory = Arc::new(Mutex::new(memory));
+    for _ in 0..2 {
+        let c = machine.pop_first_idle();
+        machine.set_pc_of(c, 4);
+        machine.set_i_register_of(c, 1, 0);
+        setup(machine, c);
+    }
+
+    for _ in 0..100 {
+        machine.step(memory.clone());
+        if machine.finished() { break }
+    }
+    assert!(machine.finished());
+    Arc::try_unwrap(memory).unwrap().into_inner().unwrap()
+}
+
+fn store_string(memory:&mut Vec<u32>, addr:usize, s:&str) {
+    for (i, b) in s.bytes().chain(Some(0)).enumerate() {
+        memory.set_8(addr + i, b);
+    }
+}
+
+fn load_string(memory:&Vec<u32>, addr:usize) -> String {
+    let mut s = String::new();
+    let mut addr = addr;
+    while memory.get_8(addr) != 0 {
+        s.push(memory.get_8(addr) as char);
+        addr += 1;
+    }
+    s
+}
+
+#[test]
+fn simtx_symbol_name() {
+    assert_eq!(symbol_name("printf@@GLIBC_2.29"), "printf");
+    assert_eq!(symbol_name("printf"), "printf");
+}
+
+#[test]
+fn simtx_custom_host_call() {
+    let mut plt = HashMap::new();
+    plt.insert(0x104, String::from("thread_id@@GLIBC_2.29"));
+    let mut machine : SIMTX<LexicoScheduler> = SIMTX::new(2, 1, plt);
+    assert_eq!(machine.unresolved_host_calls(), vec!["thread_id@@GLIBC_2.29"]);
+
+    machine.register_host_call("thread_id", thread_id);
+    assert!(machine.unresolved_host_calls().is_empty());
+
+    run_host_call_program(&mut machine, host_call_program(), |_, _| {});
+    assert_eq!(machine.error(), None);
+    assert_eq!(machine.get_i_register_of(0, 10), 100);
+    assert_eq!(machine.get_i_register_of(1, 10), 101);
+}
+
+#[test]
+fn simtx_unknown_host_call() {
+    let mut plt = HashMap::new();
+    plt.insert(0x104, String::from("frobnicate@@GLIBC_2.29"));
+    let mut machine : SIMTX<LexicoScheduler> = SIMTX::new(2, 1, plt);
+
+    run_host_call_program(&mut machine, host_call_program(), |_, _| {});
+    assert_eq!(machine.error(), Some(&GuestError::UnknownHostCall {
+        name: String::from("frobnicate"), pc: 4 }));
+}
+
+#[test]
+fn simtx_sprintf_bytes() {
+    let mut plt = HashMap::new();
+    plt.insert(0x104, String::from("sprintf"));
+    let mut machine : SIMTX<LexicoScheduler> = SIMTX::new(2, 1, plt);
+
+    // the bytes of the format past 0x7f are copied as they are
+    let mut memory = host_call_program();
+    store_string(&mut memory, 0x200, "\u{e9}%d");
+    let memory = run_host_call_program(&mut machine, memory, |m, c| {
+        m.set_i_register_of(c, 10, 0x280 + 0x10 * c as i32);
+        m.set_i_register_of(c, 11, 0x200);
+        m.set_i_register_of(c, 12, c as i32);
+    });
+    assert_eq!(machine.get_i_register_of(0, 10), 3);
+    assert_eq!(memory.get_32(0x280).to_le_bytes(), [0xc3, 0xa9, b'0', 0]);
+    assert_eq!(memory.get_32(0x290).to_le_bytes(), [0xc3, 0xa9, b'1', 0]);
+}
+
+#[test]
+fn simtx_snprintf() {
+    let mut plt = HashMap::new();
+    plt.insert(0x104, String::from("snprintf@GLIBC_2.27"));
+    let mut machine : SIMTX<LexicoScheduler> = SIMTX::new(2, 1, plt);
+
+    let mut memory = host_call_program();
+    store_string(&mut memory, 0x200, "%d|%5.2f|%-4s|%#x|%lld|%g|%e");
+    store_string(&mut memory, 0x240, "ab");
+    // varargs which do not fit in a3-a7 go on the stack, 64 bits aligned
+    let sp = 0x380;
+    memory.set_32(sp, -5000000000i64 as u32);
+    memory.set_32(sp + 4, (-5000000000i64 >> 32) as u32);
+    memory.set_32(sp + 8, 0.0001f64.to_bits() as u32);
+    memory.set_32(sp + 12, (0.0001f64.to_bits() >> 32) as u32);
+    memory.set_32(sp + 16, 1234.5f64.to_bits() as u32);
+    memory.set_32(sp + 20, (1234.5f64.to_bits() >> 32) as u32);
+
+    let pi = std::f64::consts::PI.to_bits();
+    let memory = run_host_call_program(&mut machine, memory, |m, c| {
+        m.set_i_register_of(c, 2, sp as i32);
+        m.set_i_register_of(c, 10, 0x280 + 0x40 * c as i32);
+        m.set_i_register_of(c, 11, 0x40);
+        m.set_i_register_of(c, 12, 0x200);
+        m.set_i_register_of(c, 13, c as i32 - 7);
+        m.set_i_register_of(c, 14, pi as i32);
+        m.set_i_register_of(c, 15, (pi >> 32) as i32);
+        m.set_i_register_of(c, 16, 0x240);
+        m.set_i_register_of(c, 17, 255);
+    });
+
+    assert_eq!(machine.error(), None);
+    assert_eq!(load_string(&memory, 0x280), "-7| 3.14|ab  |0xff|-5000000000|0.0001|1.234500e+03");
+    assert_eq!(load_string(&memory, 0x2c0), "-6| 3.14|ab  |0xff|-5000000000|0.0001|1.234500e+03");
+    assert_eq!(machine.get_i_register_of(0, 10), 50);
+
+    // truncated output, the return value is still the full length
+    let mut plt = HashMap::new();
+    plt.insert(0x104, String::from("snprintf"));
+    let mut machine : SIMTX<LexicoScheduler> = SIMTX::new(2, 1, plt);
+    let mut memory = host_call_program();
+    store_string(&mut memory, 0x200, "%08.3f|%+d");
+    let memory = run_host_call_program(&mut machine, memory, |m, c| {
+        m.set_i_register_of(c, 10, 0x280 + 0x40 * c as i32);
+        m.set_i_register_of(c, 11, 6);
+        m.set_i_register_of(c, 12, 0x200);
+        m.set_i_register_of(c, 14, (-2.5f64).to_bits() as i32);
+        m.set_i_register_of(c, 15, ((-2.5f64).to_bits() >> 32) as i32);
+        m.set_i_register_of(c, 16, 3);
+    });
+    assert_eq!(load_string(&memory, 0x280), "-002.");
+    assert_eq!(machine.get_i_register_of(1, 10), 11);
+}
+
+#[test]
+fn simtx_fscanf() {
+    let dir = std::env::temp_dir().join("riscv_sandbox_simtx_fscanf");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("in.txt"), "12 -3.5 word\n0x1f rest").unwrap();
+
+    let mut plt = HashMap::new();
+    plt.insert(0x104, String::from("fscanf@GLIBC_2.27"));
+    let mut machine : SIMTX<LexicoScheduler> = SIMTX::new(2, 1, plt);
+    machine.set_sandbox(&dir);
+    let fd = machine.files().fopen("/in.txt", "r").unwrap();
+
+    let mut memory = host_call_program();
+    store_string(&mut memory, 0x200, "%d %lf %s %i");
+    // lanes read one after the other from the same stream
+    let memory = run_host_call_program(&mut machine, memory, |m, c| {
+        let out = 0x280 + 0x40 * c as i32;
+        m.set_i_register_of(c, 10, files::stream(fd) as i32);
+        m.set_i_register_of(c, 11, 0x200);
+        m.set_i_register_of(c, 12, out);
+        m.set_i_register_of(c, 13, out + 8);
+        m.set_i_register_of(c, 14, out + 16);
+        m.set_i_register_of(c, 15, out + 32);
+    });
+
+    assert_eq!(machine.get_i_register_of(0, 10), 4);
+    assert_eq!(memory.get_32(0x280), 12);
+    let d = memory.get_32(0x288) as u64 | ((memory.get_32(0x28c) as u64) << 32);
+    assert_eq!(f64::from_bits(d), -3.5);
+    assert_eq!(load_string(&memory, 0x290), "word");
+    assert_eq!(memory.get_32(0x2a0), 0x1f);
+
+    // the second lane only finds "rest", which is not an integer
+    assert_eq!(machine.get_i_register_of(1, 10), 0);
+    assert_eq!(machine.files().eof(fd), Ok(false));
+}
+
+#[test]
+fn simtx_fread() {
+    let dir = std::env::temp_dir().join("riscv_sandbox_simtx_fread");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("in.txt"), "0123456789").unwrap();
+
+    let mut plt = HashMap::new();
+    plt.insert(0x104, String::from("fread"));
+    let mut machine : SIMTX<LexicoScheduler> = SIMTX::new(2, 1, plt);
+    machine.set_sandbox(&dir);
+    let fd = machine.files().fopen("/in.txt", "r").unwrap();
+
+    // fread(0x200, 4, 2, f), then the rest with an absurd size, which must
+    // not be allocated at once
+    let memory = run_host_call_program(&mut machine, host_call_program(), |m, c| {
+        let size = if c == 0 { 4 } else { -1 };
+        m.set_i_register_of(c, 10, 0x200 + 0x10 * c as i32);
+        m.set_i_register_of(c, 11, size);
+        m.set_i_register_of(c, 12, if c == 0 { 2 } else { -1 });
+        m.set_i_register_of(c, 13, files::stream(fd) as i32);
+    });
+
+    assert_eq!(machine.get_i_register_of(0, 10), 2);
+    assert_eq!(memory.get_32(0x200).to_le_bytes(), *b"0123");
+    assert_eq!(memory.get_32(0x204).to_le_bytes(), *b"4567");
+    assert_eq!(machine.get_i_register_of(1, 10), 0);
+    assert_eq!(memory.get_16(0x210).to_le_bytes(), *b"89");
+    assert_eq!(machine.files().eof(fd), Ok(true));
+}
+
+#[test]
+fn simtx_fwrite() {
+    let dir = std::env::temp_dir().join("riscv_sandbox_simtx_fwrite");
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let mut plt = HashMap::new();
+    plt.insert(0x104, String::from("fwrite"));
+    let mut machine : SIMTX<LexicoScheduler> = SIMTX::new(2, 1, plt);
+    machine.set_sandbox(&dir);
+    let fd = machine.files().fopen("/out.txt", "w").unwrap();
+
+    // fwrite(0x200, 4, 2, f), then no element at all
+    let mut memory = host_call_program();
+    store_string(&mut memory, 0x200, "01234567");
+    run_host_call_program(&mut machine, memory, |m, c| {
+        m.set_i_register_of(c, 10, 0x200);
+        m.set_i_register_of(c, 11, 4);
+        m.set_i_register_of(c, 12, if c == 0 { 2 } else { 0 });
+        m.set_i_register_of(c, 13, files::stream(fd) as i32);
+    });
+
+    assert_eq!(machine.get_i_register_of(0, 10), 2);
+    assert_eq!(machine.get_i_register_of(1, 10), 0);
+    assert_eq!(std::fs::read(dir.join("out.txt")).unwrap(), b"01234567");
+}
+
+#[test]
+fn simtx_strtok() {
+    // strtok(0x200, ","), then twice strtok(NULL, ","), storing each token
+    let mut memory : Vec<u32> = Vec::new();
+    memory.push(Instruction::nop().le());
+    for n in 0..3 {
+        if n != 0 {
+            memory.push(Instruction::create_i(OpCode::OPIMM, 10, 0, 0, 0).le());
+        }
+        memory.push(Instruction::create_i(OpCode::OPIMM, 11, 0, 0x240, 0).le());
+        let pc = memory.len() as i32 * 4;
+        memory.push(Instruction::create_j(OpCode::JAL, 1, 0x104 - pc).le());
+        memory.push(Instruction::create_s(OpCode::STORE, 0, 10, 0x300 + 4 * n, 2).le());
+    }
+    memory.push(Instruction::create_i(OpCode::JALR, 0, 8, 0, 0).le());
+    memory.resize(0x400 / 4, Instruction::nop().le());
+    store_string(&mut memory, 0x200, ",a,b,,c");
+    store_string(&mut memory, 0x240, ",");
+    let memory = Arc::new(Mutex::new(memory));
+
+    let mut plt = HashMap::new();
+    plt.insert(0x104, String::from("strtok@GLIBC_2.27"));
+    let mut machine : SIMTX<LexicoScheduler> = SIMTX::new(1, 1, plt);
+    let c = machine.pop_first_idle();
+    machine.set_pc_of(c, 4);
+    machine.set_i_register_of(c, 8, 0);
+    machine.set_i_register_of(c, 10, 0x200);
+    for _ in 0..100 {
+        machine.step(memory.clone());
+        if machine.finished() { break }
+    }
+    assert!(machine.finished());
+    assert_eq!(machine.error(), None);
+
+    let memory = memory.lock().unwrap();
+    let tokens : Vec<u32> = (0..3).map(|n| memory.get_32(0x300 + 4 * n)).collect();
+    assert_eq!(tokens, vec![0x201, 0x203, 0x206]);
+    assert_eq!(load_string(&memory, 0x201), "a");
+    assert_eq!(load_string(&memory, 0x206), "c");
+}
+
+// Counts up to a1 in a loop, from 4
+fn counting_loop() -> Vec<Instruction> {
+    vec![
+        Instruction::nop(),
+        Instruction::create_i(OpCode::OPIMM, 5, 5, 1, 0),     // 0x04
+        Instruction::create_b(OpCode::BRANCH, 5, 11, -4, 4),  // 0x08: blt t0, a1, 0x04
+        Instruction::create_i(OpCode::JALR, 0, 1, 0, 0),
+    ]
+}
+
+#[test]
+fn simtx_loops_per_machine() {
+    let program = counting_loop();
+    let code : Vec<u8> = program.iter().flat_map(|i| i.0.to_le_bytes().to_vec()).collect();
+    let cfg = Cfg::build(&code, 0);
+
+    let mut with_loops : SIMTX<LoopAwareScheduler> = SIMTX::new(4, 1, HashMap::new());
+    with_loops.load_program(&cfg);
+    let without_loops : SIMTX<LoopAwareScheduler> = SIMTX::new(4, 1, HashMap::new());
+    assert_eq!(with_loops.program().loops.get(&0x4), Some(&0x8));
+    assert!(without_loops.program().loops.is_empty());
+
+    // both machines run at the same time
+    let runs : Vec<_> = vec![with_loops, without_loops].into_iter().map(|mut machine| {
+        let program = program.clone();
+        std::thread::spawn(move || {
+            let mut memory : Vec<u32> = program.iter().map(|i| i.le()).collect();
+            memory.resize(0x100 / 4, 0);
+            let memory = Arc::new(Mutex::new(memory));
+            for lane in 0..4 {
+                let c = machine.pop_first_idle();
+                machine.set_pc_of(c, 4);
+                machine.set_i_register_of(c, 1, 0);
+                machine.set_i_register_of(c, 11, 1 + lane);
+            }
+            for _ in 0..100 {
+                machine.step(memory.clone());
+                if machine.finished() { break }
+            }
+            assert!(machine.finished());
+            (0..4).map(|c| machine.get_i_register_of(c, 5)).collect::<Vec<i32>>()
+        })
+    }).collect();
+    for run in runs {
+        assert_eq!(run.join().unwrap(), vec![1, 2, 3, 4]);
+    }
+}
+
+#[test]
+fn simtx_heap_above_stacks() {
+    let mut plt = HashMap::new();
+    plt.insert(0x104, String::from("malloc"));
+    let mut machine : SIMTX<LexicoScheduler> = SIMTX::new(2, 1, plt);
+
+    // the stacks grow down from 0x20000000, the heap up from there
+    run_host_call_program(&mut machine, host_call_program(), |m, c| {
+        m.set_i_register_of(c, 10, 16);
+    });
+    let ptrs : Vec<u32> = (0..2).map(|c| machine.get_i_register_of(c, 10) as u32).collect();
+    assert!(ptrs.iter().all(|&p| p >= 0x2000_0000), "{:x?}", ptrs);
+    assert_ne!(ptrs[0], ptrs[1]);
+}
+
+#[test]
+#[should_panic(expected = "run into the heap")]
+fn simtx_stacks_into_heap() {
+    let mut machine : SIMTX<LexicoScheduler> = SIMTX::new(32, 8, HashMap::new());
+    machine.place_stack(0x1001_0000, 0x0010_0000);
+}
+
+#[test]
+fn simtx_invalid_free() {
+    let mut plt = HashMap::new();
+    plt.insert(0x104, String::from("free@GLIBC_2.27"));
+    let mut machine : SIMTX<LexicoScheduler> = SIMTX::new(2, 1, plt);
+
+    // both lanes free the same pointer, which was never allocated
+    run_host_call_program(&mut machine, host_call_program(), |m, c| {
+        m.set_i_register_of(c, 10, 0x10000000);
+    });
+    assert_eq!(machine.error(), Some(&GuestError::InvalidFree { ptr:0x10000000, pc:4 }));
+    assert_eq!(machine.heap_stats().frees, 0);
+}
+
+#[test]
+fn simtx_memcheck() {
+    // malloc(12), then read its first word and write right after its end
+    let mut memory : HashMap<usize, u32> = HashMap::new();
+    memory.allocate_at(0, 0x400);
+    memory.insert(0x4, Instruction::create_j(OpCode::JAL, 1, 0x100).le());
+    memory.insert(0x8, Instruction::create_i(OpCode::LOAD, 11, 10, 0, 2).le());
+    memory.insert(0xc, Instruction::create_s(OpCode::STORE, 10, 11, 12, 2).le());
+    memory.insert(0x10, Instruction::create_i(OpCode::JALR, 0, 1, 0, 0).le());
+    let memory = Arc::new(Mutex::new(memory));
+
+    let mut plt = HashMap::new();
+    plt.insert(0x104, String::from("malloc@GLIBC_2.27"));
+    let mut machine : SIMTX<LexicoScheduler> = SIMTX::new(2, 1, plt);
+    machine.enable_memcheck();
+    for _ in 0..2 {
+        let c = machine.pop_first_idle();
+        machine.set_pc_of(c, 4);
+        machine.set_i_register_of(c, 1, 0);
+        machine.set_i_register_of(c, 10, 12);
+    }
+    for _ in 0..100 {
+        machine.step(memory.clone());
+        if machine.finished() { break }
+    }
+    assert!(machine.finished());
+
+    let errors = machine.memcheck_errors();
+    assert_eq!(errors.len(), 2);
+    assert_eq!((errors[0].kind, errors[0].pc, errors[0].count), (MemErrorKind::UninitializedRead, 0x8, 2));
+    assert_eq!((errors[1].kind, errors[1].pc, errors[1].count), (MemErrorKind::OutOfBounds, 0xc, 2));
+    let block = errors[1].block.unwrap();
+    assert_eq!((block.size, block.allocated.pc), (12, 0x4));
+}
@@ -0,0 +1,96 @@
+extern crate riscv_sandbox;
+
+use riscv_sandbox::machine::simtx::memcheck::{MemChecker, MemErrorKind, Access, Site};
+
+fn load(pc:i32, tid:usize, addr:usize, size:usize) -> Access {
+    Access { pc, tid, lane:tid, addr, size, write:false }
+}
+
+fn store(pc:i32, tid:usize, addr:usize, size:usize) -> Access {
+    Access { pc, tid, lane:tid, addr, size, write:true }
+}
+
+fn checker() -> MemChecker {
+    let mut c = MemChecker::new();
+    c.set_heap(0x1000, 0x2000);
+    c.on_alloc(0x1000, 20, Site { pc:0x100, tid:0 }, false);
+    c.on_alloc(0x1020, 8, Site { pc:0x104, tid:1 }, true);
+    c
+}
+
+#[test]
+fn valid_accesses() {
+    let mut c = checker();
+    c.check_access(store(0x200, 0, 0x1000, 4));
+    c.on_writes(&[(0x1000, 4)]);
+    c.check_access(load(0x204, 0, 0x1000, 4));
+    c.check_access(load(0x208, 1, 0x1024, 4));
+    // outside of the heap area
+    c.check_access(load(0x20c, 0, 0x800, 4));
+    assert!(c.errors().is_empty());
+}
+
+#[test]
+fn uninitialized_read() {
+    let mut c = checker();
+    c.on_writes(&[(0x1000, 2)]);
+    c.check_access(load(0x200, 0, 0x1000, 2));
+    c.check_access(load(0x204, 0, 0x1000, 4));
+    // reported once for the same bytes
+    c.check_access(load(0x204, 0, 0x1000, 4));
+
+    let errors = c.errors();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].kind, MemErrorKind::UninitializedRead);
+    assert_eq!(errors[0].pc, 0x204);
+    assert_eq!(errors[0].block.unwrap().allocated, Site { pc:0x100, tid:0 });
+}
+
+#[test]
+fn out_of_bounds() {
+    let mut c = checker();
+    // crosses the end of the first block
+    c.check_access(store(0x200, 0, 0x1012, 4));
+    // in the padding after it
+    c.check_access(store(0x200, 1, 0x1018, 4));
+    // above every block
+    c.check_access(load(0x204, 0, 0x1100, 4));
+
+    let errors = c.errors();
+    assert_eq!(errors.len(), 2);
+    assert_eq!(errors[0].kind, MemErrorKind::OutOfBounds);
+    assert_eq!(errors[0].count, 2);
+    assert!(errors[0].write);
+    assert_eq!(errors[0].block.unwrap().start, 0x1000);
+    assert_eq!(errors[1].block.unwrap().start, 0x1020);
+}
+
+#[test]
+fn use_after_free() {
+    let mut c = checker();
+    c.on_free(0x1020, Site { pc:0x108, tid:1 });
+    c.check_access(load(0x200, 1, 0x1024, 4));
+
+    let errors = c.errors();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].kind, MemErrorKind::UseAfterFree);
+    assert_eq!(errors[0].block.unwrap().freed, Some(Site { pc:0x108, tid:1 }));
+
+    // the memory is given back by a new allocation
+    c.on_alloc(0x1020, 8, Site { pc:0x10c, tid:1 }, true);
+    c.check_access(load(0x204, 1, 0x1024, 4));
+    assert_eq!(c.errors().len(), 1);
+}
+
+#[test]
+fn stack_overflow() {
+    let mut c = checker();
+    c.check_stack(0x200, 3, 1, 0x8000, 0x7000);
+    c.check_stack(0x204, 3, 1, 0x6ff0, 0x7000);
+    c.check_stack(0x208, 3, 1, 0x6fe0, 0x7000);
+
+    let errors = c.errors();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].kind, MemErrorKind::StackOverflow);
+    assert_eq!((errors[0].pc, errors[0].tid, errors[0].addr), (0x204, 3, 0x6ff0));
+}
@@ -0,0 +1,201 @@
+extern crate riscv_sandbox;
+
+use riscv_sandbox::machine::{MultiCoreIMachine, simtx::{
+    Machine as SIMTX,
+    scheduler::LexicoScheduler,
+    predictor::*,
+}};
+use riscv_sandbox::isa::{Instruction, OpCode};
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+const PC : i32 = 0x1_0a48;
+
+#[test]
+fn table_index() {
+    let table = TableConfig::default();
+    assert_eq!(table.entries, 1 << 16);
+    assert_eq!(table.index(0x1234_5678), 0x5678 ^ 0x1234);
+
+    let table = TableConfig::new(100, Indexing::Direct);
+    assert_eq!(table.entries, 128);
+    assert_eq!(table.index(0x1_0a48), (0x1_0a48 >> 1) & 127);
+    assert_eq!(TableConfig::new(1, Indexing::Folded).index(PC), 0);
+    assert_eq!(TableConfig::new(usize::MAX, Indexing::Folded).entries, MAX_ENTRIES);
+    // 3 pieces of 12 bits
+    assert_eq!(TableConfig::new(4096, Indexing::Folded).index(-1), 0xfff ^ 0xfff ^ 0xff);
+}
+
+#[test]
+fn config() {
+    let config : PredictorConfig = "perceptron:4096:direct".parse().unwrap();
+    assert_eq!(config, PredictorConfig { kind:PredictorKind::Perceptron, table:TableConfig::new(4096, Indexing::Direct) });
+    assert_eq!(config.build::<u32>().name(), "perceptron");
+    let config : PredictorConfig = "history".parse().unwrap();
+    assert_eq!(config.table, TableConfig::default());
+    assert_eq!(config.build::<u64>().name(), "history");
+
+    assert!("tage".parse::<PredictorConfig>().is_err());
+    assert!("counter:many".parse::<PredictorConfig>().is_err());
+    assert!("counter:16777217".parse::<PredictorConfig>().is_err());
+    assert!(format!("counter:{}", usize::MAX).parse::<PredictorConfig>().is_err());
+    assert!("pattern:64:hashed".parse::<PredictorConfig>().is_err());
+    assert!("pattern:64:direct:1".parse::<PredictorConfig>().is_err());
+}
+
+// Trains `predictor` with `outcomes` at PC, and returns its accuracy on the
+// last `measured` ones
+fn accuracy(predictor:&mut dyn DivergencePredictor<u32>, outcomes:&[BranchOutcome<u32>], measured:usize) -> f64 {
+    let mut stats = PredictionStats::default();
+    for (i, outcome) in outcomes.iter().enumerate() {
+        let predicted = predictor.predict(PC, outcome.mask());
+        if i >= outcomes.len() - measured {
+            stats.record(predicted, outcome);
+        }
+        predictor.update(PC, outcome);
+    }
+    stats.accuracy()
+}
+
+// A branch diverging every other time
+fn alternating() -> Vec<BranchOutcome<u32>> {
+    (0..200).map(|i| if i % 2 == 0 {
+        BranchOutcome::Divergent(0b0011, 0b1100)
+    } else {
+        BranchOutcome::Uniform(true, 0b1111)
+    }).collect()
+}
+
+#[test]
+fn counter() {
+    let mut predictor = CounterPredictor::<u32>::new(TableConfig::default());
+    assert!(!predictor.predict(PC, 0b1111));
+    for _ in 0..2 {
+        predictor.update(PC, &BranchOutcome::Divergent(0b0011, 0b1100));
+    }
+    // by number of threads
+    assert!(predictor.predict(PC, 0b1111));
+    assert!(predictor.predict(PC, 0b1111_0000));
+    assert!(!predictor.predict(PC, 0b11));
+    assert!(!predictor.predict(PC + 4, 0b1111));
+
+    // two uniform executions are needed to come back
+    predictor.update(PC, &BranchOutcome::Uniform(false, 0b1111));
+    assert!(predictor.predict(PC, 0b1111));
+    predictor.update(PC, &BranchOutcome::Uniform(false, 0b1111));
+    assert!(!predictor.predict(PC, 0b1111));
+
+    // no history: wrong half of the time
+    assert!(accuracy(&mut predictor, &alternating(), 100) <= 0.5);
+}
+
+#[test]
+fn history() {
+    let mut predictor = HistoryPredictor::<u32>::new(TableConfig::default());
+    assert_eq!(accuracy(&mut predictor, &alternating(), 100), 1.);
+
+    // diverging once every 4 times
+    let outcomes : Vec<BranchOutcome<u32>> = (0..200).map(|i| if i % 4 == 3 {
+        BranchOutcome::Divergent(0b01, 0b10)
+    } else {
+        BranchOutcome::Uniform(true, 0b11)
+    }).collect();
+    let mut predictor = HistoryPredictor::<u32>::with_history(TableConfig::new(256, Indexing::Direct), 4);
+    assert_eq!(accuracy(&mut predictor, &outcomes, 100), 1.);
+}
+
+#[test]
+fn pattern() {
+    let mut predictor = PatternPredictor::<u32>::new(TableConfig::default());
+    assert!(!predictor.predict(PC, 0b1111));
+    predictor.update(PC, &BranchOutcome::Divergent(0b0011, 0b1100));
+    assert!(predictor.predict(PC, 0b1111));
+    assert!(predictor.predict(PC, 0b0101));
+    assert!(!predictor.predict(PC, 0b0011));
+    assert!(!predictor.predict(PC, 0b1100));
+
+    // the threads which ran the branch since then are updated, the others
+    // are kept
+    predictor.update(PC, &BranchOutcome::Uniform(true, 0b0100));
+    assert!(!predictor.predict(PC, 0b0111));
+    assert!(predictor.predict(PC, 0b1111));
+    predictor.update(PC, &BranchOutcome::Uniform(false, 0b1111));
+    assert!(!predictor.predict(PC, 0b1111));
+}
+
+#[test]
+fn perceptron() {
+    let mut predictor = PerceptronPredictor::<u32>::new(TableConfig::default());
+    assert!(accuracy(&mut predictor, &alternating(), 100) >= 0.99);
+
+    // a branch diverging when the one before it did
+    let mut predictor = PerceptronPredictor::<u32>::with_history(TableConfig::new(1024, Indexing::Direct), 4);
+    let mut stats = PredictionStats::default();
+    for i in 0..400 {
+        let divergent = (i / 3) % 2 == 0;
+        let first = if divergent { BranchOutcome::Divergent(0b01, 0b10) } else { BranchOutcome::Uniform(true, 0b11) };
+        predictor.update(PC, &first);
+        let predicted = predictor.predict(PC + 8, 0b11);
+        if i >= 300 {
+            stats.record(predicted, &first);
+        }
+        predictor.update(PC + 8, &first);
+    }
+    assert_eq!(stats.accuracy(), 1.);
+}
+
+// Each thread counts up to a1 = its lane + 1 in a loop, from 4: the branch
+// at 8 loses a thread at each iteration
+fn counting_loop(predictor:Option<&str>) -> SIMTX<LexicoScheduler> {
+    let program = vec![
+        Instruction::nop(),
+        Instruction::create_i(OpCode::OPIMM, 5, 5, 1, 0),     // 0x04
+        Instruction::create_b(OpCode::BRANCH, 5, 11, -4, 4),  // 0x08: blt t0, a1, 0x04
+        Instruction::create_i(OpCode::JALR, 0, 1, 0, 0),
+    ];
+    let mut memory : Vec<u32> = program.iter().map(|i| i.le()).collect();
+    memory.resize(0x100 / 4, 0);
+    let memory = Arc::new(Mutex::new(memory));
+
+    let mut machine : SIMTX<LexicoScheduler> = SIMTX::new(8, 1, HashMap::new());
+    if let Some(predictor) = predictor {
+        machine.set_divergence_predictor(predictor.parse::<PredictorConfig>().unwrap().build());
+    }
+    for lane in 0..8 {
+        let c = machine.pop_first_idle();
+        machine.set_pc_of(c, 4);
+        machine.set_i_register_of(c, 1, 0);
+        machine.set_i_register_of(c, 11, lane + 1);
+    }
+    for _ in 0..1000 {
+        machine.step(memory.clone());
+        if machine.finished() { break }
+    }
+    assert!(machine.finished());
+    machine
+}
+
+#[test]
+fn machine_stats() {
+    // the counters are indexed by the number of threads, which is never the
+    // same twice: the 7 divergences are missed, and the last thread leaves
+    // on its own, which is not a prediction
+    let machine = counting_loop(None);
+    assert_eq!(machine.warp(0).predictor().name(), "counter");
+    let stats = machine.prediction_stats();
+    assert_eq!(stats.keys().cloned().collect::<Vec<i32>>(), vec![0x8]);
+    assert_eq!(stats[&0x8], PredictionStats { bad_divergent:7, ..PredictionStats::default() });
+    assert_eq!(stats[&0x8].predictions(), 7);
+    assert_eq!(stats[&0x8].divergent(), 7);
+
+    // the last thread stayed in the loop when the others left it, and its
+    // last iteration on its own is not learnt
+    let machine = counting_loop(Some("pattern:16:direct"));
+    let warp = machine.warp(0);
+    assert_eq!(warp.predictor().name(), "pattern");
+    assert!(warp.predict_divergence(0x8, 0xff));
+    assert!(!warp.predict_divergence(0x8, 0x7f));
+    assert!(!warp.predict_divergence(0x8, 0x80));
+    assert_eq!(machine.prediction_stats()[&0x8].predictions(), 7);
+}
@@ -0,0 +1,221 @@
+extern crate riscv_sandbox;
+
+use riscv_sandbox::machine::{IntegerMachine, MultiCoreIMachine
+    , iss::Machine as ISS
+    , rv32imc::Machine as RV32I
+    , rv32pthread::Machine as Pthread
+    , simtx::{Machine as SIMTX, scheduler::LexicoScheduler}};
+use riscv_sandbox::isa::{Instruction, OpCode};
+use riscv_sandbox::cosim::*;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+// Each case runs one instruction at 0x04 with t0 and t1 set, writing t2,
+// then returns to 0 from 0x08, or from 0x0c when it jumps. The data it
+// loads and stores is at 0x80.
+#[derive(Clone, Copy, Debug)]
+struct Case {
+    inst:Instruction,
+    t0:i32,
+    t1:i32,
+}
+
+const T0 : u8 = 5;
+const T1 : u8 = 6;
+const T2 : u8 = 7;
+const DATA : i32 = 0x88;
+
+const EDGES : [i32; 9] = [0, 1, -1, i32::MIN, i32::MAX, 31, 32, 33, 0x1234_5678];
+const IMMEDIATES : [i32; 6] = [0, 1, -1, 0x555, 2047, -2048];
+
+// (funct7 << 3 | funct3) of the OP instructions
+const OPS : [(&str, u16); 18] = [
+    ("add", 0x000), ("sub", 0x100), ("sll", 0x001), ("slt", 0x002), ("sltu", 0x003)
+    , ("xor", 0x004), ("srl", 0x005), ("sra", 0x105), ("or", 0x006), ("and", 0x007)
+    , ("mul", 0x008), ("mulh", 0x009), ("mulhsu", 0x00a), ("mulhu", 0x00b)
+    , ("div", 0x00c), ("divu", 0x00d), ("rem", 0x00e), ("remu", 0x00f),
+];
+
+fn cases() -> Vec<Case> {
+    let mut cases = Vec::new();
+    let mut add = |inst:Instruction, t0:i32, t1:i32| cases.push(Case { inst, t0, t1 });
+
+    for &(_, funct) in OPS.iter() {
+        for &t0 in EDGES.iter() {
+            for &t1 in EDGES.iter() {
+                add(Instruction::create_r(OpCode::OPREG, T2, T0, T1, funct), t0, t1);
+            }
+        }
+        // x0 stays 0
+        add(Instruction::create_r(OpCode::OPREG, 0, T0, T1, funct), -1, 1);
+    }
+
+    // addi, slti, sltiu, xori, ori, andi
+    for &funct in [0b000, 0b010, 0b011, 0b100, 0b110, 0b111].iter() {
+        for &t0 in EDGES.iter() {
+            for &imm in IMMEDIATES.iter() {
+                add(Instruction::create_i(OpCode::OPIMM, T2, T0, imm, funct), t0, 0);
+            }
+        }
+    }
+    // slli, srli, srai
+    for &(funct, high) in [(0b001, 0), (0b101, 0), (0b101, 0x400)].iter() {
+        for &t0 in EDGES.iter() {
+            for &shamt in [0, 1, 7, 31].iter() {
+                add(Instruction::create_i(OpCode::OPIMM, T2, T0, high | shamt, funct), t0, 0);
+            }
+        }
+    }
+
+    for &imm in [0, 0x1000, 0x7fff_f000, i32::MIN, -4096].iter() {
+        add(Instruction::create_u(OpCode::LUI, T2, imm), 0, 0);
+        add(Instruction::create_u(OpCode::AUIPC, T2, imm), 0, 0);
+    }
+    add(Instruction::create_j(OpCode::JAL, T2, 8), 0, 0);
+    add(Instruction::create_j(OpCode::JAL, 0, 8), 0, 0);
+    // the lowest bit of the target is cleared
+    for &(t0, imm) in [(0x0c, 0), (0x0c, 1), (0x10, -4), (0x0d, 0)].iter() {
+        add(Instruction::create_i(OpCode::JALR, T2, T0, imm, 0), t0, 0);
+    }
+    add(Instruction::create_i(OpCode::JALR, T0, T0, 0, 0), 0x0c, 0);
+
+    // beq, bne, blt, bge, bltu, bgeu
+    for &funct in [0b000, 0b001, 0b100, 0b101, 0b110, 0b111].iter() {
+        for &t0 in EDGES.iter() {
+            for &t1 in EDGES.iter() {
+                add(Instruction::create_b(OpCode::BRANCH, T0, T1, 8, funct), t0, t1);
+            }
+        }
+    }
+
+    // lb, lh, lw, lbu, lhu, misaligned ones included
+    for &funct in [0b000, 0b001, 0b010, 0b100, 0b101].iter() {
+        for &imm in [-8, -7, -6, -5, -1, 0, 1, 2, 3].iter() {
+            add(Instruction::create_i(OpCode::LOAD, T2, T0, imm, funct), DATA, 0);
+        }
+    }
+    // sb, sh, sw
+    for &funct in [0b000, 0b001, 0b010].iter() {
+        for &t1 in EDGES.iter() {
+            for &imm in [-1, 0, 1, 2].iter() {
+                add(Instruction::create_s(OpCode::STORE, T0, T1, imm, funct), DATA, t1);
+            }
+        }
+    }
+    cases
+}
+
+fn memory(case:&Case) -> Vec<u32> {
+    let ret = Instruction::create_i(OpCode::JALR, 0, 1, 0, 0);
+    let mut memory = vec![0, case.inst.le(), ret.le(), ret.le()];
+    memory.resize(0x80 / 4, 0);
+    // bytes 0x80, 0xff, 0x7f, 0x01, 0x00, 0x80, 0xff, 0x7f, ...
+    memory.resize(0x100 / 4, 0x80ff_7f01);
+    for word in memory.iter_mut().skip(0x80 / 4).skip(1).step_by(2) {
+        *word = 0x0080_ff7f;
+    }
+    memory
+}
+
+fn reference(case:&Case) -> ISS {
+    let mut reference = ISS::new(4);
+    reference.set_register(T0 as usize, case.t0 as u32);
+    reference.set_register(T1 as usize, case.t1 as u32);
+    reference
+}
+
+// Runs all the cases with `run`, and fails with the divergences found
+fn check<F:Fn(&Case, &mut ISS, &mut Vec<u32>) -> Result<u64, Box<Divergence>>>(run:F) {
+    let cases = cases();
+    let mut failures = Vec::new();
+    for case in &cases {
+        let mut ref_mem = memory(case);
+        let result = run(case, &mut reference(case), &mut ref_mem);
+        match result {
+            Ok(2) => { },
+            Ok(n) => failures.push(format!("{}: {} instructions retired instead of 2\n", case.inst, n)),
+            Err(divergence) => failures.push(format!("t0 = 0x{:08x}, t1 = 0x{:08x}\n{}", case.t0, case.t1, divergence)),
+        }
+    }
+    assert!(failures.is_empty(), "{} of {} cases fail, the first ones:\n{}"
+            , failures.len(), cases.len(), failures.iter().take(5).cloned().collect::<Vec<String>>().join("\n"));
+}
+
+#[test]
+fn spec() {
+    // the reference itself, on the edge cases of the specification
+    let op = |name:&str, t0:i32, t1:i32| {
+        let funct = OPS.iter().find(|(n, _)| *n == name).unwrap().1;
+        let case = Case { inst:Instruction::create_r(OpCode::OPREG, T2, T0, T1, funct), t0, t1 };
+        let mut iss = reference(&case);
+        iss.step(&mut memory(&case)).unwrap();
+        iss.get_register(T2 as usize) as i32
+    };
+    assert_eq!(op("sll", 1, 3), 8);
+    assert_eq!(op("sll", 1, 33), 2);
+    assert_eq!(op("srl", i32::MIN, 32), i32::MIN);
+    assert_eq!(op("sra", i32::MIN, -1), -1);
+    assert_eq!(op("srl", i32::MIN, -1), 1);
+    assert_eq!(op("mul", i32::MAX, 2), -2);
+    assert_eq!(op("mulh", i32::MIN, i32::MIN), 0x4000_0000);
+    assert_eq!(op("mulhsu", -1, -1), -1);
+    assert_eq!(op("mulhu", -1, -1), -2);
+    assert_eq!(op("div", i32::MIN, -1), i32::MIN);
+    assert_eq!(op("rem", i32::MIN, -1), 0);
+    assert_eq!(op("div", 7, 0), -1);
+    assert_eq!(op("divu", 7, 0), -1);
+    assert_eq!(op("rem", -7, 0), -7);
+    assert_eq!(op("remu", 7, 0), 7);
+    assert_eq!(op("div", -7, 2), -3);
+    assert_eq!(op("rem", -7, 2), -1);
+
+    let load = |funct:u8, imm:i32| {
+        let case = Case { inst:Instruction::create_i(OpCode::LOAD, T2, T0, imm, funct), t0:DATA, t1:0 };
+        let mut iss = reference(&case);
+        iss.step(&mut memory(&case)).unwrap();
+        iss.get_register(T2 as usize)
+    };
+    assert_eq!(load(0b000, -7), 0xffff_ffff);
+    assert_eq!(load(0b100, -7), 0xff);
+    assert_eq!(load(0b001, -8), 0xffff_ff80);
+    assert_eq!(load(0b101, -8), 0xff80);
+    assert_eq!(load(0b010, -8), 0x017f_ff80);
+}
+
+#[test]
+fn rv32imc() {
+    check(|case, reference, ref_mem| {
+        let mut mem = memory(case);
+        let mut machine = RV32I::new();
+        machine.set_pc(4);
+        machine.set_i_register(T0 as usize, case.t0);
+        machine.set_i_register(T1 as usize, case.t1);
+        lockstep(&mut Rv32iDut::new(&mut machine, &mut mem), reference, ref_mem, 10)
+    });
+}
+
+#[test]
+fn rv32pthread() {
+    check(|case, reference, ref_mem| {
+        let mut mem = memory(case);
+        let mut machine = Pthread::new(HashMap::new());
+        machine.set_pc_of(0, 4);
+        machine.set_i_register_of(0, T0 as usize, case.t0);
+        machine.set_i_register_of(0, T1 as usize, case.t1);
+        lockstep(&mut PthreadCore::new(&mut machine, &mut mem), reference, ref_mem, 10)
+    });
+}
+
+#[test]
+fn simtx() {
+    check(|case, reference, ref_mem| {
+        let mem = Arc::new(Mutex::new(memory(case)));
+        // the second thread of the warp, alone
+        let mut machine : SIMTX<LexicoScheduler> = SIMTX::new(2, 1, HashMap::new());
+        machine.set_pc_of(1, 4);
+        machine.set_i_register_of(1, T0 as usize, case.t0);
+        machine.set_i_register_of(1, T1 as usize, case.t1);
+        lockstep(&mut SimtxLane::new(&mut machine, mem, 1), reference, ref_mem, 10)
+    });
+}
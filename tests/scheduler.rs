@@ -0,0 +1,276 @@
+extern crate riscv_sandbox;
+
+use riscv_sandbox::cfg::Cfg;
+use riscv_sandbox::machine::{MultiCoreIMachine, simtx::{
+    Machine as SIMTX,
+    Warp, Path,
+    scheduler::{SimtxScheduler, SchedulerRegistry, SchedulerVisitor, ProgramInfo, ProbaScheduler, LexicoScheduler, TimeShareScheduler, Rng},
+}};
+use riscv_sandbox::isa::{Instruction, OpCode};
+use riscv_sandbox::types::BitSet;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+// MinPC, writing down every event it is told about
+#[derive(Clone, Default)]
+struct Recorder {
+    events:Vec<String>,
+}
+
+impl SimtxScheduler for Recorder {
+    fn schedule<BV:BitSet>(simulator:&mut Warp<Self, BV>) -> Option<usize> {
+        simulator.current_path = simulator.paths.iter()
+            .enumerate()
+            .min_by_key(|(_, p)| p.fetch_pc)
+            .map(|(i, _)| i);
+        simulator.current_path
+    }
+
+    fn on_diverge<BV:BitSet>(simulator:&mut Warp<Self, BV>, pc:i32, targets:&[Path<BV>]) {
+        let targets : Vec<String> = targets.iter().map(|t| format!("{:x}", t.fetch_pc)).collect();
+        let event = format!("cycle {}: diverge {:x} to {}", simulator.cycle(), pc, targets.join(","));
+        simulator.scheduler.events.push(event);
+    }
+
+    fn on_merge<BV:BitSet>(simulator:&mut Warp<Self, BV>, pc:i32, mask:BV) {
+        simulator.scheduler.events.push(format!("merge {:x} at {:x}", mask, pc));
+    }
+
+    fn on_path_exit<BV:BitSet>(simulator:&mut Warp<Self, BV>, mask:BV) {
+        simulator.scheduler.events.push(format!("exit {:x}", mask));
+    }
+
+    fn on_memory_op<BV:BitSet>(simulator:&mut Warp<Self, BV>, pc:i32, write:bool, addresses:&[usize]) {
+        let block = simulator.program().is_block_start(pc);
+        simulator.scheduler.events.push(format!("{} at {:x} {:x?} block start {}"
+            , if write { "store" } else { "load" }, pc, addresses, block));
+    }
+}
+
+// t0 = a0 == 0 ? 2 : 1, stored at 0x100 + a1, from 4
+fn program() -> Vec<Instruction> {
+    vec![
+        Instruction::nop(),
+        Instruction::create_b(OpCode::BRANCH, 10, 0, 12, 0), // 0x04: beq a0, zero, 0x10
+        Instruction::create_i(OpCode::OPIMM, 5, 0, 1, 0),
+        Instruction::create_j(OpCode::JAL, 0, 8),             // 0x0c: j 0x14
+        Instruction::create_i(OpCode::OPIMM, 5, 0, 2, 0),     // 0x10
+        Instruction::create_s(OpCode::STORE, 11, 5, 0x100, 2),// 0x14
+        Instruction::create_i(OpCode::JALR, 0, 1, 0, 0),
+    ]
+}
+
+fn cfg() -> Cfg {
+    let code : Vec<u8> = program().iter().flat_map(|i| i.0.to_le_bytes().to_vec()).collect();
+    Cfg::build(&code, 0)
+}
+
+#[test]
+fn program_info() {
+    let info = ProgramInfo::from_cfg(&cfg());
+    let blocks : Vec<i32> = info.blocks.iter().cloned().collect();
+    assert_eq!(blocks, vec![0x0, 0x8, 0x10, 0x14]);
+    assert!(info.backward_branches.is_empty());
+    assert!(info.loops.is_empty());
+
+    // a loop: bnez a0, 0x0
+    let code : Vec<u8> = [Instruction::nop(), Instruction::create_b(OpCode::BRANCH, 10, 0, -4, 1)]
+        .iter().flat_map(|i| i.0.to_le_bytes().to_vec()).collect();
+    let info = ProgramInfo::from_cfg(&Cfg::build(&code, 0));
+    assert_eq!(info.backward_branches.get(&0x4), Some(&0x0));
+    assert_eq!(info.loops.get(&0x0), Some(&0x4));
+}
+
+#[test]
+fn events() {
+    let mut memory : Vec<u32> = program().iter().map(|i| i.le()).collect();
+    memory.resize(0x200 / 4, 0);
+    let memory = Arc::new(Mutex::new(memory));
+
+    let mut machine : SIMTX<Recorder> = SIMTX::new(2, 1, HashMap::new());
+    machine.load_program(&cfg());
+    for lane in 0..2 {
+        let c = machine.pop_first_idle();
+        machine.set_pc_of(c, 4);
+        machine.set_i_register_of(c, 1, 0);
+        machine.set_i_register_of(c, 10, lane as i32);
+        machine.set_i_register_of(c, 11, 4 * lane as i32);
+    }
+    for _ in 0..100 {
+        machine.step(memory.clone());
+        if machine.finished() { break }
+    }
+    assert!(machine.finished());
+    assert_eq!(machine.cycle(), 6);
+    // the exited threads are given back at the next step
+    machine.step(memory.clone());
+
+    // the second thread joins the first one at the start, then the not taken
+    // side runs first, and waits at the store for the other
+    assert_eq!(machine.warp(0).scheduler.events, vec![
+        "merge 2 at 4",
+        "cycle 0: diverge 4 to 10,8",
+        "merge 1 at 14",
+        "store at 14 [100, 104] block start true",
+        "exit 3",
+    ]);
+}
+
+// Each thread counts up to a1 = its lane + 1 in a loop, from 4
+fn counting_loop() -> Vec<Instruction> {
+    vec![
+        Instruction::nop(),
+        Instruction::create_i(OpCode::OPIMM, 5, 5, 1, 0),     // 0x04
+        Instruction::create_b(OpCode::BRANCH, 5, 11, -4, 4),  // 0x08: blt t0, a1, 0x04
+        Instruction::create_i(OpCode::JALR, 0, 1, 0, 0),
+    ]
+}
+
+// Runs the counting loop on 8 threads, and returns the PC run at each step
+// with the results
+fn run<S:SimtxScheduler>(machine:&mut SIMTX<S>) -> (Vec<i32>, Vec<i32>) {
+    let program = counting_loop();
+    let code : Vec<u8> = program.iter().flat_map(|i| i.0.to_le_bytes().to_vec()).collect();
+    machine.load_program(&Cfg::build(&code, 0));
+    let mut memory : Vec<u32> = program.iter().map(|i| i.le()).collect();
+    memory.resize(0x100 / 4, 0);
+    let memory = Arc::new(Mutex::new(memory));
+    for lane in 0..8 {
+        let c = machine.pop_first_idle();
+        machine.set_pc_of(c, 4);
+        machine.set_i_register_of(c, 1, 0);
+        machine.set_i_register_of(c, 11, lane + 1);
+    }
+
+    let mut pcs = Vec::new();
+    for _ in 0..1000 {
+        machine.step(memory.clone());
+        let warp = machine.warp(0);
+        if let Some(p) = warp.current_path.and_then(|p| warp.paths.get(p)) {
+            pcs.push(p.fetch_pc);
+        }
+        if machine.finished() { break }
+    }
+    assert!(machine.finished());
+    (pcs, (0..8).map(|c| machine.get_i_register_of(c, 5)).collect())
+}
+
+#[test]
+fn proba_reproducible() {
+    let schedule = |seed| {
+        let mut machine : SIMTX<ProbaScheduler> = SIMTX::new(8, 1, HashMap::new());
+        machine.seed(seed);
+        let (pcs, results) = run(&mut machine);
+        assert_eq!(results, (1..=8).collect::<Vec<i32>>());
+        pcs
+    };
+    assert_eq!(schedule(42), schedule(42));
+    let schedules : Vec<Vec<i32>> = (0..8).map(schedule).collect();
+    assert!(schedules.iter().any(|s| *s != schedules[0]));
+}
+
+#[test]
+fn path_ages() {
+    // MinPC runs the threads still counting, the others wait after the loop
+    let mut machine : SIMTX<LexicoScheduler> = SIMTX::new(8, 1, HashMap::new());
+    let program = counting_loop();
+    let mut memory : Vec<u32> = program.iter().map(|i| i.le()).collect();
+    memory.resize(0x100 / 4, 0);
+    let memory = Arc::new(Mutex::new(memory));
+    for lane in 0..2 {
+        let c = machine.pop_first_idle();
+        machine.set_pc_of(c, 4);
+        machine.set_i_register_of(c, 1, 0);
+        machine.set_i_register_of(c, 11, 1 + 4 * lane);
+    }
+    // addi, blt (diverges), then 3 of the 4 other iterations of the second
+    // thread: the first one waited for 6 cycles
+    for _ in 0..2 + 3 * 2 {
+        machine.step(memory.clone());
+    }
+    let ages : Vec<(i32, usize)> = machine.warp(0).paths.iter()
+        .map(|p| (p.fetch_pc, p.time_since_scheduled))
+        .collect();
+    assert_eq!(ages, vec![(0x4, 0), (0xc, 6)]);
+}
+
+#[test]
+fn rng() {
+    let mut a = Rng::new(7);
+    let mut b = Rng::new(7);
+    let draws : Vec<f64> = (0..100).map(|_| a.next_f64()).collect();
+    assert!(draws.iter().all(|d| *d >= 0. && *d < 1.));
+    assert_eq!(draws, (0..100).map(|_| b.next_f64()).collect::<Vec<f64>>());
+    assert_ne!(Rng::new(7).next_u64(), Rng::new(8).next_u64());
+}
+
+// Runs the counting loop with the visited scheduler, and tells which one it was
+struct Counting;
+
+impl SchedulerVisitor for Counting {
+    type Output = (String, Vec<i32>);
+    fn visit<S:SimtxScheduler>(self) -> Self::Output {
+        let mut machine : SIMTX<S> = SIMTX::new(8, 1, HashMap::new());
+        let (_, results) = run(&mut machine);
+        (String::from(std::any::type_name::<S>()), results)
+    }
+}
+
+#[test]
+fn registry() {
+    let mut registry = SchedulerRegistry::with_builtins();
+    let names : Vec<&str> = registry.schedulers().map(|(n, _)| n).collect();
+    assert_eq!(names, vec!["lexico", "loopaware", "proba", "timeshare"]);
+
+    let counted = (1..=8).collect::<Vec<i32>>();
+    for name in &names {
+        let (_, results) = registry.visit(name, Counting).unwrap();
+        assert_eq!(results, counted);
+    }
+    let (scheduler, _) = registry.visit("timeshare", Counting).unwrap();
+    assert!(scheduler.ends_with("timeshare::Scheduler"));
+
+    let error = registry.visit("fifo", Counting).unwrap_err();
+    assert!(error.contains("fifo") && error.contains("lexico, loopaware, proba, timeshare"));
+
+    // user schedulers are chosen by name as well
+    assert!(!registry.contains("recorder"));
+    registry.register::<Recorder>("recorder", "MinPC, writing down the events");
+    let (scheduler, results) = registry.visit("recorder", Counting).unwrap();
+    assert!(scheduler.ends_with("Recorder"));
+    assert_eq!(results, counted);
+    assert!(registry.schedulers().any(|(n, d)| n == "recorder" && d == "MinPC, writing down the events"));
+}
+
+#[test]
+fn scheduler_params() {
+    let mut lexico : SIMTX<LexicoScheduler> = SIMTX::new(8, 2, HashMap::new());
+    assert!(lexico.set_scheduler_param("schedule_threshold", "16").is_ok());
+    assert!(lexico.set_scheduler_param("rr_rounds", "64").is_ok());
+    assert!(lexico.set_scheduler_param("rr_rounds", "many").is_err());
+    assert!(lexico.set_scheduler_param("window", "8").is_err());
+
+    let mut timeshare : SIMTX<TimeShareScheduler> = SIMTX::new(8, 2, HashMap::new());
+    assert!(timeshare.set_scheduler_param("pool", "128").is_ok());
+    assert!(timeshare.set_scheduler_param("rr_rounds", "64").is_err());
+
+    let mut proba : SIMTX<ProbaScheduler> = SIMTX::new(8, 1, HashMap::new());
+    for (name, value) in &[("window", "1"), ("age_scale", "0.5"), ("loop_boost", "4")] {
+        assert!(proba.set_scheduler_param(name, value).is_ok());
+    }
+    assert!(proba.set_scheduler_param("age_scale", "old").is_err());
+    for bad in &["0", "-1", "inf", "NaN"] {
+        assert!(proba.set_scheduler_param("age_scale", bad).is_err());
+    }
+
+    // the schedulers run with their new parameters
+    let (_, results) = run(&mut lexico);
+    assert_eq!(results, (1..=8).collect::<Vec<i32>>());
+    let (_, results) = run(&mut proba);
+    assert_eq!(results, (1..=8).collect::<Vec<i32>>());
+
+    // Recorder has no parameter
+    let mut recorder : SIMTX<Recorder> = SIMTX::new(8, 1, HashMap::new());
+    assert!(recorder.set_scheduler_param("window", "8").is_err());
+}
@@ -0,0 +1,111 @@
+extern crate riscv_sandbox;
+
+use riscv_sandbox::cfg::Cfg;
+use riscv_sandbox::machine::{MultiCoreIMachine, simtx::{
+    Machine as SIMTX,
+    scheduler::LexicoScheduler,
+    ipdom::ReconvergencePoints,
+    stats::*,
+}};
+use riscv_sandbox::isa::{Instruction, OpCode};
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+// t1 = 2 * (a0 == 0 ? 2 : 1), a0 being 1 in the first half of the warp
+fn run(ipdom:bool) -> SimtStats {
+    let program = vec![
+        Instruction::nop(),
+        Instruction::create_b(OpCode::BRANCH, 10, 0, 12, 0), // 0x04: beq a0, zero, 0x10
+        Instruction::create_i(OpCode::OPIMM, 5, 0, 1, 0),
+        Instruction::create_j(OpCode::JAL, 0, 8),             // 0x0c: j 0x14
+        Instruction::create_i(OpCode::OPIMM, 5, 0, 2, 0),     // 0x10
+        Instruction::create_r(OpCode::OPREG, 6, 5, 5, 0),     // 0x14: join
+        Instruction::create_i(OpCode::JALR, 0, 1, 0, 0),
+    ];
+    let mut memory : Vec<u32> = program.iter().map(|i| i.le()).collect();
+    memory.resize(0x100 / 4, 0);
+    let memory = Arc::new(Mutex::new(memory));
+
+    let mut machine : SIMTX<LexicoScheduler> = SIMTX::new(4, 1, HashMap::new());
+    machine.set_stats_period(2);
+    if ipdom {
+        let code : Vec<u8> = program.iter().flat_map(|i| i.0.to_le_bytes().to_vec()).collect();
+        machine.enable_ipdom(ReconvergencePoints::from_cfg(&Cfg::build(&code, 0)));
+    }
+    for c in 0..4 {
+        machine.set_pc_of(c, 4);
+        machine.set_i_register_of(c, 1, 0);
+        machine.set_i_register_of(c, 10, (c < 2) as i32);
+    }
+    for _ in 0..100 {
+        machine.step(memory.clone());
+        if machine.finished() { break }
+    }
+    assert!(machine.finished());
+    assert_eq!((0..4).map(|c| machine.get_i_register_of(c, 6)).collect::<Vec<i32>>(), vec![2, 2, 4, 4]);
+    machine.simt_stats()
+}
+
+#[test]
+fn simd_efficiency() {
+    // beq, addi, j with the first half, addi with the second, add, ret
+    let stats = run(false);
+    assert_eq!(stats.tpw, 4);
+    assert_eq!(stats.cycles, 6);
+    assert_eq!(stats.warps, vec![WarpStats {
+        instructions:6, active_lanes:18,
+        path_cycles:9, max_paths:2,
+        scheduler_switches:1, barrier_wait:0,
+    }]);
+    assert_eq!(stats.simd_efficiency(), 0.75);
+    assert_eq!(stats.total().average_paths(stats.cycles), 1.5);
+    assert_eq!(stats.path_samples, vec![(0, 1), (2, 2), (4, 1)]);
+}
+
+#[test]
+fn divergences() {
+    for ipdom in &[false, true] {
+        let stats = run(*ipdom);
+        // the second half joins the first one at 0x14 after 3 cycles
+        assert_eq!(stats.branches.keys().cloned().collect::<Vec<i32>>(), vec![0x4, 0x18]);
+        assert_eq!(stats.branches[&0x4], BranchStats { executions:1, divergences:1, reconvergences:1, reconvergence_cycles:3 });
+        assert_eq!(stats.branches[&0x4].reconvergence_distance(), 3.);
+        assert_eq!(stats.branches[&0x18], BranchStats { executions:1, ..BranchStats::default() });
+        assert_eq!(stats.total().scheduler_switches, 1);
+    }
+}
+
+#[test]
+fn export() {
+    let stats = run(false);
+    let json = stats.to_json();
+    assert!(json.starts_with("{\"tpw\":4,\"cycles\":6,\"total\":{\"instructions\":6,\"active_lanes\":18,\"simd_efficiency\":0.75,"));
+    assert!(json.contains("{\"pc\":\"0x4\",\"location\":\"0x00000004\",\"executions\":1,\"divergences\":1,\"divergence_rate\":1,\"reconvergences\":1,\"reconvergence_distance\":3}"));
+    assert!(json.ends_with("\"path_samples\":[[0,1],[2,2],[4,1]]}\n"));
+    assert_eq!(json.matches('{').count(), json.matches('}').count());
+    assert_eq!(json.matches('[').count(), json.matches(']').count());
+
+    let csv = stats.to_csv();
+    let lines : Vec<&str> = csv.lines().collect();
+    assert_eq!(lines[0], "scope,id,metric,value");
+    assert!(lines.iter().all(|l| l.split(',').count() == 4));
+    for line in &["global,,cycles,6", "global,,simd_efficiency,0.75", "warp,0,scheduler_switches,1"
+                 , "branch,0x4,divergences,1", "branch,0x4,reconvergence_distance,3", "paths,2,paths,2"] {
+        assert!(lines.contains(line), "{}", line);
+    }
+
+    let report = format!("{}", stats);
+    assert!(report.starts_with("=== SIMT STATS: 6 CYCLES, 75.0% SIMD EFFICIENCY ==="));
+    assert!(report.contains("0x00000004: diverged 1 times out of 1, reconverged after 3.0 cycles on average"));
+}
+
+#[test]
+fn high_pcs() {
+    let mut stats = SimtStats::default();
+    stats.branches.insert(0x8000_0010u32 as i32, BranchStats { executions:1, ..BranchStats::default() });
+
+    // the PCs are unsigned, in hexadecimal, in both exports
+    assert!(stats.to_json().contains("{\"pc\":\"0x80000010\","));
+    assert!(stats.to_csv().lines().any(|l| l == "branch,0x80000010,executions,1"));
+}
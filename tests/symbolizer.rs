@@ -0,0 +1,34 @@
+extern crate riscv_sandbox;
+extern crate elf as elflib;
+
+use riscv_sandbox::symbolizer::{Symbolizer, SourceLocation};
+
+fn hartid_test() -> Symbolizer {
+    let file = elflib::File::open_path("resources/hartid_test").unwrap();
+    Symbolizer::from_elf(&file)
+}
+
+#[test]
+fn function_of_pc() {
+    let sym = hartid_test();
+    assert_eq!(sym.function_of(0x380), Some(("main", 0)));
+    assert_eq!(sym.function_of(0x214), Some(("fast_imul", 0x14)));
+    assert_eq!(sym.function_of(0x100), None);
+    assert_eq!(sym.address_of("init"), Some(0x294));
+}
+
+#[test]
+fn location_of_pc() {
+    let sym = hartid_test();
+    let loc = |line| Some(SourceLocation { file:String::from("hartid_test.c"), line });
+    assert_eq!(sym.location_of(0x200), loc(5));
+    assert_eq!(sym.location_of(0x216), loc(6));
+    assert_eq!(sym.location_of(0x250), loc(7));
+}
+
+#[test]
+fn describe() {
+    let sym = hartid_test();
+    assert_eq!(sym.describe(0x218), "0x00000218 <fast_imul+0x18> (hartid_test.c:7)");
+    assert_eq!(Symbolizer::new().describe(0x218), "0x00000218");
+}
@@ -0,0 +1,118 @@
+extern crate riscv_sandbox;
+
+use riscv_sandbox::machine::{MultiCoreIMachine, simtx::{
+    Machine as SIMTX,
+    scheduler::LexicoScheduler,
+    timing::{TimingConfig, WarpTiming},
+}};
+use riscv_sandbox::isa::{Instruction, OpCode};
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+// Runs `program` from address 4 on a single warp of `lanes` lanes, returning
+// to address 0 ends the program. `a0` is set by `arg` for each lane.
+fn run_machine<F:Fn(usize) -> i32>(program:&[Instruction], lanes:usize, arg:F) -> SIMTX<LexicoScheduler> {
+    let mut memory : Vec<u32> = vec![Instruction::nop().le()];
+    memory.extend(program.iter().map(|i| i.le()));
+    memory.resize(0x1000 / 4, 0);
+    let memory = Arc::new(Mutex::new(memory));
+
+    let mut machine : SIMTX<LexicoScheduler> = SIMTX::new(lanes, 1, HashMap::new());
+    machine.enable_timing(TimingConfig::default());
+    for lane in 0..lanes {
+        let c = machine.pop_first_idle();
+        machine.set_pc_of(c, 4);
+        machine.set_i_register_of(c, 1, 0);
+        machine.set_i_register_of(c, 6, 1);
+        machine.set_i_register_of(c, 10, arg(lane));
+    }
+    for _ in 0..1000 {
+        machine.step(memory.clone());
+        if machine.finished() { break }
+    }
+    assert!(machine.finished());
+    machine
+}
+
+fn run<F:Fn(usize) -> i32>(program:&[Instruction], lanes:usize, arg:F) -> (u64, WarpTiming) {
+    let machine = run_machine(program, lanes, arg);
+    let timing = machine.timing().unwrap();
+    (timing.cycle(), *timing.warp(0))
+}
+
+fn ret() -> Instruction {
+    Instruction::create_i(OpCode::JALR, 0, 1, 0, 0)
+}
+
+#[test]
+fn functional_unit_latency() {
+    let (cycles, stats) = run(&[
+        Instruction::create_r(OpCode::OPREG, 5, 6, 6, 0b0000001_000), // mul
+        Instruction::create_r(OpCode::OPREG, 7, 5, 5, 0), // add, waits for the mul
+        ret(),
+    ], 1, |_| 0);
+
+    // 2 cycles of front-end, then the add waits 2 cycles for the mul
+    assert_eq!(cycles, 7);
+    assert_eq!(stats.cycles, 7);
+    assert_eq!(stats.instructions, 3);
+    assert_eq!(stats.front_end_stalls, 2);
+    assert_eq!(stats.data_stalls, 2);
+    assert_eq!(stats.memory_stalls, 0);
+    assert!((stats.ipc() - 3. / 7.).abs() < 1e-9);
+}
+
+#[test]
+fn scalar_and_divergent_loads() {
+    let program = [
+        Instruction::create_i(OpCode::LOAD, 5, 10, 0, 2),
+        Instruction::create_i(OpCode::OPIMM, 7, 5, 1, 0),
+        ret(),
+    ];
+
+    // every lane in the same line: a single transaction
+    let (coalesced, stats) = run(&program, 4, |lane| 0x200 + 4 * lane as i32);
+    assert_eq!(coalesced, 24);
+    assert_eq!(stats.memory_transactions, 1);
+    assert_eq!(stats.memory_stalls, 19);
+    assert_eq!(stats.thread_instructions, 12);
+
+    // a line per lane: 3 more transactions
+    let (divergent, stats) = run(&program, 4, |lane| 0x200 + 64 * lane as i32);
+    assert_eq!(divergent, coalesced + 3 * TimingConfig::default().transaction as u64);
+    assert_eq!(stats.memory_transactions, 4);
+    assert_eq!(stats.memory_stalls, 31);
+}
+
+#[test]
+fn path_switch_penalty() {
+    let (cycles, stats) = run(&[
+        Instruction::create_b(OpCode::BRANCH, 10, 6, 8, 4), // blt a0, t1 (= 1)
+        Instruction::create_i(OpCode::OPIMM, 11, 0, 1, 0),
+        ret(),
+    ], 2, |lane| lane as i32);
+
+    // lane 1 falls through while lane 0 waits at the return: one switch,
+    // then lane 1 merges back without any
+    assert_eq!(stats.path_switches, 1);
+    assert_eq!(stats.front_end_stalls, 5);
+    assert_eq!(cycles, 8);
+}
+
+#[test]
+fn stalled_branches_are_counted_once() {
+    let machine = run_machine(&[
+        Instruction::create_i(OpCode::OPIMM, 10, 10, -1, 0),              // 0x04: addi a0, a0, -1
+        Instruction::create_r(OpCode::OPREG, 5, 10, 6, 0b0000001_000),    // 0x08: mul t0, a0, t1
+        Instruction::create_b(OpCode::BRANCH, 5, 0, -8, 0b001),           // 0x0c: bnez t0, 0x04
+        ret(),
+    ], 2, |_| 5);
+
+    // the branch waits for the mul at every iteration, but is issued 5 times
+    let stats = machine.timing().unwrap().warp(0);
+    assert!(stats.data_stalls >= 5);
+    let branch = &machine.loop_data()[&0x0c];
+    assert_eq!(branch.times_passed, 5);
+    assert_eq!(branch.num_threads_passed, 10);
+}
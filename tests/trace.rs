@@ -0,0 +1,243 @@
+extern crate riscv_sandbox;
+
+use riscv_sandbox::cache::{CacheConfig, CachedMemory, HierarchyConfig};
+use riscv_sandbox::machine::{IntegerMachine, MultiCoreIMachine
+    , rv32imc::Machine as RV32I
+    , simtx::{Machine as SIMTX, scheduler::LexicoScheduler}};
+use riscv_sandbox::isa::{Instruction, OpCode};
+use riscv_sandbox::trace::*;
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+// A trace output we can read back
+#[derive(Clone, Default)]
+struct Shared(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Shared {
+    fn write(&mut self, buf:&[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Shared {
+    fn text(&self) -> String {
+        String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+    }
+}
+
+fn new_tracer(format:TraceFormat, filter:TraceFilter) -> (Tracer, Shared) {
+    let out = Shared::default();
+    (Tracer::new(format, filter, Box::new(out.clone())), out)
+}
+
+fn records() -> Vec<TraceRecord> {
+    let mut addi = TraceRecord::new(0, 0x8000_0000u32 as i32, 0x0010_0293);
+    addi.writes.push(RegWrite::x(5, -1));
+    let mut lw = TraceRecord::new(1, 0x1_0074, 0x0002_a503);
+    lw.writes.push(RegWrite::x(10, 42));
+    lw.accesses.push(MemAccess::load(0x2000, 4));
+    // c.sw with garbage in the upper half
+    let mut sw = TraceRecord::new(12, 0x1_0078, 0x1234_c10c);
+    sw.accesses.push(MemAccess::store(0x2004, 4, 0xdead_beef));
+    sw.warp = Some((3, 0b1011));
+    let mut flw = TraceRecord::new(0, 0x1_007a, 0x0005_2087);
+    flw.writes.push(RegWrite::f(1, 0xffff_ffff_3f80_0000));
+    flw.accesses.push(MemAccess::store(0x2008, 1, 0x7f));
+    vec![addi, lw, sw, flw]
+}
+
+#[test]
+fn spike_format() {
+    let lines : Vec<String> = records().iter().map(|r| format!("{}", r)).collect();
+    assert_eq!(lines, vec![
+        "core   0: 3 0x80000000 (0x00100293) x5  0xffffffff",
+        "core   1: 3 0x00010074 (0x0002a503) x10 0x0000002a mem 0x00002000",
+        "core  12: 3 0x00010078 (0xc10c) mem 0x00002004 0xdeadbeef",
+        "core   0: 3 0x0001007a (0x00052087) f1  0xffffffff3f800000 mem 0x00002008 0x7f",
+    ]);
+}
+
+#[test]
+fn binary_format() {
+    let (mut tracer, out) = new_tracer(TraceFormat::Binary, TraceFilter::default());
+    for record in &records() {
+        tracer.record(record);
+    }
+    assert_eq!(tracer.written(), 4);
+    tracer.finish().unwrap();
+
+    let bytes = out.0.lock().unwrap().clone();
+    assert!(bytes.starts_with(BINARY_MAGIC));
+    assert_eq!(read_binary(&bytes), Ok(records()));
+    assert!(read_binary(&bytes[..bytes.len() - 1]).is_err());
+    assert!(read_binary(b"not a trace").is_err());
+
+    assert_eq!("binary".parse(), Ok(TraceFormat::Binary));
+    assert_eq!("spike".parse(), Ok(TraceFormat::Spike));
+    assert!("json".parse::<TraceFormat>().is_err());
+}
+
+#[test]
+fn filters() {
+    let pcs = |out:&Shared| out.text().lines()
+        .map(|l| l.split_whitespace().nth(3).unwrap().to_string())
+        .collect::<Vec<String>>();
+
+    let filter = TraceFilter { pcs:Some((0x1_0000, 0x1_0079)), ..TraceFilter::default() };
+    let (mut tracer, out) = new_tracer(TraceFormat::Spike, filter);
+    for record in &records() {
+        tracer.record(record);
+    }
+    assert_eq!(pcs(&out), vec!["0x00010074", "0x00010078"]);
+
+    // the warp, or the hart without warps
+    let filter = TraceFilter { warp:Some(3), ..TraceFilter::default() };
+    let (mut tracer, out) = new_tracer(TraceFormat::Spike, filter);
+    for record in &records() {
+        tracer.record(record);
+    }
+    assert_eq!(pcs(&out), vec!["0x00010078"]);
+    let filter = TraceFilter { warp:Some(0), ..TraceFilter::default() };
+    assert!(filter.matches(0x8000_0000, 0));
+    assert!(!filter.matches(0x8000_0000, 1));
+
+    let filter = TraceFilter { skip:1, count:Some(2), ..TraceFilter::default() };
+    let (mut tracer, out) = new_tracer(TraceFormat::Spike, filter);
+    for record in records().iter().chain(records().iter()) {
+        tracer.record(record);
+    }
+    assert_eq!(tracer.written(), 2);
+    assert!(!tracer.wants(0x1_0074, 0));
+    assert_eq!(pcs(&out), vec!["0x00010074", "0x00010078"]);
+}
+
+#[test]
+fn single_core() {
+    let program = vec![
+        Instruction::create_i(OpCode::OPIMM, 1, 0, 0x40, 0),  // 0x00: li ra, 0x40
+        Instruction::create_i(OpCode::OPIMM, 2, 0, 7, 0),     // 0x04: li sp, 7
+        Instruction::create_s(OpCode::STORE, 1, 2, 4, 0b010), // 0x08: sw sp, 4(ra)
+        Instruction::create_j(OpCode::JAL, 0, 8),             // 0x0c: j 0x14
+        Instruction::create_i(OpCode::OPIMM, 3, 0, 1, 0),     // 0x10: skipped
+        Instruction::create_i(OpCode::LOAD, 3, 1, 4, 0b010),  // 0x14: lw gp, 4(ra)
+    ];
+    let mut memory : Vec<u32> = program.iter().map(|i| i.le()).collect();
+    memory.resize(0x80 / 4, Instruction::nop().le());
+
+    let mut machine = RV32I::new();
+    let (tracer, out) = new_tracer(TraceFormat::Spike, TraceFilter { count:Some(6), ..TraceFilter::default() });
+    machine.enable_trace(tracer);
+    for _ in 0..16 {
+        machine.cycle(&mut memory);
+    }
+    assert_eq!(machine.get_i_register(3), 7);
+    assert_eq!(machine.tracer().unwrap().written(), 6);
+    machine.finish_trace().unwrap();
+
+    // the bubbles after the jump are not traced
+    let line = |pc:usize, rest:&str| format!("core   0: 3 0x{:08x} (0x{:08x}){}", pc, program[pc / 4].0, rest);
+    assert_eq!(out.text(), vec![
+        line(0x00, " x1  0x00000040"),
+        line(0x04, " x2  0x00000007"),
+        line(0x08, " mem 0x00000044 0x00000007"),
+        line(0x0c, ""),
+        line(0x14, " x3  0x00000007 mem 0x00000044"),
+        format!("core   0: 3 0x00000018 (0x{:08x})", Instruction::nop().0),
+    ].join("\n") + "\n");
+}
+
+#[test]
+fn simtx() {
+    // each thread stores its a0 at 0x80 + 4 * a0, the ones with a positive
+    // a0 adding 1 to it first
+    let program = vec![
+        Instruction::nop(),
+        Instruction::create_b(OpCode::BRANCH, 10, 0, 8, 4),        // 0x04: blt a0, zero, 0x0c
+        Instruction::create_i(OpCode::OPIMM, 10, 10, 1, 0),        // 0x08
+        Instruction::create_i(OpCode::OPIMM, 5, 10, 2, 0b001),     // 0x0c: slli t0, a0, 2
+        Instruction::create_s(OpCode::STORE, 5, 10, 0x80, 0b010),  // 0x10: sw a0, 0x80(t0)
+        Instruction::create_i(OpCode::JALR, 0, 1, 0, 0),
+    ];
+    let mut memory : Vec<u32> = program.iter().map(|i| i.le()).collect();
+    memory.resize(0x100 / 4, 0);
+    let memory = Arc::new(Mutex::new(memory));
+
+    let mut machine : SIMTX<LexicoScheduler> = SIMTX::new(4, 2, HashMap::new());
+    let filter = TraceFilter { warp:Some(1), pcs:Some((0x8, 0x14)), ..TraceFilter::default() };
+    let (tracer, out) = new_tracer(TraceFormat::Binary, filter);
+    machine.enable_trace(tracer);
+    for c in 0..8 {
+        machine.set_pc_of(c, 4);
+        machine.set_i_register_of(c, 1, 0);
+        machine.set_i_register_of(c, 10, if c % 4 < 2 { c as i32 } else { -(c as i32) });
+    }
+    for _ in 0..100 {
+        machine.step(memory.clone());
+        if machine.finished() { break }
+    }
+    assert!(machine.finished());
+    machine.finish_trace().unwrap();
+
+    let records = read_binary(&out.0.lock().unwrap()).unwrap();
+    assert!(records.iter().all(|r| r.warp.map(|w| w.0) == Some(1) && r.hart / 4 == 1));
+    // addi for threads 4 and 5, then slli and sw for the 4 threads
+    assert_eq!(records.len(), 2 + 2 * 4);
+    assert_eq!(records.iter().filter(|r| r.pc == 0x8).map(|r| (r.hart, r.warp.unwrap().1)).collect::<Vec<_>>()
+               , vec![(4, 0b0011), (5, 0b0011)]);
+    assert_eq!(records.iter().find(|r| r.pc == 0x8 && r.hart == 5).unwrap().writes, vec![RegWrite::x(10, 6)]);
+    let stores : Vec<(usize, MemAccess)> = records.iter().filter(|r| r.pc == 0x10)
+        .map(|r| (r.hart, r.accesses[0]))
+        .collect();
+    assert_eq!(stores.len(), 4);
+    assert!(stores.contains(&(4, MemAccess::store(0x80 + 4 * 5, 4, 5))));
+    assert!(stores.contains(&(7, MemAccess::store(0x80 - 4 * 7, 4, -7i32 as u32 as u64))));
+    assert!(records.iter().filter(|r| r.pc == 0x10).all(|r| r.writes.is_empty()));
+}
+
+#[test]
+fn simtx_store_values() {
+    // both threads store the low byte of their a0 at 0x80, then halfword
+    let program = vec![
+        Instruction::nop(),
+        Instruction::create_s(OpCode::STORE, 0, 10, 0x80, 0b000),  // 0x04: sb a0, 0x80(zero)
+        Instruction::create_s(OpCode::STORE, 0, 10, 0x84, 0b001),  // 0x08: sh a0, 0x84(zero)
+        Instruction::create_i(OpCode::JALR, 0, 1, 0, 0),
+    ];
+    let mut memory : Vec<u32> = program.iter().map(|i| i.le()).collect();
+    memory.resize(0x100 / 4, 0);
+    let config = HierarchyConfig { l1i:Some(CacheConfig::new(256, 2, 16))
+                                 , l1d:Some(CacheConfig::new(256, 2, 16)), l2:None };
+    let memory = Arc::new(Mutex::new(CachedMemory::new(memory, config)));
+
+    let mut machine : SIMTX<LexicoScheduler> = SIMTX::new(2, 1, HashMap::new());
+    let (tracer, out) = new_tracer(TraceFormat::Binary, TraceFilter::default());
+    machine.enable_trace(tracer);
+    for c in 0..2 {
+        machine.set_pc_of(c, 4);
+        machine.set_i_register_of(c, 1, 0);
+        machine.set_i_register_of(c, 10, 0x1234_5600 + c as i32);
+    }
+    for _ in 0..100 {
+        machine.step(memory.clone());
+        if machine.finished() { break }
+    }
+    assert!(machine.finished());
+    machine.finish_trace().unwrap();
+
+    // each lane gives its own value, not the one left in memory
+    let records = read_binary(&out.0.lock().unwrap()).unwrap();
+    let stores : Vec<(usize, MemAccess)> = records.iter().filter(|r| !r.accesses.is_empty())
+        .map(|r| (r.hart, r.accesses[0]))
+        .collect();
+    assert_eq!(stores, vec![(0, MemAccess::store(0x80, 1, 0x00)), (1, MemAccess::store(0x80, 1, 0x01))
+                            , (0, MemAccess::store(0x84, 2, 0x5600)), (1, MemAccess::store(0x84, 2, 0x5601))]);
+    // and the tracer does not read the memory
+    let l1d = memory.lock().unwrap().l1d_stats().unwrap();
+    assert_eq!((l1d.reads, l1d.writes), (0, 4));
+}
@@ -0,0 +1,203 @@
+extern crate riscv_sandbox;
+
+use riscv_sandbox::machine::{IntegerMachine, MultiCoreIMachine
+    , rv32imc::Machine as RV32I
+    , rv32pthread::Machine as Pthread
+    , simtx::{Machine as SIMTX, scheduler::LexicoScheduler}};
+use riscv_sandbox::isa::{CsrId, Instruction, OpCode};
+use riscv_sandbox::types::MachineInteger;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+const ECALL : u32 = 0x0000_0073;
+const MRET : u32 = 0x3020_0073;
+
+// csrrw, csrrs, csrrc, and their immediate forms with `funct3` | 0b100
+fn csr(funct3:u8, rd:u8, rs1:u8, csr:u16) -> u32 {
+    Instruction::create_i(OpCode::SYSTEM, rd, rs1, csr as i32, funct3).le()
+}
+
+fn ret() -> u32 {
+    Instruction::jalr(0, 1, 0).le()
+}
+
+// Sets mtvec to a handler at 0x80 which counts the traps in a5, shifts
+// their causes in a4, and returns after the instruction which trapped
+fn traps() -> Vec<u32> {
+    let mut memory = vec![
+        Instruction::nop().le(),
+        Instruction::lui(5, 0x8000_0000u32 as i32).le(),     // 0x04: lui t0, 0x80000
+        Instruction::addi(5, 5, 0x100).le(),                  // 0x08: addi t0, t0, 0x100
+        csr(0b001, 0, 5, 0x305),                              // 0x0c: csrw mtvec, t0
+        csr(0b010, 16, 0, 0x305),                             // 0x10: csrr a6, mtvec
+        Instruction::addi(5, 0, 0x80).le(),                   // 0x14: li t0, 0x80
+        csr(0b001, 0, 5, 0x305),                              // 0x18: csrw mtvec, t0
+        csr(0b010, 10, 0, 0x7c0),                             // 0x1c: csrr a0, 0x7c0 (none)
+        Instruction(ECALL).le(),                              // 0x20: ecall
+        csr(0b001, 0, 5, 0xf14),                              // 0x24: csrw mhartid, t0
+        Instruction::addi(6, 0, 0xff).le(),                   // 0x28: li t1, 0xff
+        csr(0b001, 0, 6, 0x340),                              // 0x2c: csrw mscratch, t1
+        csr(0b111, 0, 5, 0x340),                              // 0x30: csrci mscratch, 5
+        csr(0b010, 13, 0, 0x340),                             // 0x34: csrr a3, mscratch
+        csr(0b010, 17, 0, 0xf14),                             // 0x38: csrr a7, mhartid
+        ret(),                                                // 0x3c
+    ];
+    memory.resize(0x80 / 4, Instruction::nop().le());
+    memory.extend_from_slice(&[
+        csr(0b010, 7, 0, 0x342),                              // 0x80: csrr t2, mcause
+        Instruction::slli(14, 14, 4).le(),                    // 0x84: slli a4, a4, 4
+        Instruction::add(14, 14, 7).le(),                     // 0x88: add a4, a4, t2
+        csr(0b010, 7, 0, 0x341),                              // 0x8c: csrr t2, mepc
+        Instruction::addi(7, 7, 4).le(),                      // 0x90: addi t2, t2, 4
+        csr(0b001, 0, 7, 0x341),                              // 0x94: csrw mepc, t2
+        Instruction::addi(15, 15, 1).le(),                    // 0x98: addi a5, a5, 1
+        Instruction(MRET).le(),                               // 0x9c: mret
+    ]);
+    memory.resize(0x100 / 4, Instruction::nop().le());
+    memory
+}
+
+// Checks the registers `traps` leaves, as given by `reg`
+fn check_traps<F:Fn(usize) -> i32>(reg:F) {
+    assert_eq!(reg(16) as u32, 0x8000_0100, "mtvec keeps all its bits");
+    assert_eq!(reg(10), 0, "an unknown CSR is not read");
+    assert_eq!(reg(14), 0x2b2, "illegal instruction, ecall, illegal instruction");
+    assert_eq!(reg(15), 3);
+    assert_eq!(reg(13), 0xfa, "csrci clears the bits of its immediate");
+}
+
+#[test]
+fn csr_numbers() {
+    assert_eq!(CsrId::from_number(0xf14), Some(CsrId::MHARTID));
+    assert_eq!(CsrId::from_number(0x305), Some(CsrId::MTVEC));
+    assert_eq!(CsrId::from_number(0x7c0), None);
+    assert_eq!(CsrId::from(0x340), CsrId::MSCRATCH);
+
+    // up to all the bits
+    assert_eq!((-1i32).bit_slice(32, 2) << 2, -4);
+    assert_eq!((-1i32).bit_slice(2, 0), 3);
+}
+
+#[test]
+fn rv32imc() {
+    // the core does not stop at address 0, return to a `j .` instead
+    let mut memory = traps();
+    memory[0] = Instruction::create_j(OpCode::JAL, 0, 0).le();
+    let mut machine = RV32I::new();
+    machine.set_pc(4);
+    for _ in 0..200 {
+        machine.cycle(&mut memory);
+    }
+    check_traps(|r| machine.get_register(r));
+    assert_eq!(machine.get_register(17), 0);
+}
+
+#[test]
+fn rv32pthread() {
+    let mut memory = traps();
+    let mut machine = Pthread::new(HashMap::new());
+    machine.set_pc_of(0, 4);
+    for _ in 0..1000 {
+        machine.cycle(&mut memory);
+        if MultiCoreIMachine::finished(&machine) { break }
+    }
+    assert!(MultiCoreIMachine::finished(&machine));
+    check_traps(|r| machine.get_i_register_of(0, r));
+}
+
+#[test]
+fn simtx() {
+    let memory = Arc::new(Mutex::new(traps()));
+    let mut machine : SIMTX<LexicoScheduler> = SIMTX::new(2, 1, HashMap::new());
+    for _ in 0..2 {
+        let c = machine.pop_first_idle();
+        machine.set_pc_of(c, 4);
+        machine.set_i_register_of(c, 1, 0);
+    }
+    for _ in 0..1000 {
+        machine.step(memory.clone());
+        if machine.finished() { break }
+    }
+    assert!(machine.finished());
+    for c in 0..2 {
+        check_traps(|r| machine.get_i_register_of(c, r));
+        assert_eq!(machine.get_i_register_of(c, 17), c as i32, "mhartid");
+    }
+}
+
+#[test]
+fn simtx_mret_diverges() {
+    // each lane returns to 0x18 + 4 * mhartid: only the first one counts in a0
+    let memory = Arc::new(Mutex::new(vec![
+        Instruction::nop().le(),
+        csr(0b010, 5, 0, 0xf14),                              // 0x04: csrr t0, mhartid
+        Instruction::slli(5, 5, 2).le(),                      // 0x08: slli t0, t0, 2
+        Instruction::addi(5, 5, 0x18).le(),                   // 0x0c: addi t0, t0, 0x18
+        csr(0b001, 0, 5, 0x341),                              // 0x10: csrw mepc, t0
+        Instruction(MRET).le(),                               // 0x14: mret
+        Instruction::addi(10, 10, 1).le(),                    // 0x18: addi a0, a0, 1
+        Instruction::addi(11, 11, 1).le(),                    // 0x1c: addi a1, a1, 1
+        ret(),                                                // 0x20
+    ]));
+    let mut machine : SIMTX<LexicoScheduler> = SIMTX::new(2, 1, HashMap::new());
+    for _ in 0..2 {
+        let c = machine.pop_first_idle();
+        machine.set_pc_of(c, 4);
+        machine.set_i_register_of(c, 1, 0);
+    }
+    for _ in 0..100 {
+        machine.step(memory.clone());
+        if machine.finished() { break }
+    }
+    assert!(machine.finished());
+    assert_eq!((machine.get_i_register_of(0, 10), machine.get_i_register_of(0, 11)), (1, 1));
+    assert_eq!((machine.get_i_register_of(1, 10), machine.get_i_register_of(1, 11)), (0, 1));
+}
+
+// Replaces the `li a0, 1` at 0x14 with the `li a0, 7` at 0x40, then runs it
+fn self_modifying() -> Vec<u32> {
+    let mut memory = vec![
+        Instruction::nop().le(),
+        Instruction::lw(5, 0, 0x40).le(),                     // 0x04: lw t0, 0x40(zero)
+        Instruction::sw(0, 5, 0x14).le(),                     // 0x08: sw t0, 0x14(zero)
+        Instruction::create_i(OpCode::FENCE, 0, 0, 0, 1).le(), // 0x0c: fence.i
+        Instruction::nop().le(),                              // 0x10
+        Instruction::addi(10, 0, 1).le(),                     // 0x14: li a0, 1
+        ret(),                                                // 0x18
+    ];
+    memory.resize(0x40 / 4, Instruction::nop().le());
+    memory.push(Instruction::addi(10, 0, 7).le());            // 0x40
+    memory
+}
+
+#[test]
+fn fence_i() {
+    let mut memory = self_modifying();
+    let mut machine = RV32I::new();
+    machine.set_pc(4);
+    for _ in 0..100 {
+        machine.cycle(&mut memory);
+        if machine.finished() { break }
+    }
+    assert_eq!(machine.get_register(10), 7);
+
+    let mut memory = self_modifying();
+    let mut machine = Pthread::new(HashMap::new());
+    machine.set_pc_of(0, 4);
+    for _ in 0..100 {
+        machine.cycle(&mut memory);
+        if MultiCoreIMachine::finished(&machine) { break }
+    }
+    assert_eq!(machine.get_i_register_of(0, 10), 7);
+
+    let memory = Arc::new(Mutex::new(self_modifying()));
+    let mut machine : SIMTX<LexicoScheduler> = SIMTX::new(1, 1, HashMap::new());
+    machine.set_pc_of(0, 4);
+    machine.set_i_register_of(0, 1, 0);
+    for _ in 0..100 {
+        machine.step(memory.clone());
+        if machine.finished() { break }
+    }
+    assert_eq!(machine.get_i_register_of(0, 10), 7);
+}